@@ -0,0 +1,12 @@
+/*
+    Parse arbitrary bytes as a program
+*/
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rust_bfi::parse_untrusted(data);
+});
@@ -0,0 +1,26 @@
+/*
+    Run arbitrary bytes as a program, with anything after the first 0 byte as
+    its input, held to limits small enough to get through lots of programs
+*/
+
+#![no_main]
+
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+
+
+fuzz_target!(|data: &[u8]| {
+    let (prog, input) = match data.iter().position(|&b| b == 0) {
+        Some(i) => (&data[..i], &data[i + 1..]),
+        None => (data, &[][..]),
+    };
+    let limits = rust_bfi::Limits {
+        max_steps: Some(100_000),
+        max_output_bytes: Some(65536),
+        max_mem_cells: Some(65536),
+        timeout: Some(Duration::from_secs(1)),
+        max_loop_depth: None,
+    };
+    let _ = rust_bfi::run_untrusted_with_limits(prog, input, limits);
+});
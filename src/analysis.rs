@@ -0,0 +1,369 @@
+/*
+    Module for static analysis of a program, measuring it without running
+    it: how many of each command it has, how deeply its loops nest, how much
+    of the source is comments and how much of the tape it can reach, worked
+    out by following where the data pointer can be through each loop (a loop
+    that leaves the data pointer somewhere else each time round can go on
+    moving it that way indefinitely). The same walk proves, where it can, that
+    a program never takes the data pointer out of memory, so that the
+    interpreter can skip checking each move on a strict tape
+
+        commands: 16 (+ 6, [ 2, > 2, < 2, - 2, ] 2)
+        instructions: 11 after optimizing
+        loops: 2, nested at most 2 deep
+        comments: 33.3% of non-blank characters
+        tape: offsets 0..2 from the starting cell, 3 cells
+*/
+
+
+use std::fmt;
+
+use crate::{dtypes, optimizer, parsing};
+
+
+// offsets from the starting cell that the data pointer can be at, None for
+// no limit in that direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reach {
+    pub left: Option<isize>,
+    pub right: Option<isize>,
+}
+
+
+impl Reach {
+    // anywhere at all
+    const UNBOUNDED: Reach = Reach { left: Option::None, right: Option::None };
+
+    fn at (offset: isize) -> Reach {
+        Reach { left: Option::Some(offset), right: Option::Some(offset) }
+    }
+
+    fn shift (self, n: isize) -> Reach {
+        Reach { left: self.left.map(|left| left + n), right: self.right.map(|right| right + n) }
+    }
+
+    // everywhere either can be
+    fn union (self, other: Reach) -> Reach {
+        Reach {
+            left: self.left.zip(other.left).map(|(a, b)| a.min(b)),
+            right: self.right.zip(other.right).map(|(a, b)| a.max(b)),
+        }
+    }
+
+    // true if everywhere other can be is inside this
+    fn contains (&self, other: Reach) -> bool {
+        let left = match (self.left, other.left) {
+            (None, _) => true,
+            (Some(limit), other) => other.is_some_and(|other| other >= limit),
+        };
+        let right = match (self.right, other.right) {
+            (None, _) => true,
+            (Some(limit), other) => other.is_some_and(|other| other <= limit),
+        };
+        left && right
+    }
+
+    // number of cells, None if there's no limit
+    pub fn cells (&self) -> Option<usize> {
+        self.left.zip(self.right).map(|(left, right)| (right - left) as usize + 1)
+    }
+}
+
+
+// as a range, e.g. 0..4, -2.. or ..
+impl fmt::Display for Reach {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(left) = self.left {
+            write!(f, "{}", left)?;
+        }
+        write!(f, "..")?;
+        if let Some(right) = self.right {
+            write!(f, "{}", right)?;
+        }
+        Ok(())
+    }
+}
+
+
+// metrics of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis {
+    // times each command appears, in the order they first do
+    pub commands: Vec<(dtypes::Command, usize)>,
+    // number of instructions the optimizer turns the commands into
+    pub instructions: usize,
+    pub loops: usize,
+    pub max_depth: usize,
+    // characters of the source that are comments, and that aren't whitespace
+    pub comment_chars: usize,
+    pub source_chars: usize,
+    // cells the program can reach
+    pub reach: Reach,
+}
+
+
+// why check_bounds() couldn't prove that a program stays in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unproven {
+    // command index of the first instruction found that might take the data
+    // pointer (or a cell it uses) out of memory
+    pub position: usize,
+    // everywhere the data pointer might get to, from where it started
+    pub reach: Reach,
+}
+
+
+// loops nested deeper than this aren't followed, the data pointer is taken to
+// be able to go anywhere from there
+const MAX_NESTING: usize = 256;
+
+
+// instructions followed (at least, a loop can be followed twice at each level
+// it's nested so this can grow far faster than the program) before giving up
+// the same way
+const MIN_FUEL: usize = 1 << 20;
+
+
+// what follow() has found out so far
+struct Walk {
+    // everywhere the data pointer (or a cell at an offset from it) has got to
+    reach: Reach,
+    // where it has to stay, and the position of the first command found that
+    // might take it outside
+    within: Reach,
+    escape: Option<usize>,
+    // loops being followed, and instructions left to follow
+    depth: usize,
+    fuel: usize,
+}
+
+
+impl Walk {
+    // for following a program of len instructions
+    fn new (within: Reach, len: usize) -> Walk {
+        Walk { reach: Reach::at(0), within, escape: Option::None, depth: 0, fuel: MIN_FUEL.max(len.saturating_mul(8)) }
+    }
+
+    // the command at position can use cells anywhere in r
+    fn visit (&mut self, r: Reach, position: usize) {
+        self.reach = self.reach.union(r);
+        if self.escape.is_none() && !self.within.contains(r) {
+            self.escape = Option::Some(position);
+        }
+    }
+}
+
+
+// follow the data pointer through the instructions from start up to end from
+// where it can be at the start, noting everywhere it goes, gives where it can
+// be at the end
+fn follow (ops: &[dtypes::Op], start: usize, end: usize, mut at: Reach, walk: &mut Walk) -> Reach {
+    let mut i = start;
+    while i < end {
+        let op = ops[i];
+        if walk.fuel == 0 || walk.depth > MAX_NESTING {
+            walk.fuel = 0;
+            walk.visit(Reach::UNBOUNDED, op.position);
+            return Reach::UNBOUNDED;
+        }
+        walk.fuel -= 1;
+        match op.instr {
+            dtypes::Instr::Move(n) => at = at.shift(n),
+            dtypes::Instr::AddTo(offset, _) | dtypes::Instr::AddAt(offset, _)
+            | dtypes::Instr::ClearAt(offset) | dtypes::Instr::OutputAt(offset) => walk.visit(at.shift(offset), op.position),
+            // as far as the nearest 0, wherever that is
+            dtypes::Instr::ScanRight => at.right = Option::None,
+            dtypes::Instr::ScanLeft => at.left = Option::None,
+            dtypes::Instr::JumpIfZero(end) => {
+                walk.depth += 1;
+                let after = follow(ops, i + 1, end, at, walk);
+                if after != at {
+                    // going round again moves it further the same way, as far as it
+                    // likes, so only limits the body didn't go past still hold
+                    let wide = Reach {
+                        left: at.left.filter(|&left| after.left.is_some_and(|after| after >= left)),
+                        right: at.right.filter(|&right| after.right.is_some_and(|after| after <= right)),
+                    };
+                    at = wide.union(follow(ops, i + 1, end, wide, walk));
+                }
+                walk.depth -= 1;
+                i = end;
+            },
+            // a procedure body only runs when it's called, and there's no
+            // telling which one a call runs
+            dtypes::Instr::DefineProc(end) => i = end,
+            dtypes::Instr::CallProc => at = Reach::UNBOUNDED,
+            // there's no following the pointers of two Brainfork threads at once
+            dtypes::Instr::Fork => at = Reach::UNBOUNDED,
+            _ => {},
+        };
+        walk.visit(at, op.position);
+        i += 1;
+    }
+    at
+}
+
+
+// prove that a loaded program (its instructions, optimized or not) starting
+// with the data pointer on cell start of mem_size cells never takes it (or a
+// cell it uses) off either end, so it can run on a strict tape without
+// checking each move
+pub fn check_bounds (ops: &[dtypes::Op], start: usize, mem_size: usize) -> Result<(), Unproven> {
+    let start = start as isize;
+    let within = Reach { left: Option::Some(-start), right: Option::Some(mem_size as isize - 1 - start) };
+    let mut walk = Walk::new(within, ops.len());
+    follow(ops, 0, ops.len(), Reach::at(0), &mut walk);
+    match walk.escape {
+        Some(position) => Err(Unproven { position, reach: walk.reach }),
+        None => Ok(()),
+    }
+}
+
+
+impl Analysis {
+    // analyze a program (without any input embedded in it), fails if its
+    // brackets don't match
+    pub fn new (code: &str, dialect: dtypes::Dialect) -> Result<Analysis, dtypes::BFError> {
+        let cmds = parsing::parse_dialect(code, dialect);
+        let ops = optimizer::lower(&cmds)?;
+        let mut commands: Vec<(dtypes::Command, usize)> = Vec::new();
+        for &cmd in &cmds {
+            match commands.iter_mut().find(|(seen, _)| *seen == cmd) {
+                Some((_, count)) => *count += 1,
+                None => commands.push((cmd, 1)),
+            };
+        }
+        let (mut loops, mut depth, mut max_depth) = (0, 0, 0);
+        for cmd in &cmds {
+            match cmd {
+                dtypes::Command::JumpRightIfZero => {
+                    loops += 1;
+                    depth += 1;
+                    max_depth = max_depth.max(depth);
+                },
+                dtypes::Command::JumpLeftIfNonZero => depth -= 1,
+                _ => {},
+            };
+        }
+        // every command, the optimizer only ever takes moves away
+        let mut walk = Walk::new(Reach::UNBOUNDED, ops.len());
+        follow(&ops, 0, ops.len(), Reach::at(0), &mut walk);
+        Ok(Analysis {
+            commands,
+            instructions: optimizer::optimize(&cmds)?.len(),
+            loops,
+            max_depth,
+            comment_chars: parsing::comment_chars(code, dialect),
+            source_chars: code.chars().filter(|c| !c.is_whitespace()).count(),
+            reach: walk.reach,
+        })
+    }
+
+    // total number of commands
+    pub fn total (&self) -> usize {
+        self.commands.iter().map(|(_, count)| count).sum()
+    }
+}
+
+
+impl fmt::Display for Analysis {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts: Vec<String> = self.commands.iter().map(|(cmd, count)| format!("{} {}", cmd, count)).collect();
+        match counts.is_empty() {
+            true => writeln!(f, "commands: 0")?,
+            false => writeln!(f, "commands: {} ({})", self.total(), counts.join(", "))?,
+        };
+        writeln!(f, "instructions: {} after optimizing", self.instructions)?;
+        writeln!(f, "loops: {}, nested at most {} deep", self.loops, self.max_depth)?;
+        let percent = match self.source_chars {
+            0 => 0.0,
+            n => self.comment_chars as f64 * 100.0 / n as f64,
+        };
+        writeln!(f, "comments: {:.1}% of non-blank characters", percent)?;
+        let size = match (self.reach.cells(), self.reach.left, self.reach.right) {
+            (Some(1), _, _) => String::from("1 cell"),
+            (Some(n), _, _) => format!("{} cells", n),
+            (None, Some(_), None) => String::from("unbounded to the right"),
+            (None, None, Some(_)) => String::from("unbounded to the left"),
+            (None, _, _) => String::from("unbounded both ways"),
+        };
+        write!(f, "tape: offsets {} from the starting cell, {}", self.reach, size)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn reach (prog: &str) -> Reach {
+        Analysis::new(prog, dtypes::Dialect::Brainfuck).unwrap().reach
+    }
+
+    #[test]
+    fn analysis_report () {
+        let analysis = Analysis::new("+++[>++[>+<-]<-] multiply\n", dtypes::Dialect::Brainfuck).unwrap();
+        assert_eq!(analysis.total(), 16);
+        assert_eq!(analysis.commands[..3], [
+            (dtypes::Command::IncrementByte, 6),
+            (dtypes::Command::JumpRightIfZero, 2),
+            (dtypes::Command::MovePointerRight, 2),
+        ]);
+        assert_eq!((analysis.loops, analysis.max_depth), (2, 2));
+        assert_eq!(analysis.to_string(), "\
+commands: 16 (+ 6, [ 2, > 2, < 2, - 2, ] 2)
+instructions: 11 after optimizing
+loops: 2, nested at most 2 deep
+comments: 33.3% of non-blank characters
+tape: offsets 0..2 from the starting cell, 3 cells");
+        assert_eq!(Analysis::new("[", dtypes::Dialect::Brainfuck),
+                   Err(dtypes::BFError::UnmatchedBracket { bracket: '[', position: 0, source: None }));
+        assert!(Analysis::new("", dtypes::Dialect::Brainfuck).unwrap().to_string().starts_with("commands: 0\n"));
+    }
+
+    #[test]
+    fn analysis_reach () {
+        assert_eq!(reach(""), Reach::at(0));
+        assert_eq!(reach(">>><<<<"), Reach { left: Some(-1), right: Some(3) });
+        // balanced loops stay put
+        assert_eq!(reach("+[>+<-]>>"), Reach { left: Some(0), right: Some(2) });
+        // loops that move the data pointer can keep going
+        assert_eq!(reach(">[>]<"), Reach { left: Some(0), right: None });
+        assert_eq!(reach("[<<->]+"), Reach { left: None, right: Some(0) });
+        assert_eq!(reach("[>[<<]]").to_string(), "..");
+        assert_eq!(reach(">[>]").cells(), None);
+        // procedure calls could go anywhere
+        let pbrain = Analysis::new("(>>)+:", dtypes::Dialect::Pbrain).unwrap();
+        assert_eq!(pbrain.reach, Reach::UNBOUNDED);
+        assert!(pbrain.to_string().ends_with("tape: offsets .. from the starting cell, unbounded both ways"));
+    }
+
+    #[test]
+    fn analysis_check_bounds () {
+        let ops = |prog| optimizer::optimize(&parsing::parse_program(prog)).unwrap();
+        assert_eq!(check_bounds(&ops("++[>+++[>+<-]<-]>>."), 0, 3), Ok(()));
+        assert_eq!(check_bounds(&ops("++[>+++[>+<-]<-]>>."), 0, 2), Err(Unproven {
+            position: 7,
+            reach: Reach { left: Some(0), right: Some(2) },
+        }));
+        // starting further along
+        assert_eq!(check_bounds(&ops("<<+>>"), 2, 3), Ok(()));
+        assert_eq!(check_bounds(&ops("<<+>>"), 1, 3).unwrap_err().position, 0);
+        // a cell used at an offset counts as well as moves
+        assert!(check_bounds(&ops(">+<"), 0, 1).is_err());
+        // loops that move the data pointer might go anywhere that way
+        assert_eq!(check_bounds(&ops("+[>+]"), 0, 30000).unwrap_err(), Unproven {
+            position: 2,
+            reach: Reach { left: Some(0), right: None },
+        });
+        assert!(check_bounds(&ops(">[>]"), 0, 30000).is_err());
+        assert!(check_bounds(&ops(""), 0, 1).is_ok());
+        // loops nested too deeply (or too hard to follow) to bother with aren't
+        // proven, rather than overflowing the stack or taking forever
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert_eq!(check_bounds(&ops(&deep), 0, 30000).unwrap_err().reach, Reach::UNBOUNDED);
+        let hard = format!("+{}{}", "[>".repeat(60), "]".repeat(60));
+        assert_eq!(check_bounds(&ops(&hard), 0, 30000).unwrap_err().reach, Reach::UNBOUNDED);
+    }
+}
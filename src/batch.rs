@@ -0,0 +1,171 @@
+/*
+    Module for running a batch of programs, one after another or spread
+    over a number of threads, each in its own interpreter, e.g. to run a
+    suite of test programs
+
+        let mut batch = rust_bfi::Batch::new(|| rust_bfi::BFInterpreter::new(30000));
+        batch.add("a.bf", "++++++++[>++++++++<-]>+.", Option::None);
+        batch.add("b.bf", ",+.", Option::Some(b"a"));
+        batch.set_threads(2);
+        for result in batch.run() {
+            println!("{}: {:?}", result.name, result.output);
+        }
+*/
+
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::{dtypes, interpreter};
+
+
+// a program waiting to be run
+struct Job {
+    name: String,
+    prog: String,
+    // None to read any input embedded in the program (or none at all)
+    input: Option<Vec<u8>>,
+}
+
+
+// how one program in a batch went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchResult {
+    // name given when the program was added
+    pub name: String,
+    pub output: Vec<u8>,
+    // statistics about the run, the result holds any error loading the program
+    pub report: dtypes::RunReport,
+}
+
+
+// run a program to completion in a fresh interpreter, collecting its output
+// rather than using the interpreter's own sink, with the given input (or any
+// embedded in the program if None)
+pub(crate) fn run_program (mut bfi: interpreter::BFInterpreter, prog: &str, input: Option<&[u8]>) -> (Vec<u8>, dtypes::RunReport) {
+    bfi.set_output_sink(interpreter::OutputSink::Buffer);
+    bfi.set_io(Option::None);
+    bfi.set_input(&[]);
+    let report = match bfi.load_program(prog) {
+        Ok(()) => {
+            if let Some(input) = input {
+                bfi.set_input(input);
+            }
+            bfi.run_with_report()
+        },
+        Err(e) => dtypes::RunReport::not_run(e),
+    };
+    (bfi.take_output(), report)
+}
+
+
+pub struct Batch {
+    jobs: Vec<Job>,
+    // makes a fresh, configured interpreter for each program
+    setup: Box<dyn Fn() -> interpreter::BFInterpreter + Send + Sync>,
+    threads: usize,
+}
+
+
+impl Batch {
+    // new empty batch, setup is called (on whichever thread runs the program) to
+    // make the interpreter for each program, the output sink and input it is
+    // given are replaced
+    pub fn new<F: Fn() -> interpreter::BFInterpreter + Send + Sync + 'static> (setup: F) -> Batch {
+        Batch { jobs: Vec::new(), setup: Box::new(setup), threads: 1 }
+    }
+
+    // add a program to the end of the batch, reading the given input (or any
+    // embedded in the program if None)
+    pub fn add (&mut self, name: &str, prog: &str, input: Option<&[u8]>) {
+        self.jobs.push(Job { name: name.to_string(), prog: prog.to_string(), input: input.map(|i| i.to_vec()) });
+    }
+
+    // number of programs to run at a time on their own threads (1 by default
+    // to run them one after another on the calling thread)
+    pub fn set_threads (&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    // number of programs in the batch
+    pub fn len (&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    // run a single program to completion
+    fn run_job (&self, job: &Job) -> BatchResult {
+        let (output, report) = run_program((self.setup)(), &job.prog, job.input.as_deref());
+        BatchResult { name: job.name.clone(), output, report }
+    }
+
+    // run every program, returning how each went in the order they were added
+    pub fn run (&self) -> Vec<BatchResult> {
+        if self.threads == 1 || self.jobs.len() < 2 {
+            return self.jobs.iter().map(|job| self.run_job(job)).collect();
+        }
+        // each thread takes the next program nobody has started on yet
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<(usize, BatchResult)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.threads.min(self.jobs.len())).map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        match self.jobs.get(i) {
+                            Some(job) => done.push((i, self.run_job(job))),
+                            None => return done,
+                        };
+                    }
+                })
+            }).collect();
+            // a panicking program takes its thread down with it, pass that on
+            handles.into_iter().flat_map(|handle| handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e))).collect()
+        });
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn batch (threads: usize) -> Batch {
+        let mut batch = Batch::new(|| {
+            let mut bfi = interpreter::BFInterpreter::new(8);
+            bfi.set_max_steps(Some(10000));
+            bfi
+        });
+        batch.add("hello", "++++++++[>++++++++<-]>+.", None);
+        batch.add("echo", ",+.,+.", Some(b"ab"));
+        batch.add("embedded", ",.!z", None);
+        batch.add("unbalanced", "[", None);
+        batch.add("forever", "+[]", None);
+        batch.set_threads(threads);
+        batch
+    }
+
+    #[test]
+    fn batch_runs_in_order () {
+        for threads in [1, 3] {
+            let results = batch(threads).run();
+            let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+            assert_eq!(names, ["hello", "echo", "embedded", "unbalanced", "forever"]);
+            assert_eq!(results[0].output, b"A");
+            assert_eq!(results[0].report.result, Ok(()));
+            assert_eq!(results[1].output, b"bc");
+            assert_eq!(results[1].report.bytes_input, 2);
+            assert_eq!(results[2].output, b"z");
+            assert!(matches!(results[3].report.result, Err(dtypes::BFError::UnmatchedBracket { bracket: '[', .. })));
+            assert!(matches!(results[4].report.result, Err(dtypes::BFError::StepLimitExceeded { .. })));
+        }
+        assert_eq!(batch(1).len(), 5);
+        assert!(Batch::new(|| interpreter::BFInterpreter::new(8)).run().is_empty());
+    }
+}
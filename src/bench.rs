@@ -0,0 +1,323 @@
+/*
+    Module for benchmarking the engines on a bundled set of classic
+    programs (or any others), each program is run on every engine a few
+    times keeping the fastest run, then the timings are put in a table:
+
+        program     engine         instructions       time   speedup
+        sierpinski  unoptimized          257749     18.7ms      1.0x
+        sierpinski  optimized             30654      5.3ms      3.6x
+*/
+
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::{dtypes, interpreter};
+
+
+// hello world, from the Wikipedia article on brainfuck
+pub const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+
+// a Sierpinski triangle, by Daniel B. Cristofani
+pub const SIERPINSKI: &str = "\
+++++++++[>+>++++<<-]>++>>+<[-[>>+<<-]+>>]>+[
+    -<<<[
+        ->[+[-]+>++>>>-<<]<[<]>>++++++[<<+++++>>-]+<<++.[-]<<
+    ]>.>+[>>]>+
+]
+";
+
+
+// the squares from 0 to 10000, by Daniel B. Cristofani
+pub const SQUARES: &str = "\
+++++[>+++++<-]>[<+++++>-]+<+[
+    >[>+>+<<-]++>>[<<+>>-]>>>[-]++>[-]+
+    >>>+[[-]++++++>>>]<<<[[<++++++++<++>>-]+<.<[>----<-]<]
+    <<[>>>>>[>>>[-]+++++++++<[>-<-]+++++++++>[-[<->-]+[<<<]]<[>+<-]>]<<-]<<-
+]
+";
+
+
+// rot13 of its input until the end of it, from the Wikipedia article on brainfuck
+pub const ROT13: &str = "\
+-,+[
+    -[
+        >>++++[>++++++++<-]
+        <+<-[
+            >+>+>-[>>>]
+            <[[>+<-]>>+>]
+            <<<<<-
+        ]
+    ]>>>[-]+
+    >--[-[<->+++[-]]]<[
+        ++++++++++++<[
+            >-[>+>>]
+            >[+[<+>-]>+>>]
+            <<<<<-
+        ]
+        >>[<+>-]
+        >[
+            -[
+                -<<[-]>>
+            ]<<[<<->>-]>>
+        ]<<[<<+>>-]
+    ]
+    <[-]
+    <.[-]
+    <-,+
+]
+";
+
+
+// the text given to rot13
+const ROT13_INPUT: &str = "The quick brown fox jumps over the lazy dog. Gur dhvpx oebja sbk whzcf bire gur ynml qbt.\n";
+
+
+// a small Mandelbrot set, 36 by 11 characters, worked out in 1/16ths on 8 bit
+// cells, it reads the most times to iterate each point in decimal up to a newline
+pub const MANDELBROT: &str = "\
+,----------[-------------------------------------->>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>[-
+<++++++++++>]<[->+<]<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<[->>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<],----------]>>>>>>>>>>>>>>>>+++++
++++++++++++++++<<<<<<<<<<<<<<<+++++++++++[->>>>>>>>>>>>>>>>>>>+++++++++++++++++++++++++++++++<<<<<<<
+<<<<<<<<<<<++++++++++++++++++++++++++++++++++++[->>>>>>[->>>>>>>>>>>>>>>>>>>>>>>>+<<<<<<+<<<<<<<<<<<
+<<<<<<<]>>>>>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>]<<<<<<[->>>>>>>>>>>>>>>+<<<<<<<<<+
+<<<<<<]>>>>>>[-<<<<<<+>>>>>>]>>>>>>>>>>+<[<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>>>>>>>>
+>>>>>>-]>[->]<<[->>>>+<<<<]<<<[->>>>>>>+<<<<<<<]<<<<<<<<<<<<<[->>>>>>>>>>>>>+<<<<<<+<<<<<<<]>>>>>>>[
+-<<<<<<<+>>>>>>>]<<<<<<<<<<[->>>>>>>>>>>>>>>>>>>+<<<<<<<<<+<<<<<<<<<<]>>>>>>>>>>[-<<<<<<<<<<+>>>>>>>
+>>>]>>>>>>>>>>+<[<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>-]>[->]<<[->>>+<<
+<]<<<[->>>>>>+<<<<<<]>>>>>>>>>>>>>>>>[->+<<<<<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>>>>]<<<<<<<<<<<<
+<<<<<<<<<<[->>>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>>>>>>>>>[<<<<<<<<<<[->>+<<]
+>>>>++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]>[-<<<+>>>]>[-]>[-<<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>
+>>]>[-<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>]<<<<<<<[->>>+<<<]>>>>>++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<
+<<<<]>[-<<<<+>>>>]>[-]>[-<<<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>>>]>[-<<<<<<<<<<<<<<<<<+>>>>>>>>>>
+>>>>>>>]<<<<<<[-]<[-]<<<<<<<<<<<[->>>>>>>>>>>>>>+<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>>++++++++<<[->+>-[>+>
+>]>[+[-<+>]>+>>]<<<<<<]>[-<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>]>[-]>[->>>+<<<]>[->>>>>+<<<<<]>>[-]<<<<<<<
+<<<<<<<<<<<<[->>>>>>>>>>>>>+<<<<<<<<<<<<<]>>>>>>>>>>>>>>>++++++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]
+>[-<<<<<<<<<<<<<<+>>>>>>>>>>>>>>]>[-]>[->>>+<<<]>[->>>>>+<<<<<]>>[-]>>>>+<[[-]<[-]>>>>>>>>>>+<<<<<<<
+<-]>[-<<<<<<<<<<<<<<<<<<<<<<<<[-<<+>+>]<[->+<]<[->>[->>>>+<<<<<+>]<[->+<]<]>>[-<<+>+>]<[->+<]<[-<[->
+>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<+<<]>>[-<<+>>]<]<[->+>+<<]>>[-<<+>>]<[->>[->>>>>>>>>>>>>+<<<<<<<<<<<<<
+<+>]<[->+<]<]>>>>>>>>>>>>>>>[->+<]>>>++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]>[-<<+>>]>[-]>[->>>+<<<]
+>[-<<<<<<<<<<<<<<+>>>>>>>>>>>>>>]<<<<<[-]>>>>>>>[-]<<<<<<<<<<<<<<<<<<<[-<<<+>+>>]<<[->>+<<]<[->>>[->
++<<<+>>]<<[->>+<<]<]>>>[-<<<+>+>>]<<[->>+<<]<[-<<[->>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<+<<<]>>>[-<<<+>>>
+]<]<<[->>+>+<<<]>>>[-<<<+>>>]<[->>>[->>>>>>>>>>>>+<<<<<<<<<<<<<<+>>]<<[->>+<<]<]>>>>>>>>>>>>>>>[->+<
+]>>>++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]>[-<<+>>]>[-]>[->>>+<<<]>[-<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>
+>>>]<<<<<[-]>>>>>>>[-]<<<<<<<<<<<<<<<<<<<<[-<<+>+>]<[->+<]<[->>>[-<<<<<<<<<<<<<<<<++>>>>>>>>>>>>>>+>
+>]<<[->>+<<]<]>>[-<<+>+>]<[->+<]<[-<<[->>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<+<<<]>>>[-<<<+>>>]<]<[->+>+<<
+]>>[-<<+>>]<[->>>[->>>>>>>>>>>>+<<<<<<<<<<<<<<+>>]<<[->>+<<]<]>>>>>>>>>>>>>>>[->+<]>>>++++<<[->+>-[>
++>>]>[+[-<+>]>+>>]<<<<<<]>[-<<+>>]>[-]>[->>>+<<<]>[-<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<++>>>>>>>>>>>>>
+>>>>>>>>>>>>>>>>>>>>]<<<<<[-]>>>>>>>[-]<<<<<<<<<<<<<<<<[->+<]<<<<<<<<<<<<<<<<<<<<<<<[->>>>>>>>>>>>>>
+>>>>>>>>>>+<<<<<<+<<<<<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>]>>>[-
+>+<]<<<<<<<<<[->>>>>>>>>>+<<<<+<<<<<<]>>>>>>[-<<<<<<+>>>>>>]>>>>[->>>+<[->-]>[->>+<]<<<<]<<<<<<<<<<<
+<<<<<<<<<<<<<<<<[->-<]>[->+<]>>+<[>>>>>>>[->+<]<<<<<<<[-]>-]>[->>>>>>[-<<<+>>>]<<<<<]>>>>>>>>>>>>>>>
+>>>>>>>>>>>>>>+<[<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>-]>[->]<<[->>>>+<<<
+<]<<<[->>>>>>>+<<<<<<<]<<<<<<<<<<<<<<<<<<<<<<<[->>>>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<<<]>>>>
+[->>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<]>>>>>>[->>>>>>>>>>>>>+<<<<<<+<<<<<<<]>>>>>>>[-<<<<<<<+>>>>>>>]
+<<<<<<<<<<[->>>>>>>>>>>>>>+<<<<+<<<<<<<<<<]>>>>>>>>>>[-<<<<<<<<<<+>>>>>>>>>>]>>>>[->>>+<[->-]>[->>+<
+]<<<<]>>>>>>+<[<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>-]>[->]<<[->>>+<<<]
+<<<[->>>>>>+<<<<<<]>>>>>>>>>>>>>>>>>>>>>+<<<<->>>]<<<<<<<<<<<<<<<<<<<<<<<<<[-]<<<[-]>>>>[-]<<<<<[-]>
+>>>>>>>>>>>>>>>>>>>>>>>>>]<<<<<<<<<<[-]<[-]<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<[-]<[-]>>>>>>>>>>>>>>>>
+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>+<[<<<+>+<[<<+<[->-]>[->>>>>>>>++++++++++++++++++++++++++++
+++++.[-]<<<<<<[-]<]>>-]>[->]<+<[<<+<[->-]>[->>>>>>>>++++++++++++++++++++++++++++++++++++++++++++++.[
+-]<<<<<<[-]<]>>-]>[->]<+<[<<+<[->-]>[->>>>>>>>++++++++++++++++++++++++++++++++++++++++++++.[-]<<<<<<
+[-]<]>>-]>[->]<+<[<<+<[->-]>[->>>>>>>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.[-]
+<<<<<<[-]<]>>-]>[->]<+<[<<+<[->-]>[->>>>>>>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++.[-]<<<<<<[-]<]>>-]>[->]<+<[<<+<[->-]>[->>>>>>>>+++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++.[-]<<<<<<[-]<]>>-]>[->]<+<[<<+<[->-]>[->>>>>>>>++++++++++++++++++++++++++++++++++++++++
++++.[-]<<<<<<[-]<]>>-]>[->]<+<[>>>>>>++++++++++++++++++++++++++++++++++++++++++.[-]<<<<<<[-]>-]>[->]
+>[-]>-]>[->>+++++++++++++++++++++++++++++++++++.[-]<]<<<<<<<<[-]<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+<[-
+>-]>[-<<<<<<<<<<<<<+>>>>>>>>>>>>>>]<<<<<<<<<<<<<<<<<<<<]>>>>>>[-]>>>>>>>>>>>>[-]>>>>>>>>>>>>>>>>>>>>
+>>>>>>>>>>>>>>>>>>>>>>++++++++++.[-]<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+<[->-]>[->>+<]<+<[
+->-]>[->>+<]<+<[->-]>[->>+<]<+<[->-]>[->>+<]<<<<<<<<<<<<<<<<<]
+";
+
+
+// the iterations given to mandelbrot
+const MANDELBROT_INPUT: &str = "8\n";
+
+
+// number of memory cells programs are run with, per the classic spec
+const MEM_SIZE: usize = 30000;
+
+
+// a program to time and the input it reads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub name: String,
+    pub source: String,
+    pub input: Vec<u8>,
+}
+
+
+// the bundled programs
+pub fn bundled () -> Vec<Program> {
+    let program = |name: &str, source: &str, input: &str| Program {
+        name: name.to_string(),
+        source: source.to_string(),
+        input: input.as_bytes().to_vec(),
+    };
+    vec![
+        program("hello", HELLO, ""),
+        program("sierpinski", SIERPINSKI, ""),
+        program("squares", SQUARES, ""),
+        program("rot13", ROT13, ROT13_INPUT),
+        program("mandelbrot", MANDELBROT, MANDELBROT_INPUT),
+    ]
+}
+
+
+// a way of running programs to time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub name: &'static str,
+    pub optimize: bool,
+    pub engine: dtypes::Engine,
+}
+
+
+// every engine that is built in, the plain unoptimized interpreter first
+pub fn engines () -> Vec<EngineConfig> {
+    vec![
+        EngineConfig { name: "unoptimized", optimize: false, engine: dtypes::Engine::Interpreter },
+        EngineConfig { name: "optimized", optimize: true, engine: dtypes::Engine::Interpreter },
+        #[cfg(feature = "jit")]
+        EngineConfig { name: "jit", optimize: true, engine: dtypes::Engine::Jit },
+    ]
+}
+
+
+// how a program did on an engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timing {
+    pub program: String,
+    pub engine: &'static str,
+    // the report from the fastest run
+    pub report: dtypes::RunReport,
+    pub output: Vec<u8>,
+}
+
+
+// run a program on an engine once, returning the report and what it output
+fn run_once (program: &Program, engine: &EngineConfig, max_steps: Option<u64>) -> (dtypes::RunReport, Vec<u8>) {
+    let mut bfi = interpreter::BFInterpreter::new(MEM_SIZE);
+    bfi.set_optimize(engine.optimize);
+    bfi.set_engine(engine.engine);
+    bfi.set_max_steps(max_steps);
+    bfi.set_output_sink(interpreter::OutputSink::Buffer);
+    let report = match bfi.load_program(&program.source) {
+        Ok(()) => {
+            bfi.set_input(&program.input);
+            bfi.run_with_report()
+        },
+        Err(e) => dtypes::RunReport::not_run(e),
+    };
+    (report, bfi.take_output())
+}
+
+
+// run a program on an engine (at least once) runs times, keeping the fastest
+// run, a run that fails isn't repeated
+pub fn time (program: &Program, engine: &EngineConfig, runs: usize, max_steps: Option<u64>) -> Timing {
+    let (mut report, mut output) = run_once(program, engine, max_steps);
+    for _ in 1..runs {
+        if report.result.is_err() {
+            break;
+        }
+        let (next, next_output) = run_once(program, engine, max_steps);
+        if next.elapsed < report.elapsed {
+            (report, output) = (next, next_output);
+        }
+    }
+    Timing { program: program.name.clone(), engine: engine.name, report, output }
+}
+
+
+// a duration with a unit that suits it
+fn format_duration (d: Duration) -> String {
+    let secs = d.as_secs_f64();
+    match secs {
+        s if s >= 1.0 => format!("{:.2}s", s),
+        s if s >= 1e-3 => format!("{:.1}ms", s * 1e3),
+        s => format!("{:.1}us", s * 1e6),
+    }
+}
+
+
+// the timings as a table, one row each, with how many times faster each engine
+// was than the first one the same program was run on (the unoptimized
+// interpreter, from engines()), failed runs show the error instead
+pub fn table (timings: &[Timing]) -> String {
+    let width = timings.iter().map(|t| t.program.len()).chain(["program".len()]).max().unwrap_or(0);
+    let mut out = String::new();
+    let _ = writeln!(out, "{:<width$}  {:<11}  {:>14}  {:>9}  {:>8}", "program", "engine", "instructions", "time", "speedup");
+    let mut baseline: Option<(&str, Duration)> = Option::None;
+    for t in timings {
+        if baseline.is_none_or(|(program, _)| program != t.program) {
+            baseline = Option::Some((&t.program, t.report.elapsed));
+        }
+        let _ = write!(out, "{:<width$}  {:<11}  ", t.program, t.engine);
+        match &t.report.result {
+            Ok(()) => {
+                let base = baseline.map_or(Duration::ZERO, |(_, elapsed)| elapsed);
+                let speedup = base.as_secs_f64() / t.report.elapsed.as_secs_f64().max(1e-9);
+                let _ = writeln!(out, "{:>14}  {:>9}  {:>7.1}x", t.report.instructions_executed, format_duration(t.report.elapsed), speedup);
+            },
+            Err(e) => {
+                let _ = writeln!(out, "error: {}", e);
+            },
+        };
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn bench_bundled_programs_run () {
+        let programs = bundled();
+        let expected: [&[u8]; 5] = [b"Hello World!\n", b"* * * *", b"9801\n10000\n", b"Gur dhvpx oebja sbk", b"\n####################################\n"];
+        for (program, expected) in programs.iter().zip(expected) {
+            let mut outputs = Vec::new();
+            // mandelbrot takes far too long unoptimized in a debug build
+            let skip = usize::from(program.name == "mandelbrot");
+            for engine in engines().into_iter().skip(skip) {
+                let timing = time(program, &engine, 1, Some(10_000_000));
+                assert_eq!(timing.report.result, Ok(()), "{} on {}", program.name, engine.name);
+                assert!(timing.output.windows(expected.len()).any(|w| w == expected), "{}", program.name);
+                outputs.push(timing.output);
+            }
+            assert!(outputs.windows(2).all(|pair| pair[0] == pair[1]), "{}", program.name);
+        }
+        let rot13 = &programs[3];
+        let timing = time(rot13, &engines()[0], 1, None);
+        assert!(timing.output.ends_with(b"The quick brown fox jumps over the lazy dog.\n"));
+    }
+
+    #[test]
+    fn bench_table () {
+        let timing = |program: &str, engine, millis, result| Timing {
+            program: program.to_string(),
+            engine,
+            report: dtypes::RunReport { result, elapsed: Duration::from_millis(millis), instructions_executed: 1000, ..dtypes::RunReport::not_run(dtypes::BFError::Io { position: 0, source: None, msg: String::new() }) },
+            output: Vec::new(),
+        };
+        let table = table(&[
+            timing("hello", "unoptimized", 8, Ok(())),
+            timing("hello", "optimized", 2, Ok(())),
+            timing("squares", "unoptimized", 1500, Ok(())),
+            timing("squares", "optimized", 0, Err(dtypes::BFError::StepLimitExceeded { position: 3, source: None, limit: 10 })),
+        ]);
+        assert_eq!(table, "\
+program  engine         instructions       time   speedup
+hello    unoptimized            1000      8.0ms      1.0x
+hello    optimized              1000      2.0ms      4.0x
+squares  unoptimized            1000      1.50s      1.0x
+squares  optimized    error: step limit of 10 exceeded at command 3
+");
+    }
+}
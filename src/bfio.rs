@@ -0,0 +1,330 @@
+/*
+    Module with the BfIo trait for plugging input and output backends into
+    the interpreter, along with backends for stdin/stdout, in-memory buffers,
+    channels, any reader and writer and seeded random input, and a writer
+    that slows output down
+*/
+
+
+use std::any::Any;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+
+// where a program's input comes from and its output goes, set one with
+// BFInterpreter::set_io()
+pub trait BfIo: Any {
+    // the next byte of input, None once there is no more
+    fn read_byte (&mut self) -> io::Result<Option<u8>>;
+
+    fn write_byte (&mut self, byte: u8) -> io::Result<()>;
+
+    // called before blocking on input and once the program stops, so that
+    // buffered output shows up
+    fn flush (&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+// reads stdin and writes stdout
+#[derive(Debug, Default)]
+pub struct StdIo;
+
+
+impl BfIo for StdIo {
+    fn read_byte (&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0_u8; 1];
+        match io::stdin().read(&mut byte)? {
+            0 => Ok(Option::None),
+            _ => Ok(Option::Some(byte[0])),
+        }
+    }
+
+    fn write_byte (&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+
+// reads from a fixed input and collects output in memory
+#[derive(Debug, Default)]
+pub struct BufferIo {
+    input: io::Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+
+impl BufferIo {
+    pub fn new (input: &[u8]) -> BufferIo {
+        BufferIo { input: io::Cursor::new(input.to_vec()), output: Vec::new() }
+    }
+
+    // input that hasn't been read yet
+    pub fn remaining_input (&self) -> &[u8] {
+        self.input.get_ref().get(self.input.position() as usize..).unwrap_or_default()
+    }
+
+    // everything written so far
+    pub fn output (&self) -> &[u8] {
+        &self.output
+    }
+}
+
+
+impl BfIo for BufferIo {
+    fn read_byte (&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0_u8; 1];
+        match self.input.read(&mut byte)? {
+            0 => Ok(Option::None),
+            _ => Ok(Option::Some(byte[0])),
+        }
+    }
+
+    fn write_byte (&mut self, byte: u8) -> io::Result<()> {
+        self.output.push(byte);
+        Ok(())
+    }
+}
+
+
+// receives input and sends output a byte at a time over channels, e.g. to run a
+// program on its own thread, reading blocks until a byte arrives and the input
+// ends once every sender has been dropped, writing fails once the receiver has
+pub struct ChannelIo {
+    input: mpsc::Receiver<u8>,
+    output: mpsc::Sender<u8>,
+}
+
+
+impl ChannelIo {
+    pub fn new (input: mpsc::Receiver<u8>, output: mpsc::Sender<u8>) -> ChannelIo {
+        ChannelIo { input, output }
+    }
+}
+
+
+impl BfIo for ChannelIo {
+    fn read_byte (&mut self) -> io::Result<Option<u8>> {
+        Ok(self.input.recv().ok())
+    }
+
+    fn write_byte (&mut self, byte: u8) -> io::Result<()> {
+        self.output.send(byte)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output channel closed"))
+    }
+}
+
+
+// reads from any reader and writes to any writer, e.g. input given up front
+// in a Cursor with output going to stdout
+#[derive(Debug, Default)]
+pub struct StreamIo<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+}
+
+
+impl<R: Read, W: Write> StreamIo<R, W> {
+    pub fn new (reader: R, writer: W) -> StreamIo<R, W> {
+        StreamIo { reader, writer }
+    }
+
+    pub fn writer (&self) -> &W {
+        &self.writer
+    }
+}
+
+
+impl<R: Read + 'static, W: Write + 'static> BfIo for StreamIo<R, W> {
+    fn read_byte (&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0_u8; 1];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(Option::None),
+            _ => Ok(Option::Some(byte[0])),
+        }
+    }
+
+    fn write_byte (&mut self, byte: u8) -> io::Result<()> {
+        self.writer.write_all(&[byte])
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+
+// reads pseudo-random bytes generated from a seed, the same seed always gives
+// the same bytes, so programs that consume input can be exercised reproducibly,
+// output goes to stdout, or use it as an input source with
+// BFInterpreter::set_input_source() to send output somewhere else
+#[derive(Debug, Clone)]
+pub struct RandomInput {
+    state: u64,
+    // bytes left before the input ends (None for endless input)
+    remaining: Option<usize>,
+}
+
+
+impl RandomInput {
+    // len bytes of input, or endless input if None
+    pub fn new (seed: u64, len: Option<usize>) -> RandomInput {
+        RandomInput { state: seed, remaining: len }
+    }
+
+    // the next byte, None once the input has ended
+    fn next_byte (&mut self) -> Option<u8> {
+        match &mut self.remaining {
+            Some(0) => return Option::None,
+            Some(n) => *n -= 1,
+            None => {},
+        };
+        Option::Some(splitmix64(&mut self.state) as u8)
+    }
+}
+
+
+// next number from a splitmix64 generator, which copes with any seed (including 0)
+pub(crate) fn splitmix64 (state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+
+impl Read for RandomInput {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        for slot in buf.iter_mut() {
+            match self.next_byte() {
+                Some(byte) => *slot = byte,
+                None => break,
+            };
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+
+impl BfIo for RandomInput {
+    fn read_byte (&mut self) -> io::Result<Option<u8>> {
+        Ok(self.next_byte())
+    }
+
+    fn write_byte (&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+
+// writes a byte at a time, flushing and then sleeping after each one, so output
+// comes out like a typewriter, e.g. to watch animated programs, use it as the
+// sink with OutputSink::Writer
+#[derive(Debug)]
+pub struct SlowWriter<W: Write> {
+    inner: W,
+    delay: Duration,
+}
+
+
+impl<W: Write> SlowWriter<W> {
+    pub fn new (inner: W, delay: Duration) -> SlowWriter<W> {
+        SlowWriter { inner, delay }
+    }
+
+    pub fn into_inner (self) -> W {
+        self.inner
+    }
+}
+
+
+impl<W: Write> Write for SlowWriter<W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.inner.write_all(&[byte])?;
+            self.inner.flush()?;
+            thread::sleep(self.delay);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn buffer_io () {
+        let mut bio = BufferIo::new(b"ab");
+        assert_eq!(bio.read_byte().unwrap(), Some(b'a'));
+        assert_eq!(bio.remaining_input(), b"b");
+        assert_eq!(bio.read_byte().unwrap(), Some(b'b'));
+        assert_eq!(bio.read_byte().unwrap(), None);
+        bio.write_byte(1).unwrap();
+        bio.write_byte(2).unwrap();
+        assert_eq!(bio.output(), [1, 2]);
+    }
+
+    #[test]
+    fn channel_io () {
+        let (in_tx, in_rx) = mpsc::channel();
+        let (out_tx, out_rx) = mpsc::channel();
+        let mut cio = ChannelIo::new(in_rx, out_tx);
+        in_tx.send(7).unwrap();
+        drop(in_tx);
+        assert_eq!(cio.read_byte().unwrap(), Some(7));
+        assert_eq!(cio.read_byte().unwrap(), None);
+        cio.write_byte(9).unwrap();
+        assert_eq!(out_rx.recv().unwrap(), 9);
+        drop(out_rx);
+        assert_eq!(cio.write_byte(9).unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn random_input () {
+        // the same seed gives the same bytes whether read one at a time or in bulk
+        let mut a = RandomInput::new(42, Some(64));
+        let mut b = RandomInput::new(42, None);
+        let mut bytes = Vec::new();
+        a.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 64);
+        for &byte in &bytes {
+            assert_eq!(b.read_byte().unwrap(), Some(byte));
+        }
+        assert_eq!(a.read_byte().unwrap(), None);
+        // and a different seed gives different bytes
+        let mut other = vec![0; 64];
+        RandomInput::new(43, None).read_exact(&mut other).unwrap();
+        assert_ne!(bytes, other);
+        assert!(bytes.iter().any(|&byte| byte != bytes[0]));
+    }
+
+    #[test]
+    fn slow_writer () {
+        let mut slow = SlowWriter::new(Vec::new(), Duration::from_millis(5));
+        let start = std::time::Instant::now();
+        slow.write_all(b"abcd").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(slow.into_inner(), b"abcd");
+    }
+}
@@ -0,0 +1,2481 @@
+/*
+    Module with command line argument parsing
+*/
+
+
+use std::fs;
+
+use rust_bfi::{compiler, config, crossval, dtypes, equiv, formatter, generator, obfuscator, parsing};
+
+
+pub const USAGE: &str = "usage: rust_bfi [run] [--config FILE] [--mem-size N] [--tape strict|wrapping|growable|bidirectional] [--sparse] [--cell-width 8|16|32] \
+                         [--eof nochange|zero|255|error] [--max-steps N] [--checkpoint FILE] [--resume FILE] \
+                         [--no-optimize] [--precompute STEPS] [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] [--enable-hash-debug] [--map SPEC | --map-file FILE] [--input TEXT | --input-file FILE | --input-random [--seed N]] [--no-embedded-input] [--raw-input] [--init-mem BYTES | --init-mem-file FILE] [--engine interpreter|jit] [--dump-mem-on-exit] [--exit-cell N] [--filter] [--flush-every-byte | --output-buffer BYTES] \
+                         [--trace] [--trace-file FILE] [--record FILE] [--profile] [--profile-trace FILE] [--coverage] [--lcov FILE] [--stats] [--output-format text|json] [--color auto|always|never] [--delay-ms N] [--debug [--break N]... [--watch N]... [--history N] | --tui] [--jobs N] [--] <program.bf>...
+       rust_bfi filter [run options] <program.bf>
+       rust_bfi compile --target rust|c|wasm [-o FILE] [--mem-size N] [--cell-width 8|16|32] \
+                         [--eof nochange|zero|255|error] [--no-optimize] [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] [--enable-hash-debug] \
+                         [--no-embedded-input] <program.bf>
+       rust_bfi check [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] [--no-embedded-input] [--bounds [--mem-size N]] [--dead-code] <program.bf>
+       rust_bfi analyze [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] [--no-embedded-input] [--bounds [--mem-size N]] [--dead-code] <program.bf>
+       rust_bfi fmt [--indent N] [--strip-comments] [--width N] [-o FILE] [--no-embedded-input] <program.bf>
+       rust_bfi minify [-o FILE] [--no-embedded-input] <program.bf>
+       rust_bfi ir [--no-optimize] [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] [--no-embedded-input] <program.bf>
+       rust_bfi explain [--no-optimize] [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] [--no-embedded-input] <program.bf>
+       rust_bfi test [--eof nochange|zero|255|error] [--max-steps N] [--jobs N] <dir>
+       rust_bfi diff [--mem-size N] [--tape strict|wrapping|growable|bidirectional] [--cell-width 8|16|32] \
+                         [--eof nochange|zero|255|error] [--max-steps N] [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] \
+                         [--input TEXT | --input-file FILE] [--no-embedded-input] <program.bf>
+       rust_bfi equiv [--inputs DIR] [--mem-size N] [--tape strict|wrapping|growable|bidirectional] [--cell-width 8|16|32] \
+                         [--eof nochange|zero|255|error] [--max-steps N] [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] [--jobs N] <a.bf> <b.bf>
+       rust_bfi gen [--len N] [--depth N] [--seed N] [--no-io] [-o FILE]
+       rust_bfi encode [-o FILE] <TEXT | --file FILE>
+       rust_bfi obfuscate [--seed N] [-o FILE] [--input TEXT | --input-file FILE] [--max-steps N] [--no-embedded-input] <program.bf>
+       rust_bfi bench [--runs N] [--max-steps N] [--input TEXT | --input-file FILE] [program.bf]...
+       rust_bfi replay [--debug] <run.bfr> <program.bf>
+       rust_bfi heatmap [--out FILE] [--mem-size N] [--eof nochange|zero|255|error] [--max-steps N] \
+                         [--dialect brainfuck|ook|blub|pbrain|brainfork] [--extensions] [--input TEXT | --input-file FILE] [--no-embedded-input] <program.bf>
+       rust_bfi smbf [--mem-size N] [--tape strict|wrapping|growable|bidirectional] [--cell-width 8|16|32] \
+                         [--eof nochange|zero|255|error] [--max-steps N] [--input TEXT | --input-file FILE] [--dump-mem-on-exit] <program.bf>
+       rust_bfi serve [--host ADDR] [--port N] [--jobs N] [--mem-size N] [--eof nochange|zero|255|error] \
+                         [--max-steps N] [--max-output N] [--timeout-ms N] [--max-body N]
+       rust_bfi completions bash|zsh|fish";
+
+
+// default number of memory cells, per the classic spec
+pub const DEFAULT_MEM_SIZE: usize = 30000;
+
+
+// memory sizes above this many cells always use a sparse tape
+pub const SPARSE_MEM_SIZE: usize = 1 << 24;
+
+
+// default number of steps the debugger can step back through
+pub const DEFAULT_HISTORY_DEPTH: usize = 10000;
+
+
+// how the result of running a program is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    // the program's output as it goes, errors and stats on stderr
+    Text,
+    // a JSON document with the output, any error and the stats once it stops
+    Json,
+}
+
+
+// when errors are shown in color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    // when stderr is a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+
+// what to do with the program, picked by the first argument
+#[derive(Debug, PartialEq, Eq)]
+pub enum Subcommand {
+    // boxed since it has far more options than the others
+    Run(Box<Options>),
+    Compile(CompileOptions),
+    Check(CheckOptions),
+    // takes the same options as check
+    Analyze(CheckOptions),
+    Fmt(FmtOptions),
+    Minify(MinifyOptions),
+    Ir(IrOptions),
+    // takes the same options as ir
+    Explain(IrOptions),
+    Test(TestOptions),
+    Gen(GenOptions),
+    Encode(EncodeOptions),
+    Obfuscate(ObfuscateOptions),
+    Diff(DiffOptions),
+    Equiv(EquivOptions),
+    Bench(BenchOptions),
+    Replay(ReplayOptions),
+    Heatmap(HeatmapOptions),
+    // run a program stored on its own tape
+    Smbf(SmbfOptions),
+    Serve(ServeOptions),
+    // print a completion script for a shell
+    Completions(Shell),
+}
+
+
+// everything that can be set from the command line when running a program
+#[derive(Debug, PartialEq, Eq)]
+pub struct Options {
+    pub path: String,
+    // further programs to run along with the first as a batch
+    pub more_paths: Vec<String>,
+    // number of batch programs to run at a time on their own threads
+    pub jobs: usize,
+    pub mem_size: usize,
+    pub tape_mode: dtypes::TapeMode,
+    // only store the pages of memory the program uses
+    pub sparse: bool,
+    pub cell_width: dtypes::CellWidth,
+    pub eof_behavior: dtypes::EofBehavior,
+    // --max-steps sets the step limit, the others only come from a config file
+    pub limits: dtypes::Limits,
+    // save a snapshot here if the program hits the step limit
+    pub checkpoint: Option<String>,
+    // carry on from a snapshot saved with --checkpoint
+    pub resume: Option<String>,
+    pub optimize: bool,
+    // run the program up to its first input or output (at most this many
+    // steps) when it is loaded
+    pub precompute: Option<u64>,
+    pub dialect: dtypes::Dialect,
+    // treat `#` as a command that shows the start of memory
+    pub hash_debug: bool,
+    // spell the commands this way rather than any dialect's, or the way given
+    // in this file
+    pub map: Option<parsing::Mapping>,
+    pub map_file: Option<String>,
+    // read input from this text rather than stdin
+    pub input: Option<String>,
+    // read input from this file rather than stdin
+    pub input_file: Option<String>,
+    // read endless pseudo-random input generated from this seed rather than stdin
+    pub input_random: Option<u64>,
+    // read input embedded after a `!` in the program file
+    pub embedded_input: bool,
+    // read keys from the terminal as they are pressed rather than a line at a
+    // time, without echoing them
+    pub raw_input: bool,
+    // preload memory with these bytes, or the bytes of this file
+    pub init_mem: Option<Vec<u8>>,
+    pub init_mem_file: Option<String>,
+    pub engine: dtypes::Engine,
+    pub dump_mem: bool,
+    // exit with the value of this cell (from where the program started) if it
+    // finishes without an error
+    pub exit_cell: Option<usize>,
+    // read stdin and write stdout as raw bytes, buffered in large blocks, for
+    // use in a pipeline
+    pub filter: bool,
+    // write out and flush each byte of output as soon as it is produced
+    pub flush_every_byte: bool,
+    // collect up to this many bytes of output before writing them out
+    pub output_buffer: Option<usize>,
+    pub trace: bool,
+    // write the trace here rather than stderr
+    pub trace_file: Option<String>,
+    // save every instruction executed and the input read here, for replay
+    pub record: Option<String>,
+    pub profile: bool,
+    // write a Chrome trace of every loop execution here
+    pub profile_trace: Option<String>,
+    // show which commands ran in a copy of the source
+    pub coverage: bool,
+    // write an lcov tracefile of which commands ran here
+    pub lcov: Option<String>,
+    pub stats: bool,
+    pub output_format: OutputFormat,
+    // whether an error is shown in color
+    pub color: ColorChoice,
+    // sleep this long after each byte of output
+    pub delay_ms: Option<u64>,
+    pub debug: bool,
+    pub breakpoints: Vec<usize>,
+    // memory cells for the debugger to pause on when they change
+    pub watchpoints: Vec<usize>,
+    // number of steps the debugger can step back through
+    pub history: usize,
+    // watch the program run in a full screen view
+    pub tui: bool,
+}
+
+
+// everything that can be set from the command line when compiling a program
+#[derive(Debug, PartialEq, Eq)]
+pub struct CompileOptions {
+    pub path: String,
+    // write to stdout if not given
+    pub output: Option<String>,
+    pub target: compiler::Target,
+    pub mem_size: usize,
+    pub cell_width: dtypes::CellWidth,
+    pub eof_behavior: dtypes::EofBehavior,
+    pub optimize: bool,
+    pub dialect: dtypes::Dialect,
+    // compile `#` into code that shows the start of memory
+    pub hash_debug: bool,
+    // strip input embedded after a `!` in the program file
+    pub embedded_input: bool,
+}
+
+
+// everything that can be set from the command line when checking a program
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheckOptions {
+    pub path: String,
+    pub dialect: dtypes::Dialect,
+    // ignore input embedded after a `!` in the program file
+    pub embedded_input: bool,
+    // also prove that the data pointer stays within mem_size cells
+    pub bounds: bool,
+    pub mem_size: usize,
+    // also point out code the optimizer drops because it can never have an effect
+    pub dead_code: bool,
+}
+
+
+// everything that can be set from the command line when formatting a program
+#[derive(Debug, PartialEq, Eq)]
+pub struct FmtOptions {
+    pub path: String,
+    // write to stdout if not given
+    pub output: Option<String>,
+    pub config: formatter::Config,
+    // pass input embedded after a `!` in the program file through untouched
+    pub embedded_input: bool,
+}
+
+
+// everything that can be set from the command line when minifying a program
+#[derive(Debug, PartialEq, Eq)]
+pub struct MinifyOptions {
+    pub path: String,
+    // write to stdout if not given
+    pub output: Option<String>,
+    // pass input embedded after a `!` in the program file through untouched
+    pub embedded_input: bool,
+}
+
+
+// everything that can be set from the command line when printing the internal
+// representation of a program
+#[derive(Debug, PartialEq, Eq)]
+pub struct IrOptions {
+    pub path: String,
+    pub optimize: bool,
+    pub dialect: dtypes::Dialect,
+    // ignore input embedded after a `!` in the program file
+    pub embedded_input: bool,
+}
+
+
+// everything that can be set from the command line when running golden file tests
+#[derive(Debug, PartialEq, Eq)]
+pub struct TestOptions {
+    pub dir: String,
+    pub eof_behavior: dtypes::EofBehavior,
+    pub max_steps: Option<u64>,
+    // number of programs to run at a time on their own threads
+    pub jobs: usize,
+}
+
+
+// everything that can be set from the command line when comparing the engines
+#[derive(Debug, PartialEq, Eq)]
+pub struct DiffOptions {
+    pub path: String,
+    pub mem_size: usize,
+    pub tape_mode: dtypes::TapeMode,
+    pub cell_width: dtypes::CellWidth,
+    pub eof_behavior: dtypes::EofBehavior,
+    pub max_steps: u64,
+    pub dialect: dtypes::Dialect,
+    // every engine is given the same input, from this text or file, otherwise
+    // whatever is embedded in the program (stdin can only be read once)
+    pub input: Option<String>,
+    pub input_file: Option<String>,
+    pub embedded_input: bool,
+}
+
+
+// everything that can be set from the command line when checking two programs
+// behave the same
+#[derive(Debug, PartialEq, Eq)]
+pub struct EquivOptions {
+    pub a: String,
+    pub b: String,
+    // every file in here is an input both programs are run on, otherwise they
+    // are run once with no input
+    pub inputs: Option<String>,
+    pub mem_size: usize,
+    pub tape_mode: dtypes::TapeMode,
+    pub cell_width: dtypes::CellWidth,
+    pub eof_behavior: dtypes::EofBehavior,
+    pub max_steps: u64,
+    pub dialect: dtypes::Dialect,
+    // number of programs to run at a time on their own threads
+    pub jobs: usize,
+}
+
+
+// everything that can be set from the command line when generating a random program
+#[derive(Debug, PartialEq, Eq)]
+pub struct GenOptions {
+    pub config: generator::Config,
+    pub seed: u64,
+    // write to stdout if not given
+    pub output: Option<String>,
+}
+
+
+// everything that can be set from the command line when generating a program
+// that prints some text
+#[derive(Debug, PartialEq, Eq)]
+pub struct EncodeOptions {
+    // the text to print, given on the command line
+    pub text: Option<String>,
+    // or read from this file
+    pub file: Option<String>,
+    // write to stdout if not given
+    pub output: Option<String>,
+}
+
+
+// everything that can be set from the command line when obfuscating a program
+#[derive(Debug, PartialEq, Eq)]
+pub struct ObfuscateOptions {
+    pub path: String,
+    pub seed: u64,
+    // write to stdout if not given
+    pub output: Option<String>,
+    // both versions are run with this input to check they do the same, otherwise
+    // with whatever is embedded in the program
+    pub input: Option<String>,
+    pub input_file: Option<String>,
+    pub max_steps: u64,
+    // pass input embedded after a `!` in the program file through untouched
+    pub embedded_input: bool,
+}
+
+
+// everything that can be set from the command line when benchmarking the engines
+#[derive(Debug, PartialEq, Eq)]
+pub struct BenchOptions {
+    // programs to time along with the bundled ones
+    pub paths: Vec<String>,
+    // number of times each program is run on each engine, keeping the fastest
+    pub runs: usize,
+    pub max_steps: Option<u64>,
+    // input for the programs given, the bundled ones have their own
+    pub input: Option<String>,
+    pub input_file: Option<String>,
+}
+
+
+// everything that can be set from the command line when replaying a recording
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReplayOptions {
+    // made with --record
+    pub recording: String,
+    pub path: String,
+    // step through the replay in the debugger rather than checking it
+    pub debug: bool,
+}
+
+
+// everything that can be set from the command line when drawing a heatmap
+#[derive(Debug, PartialEq, Eq)]
+pub struct HeatmapOptions {
+    pub path: String,
+    // write to stdout if not given
+    pub output: Option<String>,
+    pub mem_size: usize,
+    pub eof_behavior: dtypes::EofBehavior,
+    pub max_steps: Option<u64>,
+    pub dialect: dtypes::Dialect,
+    // read input from this text or file rather than stdin
+    pub input: Option<String>,
+    pub input_file: Option<String>,
+    pub embedded_input: bool,
+}
+
+
+// everything that can be set from the command line when running a
+// self-modifying program
+#[derive(Debug, PartialEq, Eq)]
+pub struct SmbfOptions {
+    pub path: String,
+    pub mem_size: usize,
+    pub tape_mode: dtypes::TapeMode,
+    pub cell_width: dtypes::CellWidth,
+    pub eof_behavior: dtypes::EofBehavior,
+    pub max_steps: Option<u64>,
+    // read input from this text or file rather than stdin
+    pub input: Option<String>,
+    pub input_file: Option<String>,
+    // show the tape, program and all, once it stops
+    pub dump_mem: bool,
+}
+
+
+// everything that can be set from the command line when serving the playground
+#[derive(Debug, PartialEq, Eq)]
+pub struct ServeOptions {
+    pub host: String,
+    pub port: u16,
+    // number of programs run at a time
+    pub jobs: usize,
+    pub mem_size: usize,
+    pub eof_behavior: dtypes::EofBehavior,
+    // caps on each program run, anything sent can be run so there's always one
+    pub max_steps: u64,
+    pub max_output: u64,
+    pub timeout_ms: u64,
+    // largest request body accepted, in bytes
+    pub max_body: usize,
+}
+
+
+// parse the value of the --eof flag
+fn parse_eof_behavior (value: &str) -> Result<dtypes::EofBehavior, String> {
+    match value {
+        "nochange" => Ok(dtypes::EofBehavior::NoChange),
+        "zero" | "0" => Ok(dtypes::EofBehavior::Zero),
+        "255" | "-1" => Ok(dtypes::EofBehavior::MinusOne),
+        "error" => Ok(dtypes::EofBehavior::Error),
+        _ => Err(format!("invalid --eof value '{}'", value)),
+    }
+}
+
+
+// RUST_BFI_* environment variables for the flags that only change how the
+// command line shows things, rather than how a program runs
+const DISPLAY_ENV_VARS: [&str; 2] = ["RUST_BFI_OUTPUT_FORMAT", "RUST_BFI_COLOR"];
+
+
+// the settings flags start from: the defaults, overridden by those in the
+// config file given with --config (if any), overridden in turn by RUST_BFI_*
+// environment variables, any other RUST_BFI_* variable is an error
+fn settings (config_file: Option<&str>, env: &[(String, String)]) -> Result<config::BFConfig, String> {
+    let config = match config_file {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("unable to read config file '{}': {}", path, e))?;
+            config::BFConfig::from_toml(&text).map_err(|e| format!("invalid config file '{}': {}", path, e))?
+        },
+        None => config::BFConfig::default(),
+    };
+    config.with_env(env.iter().filter(|(name, _)| !DISPLAY_ENV_VARS.contains(&name.as_str())).cloned())
+          .map_err(|e| format!("invalid environment variable {}", e))
+}
+
+
+// the value of one of DISPLAY_ENV_VARS parsed as its flag would be, None if
+// it isn't set (or is empty)
+fn display_setting<T> (env: &[(String, String)], name: &str, parse: fn(&str) -> Result<T, String>) -> Result<Option<T>, String> {
+    match env.iter().rev().find(|(var, value)| var == name && !value.is_empty()) {
+        Some((_, value)) => parse(value).map(Option::Some).map_err(|e| format!("invalid environment variable {}: {}", name, e)),
+        None => Ok(Option::None),
+    }
+}
+
+
+// shells that completion scripts can be written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+
+// parse the value of the --jobs flag
+fn parse_jobs (value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid --jobs value '{}'", value)),
+    }
+}
+
+
+// parse the value of the --init-mem flag, comma separated bytes
+fn parse_init_mem (value: &str) -> Result<Vec<u8>, String> {
+    value.split(',')
+        .map(|byte| byte.trim().parse::<u8>().map_err(|_| format!("invalid --init-mem value '{}'", value)))
+        .collect()
+}
+
+
+// parse the value of the --tape flag
+fn parse_tape_mode (value: &str) -> Result<dtypes::TapeMode, String> {
+    match value {
+        "strict" => Ok(dtypes::TapeMode::Strict),
+        "wrapping" => Ok(dtypes::TapeMode::Wrapping),
+        "growable" => Ok(dtypes::TapeMode::Growable),
+        "bidirectional" => Ok(dtypes::TapeMode::Bidirectional),
+        _ => Err(format!("invalid --tape value '{}'", value)),
+    }
+}
+
+
+// parse the value of the --cell-width flag
+fn parse_cell_width (value: &str) -> Result<dtypes::CellWidth, String> {
+    match value {
+        "8" => Ok(dtypes::CellWidth::U8),
+        "16" => Ok(dtypes::CellWidth::U16),
+        "32" => Ok(dtypes::CellWidth::U32),
+        _ => Err(format!("invalid --cell-width value '{}'", value)),
+    }
+}
+
+
+// parse the value of the --output-format flag
+fn parse_output_format (value: &str) -> Result<OutputFormat, String> {
+    match value {
+        "text" => Ok(OutputFormat::Text),
+        "json" => Ok(OutputFormat::Json),
+        _ => Err(format!("invalid --output-format value '{}'", value)),
+    }
+}
+
+
+// parse the value of the --color flag
+fn parse_color (value: &str) -> Result<ColorChoice, String> {
+    match value {
+        "auto" => Ok(ColorChoice::Auto),
+        "always" => Ok(ColorChoice::Always),
+        "never" => Ok(ColorChoice::Never),
+        _ => Err(format!("invalid --color value '{}'", value)),
+    }
+}
+
+
+// parse the value of the --mem-size flag, must be a positive integer
+fn parse_mem_size (value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err(String::from("--mem-size must be greater than 0")),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("invalid --mem-size value '{}'", value)),
+    }
+}
+
+
+// parse the value of the --dialect flag
+fn parse_dialect (value: &str) -> Result<dtypes::Dialect, String> {
+    match value {
+        "brainfuck" | "bf" => Ok(dtypes::Dialect::Brainfuck),
+        "ook" => Ok(dtypes::Dialect::Ook),
+        "blub" => Ok(dtypes::Dialect::Blub),
+        "extended" => Ok(dtypes::Dialect::Extended),
+        "pbrain" => Ok(dtypes::Dialect::Pbrain),
+        "brainfork" => Ok(dtypes::Dialect::Brainfork),
+        _ => Err(format!("invalid --dialect value '{}'", value)),
+    }
+}
+
+
+// --extensions turns on the Extended Type I commands, which only make sense
+// on top of plain brainfuck
+fn with_extensions (dialect: dtypes::Dialect, extensions: bool) -> Result<dtypes::Dialect, String> {
+    match (dialect, extensions) {
+        (_, false) | (dtypes::Dialect::Extended, true) => Ok(dialect),
+        (dtypes::Dialect::Brainfuck, true) => Ok(dtypes::Dialect::Extended),
+        _ => Err(String::from("--extensions can only be used with brainfuck programs")),
+    }
+}
+
+
+// parse the value of the --engine flag, the jit is only available when built with it
+fn parse_engine (value: &str) -> Result<dtypes::Engine, String> {
+    match value {
+        "interpreter" => Ok(dtypes::Engine::Interpreter),
+        #[cfg(feature = "jit")]
+        "jit" => Ok(dtypes::Engine::Jit),
+        #[cfg(not(feature = "jit"))]
+        "jit" => Err(String::from("the jit engine is not available, rebuild with --features jit")),
+        _ => Err(format!("invalid --engine value '{}'", value)),
+    }
+}
+
+
+// parse the value of the --target flag
+fn parse_target (value: &str) -> Result<compiler::Target, String> {
+    match value {
+        "rust" => Ok(compiler::Target::Rust),
+        "c" => Ok(compiler::Target::C),
+        "wasm" => Ok(compiler::Target::Wasm),
+        _ => Err(format!("invalid --target value '{}'", value)),
+    }
+}
+
+
+// parse command line arguments (not including the executable name) along with
+// the environment variables, which give settings that flags override, running
+// the program is the default when no subcommand is given
+pub fn parse_args<I: Iterator<Item = String>, E: Iterator<Item = (String, String)>> (args: I, env: E) -> Result<Subcommand, String> {
+    let env: Vec<(String, String)> = env.collect();
+    // a bad RUST_BFI_* variable is reported whichever subcommand is run, even
+    // one that has no use for it
+    settings(Option::None, &env)?;
+    display_setting(&env, "RUST_BFI_OUTPUT_FORMAT", parse_output_format)?;
+    display_setting(&env, "RUST_BFI_COLOR", parse_color)?;
+    let mut args = args.peekable();
+    match args.peek().map(String::as_str) {
+        Some("compile") => {
+            args.next();
+            Ok(Subcommand::Compile(parse_compile_args(args, &env)?))
+        },
+        Some("check") => {
+            args.next();
+            Ok(Subcommand::Check(parse_check_args(args, &env)?))
+        },
+        Some("analyze") => {
+            args.next();
+            Ok(Subcommand::Analyze(parse_check_args(args, &env)?))
+        },
+        Some("fmt") => {
+            args.next();
+            Ok(Subcommand::Fmt(parse_fmt_args(args)?))
+        },
+        Some("minify") => {
+            args.next();
+            Ok(Subcommand::Minify(parse_minify_args(args)?))
+        },
+        Some("ir") => {
+            args.next();
+            Ok(Subcommand::Ir(parse_ir_args(args, &env)?))
+        },
+        Some("explain") => {
+            args.next();
+            Ok(Subcommand::Explain(parse_ir_args(args, &env)?))
+        },
+        Some("test") => {
+            args.next();
+            Ok(Subcommand::Test(parse_test_args(args, &env)?))
+        },
+        Some("diff") => {
+            args.next();
+            Ok(Subcommand::Diff(parse_diff_args(args, &env)?))
+        },
+        Some("equiv") => {
+            args.next();
+            Ok(Subcommand::Equiv(parse_equiv_args(args, &env)?))
+        },
+        Some("gen") => {
+            args.next();
+            Ok(Subcommand::Gen(parse_gen_args(args)?))
+        },
+        Some("encode") => {
+            args.next();
+            Ok(Subcommand::Encode(parse_encode_args(args)?))
+        },
+        Some("obfuscate") => {
+            args.next();
+            Ok(Subcommand::Obfuscate(parse_obfuscate_args(args, &env)?))
+        },
+        Some("bench") => {
+            args.next();
+            Ok(Subcommand::Bench(parse_bench_args(args, &env)?))
+        },
+        Some("replay") => {
+            args.next();
+            Ok(Subcommand::Replay(parse_replay_args(args)?))
+        },
+        Some("heatmap") => {
+            args.next();
+            Ok(Subcommand::Heatmap(parse_heatmap_args(args, &env)?))
+        },
+        Some("smbf") => {
+            args.next();
+            Ok(Subcommand::Smbf(parse_smbf_args(args, &env)?))
+        },
+        Some("serve") => {
+            args.next();
+            Ok(Subcommand::Serve(parse_serve_args(args, &env)?))
+        },
+        Some("completions") => {
+            args.next();
+            Ok(Subcommand::Completions(parse_completions_args(args)?))
+        },
+        Some("run") => {
+            args.next();
+            Ok(Subcommand::Run(Box::new(parse_run_args(args, &env)?)))
+        },
+        // the same as run --filter
+        Some("filter") => {
+            args.next();
+            let args = std::iter::once(String::from("--filter")).chain(args);
+            Ok(Subcommand::Run(Box::new(parse_run_args(args, &env)?)))
+        },
+        _ => Ok(Subcommand::Run(Box::new(parse_run_args(args, &env)?))),
+    }
+}
+
+
+// parse the arguments for running a program, those after a -- are all program files
+fn parse_run_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<Options, String> {
+    let mut path: Option<String> = Option::None;
+    let mut more_paths: Vec<String> = Vec::new();
+    let mut jobs: usize = 1;
+    // the settings a config file and the environment can give, None (or false)
+    // where no flag overrides them
+    let mut config_file: Option<String> = Option::None;
+    let mut configs = 0;
+    let mut mem_size: Option<usize> = Option::None;
+    let mut tape_mode: Option<dtypes::TapeMode> = Option::None;
+    let mut sparse = false;
+    let mut cell_width: Option<dtypes::CellWidth> = Option::None;
+    let mut eof_behavior: Option<dtypes::EofBehavior> = Option::None;
+    let mut max_steps: Option<u64> = Option::None;
+    let mut no_optimize = false;
+    let mut dialect: Option<dtypes::Dialect> = Option::None;
+    let mut engine: Option<dtypes::Engine> = Option::None;
+    let mut checkpoint: Option<String> = Option::None;
+    let mut resume: Option<String> = Option::None;
+    let mut precompute: Option<u64> = Option::None;
+    let mut extensions = false;
+    let mut hash_debug = false;
+    let mut map: Option<parsing::Mapping> = Option::None;
+    let mut map_file: Option<String> = Option::None;
+    let mut input: Option<String> = Option::None;
+    let mut input_file: Option<String> = Option::None;
+    let mut input_random = false;
+    let mut seed: Option<u64> = Option::None;
+    let mut embedded_input = true;
+    let mut raw_input = false;
+    let mut init_mem: Option<Vec<u8>> = Option::None;
+    let mut init_mem_file: Option<String> = Option::None;
+    let mut dump_mem = false;
+    let mut exit_cell: Option<usize> = Option::None;
+    let mut filter = false;
+    let mut flush_every_byte = false;
+    let mut output_buffer: Option<usize> = Option::None;
+    let mut trace = false;
+    let mut trace_file: Option<String> = Option::None;
+    let mut record: Option<String> = Option::None;
+    let mut profile = false;
+    let mut profile_trace: Option<String> = Option::None;
+    let mut coverage = false;
+    let mut lcov: Option<String> = Option::None;
+    let mut stats = false;
+    let mut output_format = display_setting(env, "RUST_BFI_OUTPUT_FORMAT", parse_output_format)?.unwrap_or(OutputFormat::Text);
+    let mut color = display_setting(env, "RUST_BFI_COLOR", parse_color)?.unwrap_or(ColorChoice::Auto);
+    let mut delay_ms: Option<u64> = Option::None;
+    let mut debug = false;
+    let mut breakpoints: Vec<usize> = Vec::new();
+    let mut watchpoints: Vec<usize> = Vec::new();
+    let mut history = DEFAULT_HISTORY_DEPTH;
+    let mut tui = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                config_file = Option::Some(args.next().ok_or("--config requires a value")?);
+                configs += 1;
+            },
+            "--mem-size" => {
+                let value = args.next().ok_or("--mem-size requires a value")?;
+                mem_size = Option::Some(parse_mem_size(&value)?);
+            },
+            "--tape" => {
+                let value = args.next().ok_or("--tape requires a value")?;
+                tape_mode = Option::Some(parse_tape_mode(&value)?);
+            },
+            "--sparse" => sparse = true,
+            "--cell-width" => {
+                let value = args.next().ok_or("--cell-width requires a value")?;
+                cell_width = Option::Some(parse_cell_width(&value)?);
+            },
+            "--eof" => {
+                let value = args.next().ok_or("--eof requires a value")?;
+                eof_behavior = Option::Some(parse_eof_behavior(&value)?);
+            },
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = Option::Some(value.parse::<u64>().map_err(|_| format!("invalid --max-steps value '{}'", value))?);
+            },
+            "--checkpoint" => checkpoint = Option::Some(args.next().ok_or("--checkpoint requires a value")?),
+            "--resume" => resume = Option::Some(args.next().ok_or("--resume requires a value")?),
+            "--no-optimize" => no_optimize = true,
+            "--precompute" => {
+                let value = args.next().ok_or("--precompute requires a value")?;
+                precompute = Option::Some(value.parse::<u64>().map_err(|_| format!("invalid --precompute value '{}'", value))?);
+            },
+            "--dialect" => {
+                let value = args.next().ok_or("--dialect requires a value")?;
+                dialect = Option::Some(parse_dialect(&value)?);
+            },
+            "--extensions" => extensions = true,
+            "--enable-hash-debug" => hash_debug = true,
+            "--map" => {
+                let value = args.next().ok_or("--map requires a value")?;
+                map = Option::Some(parsing::Mapping::parse(&value).map_err(|e| format!("invalid --map value: {}", e))?);
+            },
+            "--map-file" => map_file = Option::Some(args.next().ok_or("--map-file requires a value")?),
+            "--input" => input = Option::Some(args.next().ok_or("--input requires a value")?),
+            "--input-file" => input_file = Option::Some(args.next().ok_or("--input-file requires a value")?),
+            "--input-random" => input_random = true,
+            "--init-mem" => {
+                let value = args.next().ok_or("--init-mem requires a value")?;
+                init_mem = Option::Some(parse_init_mem(&value)?);
+            },
+            "--init-mem-file" => init_mem_file = Option::Some(args.next().ok_or("--init-mem-file requires a value")?),
+            "--seed" => {
+                let value = args.next().ok_or("--seed requires a value")?;
+                seed = Option::Some(value.parse::<u64>().map_err(|_| format!("invalid --seed value '{}'", value))?);
+            },
+            "--no-embedded-input" => embedded_input = false,
+            "--raw-input" => raw_input = true,
+            "--engine" => {
+                let value = args.next().ok_or("--engine requires a value")?;
+                engine = Option::Some(parse_engine(&value)?);
+            },
+            "--dump-mem-on-exit" => dump_mem = true,
+            "--exit-cell" => {
+                let value = args.next().ok_or("--exit-cell requires a value")?;
+                exit_cell = Option::Some(value.parse::<usize>().map_err(|_| format!("invalid --exit-cell value '{}'", value))?);
+            },
+            "--filter" => filter = true,
+            "--flush-every-byte" => flush_every_byte = true,
+            "--output-buffer" => {
+                let value = args.next().ok_or("--output-buffer requires a value")?;
+                output_buffer = Option::Some(value.parse::<usize>().ok().filter(|&size| size > 0)
+                                             .ok_or_else(|| format!("invalid --output-buffer value '{}'", value))?);
+            },
+            "--trace" => trace = true,
+            "--trace-file" => {
+                trace = true;
+                trace_file = Option::Some(args.next().ok_or("--trace-file requires a value")?);
+            },
+            "--record" => record = Option::Some(args.next().ok_or("--record requires a value")?),
+            "--profile" => profile = true,
+            "--profile-trace" => profile_trace = Option::Some(args.next().ok_or("--profile-trace requires a value")?),
+            "--coverage" => coverage = true,
+            "--lcov" => lcov = Option::Some(args.next().ok_or("--lcov requires a value")?),
+            "--stats" => stats = true,
+            "--output-format" => {
+                let value = args.next().ok_or("--output-format requires a value")?;
+                output_format = parse_output_format(&value)?;
+            },
+            "--color" => {
+                let value = args.next().ok_or("--color requires a value")?;
+                color = parse_color(&value)?;
+            },
+            "--delay-ms" => {
+                let value = args.next().ok_or("--delay-ms requires a value")?;
+                delay_ms = Option::Some(value.parse::<u64>().map_err(|_| format!("invalid --delay-ms value '{}'", value))?);
+            },
+            "--debug" => debug = true,
+            "--break" => {
+                let value = args.next().ok_or("--break requires a value")?;
+                let index = value.parse::<usize>().map_err(|_| format!("invalid --break value '{}'", value))?;
+                breakpoints.push(index);
+            },
+            "--watch" => {
+                let value = args.next().ok_or("--watch requires a value")?;
+                let index = value.parse::<usize>().map_err(|_| format!("invalid --watch value '{}'", value))?;
+                watchpoints.push(index);
+            },
+            "--history" => {
+                let value = args.next().ok_or("--history requires a value")?;
+                history = value.parse::<usize>().map_err(|_| format!("invalid --history value '{}'", value))?;
+            },
+            "--tui" => tui = true,
+            "--jobs" => {
+                let value = args.next().ok_or("--jobs requires a value")?;
+                jobs = parse_jobs(&value)?;
+            },
+            // even ones that look like flags
+            "--" => {
+                for arg in args.by_ref() {
+                    match path {
+                        None => path = Option::Some(arg),
+                        Some(_) => more_paths.push(arg),
+                    };
+                }
+            },
+            _ if arg.starts_with("--") => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => more_paths.push(arg),
+        };
+    }
+    if configs > 1 {
+        return Err(String::from("only one --config may be given"));
+    }
+    // a config file and the environment give the settings flags didn't,
+    // wherever the flags were on the command line
+    let config = settings(config_file.as_deref(), env)?;
+    let mem_size = mem_size.unwrap_or(config.mem_size);
+    let tape_mode = tape_mode.unwrap_or(config.tape_mode);
+    let sparse = sparse || config.sparse;
+    let cell_width = cell_width.unwrap_or(config.cell_width);
+    let eof_behavior = eof_behavior.unwrap_or(config.eof_behavior);
+    let limits = dtypes::Limits { max_steps: max_steps.or(config.limits.max_steps), ..config.limits };
+    let optimize = config.optimize && !no_optimize;
+    let dialect = dialect.unwrap_or(config.dialect);
+    let engine = engine.unwrap_or(config.engine);
+    // a batch of programs is run without any interaction and all of their
+    // output is collected
+    if !more_paths.is_empty() && (debug || tui || trace || profile || profile_trace.is_some() || dump_mem || input_random
+                                  || checkpoint.is_some() || resume.is_some() || delay_ms.is_some() || exit_cell.is_some()
+                                  || record.is_some() || coverage || lcov.is_some() || flush_every_byte || output_buffer.is_some()
+                                  || raw_input) {
+        return Err(String::from("--debug, --tui, --trace, --profile, --profile-trace, --dump-mem-on-exit, --input-random, \
+                                 --checkpoint, --resume, --delay-ms, --exit-cell, --record, --coverage, --lcov, \
+                                 --flush-every-byte, --output-buffer and --raw-input can only be used with one program file"));
+    }
+    // stepping back in the debugger would leave commands counted that didn't run
+    if debug && (coverage || lcov.is_some()) {
+        return Err(String::from("--coverage and --lcov cannot be used with --debug"));
+    }
+    // a recording starts from a blank tape, and stepping back in the debugger
+    // would undo instructions already recorded
+    if record.is_some() && (debug || trace || resume.is_some() || init_mem.is_some() || init_mem_file.is_some()) {
+        return Err(String::from("--record cannot be used with --debug, --trace, --resume, --init-mem or --init-mem-file"));
+    }
+    // these all need to see every step the program takes
+    if precompute.is_some() && (debug || tui || trace || record.is_some() || profile || profile_trace.is_some() || coverage || lcov.is_some()) {
+        return Err(String::from("--precompute cannot be used with --debug, --tui, --trace, --record, --profile, --profile-trace, \
+                                 --coverage or --lcov"));
+    }
+    if stats && debug {
+        return Err(String::from("--stats cannot be used with --debug"));
+    }
+    if tui && !cfg!(feature = "tui") {
+        return Err(String::from("--tui is not available, rebuild with --features tui"));
+    }
+    if tui && (debug || stats) {
+        return Err(String::from("--tui cannot be used with --debug or --stats"));
+    }
+    if output_format == OutputFormat::Json && (debug || tui) {
+        return Err(String::from("--output-format json cannot be used with --debug or --tui"));
+    }
+    // the JSON document has its own exit code
+    if exit_cell.is_some() && output_format == OutputFormat::Json {
+        return Err(String::from("--exit-cell cannot be used with --output-format json"));
+    }
+    // only growable tapes can reach cells past the end
+    if let Some(index) = exit_cell {
+        if index >= mem_size && matches!(tape_mode, dtypes::TapeMode::Strict | dtypes::TapeMode::Wrapping) {
+            return Err(format!("--exit-cell {} is past the end of the {} cell tape", index, mem_size));
+        }
+    }
+    // neither of these print the output as it goes
+    if delay_ms.is_some() && (tui || output_format == OutputFormat::Json) {
+        return Err(String::from("--delay-ms cannot be used with --tui or --output-format json"));
+    }
+    // nor do they (or a slow writer) hold on to it
+    if (flush_every_byte || output_buffer.is_some()) && (tui || output_format == OutputFormat::Json || delay_ms.is_some()) {
+        return Err(String::from("--flush-every-byte and --output-buffer cannot be used with --tui, \
+                                 --output-format json or --delay-ms"));
+    }
+    if flush_every_byte && (output_buffer.is_some() || filter) {
+        return Err(String::from("--flush-every-byte cannot be used with --output-buffer or --filter"));
+    }
+    // stdin and stdout belong to the pipeline
+    if filter && (input.is_some() || input_file.is_some() || input_random || debug || tui
+                  || output_format == OutputFormat::Json || delay_ms.is_some() || !more_paths.is_empty()) {
+        return Err(String::from("--filter cannot be used with --input, --input-file, --input-random, --debug, --tui, \
+                                 --output-format json, --delay-ms or more than one program file"));
+    }
+    // the keys typed are the program's input, and the debugger and full screen
+    // view read keys themselves
+    if raw_input && (input.is_some() || input_file.is_some() || input_random || debug || tui || filter) {
+        return Err(String::from("--raw-input cannot be used with --input, --input-file, --input-random, --debug, --tui or --filter"));
+    }
+    if [input.is_some(), input_file.is_some(), input_random].iter().filter(|&&given| given).count() > 1 {
+        return Err(String::from("only one of --input, --input-file and --input-random may be given"));
+    }
+    if init_mem.is_some() && init_mem_file.is_some() {
+        return Err(String::from("only one of --init-mem and --init-mem-file may be given"));
+    }
+    if map.is_some() && map_file.is_some() {
+        return Err(String::from("only one of --map and --map-file may be given"));
+    }
+    // a mapping takes the place of the dialect, and recordings and the full
+    // screen view only know about dialects
+    if (map.is_some() || map_file.is_some()) && (dialect != dtypes::Dialect::Brainfuck || extensions || hash_debug || record.is_some() || tui) {
+        return Err(String::from("--map and --map-file cannot be used with --dialect, --extensions, --enable-hash-debug, --record or --tui"));
+    }
+    if seed.is_some() && !input_random {
+        return Err(String::from("--seed can only be used with --input-random"));
+    }
+    // allocating all of a huge tape up front would take far too much memory
+    let sparse = sparse || mem_size > SPARSE_MEM_SIZE;
+    if sparse && (checkpoint.is_some() || resume.is_some()) {
+        return Err(String::from("--checkpoint and --resume cannot be used with a sparse tape"));
+    }
+    Ok(Options {
+        path: path.ok_or("no program file given")?,
+        more_paths,
+        jobs,
+        mem_size,
+        tape_mode,
+        sparse,
+        cell_width,
+        eof_behavior,
+        limits,
+        checkpoint,
+        resume,
+        optimize,
+        precompute,
+        dialect: with_extensions(dialect, extensions)?,
+        hash_debug,
+        map,
+        map_file,
+        input,
+        input_file,
+        input_random: if input_random { Option::Some(seed.unwrap_or(0)) } else { Option::None },
+        embedded_input,
+        raw_input,
+        init_mem,
+        init_mem_file,
+        engine,
+        dump_mem,
+        exit_cell,
+        filter,
+        flush_every_byte,
+        output_buffer,
+        trace,
+        trace_file,
+        record,
+        profile,
+        profile_trace,
+        coverage,
+        lcov,
+        stats,
+        output_format,
+        color,
+        delay_ms,
+        debug,
+        breakpoints,
+        watchpoints,
+        history,
+        tui,
+    })
+}
+
+
+// parse the arguments for compiling a program
+fn parse_compile_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<CompileOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut path: Option<String> = Option::None;
+    let mut output: Option<String> = Option::None;
+    let mut target: Option<compiler::Target> = Option::None;
+    let mut mem_size = defaults.mem_size;
+    let mut cell_width = defaults.cell_width;
+    let mut eof_behavior = defaults.eof_behavior;
+    let mut optimize = defaults.optimize;
+    let mut dialect = defaults.dialect;
+    let mut extensions = false;
+    let mut hash_debug = false;
+    let mut embedded_input = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => {
+                let value = args.next().ok_or("--target requires a value")?;
+                target = Option::Some(parse_target(&value)?);
+            },
+            "-o" | "--output" => {
+                output = Option::Some(args.next().ok_or("--output requires a value")?);
+            },
+            "--mem-size" => {
+                let value = args.next().ok_or("--mem-size requires a value")?;
+                mem_size = parse_mem_size(&value)?;
+            },
+            "--cell-width" => {
+                let value = args.next().ok_or("--cell-width requires a value")?;
+                cell_width = parse_cell_width(&value)?;
+            },
+            "--eof" => {
+                let value = args.next().ok_or("--eof requires a value")?;
+                eof_behavior = parse_eof_behavior(&value)?;
+            },
+            "--no-optimize" => optimize = false,
+            "--dialect" => {
+                let value = args.next().ok_or("--dialect requires a value")?;
+                dialect = parse_dialect(&value)?;
+            },
+            "--extensions" => extensions = true,
+            "--enable-hash-debug" => hash_debug = true,
+            "--no-embedded-input" => embedded_input = false,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    Ok(CompileOptions {
+        path: path.ok_or("no program file given")?,
+        output,
+        target: target.ok_or("compile requires a --target")?,
+        mem_size,
+        cell_width,
+        eof_behavior,
+        optimize,
+        dialect: with_extensions(dialect, extensions)?,
+        hash_debug,
+        embedded_input,
+    })
+}
+
+
+// parse the arguments for checking a program
+fn parse_check_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<CheckOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut path: Option<String> = Option::None;
+    let mut dialect = defaults.dialect;
+    let mut extensions = false;
+    let mut embedded_input = true;
+    let mut bounds = false;
+    let mut mem_size: Option<usize> = Option::None;
+    let mut dead_code = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dialect" => {
+                let value = args.next().ok_or("--dialect requires a value")?;
+                dialect = parse_dialect(&value)?;
+            },
+            "--extensions" => extensions = true,
+            "--no-embedded-input" => embedded_input = false,
+            "--bounds" => bounds = true,
+            "--dead-code" => dead_code = true,
+            "--mem-size" => {
+                let value = args.next().ok_or("--mem-size requires a value")?;
+                mem_size = Option::Some(parse_mem_size(&value)?);
+            },
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    if mem_size.is_some() && !bounds {
+        return Err(String::from("--mem-size can only be used with --bounds"));
+    }
+    Ok(CheckOptions {
+        path: path.ok_or("no program file given")?,
+        dialect: with_extensions(dialect, extensions)?,
+        embedded_input,
+        bounds,
+        mem_size: mem_size.unwrap_or(defaults.mem_size),
+        dead_code,
+    })
+}
+
+
+// parse the arguments for formatting a program
+fn parse_fmt_args<I: Iterator<Item = String>> (mut args: I) -> Result<FmtOptions, String> {
+    let mut path: Option<String> = Option::None;
+    let mut output: Option<String> = Option::None;
+    let mut config = formatter::Config::default();
+    let mut embedded_input = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--indent" => {
+                let value = args.next().ok_or("--indent requires a value")?;
+                config.indent = value.parse::<usize>().map_err(|_| format!("invalid --indent value '{}'", value))?;
+            },
+            "--strip-comments" => config.strip_comments = true,
+            "--width" => {
+                let value = args.next().ok_or("--width requires a value")?;
+                match value.parse::<usize>() {
+                    Ok(width) if width > 0 => config.width = Option::Some(width),
+                    _ => return Err(format!("invalid --width value '{}'", value)),
+                };
+            },
+            "-o" | "--output" => {
+                output = Option::Some(args.next().ok_or("--output requires a value")?);
+            },
+            "--no-embedded-input" => embedded_input = false,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    Ok(FmtOptions {
+        path: path.ok_or("no program file given")?,
+        output,
+        config,
+        embedded_input,
+    })
+}
+
+
+// parse the arguments for minifying a program
+fn parse_minify_args<I: Iterator<Item = String>> (mut args: I) -> Result<MinifyOptions, String> {
+    let mut path: Option<String> = Option::None;
+    let mut output: Option<String> = Option::None;
+    let mut embedded_input = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Option::Some(args.next().ok_or("--output requires a value")?);
+            },
+            "--no-embedded-input" => embedded_input = false,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    Ok(MinifyOptions {
+        path: path.ok_or("no program file given")?,
+        output,
+        embedded_input,
+    })
+}
+
+
+// parse the arguments for printing the internal representation of a program
+fn parse_ir_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<IrOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut path: Option<String> = Option::None;
+    let mut optimize = defaults.optimize;
+    let mut dialect = defaults.dialect;
+    let mut extensions = false;
+    let mut embedded_input = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-optimize" => optimize = false,
+            "--dialect" => {
+                let value = args.next().ok_or("--dialect requires a value")?;
+                dialect = parse_dialect(&value)?;
+            },
+            "--extensions" => extensions = true,
+            "--no-embedded-input" => embedded_input = false,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    Ok(IrOptions {
+        path: path.ok_or("no program file given")?,
+        optimize,
+        dialect: with_extensions(dialect, extensions)?,
+        embedded_input,
+    })
+}
+
+
+// parse the arguments for running golden file tests
+fn parse_test_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<TestOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut dir: Option<String> = Option::None;
+    let mut eof_behavior = defaults.eof_behavior;
+    let mut max_steps = defaults.limits.max_steps;
+    let mut jobs: usize = 1;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--eof" => {
+                let value = args.next().ok_or("--eof requires a value")?;
+                eof_behavior = parse_eof_behavior(&value)?;
+            },
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = Option::Some(value.parse::<u64>().map_err(|_| format!("invalid --max-steps value '{}'", value))?);
+            },
+            "--jobs" => {
+                let value = args.next().ok_or("--jobs requires a value")?;
+                jobs = parse_jobs(&value)?;
+            },
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if dir.is_none() => dir = Option::Some(arg),
+            _ => return Err(String::from("only one test directory may be given")),
+        };
+    }
+    Ok(TestOptions {
+        dir: dir.ok_or("no test directory given")?,
+        eof_behavior,
+        max_steps,
+        jobs,
+    })
+}
+
+
+// parse the arguments for comparing the engines on a program
+fn parse_diff_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<DiffOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut path: Option<String> = Option::None;
+    let mut mem_size = defaults.mem_size;
+    let mut tape_mode = defaults.tape_mode;
+    let mut cell_width = defaults.cell_width;
+    let mut eof_behavior = defaults.eof_behavior;
+    let mut max_steps = defaults.limits.max_steps.unwrap_or(crossval::DEFAULT_MAX_STEPS);
+    let mut dialect = defaults.dialect;
+    let mut extensions = false;
+    let mut input: Option<String> = Option::None;
+    let mut input_file: Option<String> = Option::None;
+    let mut embedded_input = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mem-size" => {
+                let value = args.next().ok_or("--mem-size requires a value")?;
+                mem_size = parse_mem_size(&value)?;
+            },
+            "--tape" => {
+                let value = args.next().ok_or("--tape requires a value")?;
+                tape_mode = parse_tape_mode(&value)?;
+            },
+            "--cell-width" => {
+                let value = args.next().ok_or("--cell-width requires a value")?;
+                cell_width = parse_cell_width(&value)?;
+            },
+            "--eof" => {
+                let value = args.next().ok_or("--eof requires a value")?;
+                eof_behavior = parse_eof_behavior(&value)?;
+            },
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = value.parse::<u64>().map_err(|_| format!("invalid --max-steps value '{}'", value))?;
+            },
+            "--dialect" => {
+                let value = args.next().ok_or("--dialect requires a value")?;
+                dialect = parse_dialect(&value)?;
+            },
+            "--extensions" => extensions = true,
+            "--input" => input = Option::Some(args.next().ok_or("--input requires a value")?),
+            "--input-file" => input_file = Option::Some(args.next().ok_or("--input-file requires a value")?),
+            "--no-embedded-input" => embedded_input = false,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    if input.is_some() && input_file.is_some() {
+        return Err(String::from("only one of --input and --input-file may be given"));
+    }
+    Ok(DiffOptions {
+        path: path.ok_or("no program file given")?,
+        mem_size,
+        tape_mode,
+        cell_width,
+        eof_behavior,
+        max_steps,
+        dialect: with_extensions(dialect, extensions)?,
+        input,
+        input_file,
+        embedded_input,
+    })
+}
+
+
+// parse the arguments for checking two programs behave the same
+fn parse_equiv_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<EquivOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut paths: Vec<String> = Vec::new();
+    let mut inputs: Option<String> = Option::None;
+    let mut mem_size = defaults.mem_size;
+    let mut tape_mode = defaults.tape_mode;
+    let mut cell_width = defaults.cell_width;
+    let mut eof_behavior = defaults.eof_behavior;
+    let mut max_steps = defaults.limits.max_steps.unwrap_or(equiv::DEFAULT_MAX_STEPS);
+    let mut dialect = defaults.dialect;
+    let mut extensions = false;
+    let mut jobs: usize = 1;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--inputs" => inputs = Option::Some(args.next().ok_or("--inputs requires a value")?),
+            "--mem-size" => {
+                let value = args.next().ok_or("--mem-size requires a value")?;
+                mem_size = parse_mem_size(&value)?;
+            },
+            "--tape" => {
+                let value = args.next().ok_or("--tape requires a value")?;
+                tape_mode = parse_tape_mode(&value)?;
+            },
+            "--cell-width" => {
+                let value = args.next().ok_or("--cell-width requires a value")?;
+                cell_width = parse_cell_width(&value)?;
+            },
+            "--eof" => {
+                let value = args.next().ok_or("--eof requires a value")?;
+                eof_behavior = parse_eof_behavior(&value)?;
+            },
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = value.parse::<u64>().map_err(|_| format!("invalid --max-steps value '{}'", value))?;
+            },
+            "--dialect" => {
+                let value = args.next().ok_or("--dialect requires a value")?;
+                dialect = parse_dialect(&value)?;
+            },
+            "--extensions" => extensions = true,
+            "--jobs" => {
+                let value = args.next().ok_or("--jobs requires a value")?;
+                jobs = parse_jobs(&value)?;
+            },
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if paths.len() < 2 => paths.push(arg),
+            _ => return Err(String::from("only two program files may be given")),
+        };
+    }
+    let b = paths.pop().ok_or("two program files must be given")?;
+    let a = paths.pop().ok_or("two program files must be given")?;
+    Ok(EquivOptions {
+        a,
+        b,
+        inputs,
+        mem_size,
+        tape_mode,
+        cell_width,
+        eof_behavior,
+        max_steps,
+        dialect: with_extensions(dialect, extensions)?,
+        jobs,
+    })
+}
+
+
+// parse the arguments for generating a random program
+fn parse_gen_args<I: Iterator<Item = String>> (mut args: I) -> Result<GenOptions, String> {
+    let mut config = generator::Config::default();
+    let mut seed: u64 = 0;
+    let mut output: Option<String> = Option::None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--len" => {
+                let value = args.next().ok_or("--len requires a value")?;
+                config.len = value.parse::<usize>().map_err(|_| format!("invalid --len value '{}'", value))?;
+            },
+            "--depth" => {
+                let value = args.next().ok_or("--depth requires a value")?;
+                config.max_depth = value.parse::<usize>().map_err(|_| format!("invalid --depth value '{}'", value))?;
+            },
+            "--seed" => {
+                let value = args.next().ok_or("--seed requires a value")?;
+                seed = value.parse::<u64>().map_err(|_| format!("invalid --seed value '{}'", value))?;
+            },
+            "--no-io" => config.io = false,
+            "-o" | "--output" => {
+                output = Option::Some(args.next().ok_or("--output requires a value")?);
+            },
+            _ => return Err(format!("unrecognized option '{}'", arg)),
+        };
+    }
+    Ok(GenOptions { config, seed, output })
+}
+
+
+// parse the arguments for generating a program that prints some text
+fn parse_encode_args<I: Iterator<Item = String>> (mut args: I) -> Result<EncodeOptions, String> {
+    let mut text: Option<String> = Option::None;
+    let mut file: Option<String> = Option::None;
+    let mut output: Option<String> = Option::None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--file" => file = Option::Some(args.next().ok_or("--file requires a value")?),
+            "-o" | "--output" => {
+                output = Option::Some(args.next().ok_or("--output requires a value")?);
+            },
+            _ if arg.starts_with("--") => return Err(format!("unrecognized option '{}'", arg)),
+            _ if text.is_none() => text = Option::Some(arg),
+            _ => return Err(String::from("only one text may be given, quote it if it has spaces")),
+        };
+    }
+    if text.is_some() == file.is_some() {
+        return Err(String::from("give either the text or --file"));
+    }
+    Ok(EncodeOptions { text, file, output })
+}
+
+
+// parse the arguments for obfuscating a program
+fn parse_obfuscate_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<ObfuscateOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut path: Option<String> = Option::None;
+    let mut seed: u64 = 0;
+    let mut output: Option<String> = Option::None;
+    let mut input: Option<String> = Option::None;
+    let mut input_file: Option<String> = Option::None;
+    let mut max_steps = defaults.limits.max_steps.unwrap_or(obfuscator::DEFAULT_MAX_STEPS);
+    let mut embedded_input = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let value = args.next().ok_or("--seed requires a value")?;
+                seed = value.parse::<u64>().map_err(|_| format!("invalid --seed value '{}'", value))?;
+            },
+            "-o" | "--output" => {
+                output = Option::Some(args.next().ok_or("--output requires a value")?);
+            },
+            "--input" => input = Option::Some(args.next().ok_or("--input requires a value")?),
+            "--input-file" => input_file = Option::Some(args.next().ok_or("--input-file requires a value")?),
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = value.parse::<u64>().map_err(|_| format!("invalid --max-steps value '{}'", value))?;
+            },
+            "--no-embedded-input" => embedded_input = false,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    if input.is_some() && input_file.is_some() {
+        return Err(String::from("only one of --input and --input-file may be given"));
+    }
+    Ok(ObfuscateOptions {
+        path: path.ok_or("no program file given")?,
+        seed,
+        output,
+        input,
+        input_file,
+        max_steps,
+        embedded_input,
+    })
+}
+
+
+// parse the arguments for benchmarking the engines
+fn parse_bench_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<BenchOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut paths: Vec<String> = Vec::new();
+    let mut runs: usize = 3;
+    let mut max_steps = defaults.limits.max_steps;
+    let mut input: Option<String> = Option::None;
+    let mut input_file: Option<String> = Option::None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--runs" => {
+                let value = args.next().ok_or("--runs requires a value")?;
+                runs = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid --runs value '{}'", value)),
+                };
+            },
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = Option::Some(value.parse::<u64>().map_err(|_| format!("invalid --max-steps value '{}'", value))?);
+            },
+            "--input" => input = Option::Some(args.next().ok_or("--input requires a value")?),
+            "--input-file" => input_file = Option::Some(args.next().ok_or("--input-file requires a value")?),
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ => paths.push(arg),
+        };
+    }
+    if input.is_some() && input_file.is_some() {
+        return Err(String::from("only one of --input and --input-file may be given"));
+    }
+    Ok(BenchOptions { paths, runs, max_steps, input, input_file })
+}
+
+
+// parse the arguments for replaying a recording
+fn parse_replay_args<I: Iterator<Item = String>> (args: I) -> Result<ReplayOptions, String> {
+    let mut paths: Vec<String> = Vec::new();
+    let mut debug = false;
+    for arg in args {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ => paths.push(arg),
+        };
+    }
+    match <[String; 2]>::try_from(paths) {
+        Ok([recording, path]) => Ok(ReplayOptions { recording, path, debug }),
+        Err(_) => Err(String::from("a recording and the program file it was made from must be given")),
+    }
+}
+
+
+// parse the arguments for drawing a heatmap
+fn parse_heatmap_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<HeatmapOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut path: Option<String> = Option::None;
+    let mut output: Option<String> = Option::None;
+    let mut mem_size = defaults.mem_size;
+    let mut eof_behavior = defaults.eof_behavior;
+    let mut max_steps = defaults.limits.max_steps;
+    let mut dialect = defaults.dialect;
+    let mut extensions = false;
+    let mut input: Option<String> = Option::None;
+    let mut input_file: Option<String> = Option::None;
+    let mut embedded_input = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--out" => output = Option::Some(args.next().ok_or("--out requires a value")?),
+            "--mem-size" => {
+                let value = args.next().ok_or("--mem-size requires a value")?;
+                mem_size = parse_mem_size(&value)?;
+            },
+            "--eof" => {
+                let value = args.next().ok_or("--eof requires a value")?;
+                eof_behavior = parse_eof_behavior(&value)?;
+            },
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = Option::Some(value.parse::<u64>().map_err(|_| format!("invalid --max-steps value '{}'", value))?);
+            },
+            "--dialect" => {
+                let value = args.next().ok_or("--dialect requires a value")?;
+                dialect = parse_dialect(&value)?;
+            },
+            "--extensions" => extensions = true,
+            "--input" => input = Option::Some(args.next().ok_or("--input requires a value")?),
+            "--input-file" => input_file = Option::Some(args.next().ok_or("--input-file requires a value")?),
+            "--no-embedded-input" => embedded_input = false,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    if input.is_some() && input_file.is_some() {
+        return Err(String::from("only one of --input and --input-file may be given"));
+    }
+    Ok(HeatmapOptions {
+        path: path.ok_or("no program file given")?,
+        output,
+        mem_size,
+        eof_behavior,
+        max_steps,
+        dialect: with_extensions(dialect, extensions)?,
+        input,
+        input_file,
+        embedded_input,
+    })
+}
+
+
+// parse the arguments for running a self-modifying program
+fn parse_smbf_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<SmbfOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut path: Option<String> = Option::None;
+    let mut mem_size = defaults.mem_size;
+    let mut tape_mode = defaults.tape_mode;
+    let mut cell_width = defaults.cell_width;
+    let mut eof_behavior = defaults.eof_behavior;
+    let mut max_steps = defaults.limits.max_steps;
+    let mut input: Option<String> = Option::None;
+    let mut input_file: Option<String> = Option::None;
+    let mut dump_mem = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mem-size" => {
+                let value = args.next().ok_or("--mem-size requires a value")?;
+                mem_size = parse_mem_size(&value)?;
+            },
+            "--tape" => {
+                let value = args.next().ok_or("--tape requires a value")?;
+                tape_mode = parse_tape_mode(&value)?;
+            },
+            "--cell-width" => {
+                let value = args.next().ok_or("--cell-width requires a value")?;
+                cell_width = parse_cell_width(&value)?;
+            },
+            "--eof" => {
+                let value = args.next().ok_or("--eof requires a value")?;
+                eof_behavior = parse_eof_behavior(&value)?;
+            },
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = Option::Some(value.parse::<u64>().map_err(|_| format!("invalid --max-steps value '{}'", value))?);
+            },
+            "--input" => input = Option::Some(args.next().ok_or("--input requires a value")?),
+            "--input-file" => input_file = Option::Some(args.next().ok_or("--input-file requires a value")?),
+            "--dump-mem-on-exit" => dump_mem = true,
+            _ if arg.starts_with('-') => return Err(format!("unrecognized option '{}'", arg)),
+            _ if path.is_none() => path = Option::Some(arg),
+            _ => return Err(String::from("only one program file may be given")),
+        };
+    }
+    if input.is_some() && input_file.is_some() {
+        return Err(String::from("only one of --input and --input-file may be given"));
+    }
+    Ok(SmbfOptions {
+        path: path.ok_or("no program file given")?,
+        mem_size,
+        tape_mode,
+        cell_width,
+        eof_behavior,
+        max_steps,
+        input,
+        input_file,
+        dump_mem,
+    })
+}
+
+
+// parse the arguments for serving the playground
+fn parse_serve_args<I: Iterator<Item = String>> (mut args: I, env: &[(String, String)]) -> Result<ServeOptions, String> {
+    let defaults = settings(Option::None, env)?;
+    let mut host = String::from("127.0.0.1");
+    let mut port: u16 = 8080;
+    let mut jobs: usize = 4;
+    let mut mem_size = defaults.mem_size;
+    let mut eof_behavior = defaults.eof_behavior;
+    let mut max_steps = defaults.limits.max_steps.unwrap_or(10_000_000);
+    let mut max_output = defaults.limits.max_output_bytes.unwrap_or(65536);
+    let mut timeout_ms = defaults.limits.timeout.map_or(5000, |t| t.as_millis() as u64);
+    let mut max_body: usize = 1 << 20;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" => host = args.next().ok_or("--host requires a value")?,
+            "--port" => {
+                let value = args.next().ok_or("--port requires a value")?;
+                port = value.parse::<u16>().map_err(|_| format!("invalid --port value '{}'", value))?;
+            },
+            "--jobs" => {
+                let value = args.next().ok_or("--jobs requires a value")?;
+                jobs = parse_jobs(&value)?;
+            },
+            "--mem-size" => {
+                let value = args.next().ok_or("--mem-size requires a value")?;
+                mem_size = parse_mem_size(&value)?;
+            },
+            "--eof" => {
+                let value = args.next().ok_or("--eof requires a value")?;
+                eof_behavior = parse_eof_behavior(&value)?;
+            },
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps requires a value")?;
+                max_steps = match value.parse::<u64>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid --max-steps value '{}'", value)),
+                };
+            },
+            "--max-output" => {
+                let value = args.next().ok_or("--max-output requires a value")?;
+                max_output = match value.parse::<u64>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid --max-output value '{}'", value)),
+                };
+            },
+            "--timeout-ms" => {
+                let value = args.next().ok_or("--timeout-ms requires a value")?;
+                timeout_ms = match value.parse::<u64>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid --timeout-ms value '{}'", value)),
+                };
+            },
+            "--max-body" => {
+                let value = args.next().ok_or("--max-body requires a value")?;
+                max_body = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid --max-body value '{}'", value)),
+                };
+            },
+            _ => return Err(format!("unrecognized option '{}'", arg)),
+        };
+    }
+    // the flags can't be 0, but limits from the environment could be
+    if max_steps == 0 || max_output == 0 || timeout_ms == 0 {
+        return Err(String::from("the server's step, output and time limits must be greater than 0"));
+    }
+    Ok(ServeOptions { host, port, jobs, mem_size, eof_behavior, max_steps, max_output, timeout_ms, max_body })
+}
+
+
+// parse the arguments for printing a completion script
+fn parse_completions_args<I: Iterator<Item = String>> (mut args: I) -> Result<Shell, String> {
+    let shell = match args.next().as_deref() {
+        Some("bash") => Shell::Bash,
+        Some("zsh") => Shell::Zsh,
+        Some("fish") => Shell::Fish,
+        Some(other) => return Err(format!("invalid shell '{}', expected bash, zsh or fish", other)),
+        None => return Err(String::from("no shell given")),
+    };
+    match args.next() {
+        Some(arg) => Err(format!("unexpected argument '{}'", arg)),
+        None => Ok(shell),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // parse a space separated argument string
+    fn parse_any (args: &str) -> Result<Subcommand, String> {
+        parse_args(args.split_whitespace().map(String::from), std::iter::empty())
+    }
+
+    // parse a space separated argument string that should run a program
+    fn parse (args: &str) -> Result<Options, String> {
+        match parse_any(args)? {
+            Subcommand::Run(opts) => Ok(*opts),
+            other => panic!("expected run options, got {:?}", other),
+        }
+    }
+
+    // parse a space separated argument string that should compile a program
+    fn parse_compile (args: &str) -> Result<CompileOptions, String> {
+        match parse_any(args)? {
+            Subcommand::Compile(opts) => Ok(opts),
+            other => panic!("expected compile options, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_args_defaults () {
+        let opts = parse("prog.bf").unwrap();
+        assert_eq!(opts, Options {
+            path: String::from("prog.bf"),
+            more_paths: Vec::new(),
+            jobs: 1,
+            mem_size: DEFAULT_MEM_SIZE,
+            tape_mode: dtypes::TapeMode::Strict,
+            sparse: false,
+            cell_width: dtypes::CellWidth::U8,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            limits: dtypes::Limits::default(),
+            checkpoint: None,
+            resume: None,
+            optimize: true,
+            precompute: None,
+            dialect: dtypes::Dialect::Brainfuck,
+            hash_debug: false,
+            map: None,
+            map_file: None,
+            input: None,
+            input_file: None,
+            input_random: None,
+            embedded_input: true,
+            raw_input: false,
+            init_mem: None,
+            init_mem_file: None,
+            engine: dtypes::Engine::Interpreter,
+            dump_mem: false,
+            exit_cell: None,
+            filter: false,
+            flush_every_byte: false,
+            output_buffer: None,
+            trace: false,
+            trace_file: None,
+            record: None,
+            profile: false,
+            profile_trace: None,
+            coverage: false,
+            lcov: None,
+            stats: false,
+            output_format: OutputFormat::Text,
+            color: ColorChoice::Auto,
+            delay_ms: None,
+            debug: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            history: DEFAULT_HISTORY_DEPTH,
+            tui: false,
+        });
+    }
+
+    #[test]
+    fn parse_args_flags () {
+        let opts = parse("--mem-size 64 prog.bf --eof 255 --tape wrapping --cell-width 16").unwrap();
+        assert_eq!(opts.cell_width, dtypes::CellWidth::U16);
+        assert_eq!(opts.mem_size, 64);
+        assert_eq!(opts.tape_mode, dtypes::TapeMode::Wrapping);
+        assert_eq!(opts.eof_behavior, dtypes::EofBehavior::MinusOne);
+        assert!(parse("--sparse prog.bf").unwrap().sparse);
+        // huge tapes are always sparse
+        assert!(parse("--mem-size 1000000000 prog.bf").unwrap().sparse);
+        let opts = parse("--max-steps 1000 prog.bf").unwrap();
+        assert_eq!(opts.limits.max_steps, Some(1000));
+        // settings from a config file, which flags override wherever they are
+        let path = std::env::temp_dir().join(format!("rust_bfi_config_{}.toml", std::process::id()));
+        fs::write(&path, "mem_size = 64\ntape = \"wrapping\"\n[limits]\nmax_steps = 5\nmax_output_bytes = 10\n").unwrap();
+        let opts = parse(&format!("--max-steps 99 prog.bf --config {}", path.display()));
+        let twice = parse(&format!("--config {0} --config {0} prog.bf", path.display()));
+        fs::remove_file(&path).unwrap();
+        let opts = opts.unwrap();
+        assert_eq!((opts.mem_size, opts.tape_mode), (64, dtypes::TapeMode::Wrapping));
+        assert_eq!(opts.limits, dtypes::Limits { max_steps: Some(99), max_output_bytes: Some(10), ..Default::default() });
+        assert!(twice.is_err());
+        assert!(parse(&format!("--config {} prog.bf", path.display())).unwrap_err().contains("unable to read config file"));
+        assert!(parse("prog.bf --config").is_err());
+        // only a flag is taken for one, not another flag's value or a program file
+        let opts = parse("--input --config prog.bf").unwrap();
+        assert_eq!((opts.input.as_deref(), opts.path.as_str()), (Some("--config"), "prog.bf"));
+        let opts = parse("--mem-size 64 -- prog.bf --config --mem-size").unwrap();
+        assert_eq!((opts.path.as_str(), opts.more_paths.clone(), opts.mem_size), ("prog.bf", vec![String::from("--config"), String::from("--mem-size")], 64));
+        let opts = parse("--checkpoint a.snap --resume b.snap prog.bf").unwrap();
+        assert_eq!(opts.checkpoint, Some(String::from("a.snap")));
+        assert_eq!(opts.resume, Some(String::from("b.snap")));
+        assert!(!parse("--no-optimize prog.bf").unwrap().optimize);
+        assert_eq!(parse("--dialect blub prog.blub").unwrap().dialect, dtypes::Dialect::Blub);
+        assert_eq!(parse("--extensions prog.bf").unwrap().dialect, dtypes::Dialect::Extended);
+        assert!(parse("--enable-hash-debug prog.bf").unwrap().hash_debug);
+        assert_eq!(parse("--dialect pbrain prog.b").unwrap().dialect, dtypes::Dialect::Pbrain);
+        assert_eq!(parse("--dialect brainfork prog.b").unwrap().dialect, dtypes::Dialect::Brainfork);
+        assert_eq!(parse("--dialect extended --extensions prog.bf").unwrap().dialect, dtypes::Dialect::Extended);
+        assert!(!parse("--no-embedded-input prog.bf").unwrap().embedded_input);
+        assert_eq!(parse("--input abc prog.bf").unwrap().input, Some(String::from("abc")));
+        assert_eq!(parse("--input-file in.txt prog.bf").unwrap().input_file, Some(String::from("in.txt")));
+        assert_eq!(parse("--input-random --seed 42 prog.bf").unwrap().input_random, Some(42));
+        assert_eq!(parse("--input-random prog.bf").unwrap().input_random, Some(0));
+        assert_eq!(parse("--engine interpreter prog.bf").unwrap().engine, dtypes::Engine::Interpreter);
+        assert!(parse("prog.bf --dump-mem-on-exit").unwrap().dump_mem);
+        assert!(parse("--trace prog.bf").unwrap().trace);
+        assert!(parse("--profile prog.bf").unwrap().profile);
+        assert_eq!(parse("--profile-trace run.json prog.bf").unwrap().profile_trace, Some(String::from("run.json")));
+        assert!(parse("--stats prog.bf").unwrap().stats);
+        assert_eq!(parse("--output-format json prog.bf").unwrap().output_format, OutputFormat::Json);
+        assert_eq!(parse("--color never prog.bf").unwrap().color, ColorChoice::Never);
+        assert_eq!(parse("--color always prog.bf").unwrap().color, ColorChoice::Always);
+        assert_eq!(parse("--delay-ms 30 prog.bf").unwrap().delay_ms, Some(30));
+        assert_eq!(parse("--exit-cell 2 prog.bf").unwrap().exit_cell, Some(2));
+        assert_eq!(parse("--init-mem 72,101,108 prog.bf").unwrap().init_mem, Some(vec![72, 101, 108]));
+        assert_eq!(parse("--init-mem-file data.bin prog.bf").unwrap().init_mem_file, Some(String::from("data.bin")));
+        assert_eq!(parse("--map +=up,-=down prog.bf").unwrap().map, Some(parsing::Mapping::parse("+=up,-=down").unwrap()));
+        assert_eq!(parse("--map-file words.map prog.bf").unwrap().map_file, Some(String::from("words.map")));
+        let opts = parse("--trace-file trace.log prog.bf").unwrap();
+        assert!(opts.trace);
+        assert_eq!(opts.trace_file, Some(String::from("trace.log")));
+        assert_eq!(parse("--record run.bfr prog.bf").unwrap().record, Some(String::from("run.bfr")));
+        assert!(parse("--coverage prog.bf").unwrap().coverage);
+        assert_eq!(parse("--lcov prog.info prog.bf").unwrap().lcov, Some(String::from("prog.info")));
+        let opts = parse("--debug --break 3 --break 10 --watch 2 prog.bf").unwrap();
+        assert!(opts.debug);
+        assert_eq!(opts.breakpoints, vec![3, 10]);
+        assert_eq!(opts.watchpoints, vec![2]);
+        assert_eq!(parse("--debug --history 50 prog.bf").unwrap().history, 50);
+        let opts = parse("a.bf --jobs 4 b.bf c.bf --stats").unwrap();
+        assert_eq!(opts.path, "a.bf");
+        assert_eq!(opts.more_paths, vec![String::from("b.bf"), String::from("c.bf")]);
+        assert_eq!(opts.jobs, 4);
+        #[cfg(feature = "tui")]
+        assert!(parse("--tui prog.bf").unwrap().tui);
+    }
+
+    #[test]
+    fn parse_args_errors () {
+        assert!(parse("").is_err());
+        assert!(parse("a.bf b.bf --debug").is_err());
+        assert!(parse("a.bf b.bf --resume a.snap").is_err());
+        assert!(parse("--sparse --checkpoint a.snap prog.bf").is_err());
+        assert!(parse("--output-format xml prog.bf").is_err());
+        assert!(parse("--color sometimes prog.bf").is_err());
+        assert!(parse("--output-format json --debug prog.bf").is_err());
+        assert!(parse("--delay-ms 30 --output-format json prog.bf").is_err());
+        assert!(parse("--delay-ms fast prog.bf").is_err());
+        assert!(parse("a.bf b.bf --delay-ms 30").is_err());
+        assert!(parse("--exit-cell 0 --output-format json prog.bf").is_err());
+        assert!(parse("--exit-cell -1 prog.bf").is_err());
+        assert!(parse("--exit-cell 30000 prog.bf").is_err());
+        assert!(parse("--exit-cell 4 --mem-size 4 --tape wrapping prog.bf").is_err());
+        assert!(parse("--exit-cell 30000 --tape growable prog.bf").is_ok());
+        assert!(parse("--init-mem 1,256 prog.bf").is_err());
+        assert!(parse("--init-mem 1 --init-mem-file data.bin prog.bf").is_err());
+        assert!(parse("--map +=up --map-file words.map prog.bf").is_err());
+        assert!(parse("--map +up prog.bf").is_err());
+        assert!(parse("--map +=up --dialect ook prog.bf").is_err());
+        assert!(parse("--map-file words.map --record run.bfr prog.bf").is_err());
+        assert!(parse("--jobs 0 a.bf b.bf").is_err());
+        assert!(parse("--mem-size 0 prog.bf").is_err());
+        assert!(parse("--mem-size -3 prog.bf").is_err());
+        assert!(parse("prog.bf --mem-size").is_err());
+        assert!(parse("--eof maybe prog.bf").is_err());
+        assert!(parse("--tape circular prog.bf").is_err());
+        assert!(parse("--break here prog.bf").is_err());
+        assert!(parse("--watch cell prog.bf").is_err());
+        assert!(parse("--history -1 prog.bf").is_err());
+        assert!(parse("--cell-width 64 prog.bf").is_err());
+        assert!(parse("--max-steps lots prog.bf").is_err());
+        assert!(parse("--engine turbo prog.bf").is_err());
+        assert!(parse("--dialect cow prog.bf").is_err());
+        assert!(parse("--dialect ook --extensions prog.bf").is_err());
+        assert!(parse("prog.bf --trace-file").is_err());
+        assert!(parse("a.bf b.bf --record run.bfr").is_err());
+        assert!(parse("--record run.bfr --debug prog.bf").is_err());
+        assert!(parse("--record run.bfr --init-mem 1 prog.bf").is_err());
+        assert!(parse("a.bf b.bf --coverage").is_err());
+        assert!(parse("--lcov prog.info --debug prog.bf").is_err());
+        assert!(parse("prog.bf --lcov").is_err());
+        assert!(parse("a.bf b.bf --profile-trace run.json").is_err());
+        assert!(parse("prog.bf --profile-trace").is_err());
+        assert!(parse("--input abc --input-file in.txt prog.bf").is_err());
+        assert!(parse("--input abc --input-random prog.bf").is_err());
+        assert!(parse("--seed 42 prog.bf").is_err());
+        assert!(parse("--input-random --seed x prog.bf").is_err());
+        assert!(parse("--stats --debug prog.bf").is_err());
+        #[cfg(feature = "tui")]
+        assert!(parse("--tui --debug prog.bf").is_err());
+        #[cfg(not(feature = "tui"))]
+        assert!(parse("--tui prog.bf").is_err());
+        assert!(parse("--bogus prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_run_subcommand () {
+        assert_eq!(parse("run --mem-size 64 prog.bf").unwrap(), parse("--mem-size 64 prog.bf").unwrap());
+    }
+
+    #[test]
+    fn parse_args_filter () {
+        assert!(parse("--filter prog.bf").unwrap().filter);
+        assert_eq!(parse("filter --eof 0 rot13.bf").unwrap(), parse("--filter --eof 0 rot13.bf").unwrap());
+        assert!(parse("filter --input abc prog.bf").is_err());
+        assert!(parse("filter --debug prog.bf").is_err());
+        assert!(parse("filter --output-format json prog.bf").is_err());
+        assert!(parse("filter a.bf b.bf").is_err());
+        assert_eq!(parse("filter --output-buffer 1048576 prog.bf").unwrap().output_buffer, Some(1 << 20));
+        assert!(parse("filter --flush-every-byte prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_output_flushing () {
+        assert!(parse("--flush-every-byte prog.bf").unwrap().flush_every_byte);
+        assert_eq!(parse("--output-buffer 4096 prog.bf").unwrap().output_buffer, Some(4096));
+        assert!(parse("--output-buffer 0 prog.bf").is_err());
+        assert!(parse("--output-buffer big prog.bf").is_err());
+        assert!(parse("--flush-every-byte --output-buffer 4096 prog.bf").is_err());
+        assert!(parse("--flush-every-byte --tui prog.bf").is_err());
+        assert!(parse("--output-buffer 4096 --output-format json prog.bf").is_err());
+        assert!(parse("--flush-every-byte a.bf b.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_precompute () {
+        assert_eq!(parse("--precompute 100000 prog.bf").unwrap().precompute, Some(100000));
+        assert_eq!(parse("--precompute 100 a.bf b.bf").unwrap().precompute, Some(100));
+        assert!(parse("--precompute prog.bf").is_err());
+        assert!(parse("--precompute -1 prog.bf").is_err());
+        assert!(parse("--precompute 100 --debug prog.bf").is_err());
+        assert!(parse("--precompute 100 --profile prog.bf").is_err());
+        assert!(parse("--precompute 100 --trace prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_raw_input () {
+        assert!(parse("--raw-input game.bf").unwrap().raw_input);
+        assert!(parse("--raw-input --input abc game.bf").is_err());
+        assert!(parse("--raw-input --debug game.bf").is_err());
+        assert!(parse("--raw-input --tui game.bf").is_err());
+        assert!(parse("filter --raw-input game.bf").is_err());
+        assert!(parse("--raw-input a.bf b.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_compile () {
+        let opts = parse_compile("compile --target rust prog.bf").unwrap();
+        assert_eq!(opts, CompileOptions {
+            path: String::from("prog.bf"),
+            output: None,
+            target: compiler::Target::Rust,
+            mem_size: DEFAULT_MEM_SIZE,
+            cell_width: dtypes::CellWidth::U8,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            optimize: true,
+            dialect: dtypes::Dialect::Brainfuck,
+            hash_debug: false,
+            embedded_input: true,
+        });
+        assert!(parse_compile("compile --target c --enable-hash-debug prog.bf").unwrap().hash_debug);
+        let opts = parse_compile("compile prog.bf -o prog.rs --target rust --cell-width 32 --eof zero --no-optimize").unwrap();
+        assert_eq!(opts.output, Some(String::from("prog.rs")));
+        assert_eq!(opts.cell_width, dtypes::CellWidth::U32);
+        assert_eq!(opts.eof_behavior, dtypes::EofBehavior::Zero);
+        assert!(!opts.optimize);
+        assert_eq!(parse_compile("compile --target c --dialect ook prog.ook").unwrap().dialect, dtypes::Dialect::Ook);
+        assert_eq!(parse_compile("compile --target c --extensions prog.bf").unwrap().dialect, dtypes::Dialect::Extended);
+        assert!(!parse_compile("compile --target c --no-embedded-input prog.bf").unwrap().embedded_input);
+        assert_eq!(parse_compile("compile --target c prog.bf").unwrap().target, compiler::Target::C);
+        assert_eq!(parse_compile("compile --target wasm prog.bf").unwrap().target, compiler::Target::Wasm);
+        assert!(parse_compile("compile prog.bf").is_err());
+        assert!(parse_compile("compile --target cobol prog.bf").is_err());
+        assert!(parse_compile("compile --target rust").is_err());
+        assert!(parse_compile("compile --target rust --tape wrapping prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_check () {
+        assert_eq!(parse_any("check prog.bf").unwrap(), Subcommand::Check(CheckOptions {
+            path: String::from("prog.bf"),
+            dialect: dtypes::Dialect::Brainfuck,
+            embedded_input: true,
+            bounds: false,
+            mem_size: DEFAULT_MEM_SIZE,
+            dead_code: false,
+        }));
+        assert_eq!(parse_any("check --dialect ook --no-embedded-input prog.ook").unwrap(), Subcommand::Check(CheckOptions {
+            path: String::from("prog.ook"),
+            dialect: dtypes::Dialect::Ook,
+            embedded_input: false,
+            bounds: false,
+            mem_size: DEFAULT_MEM_SIZE,
+            dead_code: false,
+        }));
+        assert_eq!(parse_any("check --bounds --mem-size 100 --dead-code prog.bf").unwrap(), Subcommand::Check(CheckOptions {
+            path: String::from("prog.bf"),
+            dialect: dtypes::Dialect::Brainfuck,
+            embedded_input: true,
+            bounds: true,
+            mem_size: 100,
+            dead_code: true,
+        }));
+        assert!(parse_any("check --mem-size 100 prog.bf").is_err());
+        assert!(parse_any("check").is_err());
+        assert!(parse_any("check --debug prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_analyze () {
+        assert_eq!(parse_any("analyze --dialect pbrain prog.b").unwrap(), Subcommand::Analyze(CheckOptions {
+            path: String::from("prog.b"),
+            dialect: dtypes::Dialect::Pbrain,
+            embedded_input: true,
+            bounds: false,
+            mem_size: DEFAULT_MEM_SIZE,
+            dead_code: false,
+        }));
+        assert_eq!(parse_any("analyze --dead-code prog.bf").unwrap(), Subcommand::Analyze(CheckOptions {
+            path: String::from("prog.bf"),
+            dialect: dtypes::Dialect::Brainfuck,
+            embedded_input: true,
+            bounds: false,
+            mem_size: DEFAULT_MEM_SIZE,
+            dead_code: true,
+        }));
+        assert!(parse_any("analyze").is_err());
+    }
+
+    #[test]
+    fn parse_args_fmt () {
+        assert_eq!(parse_any("fmt prog.bf").unwrap(), Subcommand::Fmt(FmtOptions {
+            path: String::from("prog.bf"),
+            output: None,
+            config: formatter::Config::default(),
+            embedded_input: true,
+        }));
+        let opts = parse_any("fmt --indent 2 --strip-comments --width 60 -o out.bf --no-embedded-input prog.bf").unwrap();
+        assert_eq!(opts, Subcommand::Fmt(FmtOptions {
+            path: String::from("prog.bf"),
+            output: Some(String::from("out.bf")),
+            config: formatter::Config { indent: 2, strip_comments: true, width: Some(60) },
+            embedded_input: false,
+        }));
+        assert!(parse_any("fmt --width 0 prog.bf").is_err());
+        assert!(parse_any("fmt --indent wide prog.bf").is_err());
+        assert!(parse_any("fmt").is_err());
+    }
+
+    #[test]
+    fn parse_args_minify () {
+        assert_eq!(parse_any("minify prog.bf").unwrap(), Subcommand::Minify(MinifyOptions {
+            path: String::from("prog.bf"),
+            output: None,
+            embedded_input: true,
+        }));
+        assert_eq!(parse_any("minify -o min.bf --no-embedded-input prog.bf").unwrap(), Subcommand::Minify(MinifyOptions {
+            path: String::from("prog.bf"),
+            output: Some(String::from("min.bf")),
+            embedded_input: false,
+        }));
+        assert!(parse_any("minify").is_err());
+        assert!(parse_any("minify --indent 2 prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_ir () {
+        assert_eq!(parse_any("ir prog.bf").unwrap(), Subcommand::Ir(IrOptions {
+            path: String::from("prog.bf"),
+            optimize: true,
+            dialect: dtypes::Dialect::Brainfuck,
+            embedded_input: true,
+        }));
+        assert_eq!(parse_any("ir --no-optimize --extensions --no-embedded-input prog.bf").unwrap(), Subcommand::Ir(IrOptions {
+            path: String::from("prog.bf"),
+            optimize: false,
+            dialect: dtypes::Dialect::Extended,
+            embedded_input: false,
+        }));
+        assert!(parse_any("ir").is_err());
+        assert!(parse_any("ir --target c prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_explain () {
+        assert_eq!(parse_any("explain --no-optimize prog.bf").unwrap(), Subcommand::Explain(IrOptions {
+            path: String::from("prog.bf"),
+            optimize: false,
+            dialect: dtypes::Dialect::Brainfuck,
+            embedded_input: true,
+        }));
+        assert!(parse_any("explain").is_err());
+    }
+
+    #[test]
+    fn parse_args_test () {
+        assert_eq!(parse_any("test tests/").unwrap(), Subcommand::Test(TestOptions {
+            dir: String::from("tests/"),
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            max_steps: None,
+            jobs: 1,
+        }));
+        assert_eq!(parse_any("test --eof 0 --max-steps 500 --jobs 4 golden").unwrap(), Subcommand::Test(TestOptions {
+            dir: String::from("golden"),
+            eof_behavior: dtypes::EofBehavior::Zero,
+            max_steps: Some(500),
+            jobs: 4,
+        }));
+        assert!(parse_any("test").is_err());
+        assert!(parse_any("test a b").is_err());
+        assert!(parse_any("test --jobs 0 golden").is_err());
+    }
+
+    #[test]
+    fn parse_args_diff () {
+        assert_eq!(parse_any("diff prog.bf").unwrap(), Subcommand::Diff(DiffOptions {
+            path: String::from("prog.bf"),
+            mem_size: DEFAULT_MEM_SIZE,
+            tape_mode: dtypes::TapeMode::Strict,
+            cell_width: dtypes::CellWidth::U8,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            max_steps: crossval::DEFAULT_MAX_STEPS,
+            dialect: dtypes::Dialect::Brainfuck,
+            input: None,
+            input_file: None,
+            embedded_input: true,
+        }));
+        let opts = parse_any("diff --tape growable --eof 0 --max-steps 99 --input abc --extensions prog.bf").unwrap();
+        assert!(matches!(opts, Subcommand::Diff(DiffOptions {
+            tape_mode: dtypes::TapeMode::Growable,
+            eof_behavior: dtypes::EofBehavior::Zero,
+            max_steps: 99,
+            dialect: dtypes::Dialect::Extended,
+            input: Some(_),
+            ..
+        })));
+        assert!(parse_any("diff --input a --input-file b prog.bf").is_err());
+        assert!(parse_any("diff a.bf b.bf").is_err());
+        assert!(parse_any("diff").is_err());
+    }
+
+    #[test]
+    fn parse_args_equiv () {
+        assert_eq!(parse_any("equiv a.bf b.bf").unwrap(), Subcommand::Equiv(EquivOptions {
+            a: String::from("a.bf"),
+            b: String::from("b.bf"),
+            inputs: None,
+            mem_size: DEFAULT_MEM_SIZE,
+            tape_mode: dtypes::TapeMode::Strict,
+            cell_width: dtypes::CellWidth::U8,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            max_steps: equiv::DEFAULT_MAX_STEPS,
+            dialect: dtypes::Dialect::Brainfuck,
+            jobs: 1,
+        }));
+        let opts = parse_any("equiv --inputs corpus/ a.bf --eof 0 --max-steps 99 --jobs 4 b.bf").unwrap();
+        assert!(matches!(opts, Subcommand::Equiv(EquivOptions {
+            inputs: Some(ref dir),
+            eof_behavior: dtypes::EofBehavior::Zero,
+            max_steps: 99,
+            jobs: 4,
+            ..
+        }) if dir == "corpus/"));
+        assert!(parse_any("equiv a.bf").is_err());
+        assert!(parse_any("equiv a.bf b.bf c.bf").is_err());
+        assert!(parse_any("equiv --inputs").is_err());
+    }
+
+    #[test]
+    fn parse_args_gen () {
+        assert_eq!(parse_any("gen").unwrap(), Subcommand::Gen(GenOptions {
+            config: generator::Config::default(),
+            seed: 0,
+            output: None,
+        }));
+        assert_eq!(parse_any("gen --len 500 --depth 2 --seed 9 --no-io -o fuzz.bf").unwrap(), Subcommand::Gen(GenOptions {
+            config: generator::Config { len: 500, max_depth: 2, io: false },
+            seed: 9,
+            output: Some(String::from("fuzz.bf")),
+        }));
+        assert!(parse_any("gen --len").is_err());
+        assert!(parse_any("gen prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_encode () {
+        assert_eq!(parse_any("encode Hello!").unwrap(), Subcommand::Encode(EncodeOptions {
+            text: Some(String::from("Hello!")),
+            file: None,
+            output: None,
+        }));
+        assert_eq!(parse_any("encode --file poem.txt -o poem.bf").unwrap(), Subcommand::Encode(EncodeOptions {
+            text: None,
+            file: Some(String::from("poem.txt")),
+            output: Some(String::from("poem.bf")),
+        }));
+        assert!(parse_any("encode").is_err());
+        assert!(parse_any("encode Hello --file poem.txt").is_err());
+        assert!(parse_any("encode Hello World").is_err());
+    }
+
+    #[test]
+    fn parse_args_obfuscate () {
+        assert_eq!(parse_any("obfuscate prog.bf").unwrap(), Subcommand::Obfuscate(ObfuscateOptions {
+            path: String::from("prog.bf"),
+            seed: 0,
+            output: None,
+            input: None,
+            input_file: None,
+            max_steps: obfuscator::DEFAULT_MAX_STEPS,
+            embedded_input: true,
+        }));
+        assert_eq!(parse_any("obfuscate --seed 7 -o noisy.bf --input hi --max-steps 100 --no-embedded-input prog.bf").unwrap(),
+                   Subcommand::Obfuscate(ObfuscateOptions {
+            path: String::from("prog.bf"),
+            seed: 7,
+            output: Some(String::from("noisy.bf")),
+            input: Some(String::from("hi")),
+            input_file: None,
+            max_steps: 100,
+            embedded_input: false,
+        }));
+        assert!(parse_any("obfuscate").is_err());
+        assert!(parse_any("obfuscate --seed x prog.bf").is_err());
+        assert!(parse_any("obfuscate --input a --input-file b prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_bench () {
+        assert_eq!(parse_any("bench").unwrap(), Subcommand::Bench(BenchOptions {
+            paths: Vec::new(),
+            runs: 3,
+            max_steps: None,
+            input: None,
+            input_file: None,
+        }));
+        assert_eq!(parse_any("bench --runs 5 --max-steps 99 --input-file in.txt a.bf b.bf").unwrap(), Subcommand::Bench(BenchOptions {
+            paths: vec![String::from("a.bf"), String::from("b.bf")],
+            runs: 5,
+            max_steps: Some(99),
+            input: None,
+            input_file: Some(String::from("in.txt")),
+        }));
+        assert!(parse_any("bench --runs 0").is_err());
+        assert!(parse_any("bench --input a --input-file b").is_err());
+        assert!(parse_any("bench --fast").is_err());
+    }
+
+    #[test]
+    fn parse_args_replay () {
+        assert_eq!(parse_any("replay run.bfr prog.bf").unwrap(), Subcommand::Replay(ReplayOptions {
+            recording: String::from("run.bfr"),
+            path: String::from("prog.bf"),
+            debug: false,
+        }));
+        assert!(matches!(parse_any("replay --debug run.bfr prog.bf").unwrap(), Subcommand::Replay(ReplayOptions { debug: true, .. })));
+        assert!(parse_any("replay run.bfr").is_err());
+        assert!(parse_any("replay run.bfr a.bf b.bf").is_err());
+        assert!(parse_any("replay --fast run.bfr prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_heatmap () {
+        assert_eq!(parse_any("heatmap prog.bf").unwrap(), Subcommand::Heatmap(HeatmapOptions {
+            path: String::from("prog.bf"),
+            output: None,
+            mem_size: DEFAULT_MEM_SIZE,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            max_steps: None,
+            dialect: dtypes::Dialect::Brainfuck,
+            input: None,
+            input_file: None,
+            embedded_input: true,
+        }));
+        let opts = parse_any("heatmap prog.bf --out heatmap.html --max-steps 1000 --input abc --eof 0").unwrap();
+        assert!(matches!(opts, Subcommand::Heatmap(HeatmapOptions {
+            output: Some(ref out), max_steps: Some(1000), input: Some(_), eof_behavior: dtypes::EofBehavior::Zero, ..
+        }) if out == "heatmap.html"));
+        assert!(parse_any("heatmap").is_err());
+        assert!(parse_any("heatmap prog.bf --out").is_err());
+        assert!(parse_any("heatmap a.bf b.bf").is_err());
+        assert!(parse_any("heatmap --input a --input-file b prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_smbf () {
+        assert_eq!(parse_any("smbf prog.bf").unwrap(), Subcommand::Smbf(SmbfOptions {
+            path: String::from("prog.bf"),
+            mem_size: DEFAULT_MEM_SIZE,
+            tape_mode: dtypes::TapeMode::Strict,
+            cell_width: dtypes::CellWidth::U8,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            max_steps: None,
+            input: None,
+            input_file: None,
+            dump_mem: false,
+        }));
+        let opts = parse_any("smbf --tape growable --cell-width 16 --eof 0 --input abc --dump-mem-on-exit prog.bf").unwrap();
+        assert!(matches!(opts, Subcommand::Smbf(SmbfOptions {
+            tape_mode: dtypes::TapeMode::Growable, cell_width: dtypes::CellWidth::U16, eof_behavior: dtypes::EofBehavior::Zero,
+            input: Some(_), dump_mem: true, ..
+        })));
+        assert!(parse_any("smbf").is_err());
+        assert!(parse_any("smbf --dialect ook prog.bf").is_err());
+        assert!(parse_any("smbf a.bf b.bf").is_err());
+        assert!(parse_any("smbf --input a --input-file b prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_serve () {
+        assert_eq!(parse_any("serve").unwrap(), Subcommand::Serve(ServeOptions {
+            host: String::from("127.0.0.1"),
+            port: 8080,
+            jobs: 4,
+            mem_size: DEFAULT_MEM_SIZE,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            max_steps: 10_000_000,
+            max_output: 65536,
+            timeout_ms: 5000,
+            max_body: 1 << 20,
+        }));
+        let opts = parse_any("serve --host 0.0.0.0 --port 3000 --jobs 8 --max-steps 500 --timeout-ms 100 --eof 0").unwrap();
+        assert!(matches!(opts, Subcommand::Serve(ServeOptions {
+            port: 3000, jobs: 8, max_steps: 500, timeout_ms: 100, eof_behavior: dtypes::EofBehavior::Zero, ref host, ..
+        }) if host == "0.0.0.0"));
+        assert!(parse_any("serve --port 70000").is_err());
+        assert!(parse_any("serve --max-steps 0").is_err());
+        assert!(parse_any("serve --jobs 0").is_err());
+        assert!(parse_any("serve --port").is_err());
+        assert!(parse_any("serve prog.bf").is_err());
+    }
+
+    #[test]
+    fn parse_args_completions () {
+        assert_eq!(parse_any("completions bash").unwrap(), Subcommand::Completions(Shell::Bash));
+        assert_eq!(parse_any("completions zsh").unwrap(), Subcommand::Completions(Shell::Zsh));
+        assert_eq!(parse_any("completions fish").unwrap(), Subcommand::Completions(Shell::Fish));
+        assert!(parse_any("completions").is_err());
+        assert!(parse_any("completions powershell").is_err());
+        assert!(parse_any("completions bash zsh").is_err());
+    }
+
+    #[test]
+    fn parse_args_env () {
+        let env = |vars: &[(&str, &str)]| -> Vec<(String, String)> {
+            vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+        };
+        let parse_env = |args: &str, vars: &[(&str, &str)]| parse_args(args.split_whitespace().map(String::from), env(vars).into_iter());
+        let vars = [("RUST_BFI_MEM_SIZE", "64"), ("RUST_BFI_EOF", "zero"), ("RUST_BFI_MAX_STEPS", "500"), ("RUST_BFI_TAPE", "growable")];
+        // the environment overrides the defaults
+        let Subcommand::Run(opts) = parse_env("prog.bf", &vars).unwrap() else { panic!("expected run options") };
+        assert_eq!((opts.mem_size, opts.eof_behavior, opts.tape_mode), (64, dtypes::EofBehavior::Zero, dtypes::TapeMode::Growable));
+        assert_eq!(opts.limits.max_steps, Some(500));
+        // and flags override the environment
+        let Subcommand::Run(opts) = parse_env("--eof error --max-steps 7 prog.bf", &vars).unwrap() else { panic!("expected run options") };
+        assert_eq!((opts.mem_size, opts.eof_behavior, opts.limits.max_steps), (64, dtypes::EofBehavior::Error, Some(7)));
+        // it applies to every subcommand taking the same settings
+        assert!(matches!(parse_env("diff prog.bf", &vars).unwrap(), Subcommand::Diff(DiffOptions {
+            mem_size: 64, eof_behavior: dtypes::EofBehavior::Zero, max_steps: 500, tape_mode: dtypes::TapeMode::Growable, ..
+        })));
+        assert!(matches!(parse_env("equiv a.bf b.bf --max-steps 9", &vars).unwrap(), Subcommand::Equiv(EquivOptions { mem_size: 64, max_steps: 9, .. })));
+        assert!(matches!(parse_env("test dir", &vars).unwrap(), Subcommand::Test(TestOptions { max_steps: Some(500), .. })));
+        assert!(matches!(parse_env("serve", &vars).unwrap(), Subcommand::Serve(ServeOptions { max_steps: 500, timeout_ms: 5000, .. })));
+        // including those only checking or printing a program
+        let vars = [("RUST_BFI_MEM_SIZE", "64"), ("RUST_BFI_DIALECT", "ook"), ("RUST_BFI_OPTIMIZE", "false")];
+        assert!(matches!(parse_env("check --bounds prog.bf", &vars).unwrap(), Subcommand::Check(CheckOptions {
+            mem_size: 64, dialect: dtypes::Dialect::Ook, ..
+        })));
+        assert!(matches!(parse_env("analyze prog.bf", &vars).unwrap(), Subcommand::Analyze(CheckOptions { dialect: dtypes::Dialect::Ook, .. })));
+        assert!(matches!(parse_env("ir --dialect pbrain prog.bf", &vars).unwrap(), Subcommand::Ir(IrOptions {
+            optimize: false, dialect: dtypes::Dialect::Pbrain, ..
+        })));
+        assert!(matches!(parse_env("compile --target c prog.bf", &vars).unwrap(), Subcommand::Compile(CompileOptions { optimize: false, .. })));
+        assert!(matches!(parse_env("obfuscate prog.bf", &[("RUST_BFI_MAX_STEPS", "40")]).unwrap(), Subcommand::Obfuscate(ObfuscateOptions {
+            max_steps: 40, ..
+        })));
+        // and those that only change how the results are shown
+        let vars = [("RUST_BFI_DIALECT", "ook"), ("RUST_BFI_OPTIMIZE", "false"), ("RUST_BFI_COLOR", "never"), ("RUST_BFI_OUTPUT_FORMAT", "json")];
+        let Subcommand::Run(opts) = parse_env("--color always prog.bf", &vars).unwrap() else { panic!("expected run options") };
+        assert_eq!((opts.dialect, opts.optimize), (dtypes::Dialect::Ook, false));
+        assert_eq!((opts.color, opts.output_format), (ColorChoice::Always, OutputFormat::Json));
+        // subcommands without the setting ignore it, as long as it is one
+        assert!(parse_env("fmt prog.bf", &vars).is_ok());
+        assert_eq!(parse_env("fmt prog.bf", &[("RUST_BFI_MEMSIZE", "64")]).unwrap_err(),
+                   "invalid environment variable RUST_BFI_MEMSIZE: unknown setting");
+        assert_eq!(parse_env("gen", &[("RUST_BFI_COLOR", "sometimes")]).unwrap_err(),
+                   "invalid environment variable RUST_BFI_COLOR: invalid --color value 'sometimes'");
+        let error = parse_env("prog.bf", &[("RUST_BFI_EOF", "sometimes")]).unwrap_err();
+        assert_eq!(error, "invalid environment variable RUST_BFI_EOF: invalid eof 'sometimes'");
+        assert!(parse_env("serve", &[("RUST_BFI_MAX_STEPS", "0")]).is_err());
+    }
+}
@@ -0,0 +1,779 @@
+/*
+    Module with backends that compile lowered programs into
+    source code for other languages, or a WebAssembly module
+*/
+
+
+use crate::dtypes;
+
+
+// languages that programs can be compiled to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Rust,
+    C,
+    Wasm,
+}
+
+
+// machine configuration baked into compiled programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub mem_size: usize,
+    pub cell_width: dtypes::CellWidth,
+    pub eof_behavior: dtypes::EofBehavior,
+}
+
+
+// compile lowered instructions into a standalone program for the target (source
+// code, or a binary module for wasm), pbrain procedures and brainfork threads
+// can't be compiled and a program stops with an error if it reaches one, the
+// `#` debug command writes to stderr except in wasm where it does nothing
+pub fn compile (ops: &[dtypes::Op], target: Target, config: &Config) -> Vec<u8> {
+    match target {
+        Target::Rust => compile_rust(ops, config).into_bytes(),
+        Target::C => compile_c(ops, config).into_bytes(),
+        Target::Wasm => compile_wasm(ops, config),
+    }
+}
+
+
+// builds up source code one indented line at a time
+struct Emitter {
+    src: String,
+    depth: usize,
+}
+
+
+impl Emitter {
+    fn new () -> Emitter {
+        Emitter { src: String::new(), depth: 0 }
+    }
+
+    // append a line at the current indentation level
+    fn line (&mut self, text: &str) {
+        if !text.is_empty() {
+            self.src.push_str(&"    ".repeat(self.depth));
+            self.src.push_str(text);
+        }
+        self.src.push('\n');
+    }
+
+    // append a line that opens a block and indent everything after it
+    fn open (&mut self, text: &str) {
+        self.line(text);
+        self.depth += 1;
+    }
+
+    // dedent and append a line that closes a block
+    fn close (&mut self, text: &str) {
+        self.depth -= 1;
+        self.line(text);
+    }
+}
+
+
+// name of the Rust integer type for a cell width
+fn rust_cell_type (cell_width: dtypes::CellWidth) -> &'static str {
+    match cell_width {
+        dtypes::CellWidth::U8 => "u8",
+        dtypes::CellWidth::U16 => "u16",
+        dtypes::CellWidth::U32 => "u32",
+    }
+}
+
+
+// a signed amount as the equivalent unsigned cell value (i.e. mod 2^width)
+fn cell_literal (n: i32, cell_width: dtypes::CellWidth) -> u32 {
+    (n as u32) & cell_width.max_value()
+}
+
+
+// index expression for the cell at an offset from the data pointer
+fn index_expr (offset: isize) -> String {
+    match offset {
+        0 => String::from("p"),
+        o if o < 0 => format!("p - {}", o.unsigned_abs()),
+        o => format!("p + {}", o),
+    }
+}
+
+
+// true if a program needs the Extended Type I storage cell
+fn uses_storage (ops: &[dtypes::Op]) -> bool {
+    ops.iter().any(|op| matches!(op.instr,
+        dtypes::Instr::Store | dtypes::Instr::Retrieve | dtypes::Instr::Xor | dtypes::Instr::And | dtypes::Instr::Or))
+}
+
+
+// name of the C fixed width integer type for a cell width
+fn c_cell_type (cell_width: dtypes::CellWidth) -> &'static str {
+    match cell_width {
+        dtypes::CellWidth::U8 => "uint8_t",
+        dtypes::CellWidth::U16 => "uint16_t",
+        dtypes::CellWidth::U32 => "uint32_t",
+    }
+}
+
+
+// compile into a Rust source file with a main() that runs the program on
+// stdin/stdout, moving off either end of memory panics like a strict tape
+pub fn compile_rust (ops: &[dtypes::Op], config: &Config) -> String {
+    let cell = rust_cell_type(config.cell_width);
+    let mut e = Emitter::new();
+    e.line("// generated by rust_bfi");
+    e.line("#![allow(unused)]");
+    e.line("");
+    e.line("use std::io::{self, Read, Write};");
+    e.line("");
+    e.open("fn read_byte (input: &mut impl Read) -> Option<u8> {");
+    e.line("let mut byte = [0_u8; 1];");
+    e.open("match input.read(&mut byte) {");
+    e.line("Ok(1) => Some(byte[0]),");
+    e.line("_ => None,");
+    e.close("}");
+    e.close("}");
+    e.line("");
+    e.open("fn main () {");
+    e.line(&format!("let mut mem: Vec<{}> = vec![0; {}];", cell, config.mem_size));
+    e.line("let mut p: usize = 0;");
+    e.line("let mut input = io::stdin().lock();");
+    e.line("let mut output = io::BufWriter::new(io::stdout().lock());");
+    if uses_storage(ops) {
+        e.line(&format!("let mut s: {} = 0;", cell));
+    }
+    for op in ops {
+        match op.instr {
+            dtypes::Instr::Add(n) if n < 0 => {
+                e.line(&format!("mem[p] = mem[p].wrapping_sub({});", cell_literal(-n, config.cell_width)));
+            },
+            dtypes::Instr::Add(n) => {
+                e.line(&format!("mem[p] = mem[p].wrapping_add({});", cell_literal(n, config.cell_width)));
+            },
+            dtypes::Instr::Move(n) if n < 0 => e.line(&format!("p -= {};", n.unsigned_abs())),
+            dtypes::Instr::Move(n) => e.line(&format!("p += {};", n)),
+            dtypes::Instr::OutputByte => e.line("output.write_all(&[mem[p] as u8]).unwrap();"),
+            dtypes::Instr::InputByte => {
+                // flush first so prompts show up before blocking on input
+                e.line("output.flush().unwrap();");
+                e.open("match read_byte(&mut input) {");
+                e.line(&format!("Some(byte) => mem[p] = byte as {},", cell));
+                e.line(&match config.eof_behavior {
+                    dtypes::EofBehavior::NoChange => String::from("None => {},"),
+                    dtypes::EofBehavior::Zero => String::from("None => mem[p] = 0,"),
+                    dtypes::EofBehavior::MinusOne => format!("None => mem[p] = {}::MAX,", cell),
+                    dtypes::EofBehavior::Error => {
+                        String::from("None => { eprintln!(\"error: input exhausted\"); std::process::exit(1) },")
+                    },
+                });
+                e.close("}");
+            },
+            dtypes::Instr::JumpIfZero(_) => e.open("while mem[p] != 0 {"),
+            dtypes::Instr::JumpIfNonZero(_) => e.close("}"),
+            dtypes::Instr::Clear => e.line("mem[p] = 0;"),
+            dtypes::Instr::AddTo(offset, factor) => {
+                // the loop this came from only touches the other cell if it runs at all
+                let i = index_expr(offset);
+                e.open("if mem[p] != 0 {");
+                e.line(&format!("mem[{}] = mem[{}].wrapping_add(mem[p].wrapping_mul({}));",
+                                i, i, cell_literal(factor, config.cell_width)));
+                e.close("}");
+            },
+            dtypes::Instr::AddAt(offset, n) if n < 0 => {
+                let i = index_expr(offset);
+                e.line(&format!("mem[{}] = mem[{}].wrapping_sub({});", i, i, cell_literal(-n, config.cell_width)));
+            },
+            dtypes::Instr::AddAt(offset, n) => {
+                let i = index_expr(offset);
+                e.line(&format!("mem[{}] = mem[{}].wrapping_add({});", i, i, cell_literal(n, config.cell_width)));
+            },
+            dtypes::Instr::ClearAt(offset) => e.line(&format!("mem[{}] = 0;", index_expr(offset))),
+            dtypes::Instr::OutputAt(offset) => e.line(&format!("output.write_all(&[mem[{}] as u8]).unwrap();", index_expr(offset))),
+            // running off the end panics like moving there would
+            dtypes::Instr::ScanRight => e.line("p += mem[p..].iter().position(|&c| c == 0).unwrap();"),
+            dtypes::Instr::ScanLeft => e.line("p = mem[..=p].iter().rposition(|&c| c == 0).unwrap();"),
+            dtypes::Instr::End => {
+                e.line("output.flush().unwrap();");
+                e.line("return;");
+            },
+            dtypes::Instr::Store => e.line("s = mem[p];"),
+            dtypes::Instr::Retrieve => e.line("mem[p] = s;"),
+            dtypes::Instr::ShiftRight => e.line("mem[p] >>= 1;"),
+            dtypes::Instr::ShiftLeft => e.line("mem[p] <<= 1;"),
+            dtypes::Instr::Not => e.line("mem[p] = !mem[p];"),
+            dtypes::Instr::Xor => e.line("mem[p] ^= s;"),
+            dtypes::Instr::And => e.line("mem[p] &= s;"),
+            dtypes::Instr::Or => e.line("mem[p] |= s;"),
+            dtypes::Instr::DefineProc(_) | dtypes::Instr::EndProc | dtypes::Instr::CallProc => {
+                e.line("panic!(\"pbrain procedures are not supported\");");
+            },
+            dtypes::Instr::Fork => e.line("panic!(\"brainfork threads are not supported\");"),
+            dtypes::Instr::Debug => {
+                e.line("output.flush().unwrap();");
+                e.line("eprint!(\"#  data pointer: {}  cells:\", p);");
+                e.open(&format!("for (i, c) in mem.iter().take({}).enumerate() {{", dtypes::HASH_DEBUG_CELLS));
+                e.line("if i == p { eprint!(\" [{}]\", c) } else { eprint!(\" {}\", c) }");
+                e.close("}");
+                e.line("eprintln!();");
+            },
+        };
+    }
+    e.line("output.flush().unwrap();");
+    e.close("}");
+    e.src
+}
+
+
+// compile into a portable C source file with a main() that runs the program on
+// stdin/stdout, unlike the Rust backend the data pointer is not bounds checked
+pub fn compile_c (ops: &[dtypes::Op], config: &Config) -> String {
+    let cell = c_cell_type(config.cell_width);
+    let mut e = Emitter::new();
+    e.line("/* generated by rust_bfi */");
+    e.line("#include <stdint.h>");
+    e.line("#include <stdio.h>");
+    e.line("");
+    e.line(&format!("static {} mem[{}];", cell, config.mem_size));
+    e.line("");
+    e.open("int main (void) {");
+    e.line("size_t p = 0;");
+    if ops.iter().any(|op| op.instr == dtypes::Instr::InputByte) {
+        e.line("int c;");
+    }
+    if uses_storage(ops) {
+        e.line(&format!("{} s = 0;", cell));
+    }
+    for op in ops {
+        match op.instr {
+            dtypes::Instr::Add(n) if n < 0 => e.line(&format!("mem[p] -= {}u;", cell_literal(-n, config.cell_width))),
+            dtypes::Instr::Add(n) => e.line(&format!("mem[p] += {}u;", cell_literal(n, config.cell_width))),
+            dtypes::Instr::Move(n) if n < 0 => e.line(&format!("p -= {};", n.unsigned_abs())),
+            dtypes::Instr::Move(n) => e.line(&format!("p += {};", n)),
+            dtypes::Instr::OutputByte => e.line("putchar((unsigned char)mem[p]);"),
+            dtypes::Instr::InputByte => {
+                // flush first so prompts show up before blocking on input
+                e.line("fflush(stdout);");
+                e.line("c = getchar();");
+                e.open("if (c != EOF) {");
+                e.line(&format!("mem[p] = ({})c;", cell));
+                match config.eof_behavior {
+                    dtypes::EofBehavior::NoChange => e.close("}"),
+                    dtypes::EofBehavior::Zero => {
+                        e.close("} else {");
+                        e.depth += 1;
+                        e.line("mem[p] = 0;");
+                        e.close("}");
+                    },
+                    dtypes::EofBehavior::MinusOne => {
+                        e.close("} else {");
+                        e.depth += 1;
+                        e.line(&format!("mem[p] = {}u;", config.cell_width.max_value()));
+                        e.close("}");
+                    },
+                    dtypes::EofBehavior::Error => {
+                        e.close("} else {");
+                        e.depth += 1;
+                        e.line("fprintf(stderr, \"error: input exhausted\\n\");");
+                        e.line("return 1;");
+                        e.close("}");
+                    },
+                };
+            },
+            dtypes::Instr::JumpIfZero(_) => e.open("while (mem[p]) {"),
+            dtypes::Instr::JumpIfNonZero(_) => e.close("}"),
+            dtypes::Instr::Clear => e.line("mem[p] = 0;"),
+            dtypes::Instr::AddTo(offset, factor) => {
+                // multiply as uint32_t so narrower cells are not promoted to
+                // (signed, overflowing) int
+                let i = index_expr(offset);
+                e.open("if (mem[p]) {");
+                e.line(&format!("mem[{}] += (uint32_t)mem[p] * {}u;", i, cell_literal(factor, config.cell_width)));
+                e.close("}");
+            },
+            dtypes::Instr::AddAt(offset, n) if n < 0 => {
+                e.line(&format!("mem[{}] -= {}u;", index_expr(offset), cell_literal(-n, config.cell_width)));
+            },
+            dtypes::Instr::AddAt(offset, n) => e.line(&format!("mem[{}] += {}u;", index_expr(offset), cell_literal(n, config.cell_width))),
+            dtypes::Instr::ClearAt(offset) => e.line(&format!("mem[{}] = 0;", index_expr(offset))),
+            dtypes::Instr::OutputAt(offset) => e.line(&format!("putchar((unsigned char)mem[{}]);", index_expr(offset))),
+            dtypes::Instr::ScanRight => e.line("while (mem[p]) p++;"),
+            dtypes::Instr::ScanLeft => e.line("while (mem[p]) p--;"),
+            dtypes::Instr::End => {
+                e.line("fflush(stdout);");
+                e.line("return 0;");
+            },
+            dtypes::Instr::Store => e.line("s = mem[p];"),
+            dtypes::Instr::Retrieve => e.line("mem[p] = s;"),
+            dtypes::Instr::ShiftRight => e.line("mem[p] >>= 1;"),
+            dtypes::Instr::ShiftLeft => e.line("mem[p] <<= 1;"),
+            dtypes::Instr::Not => e.line("mem[p] = ~mem[p];"),
+            dtypes::Instr::Xor => e.line("mem[p] ^= s;"),
+            dtypes::Instr::And => e.line("mem[p] &= s;"),
+            dtypes::Instr::Or => e.line("mem[p] |= s;"),
+            dtypes::Instr::DefineProc(_) | dtypes::Instr::EndProc | dtypes::Instr::CallProc => {
+                e.line("fprintf(stderr, \"error: pbrain procedures are not supported\\n\");");
+                e.line("return 1;");
+            },
+            dtypes::Instr::Fork => {
+                e.line("fprintf(stderr, \"error: brainfork threads are not supported\\n\");");
+                e.line("return 1;");
+            },
+            dtypes::Instr::Debug => {
+                e.line("fflush(stdout);");
+                e.line("fprintf(stderr, \"#  data pointer: %lu  cells:\", (unsigned long)p);");
+                e.open(&format!("for (size_t i = 0; i < {} && i < {}; i++) {{", dtypes::HASH_DEBUG_CELLS, config.mem_size));
+                e.line("fprintf(stderr, i == p ? \" [%lu]\" : \" %lu\", (unsigned long)mem[i]);");
+                e.close("}");
+                e.line("fprintf(stderr, \"\\n\");");
+            },
+        };
+    }
+    e.line("fflush(stdout);");
+    e.line("return 0;");
+    e.close("}");
+    e.src
+}
+
+// WebAssembly opcodes used by compile_wasm()
+mod op {
+    pub const UNREACHABLE: u8 = 0x00;
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const ELSE: u8 = 0x05;
+    pub const END: u8 = 0x0B;
+    pub const BR: u8 = 0x0C;
+    pub const BR_IF: u8 = 0x0D;
+    pub const RETURN: u8 = 0x0F;
+    pub const CALL: u8 = 0x10;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_LT_S: u8 = 0x48;
+    pub const I32_ADD: u8 = 0x6A;
+    pub const I32_MUL: u8 = 0x6C;
+    pub const I32_AND: u8 = 0x71;
+    pub const I32_OR: u8 = 0x72;
+    pub const I32_XOR: u8 = 0x73;
+    pub const I32_SHL: u8 = 0x74;
+    pub const I32_SHR_U: u8 = 0x76;
+    // block type of a block that leaves nothing on the stack
+    pub const EMPTY: u8 = 0x40;
+    pub const I32: u8 = 0x7F;
+}
+
+
+// append an unsigned LEB128 number
+fn leb_u32 (out: &mut Vec<u8>, mut n: u32) {
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+
+// append a signed LEB128 number
+fn leb_i32 (out: &mut Vec<u8>, mut n: i32) {
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        // done once the rest is just the sign of what's been written
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+
+// append a length prefixed string
+fn wasm_name (out: &mut Vec<u8>, name: &str) {
+    leb_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+
+// append a section with its id and size
+fn wasm_section (out: &mut Vec<u8>, id: u8, body: &[u8]) {
+    out.push(id);
+    leb_u32(out, body.len() as u32);
+    out.extend_from_slice(body);
+}
+
+
+// builds up the body of the run function for compile_wasm()
+struct WasmEmitter {
+    code: Vec<u8>,
+    // bytes per cell
+    width: i32,
+    // load and store opcodes for a cell along with the alignment (log 2)
+    load: u8,
+    store: u8,
+    align: u32,
+}
+
+
+impl WasmEmitter {
+    // indices of the locals in the run function
+    const P: u32 = 0;
+    const S: u32 = 1;
+    const C: u32 = 2;
+    // indices of the imported functions
+    const READ_BYTE: u32 = 0;
+    const WRITE_BYTE: u32 = 1;
+
+    fn new (cell_width: dtypes::CellWidth) -> WasmEmitter {
+        let (width, load, store, align) = match cell_width {
+            // i32.load8_u, i32.store8
+            dtypes::CellWidth::U8 => (1, 0x2D, 0x3A, 0),
+            // i32.load16_u, i32.store16
+            dtypes::CellWidth::U16 => (2, 0x2F, 0x3B, 1),
+            // i32.load, i32.store
+            dtypes::CellWidth::U32 => (4, 0x28, 0x36, 2),
+        };
+        WasmEmitter { code: Vec::new(), width, load, store, align }
+    }
+
+    fn op (&mut self, opcode: u8) {
+        self.code.push(opcode);
+    }
+
+    // an opcode that takes an index (local, function or branch depth)
+    fn op_index (&mut self, opcode: u8, index: u32) {
+        self.code.push(opcode);
+        leb_u32(&mut self.code, index);
+    }
+
+    fn i32_const (&mut self, n: i32) {
+        self.code.push(op::I32_CONST);
+        leb_i32(&mut self.code, n);
+    }
+
+    // push the address of the cell at an offset from the data pointer
+    fn address (&mut self, offset: isize) {
+        self.op_index(op::LOCAL_GET, WasmEmitter::P);
+        if offset != 0 {
+            self.i32_const((offset as i32).wrapping_mul(self.width));
+            self.op(op::I32_ADD);
+        }
+    }
+
+    // load from (store to) the address on the stack
+    fn memory (&mut self, opcode: u8) {
+        self.code.push(opcode);
+        leb_u32(&mut self.code, self.align);
+        leb_u32(&mut self.code, 0);
+    }
+
+    // push the value of the cell at the data pointer
+    fn load_cell (&mut self) {
+        self.address(0);
+        self.memory(self.load);
+    }
+
+    // set the cell at the data pointer to the value computed by value()
+    fn store_cell (&mut self, value: impl FnOnce(&mut WasmEmitter)) {
+        self.address(0);
+        value(self);
+        self.memory(self.store);
+    }
+
+    // combine the cell at the data pointer with a constant or the storage cell
+    fn update_cell (&mut self, opcode: u8, operand: impl FnOnce(&mut WasmEmitter)) {
+        self.store_cell(|e| {
+            e.load_cell();
+            operand(e);
+            e.op(opcode);
+        });
+    }
+}
+
+
+// compile into a binary WebAssembly module that exports its memory and a run()
+// function that runs the program, input and output go through the functions
+// env.read_byte() -> i32 (a byte, or -1 at the end of the input) and
+// env.write_byte(i32) it imports, like the C backend the data pointer is not
+// bounds checked (beyond wasm's own checks on memory accesses)
+pub fn compile_wasm (ops: &[dtypes::Op], config: &Config) -> Vec<u8> {
+    let mut e = WasmEmitter::new(config.cell_width);
+    for op in ops {
+        match op.instr {
+            dtypes::Instr::Add(n) => e.update_cell(op::I32_ADD, |e| e.i32_const(n)),
+            dtypes::Instr::Move(n) => {
+                e.address(n);
+                e.op_index(op::LOCAL_SET, WasmEmitter::P);
+            },
+            dtypes::Instr::OutputByte => {
+                e.load_cell();
+                e.op_index(op::CALL, WasmEmitter::WRITE_BYTE);
+            },
+            dtypes::Instr::InputByte => {
+                e.op_index(op::CALL, WasmEmitter::READ_BYTE);
+                e.op_index(op::LOCAL_SET, WasmEmitter::C);
+                e.op_index(op::LOCAL_GET, WasmEmitter::C);
+                e.i32_const(0);
+                e.op(op::I32_LT_S);
+                e.code.extend_from_slice(&[op::IF, op::EMPTY]);
+                match config.eof_behavior {
+                    dtypes::EofBehavior::NoChange => {},
+                    dtypes::EofBehavior::Zero => e.store_cell(|e| e.i32_const(0)),
+                    // stores truncate to the cell width
+                    dtypes::EofBehavior::MinusOne => e.store_cell(|e| e.i32_const(-1)),
+                    dtypes::EofBehavior::Error => e.op(op::UNREACHABLE),
+                };
+                e.op(op::ELSE);
+                e.store_cell(|e| e.op_index(op::LOCAL_GET, WasmEmitter::C));
+                e.op(op::END);
+            },
+            // the loop is a block (to break out of) around a loop (to branch
+            // back to the top of)
+            dtypes::Instr::JumpIfZero(_) => {
+                e.code.extend_from_slice(&[op::BLOCK, op::EMPTY, op::LOOP, op::EMPTY]);
+                e.load_cell();
+                e.op(op::I32_EQZ);
+                e.op_index(op::BR_IF, 1);
+            },
+            dtypes::Instr::JumpIfNonZero(_) => {
+                e.op_index(op::BR, 0);
+                e.code.extend_from_slice(&[op::END, op::END]);
+            },
+            dtypes::Instr::Clear => e.store_cell(|e| e.i32_const(0)),
+            dtypes::Instr::AddTo(offset, factor) => {
+                // the loop this came from only touches the other cell if it runs at all
+                e.load_cell();
+                e.code.extend_from_slice(&[op::IF, op::EMPTY]);
+                e.address(offset);
+                e.address(offset);
+                e.memory(e.load);
+                e.load_cell();
+                e.i32_const(factor);
+                e.op(op::I32_MUL);
+                e.op(op::I32_ADD);
+                e.memory(e.store);
+                e.op(op::END);
+            },
+            dtypes::Instr::AddAt(offset, n) => {
+                e.address(offset);
+                e.address(offset);
+                e.memory(e.load);
+                e.i32_const(n);
+                e.op(op::I32_ADD);
+                e.memory(e.store);
+            },
+            dtypes::Instr::ClearAt(offset) => {
+                e.address(offset);
+                e.i32_const(0);
+                e.memory(e.store);
+            },
+            dtypes::Instr::OutputAt(offset) => {
+                e.address(offset);
+                e.memory(e.load);
+                e.op_index(op::CALL, WasmEmitter::WRITE_BYTE);
+            },
+            dtypes::Instr::ScanRight | dtypes::Instr::ScanLeft => {
+                e.code.extend_from_slice(&[op::BLOCK, op::EMPTY, op::LOOP, op::EMPTY]);
+                e.load_cell();
+                e.op(op::I32_EQZ);
+                e.op_index(op::BR_IF, 1);
+                e.address(if op.instr == dtypes::Instr::ScanRight { 1 } else { -1 });
+                e.op_index(op::LOCAL_SET, WasmEmitter::P);
+                e.op_index(op::BR, 0);
+                e.code.extend_from_slice(&[op::END, op::END]);
+            },
+            dtypes::Instr::End => e.op(op::RETURN),
+            dtypes::Instr::Store => {
+                e.load_cell();
+                e.op_index(op::LOCAL_SET, WasmEmitter::S);
+            },
+            dtypes::Instr::Retrieve => e.store_cell(|e| e.op_index(op::LOCAL_GET, WasmEmitter::S)),
+            dtypes::Instr::ShiftRight => e.update_cell(op::I32_SHR_U, |e| e.i32_const(1)),
+            dtypes::Instr::ShiftLeft => e.update_cell(op::I32_SHL, |e| e.i32_const(1)),
+            dtypes::Instr::Not => e.update_cell(op::I32_XOR, |e| e.i32_const(-1)),
+            dtypes::Instr::Xor => e.update_cell(op::I32_XOR, |e| e.op_index(op::LOCAL_GET, WasmEmitter::S)),
+            dtypes::Instr::And => e.update_cell(op::I32_AND, |e| e.op_index(op::LOCAL_GET, WasmEmitter::S)),
+            dtypes::Instr::Or => e.update_cell(op::I32_OR, |e| e.op_index(op::LOCAL_GET, WasmEmitter::S)),
+            dtypes::Instr::DefineProc(_) | dtypes::Instr::EndProc | dtypes::Instr::CallProc | dtypes::Instr::Fork => e.op(op::UNREACHABLE),
+            // there's nowhere to show anything, so # does nothing
+            dtypes::Instr::Debug => {},
+        };
+    }
+    e.op(op::END);
+
+    let mut module: Vec<u8> = b"\0asm".to_vec();
+    module.extend_from_slice(&[1, 0, 0, 0]);
+    // types: read_byte, write_byte and run
+    let mut types = vec![3];
+    types.extend_from_slice(&[0x60, 0, 1, op::I32]);
+    types.extend_from_slice(&[0x60, 1, op::I32, 0]);
+    types.extend_from_slice(&[0x60, 0, 0]);
+    wasm_section(&mut module, 1, &types);
+    let mut imports = vec![2];
+    for (index, name) in ["read_byte", "write_byte"].iter().enumerate() {
+        wasm_name(&mut imports, "env");
+        wasm_name(&mut imports, name);
+        imports.extend_from_slice(&[0, index as u8]);
+    }
+    wasm_section(&mut module, 2, &imports);
+    // run() has the third type
+    wasm_section(&mut module, 3, &[1, 2]);
+    // enough 64KiB pages for every cell, with no maximum
+    let bytes = config.mem_size as u64 * e.width as u64;
+    let pages = bytes.div_ceil(65536).clamp(1, 65536) as u32;
+    let mut memory = vec![1, 0];
+    leb_u32(&mut memory, pages);
+    wasm_section(&mut module, 5, &memory);
+    let mut exports = vec![2];
+    wasm_name(&mut exports, "run");
+    exports.extend_from_slice(&[0, 2]);
+    wasm_name(&mut exports, "memory");
+    exports.extend_from_slice(&[2, 0]);
+    wasm_section(&mut module, 7, &exports);
+    // one function with three i32 locals: p, s and c
+    let mut body = vec![1, 3, op::I32];
+    body.extend_from_slice(&e.code);
+    let mut code = vec![1];
+    leb_u32(&mut code, body.len() as u32);
+    code.extend_from_slice(&body);
+    wasm_section(&mut module, 10, &code);
+    module
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{optimizer, parsing};
+
+    fn config (cell_width: dtypes::CellWidth) -> Config {
+        Config { mem_size: 100, cell_width, eof_behavior: dtypes::EofBehavior::NoChange }
+    }
+
+    #[test]
+    fn compile_rust_program () {
+        let ops = optimizer::optimize(&parsing::parse_program("+++[>++<-]>.,<--")).unwrap();
+        let src = compile_rust(&ops, &config(dtypes::CellWidth::U8));
+        assert!(src.contains("let mut mem: Vec<u8> = vec![0; 100];"));
+        assert!(src.contains("    mem[p] = mem[p].wrapping_add(3);\n"));
+        assert!(src.contains("    if mem[p] != 0 {\n        mem[p + 1] = mem[p + 1].wrapping_add(mem[p].wrapping_mul(2));\n    }\n"));
+        assert!(src.contains("    mem[p] = 0;\n    p += 1;\n"));
+        assert!(src.contains("output.write_all(&[mem[p] as u8]).unwrap();"));
+        assert!(src.contains("None => {},"));
+        assert!(src.contains("    p -= 1;\n    mem[p] = mem[p].wrapping_sub(2);\n"));
+        // moves there and back are offset-addressed
+        let ops = optimizer::optimize(&parsing::parse_program(">-<.<.>")).unwrap();
+        let src = compile_rust(&ops, &config(dtypes::CellWidth::U8));
+        assert!(src.contains("    mem[p + 1] = mem[p + 1].wrapping_sub(1);\n"));
+        assert!(src.contains("    output.write_all(&[mem[p - 1] as u8]).unwrap();\n"));
+        assert!(!src.contains("p += 1;"));
+        let ops = optimizer::optimize(&parsing::parse_program("[<]")).unwrap();
+        let src = compile_rust(&ops, &config(dtypes::CellWidth::U8));
+        assert!(src.contains("    while mem[p] != 0 {\n        p = mem[..=p].iter().rposition(|&c| c == 0).unwrap();\n    }\n"));
+    }
+
+    #[test]
+    fn compile_rust_loops_and_widths () {
+        let ops = optimizer::optimize(&parsing::parse_program("+[>[-<-->]<]")).unwrap();
+        let src = compile_rust(&ops, &config(dtypes::CellWidth::U16));
+        assert!(src.contains("let mut mem: Vec<u16>"));
+        assert!(src.contains("    while mem[p] != 0 {\n        p += 1;\n"));
+        // factor of -2 as a 16 bit cell value
+        assert!(src.contains("mem[p - 1] = mem[p - 1].wrapping_add(mem[p].wrapping_mul(65534));"));
+        assert!(src.contains("        p -= 1;\n    }\n"));
+    }
+
+    #[test]
+    fn compile_c_program () {
+        let ops = optimizer::optimize(&parsing::parse_program("+++[>++<-]>.<--[>-<-]")).unwrap();
+        let mut cfg = config(dtypes::CellWidth::U16);
+        cfg.eof_behavior = dtypes::EofBehavior::MinusOne;
+        let src = compile_c(&ops, &cfg);
+        assert!(src.contains("static uint16_t mem[100];"));
+        assert!(src.contains("    mem[p] += 3u;\n"));
+        assert!(src.contains("    if (mem[p]) {\n        mem[p + 1] += (uint32_t)mem[p] * 2u;\n    }\n    mem[p] = 0;\n"));
+        assert!(src.contains("    putchar((unsigned char)mem[p + 1]);\n    mem[p] -= 2u;\n"));
+        assert!(src.contains("mem[p + 1] += (uint32_t)mem[p] * 65535u;"));
+        let ops = optimizer::optimize(&parsing::parse_program("+[,]")).unwrap();
+        let src = compile_c(&ops, &cfg);
+        assert!(src.contains("    while (mem[p]) {\n        fflush(stdout);\n"));
+        assert!(src.contains("        } else {\n            mem[p] = 65535u;\n        }\n    }\n"));
+    }
+
+    #[test]
+    fn compile_extended () {
+        let ops = optimizer::optimize(&parsing::parse_dialect("+$}~^@.", dtypes::Dialect::Extended)).unwrap();
+        let src = compile_rust(&ops, &config(dtypes::CellWidth::U8));
+        assert!(src.contains("let mut s: u8 = 0;"));
+        assert!(src.contains("    s = mem[p];\n    mem[p] >>= 1;\n    mem[p] = !mem[p];\n    mem[p] ^= s;\n"));
+        assert!(src.contains("    output.flush().unwrap();\n    return;\n"));
+        let src = compile_c(&ops, &config(dtypes::CellWidth::U8));
+        assert!(src.contains("    uint8_t s = 0;\n"));
+        assert!(src.contains("    mem[p] = ~mem[p];\n"));
+        assert!(src.contains("    fflush(stdout);\n    return 0;\n    putchar"));
+        // plain programs don't get a storage cell
+        let ops = optimizer::optimize(&parsing::parse_program("+.")).unwrap();
+        assert!(!compile_c(&ops, &config(dtypes::CellWidth::U8)).contains(" s = 0;"));
+    }
+
+    #[test]
+    fn compile_hash_debug () {
+        let tokens = parsing::tokenize_with_debug("+#", dtypes::Dialect::Brainfuck, true);
+        let cmds: Vec<dtypes::Command> = tokens.iter().map(|token| token.command).collect();
+        let ops = optimizer::optimize(&cmds).unwrap();
+        let src = compile_rust(&ops, &config(dtypes::CellWidth::U8));
+        assert!(src.contains("    for (i, c) in mem.iter().take(16).enumerate() {\n"));
+        let src = compile_c(&ops, &config(dtypes::CellWidth::U8));
+        assert!(src.contains("    for (size_t i = 0; i < 16 && i < 100; i++) {\n"));
+    }
+
+    #[test]
+    fn leb128_encoding () {
+        let encode_u = |n| { let mut out = Vec::new(); leb_u32(&mut out, n); out };
+        let encode_i = |n| { let mut out = Vec::new(); leb_i32(&mut out, n); out };
+        assert_eq!(encode_u(0), [0]);
+        assert_eq!(encode_u(127), [0x7F]);
+        assert_eq!(encode_u(624485), [0xE5, 0x8E, 0x26]);
+        assert_eq!(encode_i(63), [0x3F]);
+        assert_eq!(encode_i(64), [0xC0, 0x00]);
+        assert_eq!(encode_i(-1), [0x7F]);
+        assert_eq!(encode_i(-64), [0x40]);
+        assert_eq!(encode_i(-123456), [0xC0, 0xBB, 0x78]);
+    }
+
+    #[test]
+    fn compile_wasm_module () {
+        let ops = optimizer::optimize(&parsing::parse_program("+.")).unwrap();
+        let module = compile(&ops, Target::Wasm, &config(dtypes::CellWidth::U8));
+        assert!(module.starts_with(b"\0asm\x01\0\0\0"));
+        // imports and exports by name, and one page of memory
+        for name in [&b"\x03env\x09read_byte"[..], b"\x03env\x0awrite_byte", b"\x03run\0\x02", b"\x06memory\x02\0"] {
+            assert!(module.windows(name.len()).any(|w| w == name), "{:?}", name);
+        }
+        assert!(module.windows(5).any(|w| w == [5, 3, 1, 0, 1]));
+        // locals, add 1 to the cell (load8_u/store8), write it and end
+        assert!(module.ends_with(&[
+            1, 3, 0x7F,
+            0x20, 0, 0x20, 0, 0x2D, 0, 0, 0x41, 1, 0x6A, 0x3A, 0, 0,
+            0x20, 0, 0x2D, 0, 0, 0x10, 1,
+            0x0B,
+        ]));
+        // 16 bit cells, moves step two bytes and memory grows to fit
+        let ops = optimizer::optimize(&parsing::parse_program(">>>")).unwrap();
+        let mut cfg = config(dtypes::CellWidth::U16);
+        cfg.mem_size = 40000;
+        let module = compile(&ops, Target::Wasm, &cfg);
+        assert!(module.windows(5).any(|w| w == [5, 3, 1, 0, 2]));
+        assert!(module.ends_with(&[0x20, 0, 0x41, 6, 0x6A, 0x21, 0, 0x0B]));
+        // add to the cell at an offset without moving
+        let ops = optimizer::optimize(&parsing::parse_program(">+<")).unwrap();
+        let module = compile(&ops, Target::Wasm, &config(dtypes::CellWidth::U8));
+        assert!(module.ends_with(&[0x20, 0, 0x41, 1, 0x6A, 0x20, 0, 0x41, 1, 0x6A, 0x2D, 0, 0, 0x41, 1, 0x6A, 0x3A, 0, 0, 0x0B]));
+    }
+}
@@ -0,0 +1,300 @@
+/*
+    Module for writing shell completion scripts, e.g.
+
+        rust_bfi completions bash > /etc/bash_completion.d/rust_bfi
+
+    the subcommands and their flags are read from the usage text rather than
+    listed again here, so a flag added to the usage can be completed without
+    touching this module: each line of the usage is a subcommand, anything
+    starting with - is a flag, and the word after a flag is its value, a
+    list of choices (strict|wrapping) or a placeholder in capitals (N, FILE)
+*/
+
+
+use crate::cli;
+
+
+// what a flag (or a subcommand's positional arguments) takes
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    // nothing, the flag is a switch
+    Nothing,
+    // some text that can't be guessed, e.g. a number
+    Text,
+    File,
+    Dir,
+    Choices(Vec<String>),
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Flag {
+    name: String,
+    value: Value,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Command {
+    name: String,
+    flags: Vec<Flag>,
+    // what the arguments that aren't flags are
+    positional: Value,
+}
+
+
+// the value a word after a flag (or on its own) describes, with any brackets
+// and repeat dots around it removed
+fn value (word: &str) -> Value {
+    let word = word.trim_matches(|c| matches!(c, '[' | ']' | '<' | '>' | '.'));
+    if word.contains('|') {
+        Value::Choices(word.split('|').map(String::from).collect())
+    } else if word.contains("FILE") || word.ends_with(".bf") || word.ends_with(".bfr") {
+        Value::File
+    } else if word.contains("DIR") || word == "dir" {
+        Value::Dir
+    } else {
+        Value::Text
+    }
+}
+
+
+// every subcommand in the usage text with its flags, run first
+fn commands () -> Vec<Command> {
+    let mut commands: Vec<Command> = Vec::new();
+    for line in cli::USAGE.lines() {
+        let line = line.trim().trim_start_matches("usage:").trim_start();
+        let mut words = line.split_whitespace().skip(1).peekable();
+        let name = match words.peek() {
+            Some(&"[run]") => String::from("run"),
+            Some(word) if !word.starts_with(['[', '<', '-']) => word.to_string(),
+            _ => continue,
+        };
+        words.next();
+        let mut command = Command { name, flags: Vec::new(), positional: Value::Nothing };
+        while let Some(word) = words.next() {
+            let bare = word.trim_matches(|c| matches!(c, '[' | ']' | '<' | '>' | '.' | '|'));
+            if word == "[run" {
+                // takes the run options
+                words.next();
+                command.flags = commands.first().map(|run| run.flags.clone()).unwrap_or_default();
+            } else if bare.starts_with('-') {
+                // a value follows unless the flag's brackets close straight after it
+                let takes_value = !word.ends_with(']') && words.peek().is_some_and(|next| {
+                    let next = next.trim_start_matches(['[', '<']);
+                    !next.starts_with('-') && !next.starts_with('|')
+                });
+                let value = match takes_value {
+                    true => words.next().map_or(Value::Text, value),
+                    false => Value::Nothing,
+                };
+                if !command.flags.iter().any(|flag| flag.name == bare) {
+                    command.flags.push(Flag { name: bare.to_string(), value });
+                }
+            } else if bare.is_empty() {
+                continue;
+            } else if command.positional == Value::Nothing {
+                command.positional = value(word);
+            }
+        }
+        commands.push(command);
+    }
+    commands
+}
+
+
+// the words of a list of choices, for a shell to complete
+fn choices (value: &Value) -> String {
+    match value {
+        Value::Choices(choices) => choices.join(" "),
+        _ => String::new(),
+    }
+}
+
+
+// a bash script completing with `complete -F`
+fn bash (commands: &[Command]) -> String {
+    let names: Vec<&str> = commands.iter().map(|command| command.name.as_str()).collect();
+    let mut out = String::from("# bash completion for rust_bfi\n_rust_bfi () {\n");
+    out.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\" command=run\n");
+    out.push_str(&format!("    case \"${{COMP_WORDS[1]}}\" in\n        {})\n            command=\"${{COMP_WORDS[1]}}\" ;;\n    esac\n", names.join("|")));
+    out.push_str("    case \"$command:$prev\" in\n");
+    for command in commands {
+        for flag in &command.flags {
+            let reply = match &flag.value {
+                Value::Nothing => continue,
+                Value::Text => String::from("COMPREPLY=()"),
+                Value::File => String::from("COMPREPLY=($(compgen -f -- \"$cur\"))"),
+                Value::Dir => String::from("COMPREPLY=($(compgen -d -- \"$cur\"))"),
+                choices @ Value::Choices(_) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", self::choices(choices)),
+            };
+            out.push_str(&format!("        {}:{}) {}; return ;;\n", command.name, flag.name, reply));
+        }
+    }
+    out.push_str("    esac\n    if [[ \"$cur\" == -* ]]; then\n        case \"$command\" in\n");
+    for command in commands {
+        let flags: Vec<&str> = command.flags.iter().map(|flag| flag.name.as_str()).collect();
+        out.push_str(&format!("            {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) ;;\n", command.name, flags.join(" ")));
+    }
+    out.push_str("        esac\n    elif [[ $COMP_CWORD -eq 1 ]]; then\n");
+    out.push_str(&format!("        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\") $(compgen -f -- \"$cur\"))\n", names.join(" ")));
+    out.push_str("    else\n        case \"$command\" in\n");
+    for command in commands {
+        let reply = match &command.positional {
+            Value::Nothing | Value::Text => String::from("COMPREPLY=()"),
+            Value::File => String::from("COMPREPLY=($(compgen -f -- \"$cur\"))"),
+            Value::Dir => String::from("COMPREPLY=($(compgen -d -- \"$cur\"))"),
+            choices @ Value::Choices(_) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", self::choices(choices)),
+        };
+        out.push_str(&format!("            {}) {} ;;\n", command.name, reply));
+    }
+    out.push_str("        esac\n    fi\n}\ncomplete -o filenames -F _rust_bfi rust_bfi\n");
+    out
+}
+
+
+// what zsh's _arguments should complete a value with
+fn zsh_action (value: &Value) -> String {
+    match value {
+        Value::Nothing | Value::Text => String::from(" "),
+        Value::File => String::from("_files"),
+        Value::Dir => String::from("_files -/"),
+        Value::Choices(_) => format!("({})", choices(value)),
+    }
+}
+
+
+// a zsh script for the completion system, using _arguments for each subcommand
+fn zsh (commands: &[Command]) -> String {
+    let names: Vec<&str> = commands.iter().map(|command| command.name.as_str()).collect();
+    let mut out = String::from("#compdef rust_bfi\n\n_rust_bfi () {\n");
+    out.push_str("    if (( CURRENT == 2 )) && [[ $words[2] != -* ]]; then\n");
+    out.push_str(&format!("        _alternative 'subcommands:subcommand:({})' 'files:program:_files'\n        return\n    fi\n", names.join(" ")));
+    out.push_str("    local command=run\n    case $words[2] in\n");
+    out.push_str(&format!("        {})\n            command=$words[2]\n            shift words\n            (( CURRENT-- )) ;;\n    esac\n", names.join("|")));
+    out.push_str("    case $command in\n");
+    for command in commands {
+        out.push_str(&format!("        {})\n            _arguments", command.name));
+        for flag in &command.flags {
+            match flag.value {
+                Value::Nothing => out.push_str(&format!(" \\\n                '*{}'", flag.name)),
+                _ => out.push_str(&format!(" \\\n                '*{}:value:{}'", flag.name, zsh_action(&flag.value))),
+            };
+        }
+        if command.positional != Value::Nothing {
+            out.push_str(&format!(" \\\n                '*:argument:{}'", zsh_action(&command.positional)));
+        }
+        out.push_str(" ;;\n");
+    }
+    out.push_str("    esac\n}\n\n_rust_bfi \"$@\"\n");
+    out
+}
+
+
+// a fish script of complete commands
+fn fish (commands: &[Command]) -> String {
+    let names: Vec<&str> = commands.iter().map(|command| command.name.as_str()).collect();
+    let mut out = String::from("# fish completion for rust_bfi\n");
+    out.push_str(&format!("complete -c rust_bfi -n __fish_use_subcommand -a '{}'\n", names.join(" ")));
+    for command in commands {
+        // running is the default, so its flags are there until another subcommand is given
+        let condition = match command.name.as_str() {
+            "run" => format!("not __fish_seen_subcommand_from {}", names.iter().filter(|&&name| name != "run").copied().collect::<Vec<_>>().join(" ")),
+            name => format!("__fish_seen_subcommand_from {}", name),
+        };
+        for flag in &command.flags {
+            let name = match flag.name.strip_prefix("--") {
+                Some(long) => format!("-l {}", long),
+                None => format!("-s {}", flag.name.trim_start_matches('-')),
+            };
+            let value = match &flag.value {
+                Value::Nothing => String::new(),
+                Value::Text => String::from(" -x"),
+                Value::File | Value::Dir => String::from(" -r -F"),
+                Value::Choices(_) => format!(" -x -a '{}'", choices(&flag.value)),
+            };
+            out.push_str(&format!("complete -c rust_bfi -n '{}' {}{}\n", condition, name, value));
+        }
+        if let Value::Choices(_) = command.positional {
+            out.push_str(&format!("complete -c rust_bfi -n '{}' -x -a '{}'\n", condition, choices(&command.positional)));
+        }
+    }
+    out
+}
+
+
+// the completion script for a shell
+pub fn script (shell: cli::Shell) -> String {
+    let commands = commands();
+    match shell {
+        cli::Shell::Bash => bash(&commands),
+        cli::Shell::Zsh => zsh(&commands),
+        cli::Shell::Fish => fish(&commands),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn command (name: &str) -> Command {
+        commands().into_iter().find(|command| command.name == name).unwrap()
+    }
+
+    fn flag (subcommand: &str, name: &str) -> Option<Value> {
+        command(subcommand).flags.into_iter().find(|flag| flag.name == name).map(|flag| flag.value)
+    }
+
+    #[test]
+    fn completions_read_usage () {
+        let names: Vec<String> = commands().into_iter().map(|command| command.name).collect();
+        assert_eq!(names[0], "run");
+        for name in ["filter", "compile", "test", "diff", "equiv", "gen", "serve", "completions"] {
+            assert!(names.iter().any(|n| n == name), "{} missing from {:?}", name, names);
+        }
+        // every subcommand listed is one the parser knows, rather than a program to run
+        for name in names.iter().filter(|&name| name != "run" && name != "filter") {
+            let parsed = cli::parse_args([name.clone()].into_iter(), std::iter::empty());
+            assert!(!matches!(parsed, Ok(cli::Subcommand::Run(_))), "{}", name);
+        }
+        let strings = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+        assert_eq!(flag("run", "--tape"), Some(Value::Choices(strings(&["strict", "wrapping", "growable", "bidirectional"]))));
+        assert_eq!(flag("run", "--sparse"), Some(Value::Nothing));
+        assert_eq!(flag("run", "--max-steps"), Some(Value::Text));
+        assert_eq!(flag("run", "--config"), Some(Value::File));
+        assert_eq!(flag("run", "--input-random"), Some(Value::Nothing));
+        assert_eq!(flag("run", "--seed"), Some(Value::Text));
+        assert_eq!(flag("run", "--break"), Some(Value::Text));
+        assert_eq!(flag("run", "--tui"), Some(Value::Nothing));
+        assert_eq!(flag("filter", "--eof"), flag("run", "--eof"));
+        assert_eq!(flag("compile", "-o"), Some(Value::File));
+        assert_eq!(flag("equiv", "--inputs"), Some(Value::Dir));
+        assert_eq!(flag("encode", "--file"), Some(Value::File));
+        assert_eq!(command("test").positional, Value::Dir);
+        assert_eq!(command("run").positional, Value::File);
+        assert_eq!(command("gen").positional, Value::Nothing);
+        assert_eq!(command("completions").positional, Value::Choices(strings(&["bash", "zsh", "fish"])));
+    }
+
+    #[test]
+    fn completions_scripts () {
+        let bash = script(cli::Shell::Bash);
+        assert!(bash.contains("complete -o filenames -F _rust_bfi rust_bfi"));
+        assert!(bash.contains("run:--tape) COMPREPLY=($(compgen -W \"strict wrapping growable bidirectional\" -- \"$cur\")); return ;;"));
+        assert!(bash.contains("equiv:--inputs) COMPREPLY=($(compgen -d -- \"$cur\")); return ;;"));
+        let zsh = script(cli::Shell::Zsh);
+        assert!(zsh.starts_with("#compdef rust_bfi\n"));
+        assert!(zsh.contains("'*--eof:value:(nochange zero 255 error)'"));
+        let fish = script(cli::Shell::Fish);
+        assert!(fish.contains("complete -c rust_bfi -n '__fish_seen_subcommand_from compile' -l target -x -a 'rust c wasm'\n"));
+        assert!(fish.contains("-s o -r -F\n"));
+        // every subcommand is offered by every shell
+        for command in commands() {
+            for script in [&bash, &zsh, &fish] {
+                assert!(script.contains(&command.name), "{}", command.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+/*
+    Module for reading an interpreter's settings from a TOML file, so a
+    setup can be kept with a program and shared rather than retyped as a
+    long list of flags
+
+        # bfi.toml
+        mem_size = 65536
+        tape = "growable"
+        sparse = false
+        cell_width = 16
+        eof = "zero"
+        engine = "interpreter"
+        dialect = "brainfuck"
+        optimize = true
+
+        [limits]
+        max_steps = 10_000_000
+        max_output_bytes = 4096
+        max_mem_cells = 1_048_576
+        timeout_ms = 5000
+        max_loop_depth = 1000
+
+        let config = rust_bfi::BFConfig::from_toml(&std::fs::read_to_string("bfi.toml")?)?;
+        let mut bfi = config.interpreter();
+
+    every key is optional and takes the same values as the matching command
+    line flag, only the parts of TOML these need are understood: comments,
+    bare keys, strings, integers, booleans and the [limits] table
+
+    each key can also be set with an environment variable, e.g. in CI or a
+    container, named after it in capitals (RUST_BFI_MEM_SIZE, RUST_BFI_EOF,
+    RUST_BFI_MAX_STEPS, ...), with_env() puts them over the file's settings and
+    rejects any other RUST_BFI_* name
+
+        let config = rust_bfi::BFConfig::default().with_env(std::env::vars())?;
+*/
+
+
+use std::time::Duration;
+
+use crate::{dtypes, interpreter, tape};
+
+
+// start of the names of the environment variables that override settings
+pub const ENV_PREFIX: &str = "RUST_BFI_";
+
+
+// every key that can be set, those in the [limits] table prefixed with limits.
+const KEYS: [&str; 13] = ["mem_size", "tape", "sparse", "cell_width", "eof", "engine", "dialect", "optimize", "limits.max_steps",
+                          "limits.max_output_bytes", "limits.max_mem_cells", "limits.timeout_ms", "limits.max_loop_depth"];
+
+
+// settings an interpreter is made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BFConfig {
+    pub mem_size: usize,
+    pub tape_mode: dtypes::TapeMode,
+    // only store the pages of memory the program uses
+    pub sparse: bool,
+    pub cell_width: dtypes::CellWidth,
+    pub eof_behavior: dtypes::EofBehavior,
+    pub limits: dtypes::Limits,
+    pub engine: dtypes::Engine,
+    pub dialect: dtypes::Dialect,
+    // fold and lower the program before running it
+    pub optimize: bool,
+}
+
+
+impl Default for BFConfig {
+    // the classic spec, 30000 byte cells on a strict tape with no limits
+    fn default () -> BFConfig {
+        BFConfig {
+            mem_size: 30000,
+            tape_mode: dtypes::TapeMode::Strict,
+            sparse: false,
+            cell_width: dtypes::CellWidth::U8,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            limits: dtypes::Limits::default(),
+            engine: dtypes::Engine::Interpreter,
+            dialect: dtypes::Dialect::Brainfuck,
+            optimize: true,
+        }
+    }
+}
+
+
+// a value on the right of an =
+#[derive(Debug, PartialEq, Eq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+
+impl Value {
+    // the value of an environment variable, which has no quotes around strings
+    fn from_env (text: &str) -> Value {
+        match text {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => text.replace('_', "").parse::<i64>().map_or_else(|_| Value::Str(text.to_string()), Value::Int),
+        }
+    }
+
+    // the value as the command line would have it, e.g. the 16 in cell_width = 16
+    fn text (&self) -> String {
+        match self {
+            Value::Str(s) => s.clone(),
+            Value::Int(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+        }
+    }
+
+    fn count<T: TryFrom<i64>> (&self) -> Result<T, String> {
+        match self {
+            Value::Int(n) => T::try_from(*n).map_err(|_| format!("{} is out of range", n)),
+            _ => Err(format!("expected a number, found '{}'", self.text())),
+        }
+    }
+
+    fn flag (&self) -> Result<bool, String> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(format!("expected true or false, found '{}'", self.text())),
+        }
+    }
+}
+
+
+// the value on a line, after the =, up to any comment
+fn parse_value (text: &str) -> Result<Value, String> {
+    let text = text.trim_start();
+    if let Some(quote) = text.chars().next().filter(|&c| c == '"' || c == '\'') {
+        let end = text[1..].find(quote).ok_or("unterminated string")? + 1;
+        let rest = text[end + 1..].trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(format!("unexpected '{}' after string", rest));
+        }
+        return Ok(Value::Str(text[1..end].to_string()));
+    }
+    let text = text.split('#').next().unwrap_or("").trim();
+    match text {
+        "" => Err(String::from("missing value")),
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => text.replace('_', "").parse::<i64>().map(Value::Int).map_err(|_| format!("invalid value '{}'", text)),
+    }
+}
+
+
+// the tape mode named by a value, as the --tape flag takes
+fn tape_mode (value: &Value) -> Result<dtypes::TapeMode, String> {
+    match value.text().as_str() {
+        "strict" => Ok(dtypes::TapeMode::Strict),
+        "wrapping" => Ok(dtypes::TapeMode::Wrapping),
+        "growable" => Ok(dtypes::TapeMode::Growable),
+        "bidirectional" => Ok(dtypes::TapeMode::Bidirectional),
+        other => Err(format!("invalid tape '{}'", other)),
+    }
+}
+
+
+// the cell width named by a value, as the --cell-width flag takes
+fn cell_width (value: &Value) -> Result<dtypes::CellWidth, String> {
+    match value.text().as_str() {
+        "8" => Ok(dtypes::CellWidth::U8),
+        "16" => Ok(dtypes::CellWidth::U16),
+        "32" => Ok(dtypes::CellWidth::U32),
+        other => Err(format!("invalid cell_width '{}'", other)),
+    }
+}
+
+
+// the EOF behavior named by a value, as the --eof flag takes
+fn eof_behavior (value: &Value) -> Result<dtypes::EofBehavior, String> {
+    match value.text().as_str() {
+        "nochange" => Ok(dtypes::EofBehavior::NoChange),
+        "zero" | "0" => Ok(dtypes::EofBehavior::Zero),
+        "255" | "-1" => Ok(dtypes::EofBehavior::MinusOne),
+        "error" => Ok(dtypes::EofBehavior::Error),
+        other => Err(format!("invalid eof '{}'", other)),
+    }
+}
+
+
+// the engine named by a value, as the --engine flag takes
+fn engine (value: &Value) -> Result<dtypes::Engine, String> {
+    match value.text().as_str() {
+        "interpreter" => Ok(dtypes::Engine::Interpreter),
+        #[cfg(feature = "jit")]
+        "jit" => Ok(dtypes::Engine::Jit),
+        #[cfg(not(feature = "jit"))]
+        "jit" => Err(String::from("the jit engine is not available, rebuild with --features jit")),
+        other => Err(format!("invalid engine '{}'", other)),
+    }
+}
+
+
+// the dialect named by a value, as the --dialect flag takes
+fn dialect (value: &Value) -> Result<dtypes::Dialect, String> {
+    match value.text().as_str() {
+        "brainfuck" | "bf" => Ok(dtypes::Dialect::Brainfuck),
+        "ook" => Ok(dtypes::Dialect::Ook),
+        "blub" => Ok(dtypes::Dialect::Blub),
+        "extended" => Ok(dtypes::Dialect::Extended),
+        "pbrain" => Ok(dtypes::Dialect::Pbrain),
+        "brainfork" => Ok(dtypes::Dialect::Brainfork),
+        other => Err(format!("invalid dialect '{}'", other)),
+    }
+}
+
+
+impl BFConfig {
+    // settings from the text of a TOML file, starting from the defaults,
+    // unknown or repeated keys are errors so typos don't go unnoticed
+    pub fn from_toml (text: &str) -> Result<BFConfig, String> {
+        let mut config = BFConfig::default();
+        let mut table = String::new();
+        let mut seen: Vec<String> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let at = |e: String| format!("line {}: {}", i + 1, e);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let name = header.split('#').next().unwrap_or("").trim_end();
+                table = match name.strip_suffix(']').map(str::trim) {
+                    Some("limits") => String::from("limits"),
+                    Some(name) => return Err(at(format!("unknown table [{}]", name))),
+                    None => return Err(at(String::from("unterminated table header"))),
+                };
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| at(String::from("expected key = value")))?;
+            let key = key.trim();
+            let value = parse_value(value).map_err(at)?;
+            let full = if table.is_empty() { key.to_string() } else { format!("{}.{}", table, key) };
+            if seen.contains(&full) {
+                return Err(at(format!("{} is set more than once", full)));
+            }
+            config.set(&full, &value).map_err(|e| at(format!("{}: {}", key, e)))?;
+            seen.push(full);
+        }
+        Ok(config)
+    }
+
+    // these settings with any set by RUST_BFI_* environment variables (given as
+    // name, value pairs, e.g. from std::env::vars()) in their place, variables
+    // that are empty are ignored, and RUST_BFI_* ones that aren't named after a
+    // key are errors so typos don't go unnoticed
+    pub fn with_env<I: IntoIterator<Item = (String, String)>> (mut self, vars: I) -> Result<BFConfig, String> {
+        for (name, value) in vars {
+            let Some(suffix) = name.strip_prefix(ENV_PREFIX) else { continue };
+            let key = KEYS.iter()
+                .find(|key| key.trim_start_matches("limits.").to_uppercase() == suffix)
+                .ok_or_else(|| format!("{}: unknown setting", name))?;
+            if !value.is_empty() {
+                self.set(key, &Value::from_env(&value)).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        Ok(self)
+    }
+
+    // change one setting, key is one of KEYS
+    fn set (&mut self, key: &str, value: &Value) -> Result<(), String> {
+        match key {
+            "mem_size" => value.count()
+                .and_then(|n: usize| if n > 0 { Ok(n) } else { Err(String::from("mem_size must be greater than 0")) })
+                .map(|n| self.mem_size = n),
+            "tape" => tape_mode(value).map(|mode| self.tape_mode = mode),
+            "sparse" => value.flag().map(|sparse| self.sparse = sparse),
+            "cell_width" => cell_width(value).map(|width| self.cell_width = width),
+            "eof" => eof_behavior(value).map(|eof| self.eof_behavior = eof),
+            "engine" => engine(value).map(|engine| self.engine = engine),
+            "dialect" => dialect(value).map(|dialect| self.dialect = dialect),
+            "optimize" => value.flag().map(|optimize| self.optimize = optimize),
+            "limits.max_steps" => value.count().map(|n| self.limits.max_steps = Option::Some(n)),
+            "limits.max_output_bytes" => value.count().map(|n| self.limits.max_output_bytes = Option::Some(n)),
+            "limits.max_mem_cells" => value.count().map(|n| self.limits.max_mem_cells = Option::Some(n)),
+            "limits.timeout_ms" => value.count().map(|ms| self.limits.timeout = Option::Some(Duration::from_millis(ms))),
+            "limits.max_loop_depth" => value.count().map(|n| self.limits.max_loop_depth = Option::Some(n)),
+            _ => Err(format!("unknown key '{}'", key)),
+        }
+    }
+
+    // a fresh interpreter with these settings
+    pub fn interpreter (&self) -> interpreter::BFInterpreter {
+        let mut bfi = match self.sparse {
+            true => interpreter::BFInterpreter::with_tape(Box::new(tape::SparseTape::new(self.tape_mode, self.mem_size)), self.cell_width),
+            false => interpreter::BFInterpreter::with_config(self.mem_size, self.tape_mode, self.cell_width),
+        };
+        bfi.set_eof_behavior(self.eof_behavior);
+        bfi.set_limits(self.limits);
+        bfi.set_engine(self.engine);
+        bfi.set_dialect(self.dialect);
+        bfi.set_optimize(self.optimize);
+        bfi
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn config_from_toml () {
+        let text = "
+            # a comment
+            mem_size = 65_536   # cells
+            tape = \"growable\"
+            sparse = true
+            cell_width = 16
+            eof = 'zero'
+            engine = \"interpreter\"
+            dialect = \"ook\"
+            optimize = false
+
+            [limits]
+            max_steps = 1000
+            timeout_ms = 250
+        ";
+        let config = BFConfig::from_toml(text).unwrap();
+        assert_eq!(config, BFConfig {
+            mem_size: 65536,
+            tape_mode: dtypes::TapeMode::Growable,
+            sparse: true,
+            cell_width: dtypes::CellWidth::U16,
+            eof_behavior: dtypes::EofBehavior::Zero,
+            limits: dtypes::Limits { max_steps: Some(1000), timeout: Some(Duration::from_millis(250)), ..Default::default() },
+            engine: dtypes::Engine::Interpreter,
+            dialect: dtypes::Dialect::Ook,
+            optimize: false,
+        });
+        assert_eq!(BFConfig::from_toml("").unwrap(), BFConfig::default());
+        // the flag's other spellings work too
+        assert_eq!(BFConfig::from_toml("eof = -1\ncell_width = \"32\"").unwrap().eof_behavior, dtypes::EofBehavior::MinusOne);
+        // and the interpreter made from it follows them
+        let mut bfi = config.interpreter();
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.load_program("Ook. Ook. Ook! Ook? Ook? Ook!").unwrap();
+        assert!(matches!(bfi.run(), Err(dtypes::BFError::StepLimitExceeded { limit: 1000, .. })));
+        assert_eq!(bfi.limits(), config.limits);
+    }
+
+    #[test]
+    fn config_from_toml_errors () {
+        let error = |text: &str| BFConfig::from_toml(text).unwrap_err();
+        assert_eq!(error("mem_size = 10\nmem_sise = 10"), "line 2: mem_sise: unknown key 'mem_sise'");
+        assert_eq!(error("[limits]\nmem_size = 10"), "line 2: mem_size: unknown key 'limits.mem_size'");
+        assert_eq!(error("tape = \"sticky\""), "line 1: tape: invalid tape 'sticky'");
+        assert_eq!(error("mem_size = 0"), "line 1: mem_size: mem_size must be greater than 0");
+        assert_eq!(error("[limits]\nmax_steps = -5"), "line 2: max_steps: -5 is out of range");
+        assert_eq!(error("mem_size = \"big\""), "line 1: mem_size: expected a number, found 'big'");
+        assert_eq!(error("eof = 0\neof = 0"), "line 2: eof is set more than once");
+        assert_eq!(error("[memory]"), "line 1: unknown table [memory]");
+        assert!(error("tape = \"strict").contains("unterminated string"));
+        assert!(error("tape").contains("expected key = value"));
+        assert!(error("sparse = yes").contains("invalid value 'yes'"));
+    }
+
+    // environment variables as std::env::vars() gives them
+    fn env (vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn config_with_env () {
+        let config = BFConfig::default().with_env(env(&[
+            ("RUST_BFI_MEM_SIZE", "1_000"),
+            ("RUST_BFI_TAPE", "bidirectional"),
+            ("RUST_BFI_SPARSE", "true"),
+            ("RUST_BFI_EOF", "-1"),
+            ("RUST_BFI_MAX_LOOP_DEPTH", "50"),
+            ("RUST_BFI_DIALECT", "brainfork"),
+            ("RUST_BFI_OPTIMIZE", "false"),
+            // not settings
+            ("PATH", "/bin"),
+            ("RUST_BFI_CELL_WIDTH", ""),
+        ])).unwrap();
+        assert_eq!(config, BFConfig {
+            mem_size: 1000,
+            tape_mode: dtypes::TapeMode::Bidirectional,
+            sparse: true,
+            eof_behavior: dtypes::EofBehavior::MinusOne,
+            limits: dtypes::Limits { max_loop_depth: Some(50), ..Default::default() },
+            dialect: dtypes::Dialect::Brainfork,
+            optimize: false,
+            ..Default::default()
+        });
+        assert_eq!(BFConfig::default().with_env(env(&[("RUST_BFI_EOF", "sometimes")])).unwrap_err(), "RUST_BFI_EOF: invalid eof 'sometimes'");
+        assert!(BFConfig::default().with_env(env(&[("RUST_BFI_MEM_SIZE", "lots")])).is_err());
+        assert!(BFConfig::default().with_env(env(&[("RUST_BFI_SPARSE", "1")])).is_err());
+        // misspelled names aren't passed over
+        assert_eq!(BFConfig::default().with_env(env(&[("RUST_BFI_MEMSIZE", "10")])).unwrap_err(), "RUST_BFI_MEMSIZE: unknown setting");
+        assert_eq!(BFConfig::default().with_env(env(&[("RUST_BFI_COLOR", "")])).unwrap_err(), "RUST_BFI_COLOR: unknown setting");
+    }
+
+    #[test]
+    fn config_precedence () {
+        // defaults, then the config file, then the environment
+        let file = BFConfig::from_toml("mem_size = 100\neof = \"zero\"\n[limits]\nmax_steps = 10\ntimeout_ms = 20").unwrap();
+        let config = file.with_env(env(&[("RUST_BFI_EOF", "error"), ("RUST_BFI_MAX_STEPS", "30")])).unwrap();
+        assert_eq!(config.mem_size, 100);
+        assert_eq!(config.eof_behavior, dtypes::EofBehavior::Error);
+        assert_eq!(config.limits, dtypes::Limits { max_steps: Some(30), timeout: Some(Duration::from_millis(20)), ..Default::default() });
+        assert_eq!(config.cell_width, BFConfig::default().cell_width);
+        // the last of a repeated variable wins
+        let config = BFConfig::default().with_env(env(&[("RUST_BFI_MEM_SIZE", "5"), ("RUST_BFI_MEM_SIZE", "6")])).unwrap();
+        assert_eq!(config.mem_size, 6);
+    }
+}
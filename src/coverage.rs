@@ -0,0 +1,224 @@
+/*
+    Module for reporting which commands of a program were executed, for
+    finding dead code and loops that are never entered (or never skipped).
+    Built from the counters a profile collects, as an annotated copy of the
+    source, gcov style:
+
+            2 | ++[>+<-]>[-]
+            1 | >[-<]<.
+              |   ^^^
+        ##### | [-]
+            - | a comment
+
+    with the times each line ran, `#####` for lines that never did and carets
+    under the commands that didn't on lines that partly ran, or as an lcov
+    tracefile for tools like genhtml
+*/
+
+
+use std::fmt::{self, Write};
+
+use crate::{dtypes, profile};
+
+
+// a loop and the two ways its [ can go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopCoverage {
+    // where its [ is
+    pub pos: dtypes::SourcePos,
+    // times the body was entered
+    pub entered: u64,
+    // times the [ was on a zero cell and jumped past the body
+    pub skipped: u64,
+}
+
+
+// how often each command of a program ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    // line and column of each source command and the number of times it was
+    // executed
+    pub commands: Vec<(dtypes::SourcePos, u64)>,
+    // in source order
+    pub loops: Vec<LoopCoverage>,
+}
+
+
+impl Coverage {
+    // coverage of a loaded program (ops, with the positions of its source
+    // commands) from its profile, ops covering a run of commands count for all
+    // of them, so only an unoptimized program shows loops folded into a
+    // single instruction accurately
+    pub fn new (sources: &[dtypes::SourcePos], ops: &[dtypes::Op], profile: &profile::Profile) -> Coverage {
+        let mut commands: Vec<(dtypes::SourcePos, u64)> = sources.iter().map(|&pos| (pos, 0)).collect();
+        for (i, op) in ops.iter().enumerate() {
+            let end = ops.get(i + 1).map_or(commands.len(), |next| next.position);
+            let hits = profile.hits.get(&op.position).copied().unwrap_or(0);
+            for command in commands.iter_mut().take(end).skip(op.position) {
+                command.1 = hits;
+            }
+        }
+        let loops = ops.iter()
+            .filter(|op| matches!(op.instr, dtypes::Instr::JumpIfZero(_)))
+            .filter_map(|op| {
+                let &(pos, hits) = commands.get(op.position)?;
+                let entered = profile.loops.get(&op.position).map_or(0, |stats| stats.entries);
+                Option::Some(LoopCoverage { pos, entered, skipped: hits.saturating_sub(entered) })
+            })
+            .collect();
+        Coverage { commands, loops }
+    }
+
+    // number of commands executed at least once
+    pub fn executed (&self) -> usize {
+        self.commands.iter().filter(|&&(_, hits)| hits > 0).count()
+    }
+
+    // times each line with commands on it ran (the most any of its commands
+    // did), in order
+    fn line_hits (&self) -> Vec<(usize, u64)> {
+        let mut lines: Vec<(usize, u64)> = Vec::new();
+        for &(pos, hits) in &self.commands {
+            match lines.last_mut() {
+                Some((line, most)) if *line == pos.line => *most = (*most).max(hits),
+                _ => lines.push((pos.line, hits)),
+            };
+        }
+        lines
+    }
+
+    // the program source (the same source the program was loaded from)
+    // annotated with how often each line ran
+    pub fn annotate (&self, prog: &str) -> String {
+        let lines = self.line_hits();
+        let gutter = lines.iter().map(|(_, hits)| hits.to_string().len()).chain([5]).max().unwrap_or(5);
+        let mut out = String::new();
+        for (n, text) in prog.lines().enumerate() {
+            let n = n + 1;
+            let count = match lines.iter().find(|(line, _)| *line == n) {
+                Some((_, 0)) => String::from("#####"),
+                Some((_, hits)) => hits.to_string(),
+                None => String::from("-"),
+            };
+            let _ = writeln!(out, "{:>w$} | {}", count, text, w = gutter);
+            // point out what didn't run on lines that partly did
+            let missed: Vec<usize> = self.commands.iter()
+                .filter(|(pos, hits)| pos.line == n && *hits == 0)
+                .map(|(pos, _)| pos.column)
+                .collect();
+            if !missed.is_empty() && count != "#####" {
+                let marks: String = text.chars().enumerate()
+                    .take(missed.iter().max().copied().unwrap_or(0))
+                    .map(|(i, c)| match (missed.contains(&(i + 1)), c) {
+                        (true, _) => '^',
+                        (false, '\t') => '\t',
+                        (false, _) => ' ',
+                    })
+                    .collect();
+                let _ = writeln!(out, "{:>w$} | {}", "", marks, w = gutter);
+            }
+        }
+        out
+    }
+
+    // lcov tracefile for the program at path, lines with commands on them are
+    // lines of code and each loop is a branch that can be entered or skipped
+    pub fn lcov (&self, path: &str) -> String {
+        let lines = self.line_hits();
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:\nSF:{}", path);
+        for (block, lp) in self.loops.iter().enumerate() {
+            // `-` for both when the [ itself never ran
+            let taken = |n: u64| if lp.entered + lp.skipped == 0 { String::from("-") } else { n.to_string() };
+            let _ = writeln!(out, "BRDA:{},{},0,{}", lp.pos.line, block, taken(lp.entered));
+            let _ = writeln!(out, "BRDA:{},{},1,{}", lp.pos.line, block, taken(lp.skipped));
+        }
+        let branches_hit = self.loops.iter().map(|lp| (lp.entered > 0) as usize + (lp.skipped > 0) as usize).sum::<usize>();
+        let _ = writeln!(out, "BRF:{}\nBRH:{}", self.loops.len() * 2, branches_hit);
+        for (line, hits) in &lines {
+            let _ = writeln!(out, "DA:{},{}", line, hits);
+        }
+        let lines_hit = lines.iter().filter(|(_, hits)| *hits > 0).count();
+        let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", lines.len(), lines_hit);
+        out
+    }
+}
+
+
+// summary: how many commands ran and how many loops were entered
+impl fmt::Display for Coverage {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.commands.len();
+        let percent = if total == 0 { 100.0 } else { self.executed() as f64 * 100.0 / total as f64 };
+        let entered = self.loops.iter().filter(|lp| lp.entered > 0).count();
+        write!(f, "coverage: {} of {} commands executed ({:.1}%), {} of {} loops entered",
+               self.executed(), total, percent, entered, self.loops.len())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::interpreter;
+
+    // run a program unoptimized with profiling on and take its coverage
+    fn coverage (prog: &str, optimize: bool) -> Coverage {
+        let mut bfi = interpreter::BFInterpreter::new(100);
+        bfi.set_optimize(optimize);
+        bfi.set_profiling(true);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.load_program(prog).unwrap();
+        bfi.run().unwrap();
+        Coverage::new(bfi.sources(), bfi.instructions(), bfi.profile().unwrap())
+    }
+
+    #[test]
+    fn coverage_annotate () {
+        let prog = "++[>+<-]>[-]\n[\n  never run -\n]\n a comment\n";
+        let cov = coverage(prog, false);
+        assert_eq!(cov.commands.len(), 15);
+        assert_eq!(cov.executed(), 13);
+        assert_eq!(cov.loops, [
+            LoopCoverage { pos: dtypes::SourcePos { line: 1, column: 3 }, entered: 1, skipped: 0 },
+            LoopCoverage { pos: dtypes::SourcePos { line: 1, column: 10 }, entered: 1, skipped: 0 },
+            LoopCoverage { pos: dtypes::SourcePos { line: 2, column: 1 }, entered: 0, skipped: 1 },
+        ]);
+        assert_eq!(cov.annotate(prog), concat!(
+            "    2 | ++[>+<-]>[-]\n",
+            "    1 | [\n",
+            "##### |   never run -\n",
+            "##### | ]\n",
+            "    - |  a comment\n",
+        ));
+        assert_eq!(cov.to_string(), "coverage: 13 of 15 commands executed (86.7%), 2 of 3 loops entered");
+        // a loop that is skipped leaves its body marked on a line that ran
+        let cov = coverage("+>[-<]<.", false);
+        assert_eq!(cov.annotate("+>[-<]<."), "    1 | +>[-<]<.\n      |    ^^^\n");
+        // runs folded into one instruction count for all of their commands
+        assert_eq!(coverage("+++>--", true).executed(), 6);
+    }
+
+    #[test]
+    fn coverage_lcov () {
+        let prog = "+[-]\n[.]";
+        assert_eq!(coverage(prog, false).lcov("prog.bf"), "\
+TN:
+SF:prog.bf
+BRDA:1,0,0,1
+BRDA:1,0,1,0
+BRDA:2,1,0,0
+BRDA:2,1,1,1
+BRF:4
+BRH:2
+DA:1,1
+DA:2,1
+LF:2
+LH:2
+end_of_record
+");
+        let cov = coverage("[[-]]", false);
+        assert!(cov.lcov("a.bf").contains("BRDA:1,1,0,-\nBRDA:1,1,1,-\n"));
+    }
+}
@@ -0,0 +1,187 @@
+/*
+    Module for cross-validating the engines, a program is run through the
+    plain unoptimized interpreter (the reference) and the optimized ones
+    with the same input, then the output and final tape are compared to
+    find the first place they differ
+*/
+
+
+use std::fmt;
+
+use crate::{dtypes, interpreter};
+
+
+// step limit the engines are run with unless given another, so a program that
+// never finishes is reported as such rather than running forever
+pub const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+// how a program finished on one engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    // which engine this was, e.g. "optimized"
+    pub engine: String,
+    pub result: Result<(), dtypes::BFError>,
+    pub output: Vec<u8>,
+    // the cells, the first origin of them being left of where the program started
+    pub cells: Vec<u32>,
+    pub origin: usize,
+    pub data_ptr: usize,
+}
+
+
+impl Outcome {
+    // value of the cell at a position relative to where the program started,
+    // cells past either end are 0 as they would be once the tape grew
+    fn cell (&self, position: isize) -> u32 {
+        usize::try_from(position + self.origin as isize).ok()
+            .and_then(|i| self.cells.get(i).copied())
+            .unwrap_or(0)
+    }
+
+    // whether the program was stopped by the step limit, after which engines
+    // that fold commands together have done different amounts of work
+    fn hit_step_limit (&self) -> bool {
+        matches!(self.result, Err(dtypes::BFError::StepLimitExceeded { .. }))
+    }
+}
+
+
+// the first difference between an engine and the reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    // the byte output at index (None if one of them stopped outputting)
+    Output { index: usize, expected: Option<u8>, actual: Option<u8> },
+    Result { expected: Result<(), dtypes::BFError>, actual: Result<(), dtypes::BFError> },
+    // the cell at a position relative to where the program started
+    Cell { position: isize, expected: u32, actual: u32 },
+    DataPointer { expected: isize, actual: isize },
+}
+
+
+impl fmt::Display for Divergence {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let byte = |b: &Option<u8>| b.map_or(String::from("nothing"), |b| format!("{:#04x}", b));
+        let result = |r: &Result<(), dtypes::BFError>| match r {
+            Ok(()) => String::from("finished"),
+            Err(e) => e.to_string(),
+        };
+        match self {
+            Divergence::Output { index, expected, actual } => {
+                write!(f, "output byte {} is {}, expected {}", index, byte(actual), byte(expected))
+            },
+            Divergence::Result { expected, actual } => {
+                write!(f, "{}, expected {}", result(actual), result(expected))
+            },
+            Divergence::Cell { position, expected, actual } => {
+                write!(f, "cell {} is {}, expected {}", position, actual, expected)
+            },
+            Divergence::DataPointer { expected, actual } => {
+                write!(f, "data pointer is {}, expected {}", actual, expected)
+            },
+        }
+    }
+}
+
+
+// load and run a program on an interpreter that has already been set up for an
+// engine, reading the given input (or any embedded in the program if None)
+pub fn run_engine (engine: &str, bfi: &mut interpreter::BFInterpreter, prog: &str, input: Option<&[u8]>) -> Outcome {
+    bfi.set_output_sink(interpreter::OutputSink::Buffer);
+    bfi.set_input(&[]);
+    let result = bfi.load_program(prog).and_then(|()| {
+        if let Some(input) = input {
+            bfi.set_input(input);
+        }
+        bfi.run()
+    });
+    Outcome {
+        engine: engine.to_string(),
+        result,
+        output: bfi.take_output(),
+        cells: (0..bfi.memory_size()).filter_map(|i| bfi.cell(i)).collect(),
+        origin: bfi.tape_origin(),
+        data_ptr: bfi.data_pointer(),
+    }
+}
+
+
+// the first way actual differs from the reference, looking at the output first
+// since that is the first thing a program does that can be seen, if either hit
+// the step limit only the output they both got to is compared
+pub fn compare (reference: &Outcome, actual: &Outcome) -> Option<Divergence> {
+    let limited = reference.hit_step_limit() || actual.hit_step_limit();
+    let (a, b) = (&reference.output, &actual.output);
+    let shared = a.len().min(b.len());
+    if let Some(index) = (0..shared).find(|&i| a[i] != b[i]) {
+        return Option::Some(Divergence::Output { index, expected: Option::Some(a[index]), actual: Option::Some(b[index]) });
+    }
+    if limited {
+        return Option::None;
+    }
+    if a.len() != b.len() {
+        return Option::Some(Divergence::Output { index: shared, expected: a.get(shared).copied(), actual: b.get(shared).copied() });
+    }
+    if reference.result != actual.result {
+        return Option::Some(Divergence::Result { expected: reference.result.clone(), actual: actual.result.clone() });
+    }
+    // the tapes may have grown by different amounts, so go over both
+    let span = |o: &Outcome| (-(o.origin as isize), o.cells.len() as isize - o.origin as isize);
+    let ((lo_a, hi_a), (lo_b, hi_b)) = (span(reference), span(actual));
+    for position in lo_a.min(lo_b)..hi_a.max(hi_b) {
+        let (expected, actual) = (reference.cell(position), actual.cell(position));
+        if expected != actual {
+            return Option::Some(Divergence::Cell { position, expected, actual });
+        }
+    }
+    let pointer = |o: &Outcome| o.data_ptr as isize - o.origin as isize;
+    if pointer(reference) != pointer(actual) {
+        return Option::Some(Divergence::DataPointer { expected: pointer(reference), actual: pointer(actual) });
+    }
+    Option::None
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn outcome (output: &[u8], cells: &[u32], origin: usize, data_ptr: usize) -> Outcome {
+        Outcome { engine: String::from("test"), result: Ok(()), output: output.to_vec(), cells: cells.to_vec(), origin, data_ptr }
+    }
+
+    #[test]
+    fn crossval_engines_agree () {
+        let prog = "++++++[>++++++++<-]>+.[-]<<<+>>>>>,[.,]";
+        let mut outcomes = Vec::new();
+        for optimize in [false, true] {
+            let mut bfi = interpreter::BFInterpreter::with_config(4, dtypes::TapeMode::Bidirectional, dtypes::CellWidth::U8);
+            bfi.set_optimize(optimize);
+            bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+            outcomes.push(run_engine("engine", &mut bfi, prog, Some(b"hi")));
+        }
+        assert_eq!(outcomes[0].output, b"1hi");
+        assert_eq!(compare(&outcomes[0], &outcomes[1]), None);
+    }
+
+    #[test]
+    fn crossval_finds_first_divergence () {
+        let reference = outcome(b"abc", &[0, 1, 2], 1, 2);
+        assert_eq!(compare(&reference, &outcome(b"abd", &[0, 1, 2], 1, 2)),
+                   Some(Divergence::Output { index: 2, expected: Some(b'c'), actual: Some(b'd') }));
+        assert_eq!(compare(&reference, &outcome(b"ab", &[0, 1, 2], 1, 2)),
+                   Some(Divergence::Output { index: 2, expected: Some(b'c'), actual: None }));
+        // growing further doesn't matter as long as the extra cells are 0
+        assert_eq!(compare(&reference, &outcome(b"abc", &[0, 0, 0, 1, 2, 0], 3, 4)), None);
+        assert_eq!(compare(&reference, &outcome(b"abc", &[0, 1, 3], 1, 2)),
+                   Some(Divergence::Cell { position: 1, expected: 2, actual: 3 }));
+        assert_eq!(compare(&reference, &outcome(b"abc", &[0, 1, 2], 1, 1)),
+                   Some(Divergence::DataPointer { expected: 1, actual: 0 }));
+        let failed = Outcome { result: Err(dtypes::BFError::PointerUnderrun { position: 3, source: None }), ..reference.clone() };
+        assert!(matches!(compare(&reference, &failed), Some(Divergence::Result { .. })));
+        // after the step limit only the output so far is compared
+        let limited = Outcome { result: Err(dtypes::BFError::StepLimitExceeded { position: 0, source: None, limit: 5 }), ..outcome(b"ab", &[9], 0, 0) };
+        assert_eq!(compare(&reference, &limited), None);
+        assert_eq!(Divergence::Output { index: 2, expected: Some(b'c'), actual: None }.to_string(), "output byte 2 is nothing, expected 0x63");
+    }
+}
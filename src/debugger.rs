@@ -0,0 +1,306 @@
+/*
+    Module with an interactive step-by-step debugger
+*/
+
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use crate::{dtypes, interpreter};
+
+
+const HELP: &str = "\
+commands:
+  s, step [N]        execute the next N commands (default 1)
+  sb, step-back [N]  undo the last N commands (default 1)
+  c, continue        run until a breakpoint or # is hit or the program finishes
+  b, break N         set a breakpoint on command index N
+  d, delete N        remove the breakpoint on command index N
+  w, watch cell N    pause whenever memory cell N changes
+  u, unwatch cell N  remove the watchpoint on memory cell N
+  l, list            list breakpoints and watchpoints
+  p, print           print the interpreter state in full
+  h, help            show this message
+  q, quit            stop debugging
+an empty line repeats step";
+
+
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    // memory cells to pause on when they change
+    watchpoints: BTreeSet<usize>,
+}
+
+
+impl Default for Debugger {
+    fn default () -> Debugger {
+        Debugger::new()
+    }
+}
+
+
+impl Debugger {
+    pub fn new () -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    // pause before executing the command at index
+    pub fn add_breakpoint (&mut self, index: usize) {
+        self.breakpoints.insert(index);
+    }
+
+    // pause whenever the memory cell at index changes
+    pub fn add_watchpoint (&mut self, index: usize) {
+        self.watchpoints.insert(index);
+    }
+
+    // write the current instruction, data pointer, and nearby cells
+    fn print_state<W: Write> (&self, bfi: &interpreter::BFInterpreter, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{}", bfi.summary())
+    }
+
+    // step once, reporting any error and changes to watched cells, returns false
+    // once there is nothing left to do or a watched cell changed
+    fn step<W: Write> (&self, bfi: &mut interpreter::BFInterpreter, out: &mut W) -> io::Result<bool> {
+        let (ip, instr) = (bfi.instruction_pointer(), bfi.current_instruction());
+        let watched: Vec<Option<u32>> = self.watchpoints.iter().map(|&i| bfi.cell(i)).collect();
+        let result = bfi.step();
+        let mut changed = false;
+        for (&i, before) in self.watchpoints.iter().zip(watched) {
+            // a cell that grew into existence starts out as 0
+            let (before, after) = (before.unwrap_or(0), bfi.cell(i).unwrap_or(0));
+            if before != after {
+                changed = true;
+                match instr {
+                    Some(instr) => writeln!(out, "cell {} changed from {} to {} by command {}: {}", i, before, after, ip, instr)?,
+                    None => writeln!(out, "cell {} changed from {} to {}", i, before, after)?,
+                };
+            }
+        }
+        if let Err(e) = result {
+            writeln!(out, "error: {}", e)?;
+            return Ok(false);
+        }
+        if bfi.is_finished() {
+            writeln!(out, "program finished")?;
+            return Ok(false);
+        }
+        Ok(!changed)
+    }
+
+    // run the program interactively, reading debugger commands from input and
+    // writing state to out, returns the result of the program (if it finished)
+    pub fn run<R: BufRead, W: Write> (&mut self, bfi: &mut interpreter::BFInterpreter,
+                                      input: &mut R, out: &mut W) -> io::Result<Option<Result<(), dtypes::BFError>>> {
+        self.print_state(bfi, out)?;
+        let mut line = String::new();
+        loop {
+            write!(out, "(bfdb) ")?;
+            out.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                // treat end of input like quit
+                writeln!(out)?;
+                return Ok(Option::None);
+            }
+            let mut words = line.split_whitespace().peekable();
+            let cmd = words.next().unwrap_or("step");
+            // watchpoints are on memory cells, i.e. `watch cell N`
+            if matches!(cmd, "w" | "watch" | "u" | "unwatch") && words.peek() == Option::Some(&"cell") {
+                words.next();
+            }
+            let arg = words.next().map(|w| w.parse::<usize>());
+            match (cmd, arg) {
+                ("s" | "step", None) => {
+                    self.step(bfi, out)?;
+                },
+                ("s" | "step", Some(Ok(n))) => {
+                    for _ in 0..n {
+                        if !self.step(bfi, out)? {
+                            break;
+                        }
+                    }
+                },
+                ("sb" | "step-back", arg @ (None | Some(Ok(_)))) => {
+                    let n = arg.map_or(1, |n| n.unwrap_or(1));
+                    for _ in 0..n {
+                        if !bfi.step_back() {
+                            writeln!(out, "no more history to step back through")?;
+                            break;
+                        }
+                    }
+                },
+                ("c" | "continue", None) => {
+                    // always take at least one step so continuing from a
+                    // breakpoint doesn't stop on it again
+                    while self.step(bfi, out)? {
+                        if self.breakpoints.contains(&bfi.instruction_pointer()) {
+                            writeln!(out, "breakpoint at command {}", bfi.instruction_pointer())?;
+                            break;
+                        }
+                        if bfi.current_instruction() == Option::Some(dtypes::Instr::Debug) {
+                            writeln!(out, "# at command {}", bfi.instruction_pointer())?;
+                            break;
+                        }
+                    }
+                },
+                ("b" | "break", Some(Ok(n))) => {
+                    self.breakpoints.insert(n);
+                    continue;
+                },
+                ("d" | "delete", Some(Ok(n))) => {
+                    if !self.breakpoints.remove(&n) {
+                        writeln!(out, "no breakpoint on command {}", n)?;
+                    }
+                    continue;
+                },
+                ("w" | "watch", Some(Ok(n))) => {
+                    self.watchpoints.insert(n);
+                    continue;
+                },
+                ("u" | "unwatch", Some(Ok(n))) => {
+                    if !self.watchpoints.remove(&n) {
+                        writeln!(out, "no watchpoint on cell {}", n)?;
+                    }
+                    continue;
+                },
+                ("l" | "list", None) => {
+                    for b in &self.breakpoints {
+                        writeln!(out, "breakpoint on command {}", b)?;
+                    }
+                    for w in &self.watchpoints {
+                        writeln!(out, "watchpoint on cell {}", w)?;
+                    }
+                    continue;
+                },
+                ("p" | "print", None) => {
+                    writeln!(out, "{}", bfi)?;
+                    continue;
+                },
+                ("h" | "help", None) => {
+                    writeln!(out, "{}", HELP)?;
+                    continue;
+                },
+                ("q" | "quit", None) => return Ok(Option::None),
+                _ => {
+                    writeln!(out, "unrecognized command, type 'help' for a list of commands")?;
+                    continue;
+                },
+            };
+            // make program output visible before showing where it stopped
+            io::stdout().flush()?;
+            self.print_state(bfi, out)?;
+            if bfi.is_finished() {
+                return Ok(Option::Some(bfi.step()));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Cursor;
+
+    // run a debugging session with scripted commands, returning the transcript
+    fn session (prog: &str, breakpoints: &[usize], commands: &str)
+                -> (interpreter::BFInterpreter, Option<Result<(), dtypes::BFError>>, String) {
+        let mut bfi = interpreter::BFInterpreter::new(8);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.set_optimize(false);
+        bfi.set_history_depth(4);
+        bfi.load_program(prog).unwrap();
+        let mut dbg = Debugger::new();
+        for b in breakpoints {
+            dbg.add_breakpoint(*b);
+        }
+        let mut out: Vec<u8> = Vec::new();
+        let result = dbg.run(&mut bfi, &mut Cursor::new(commands.as_bytes()), &mut out).unwrap();
+        (bfi, result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn debugger_step_and_print () {
+        let (bfi, result, out) = session("+>++", &[], "s\n\nstep 5\n");
+        assert_eq!(result, Some(Ok(())));
+        assert!(bfi.is_finished());
+        assert!(out.contains("command 0: +  data pointer: 0  cells: [0] 0 0 0 0"));
+        assert!(out.contains("command 2: +  data pointer: 1  cells: 1 [0] 0 0 0 0"));
+        assert!(out.contains("program finished"));
+        assert!(out.contains("command 4: <end of program>  data pointer: 1  cells: 1 [2] 0 0 0 0  (finished)"), "{}", out);
+        // the full view
+        let (_, _, out) = session("+>++", &[], "s 3\np\nq\n");
+        assert!(out.contains("command 3 of 4: + (line 1, column 4)\ndata pointer: 1  cells 0 to 5: 1 [1] 0 0 0 0\n\
+                              steps: 3  output: 0 bytes  input: 0 bytes\nstate: paused\n"), "{}", out);
+    }
+
+    #[test]
+    fn debugger_step_back () {
+        // back over output and past the end of the history
+        let (bfi, result, out) = session("++.>+", &[], "s 4\nsb\nsb 2\nsb 3\nq\n");
+        assert_eq!(result, None);
+        assert_eq!(bfi.output(), b"");
+        assert!(out.contains("command 1: +  data pointer: 0  cells: [1] 0 0 0 0"));
+        assert!(out.contains("command 3: >  data pointer: 0  cells: [2] 0 0 0 0"));
+        assert!(out.contains("no more history to step back through\ncommand 0: +"));
+        assert_eq!(bfi.instruction_pointer(), 0);
+    }
+
+    #[test]
+    fn debugger_breakpoints () {
+        // stop at the breakpoint given up front, then one set interactively
+        let (bfi, result, out) = session("+++>+++", &[2], "c\nb 5\nc\nq\n");
+        assert_eq!(result, None);
+        assert_eq!(bfi.instruction_pointer(), 5);
+        assert!(out.contains("breakpoint at command 2"));
+        assert!(out.contains("breakpoint at command 5"));
+    }
+
+    #[test]
+    fn debugger_pauses_on_hash () {
+        let mut bfi = interpreter::BFInterpreter::new(8);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.set_optimize(false);
+        bfi.set_hash_debug(true);
+        bfi.load_program("++#>+#+").unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        let result = Debugger::new().run(&mut bfi, &mut Cursor::new(b"c\nc\nq\n"), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(result, None);
+        assert!(out.contains("# at command 2\ncommand 2: #  data pointer: 0  cells: [2] 0 0 0 0"));
+        assert!(out.contains("# at command 5\ncommand 5: #  data pointer: 1  cells: 2 [1] 0 0 0 0"));
+    }
+
+    #[test]
+    fn debugger_reports_errors () {
+        let (_, result, out) = session("<", &[], "c\n");
+        let source = Some(dtypes::SourcePos { line: 1, column: 1 });
+        assert_eq!(result, Some(Err(dtypes::BFError::PointerUnderrun { position: 0, source })));
+        assert!(out.contains("error: data pointer underran available memory at command 0 (line 1, column 1)"));
+    }
+
+    #[test]
+    fn debugger_watchpoints () {
+        // stops when cell 1 is set, then each time the copy loop changes cell 2,
+        // and not for changes to other cells
+        let prog = "++>+<[->>+<<]>-";
+        let (bfi, result, out) = session(prog, &[], "watch cell 1\nc\nw 2\nl\nc\nc\nunwatch cell 1\nu 1\nq\n");
+        assert_eq!(result, None);
+        assert!(out.contains("cell 1 changed from 0 to 1 by command 3: +\n"));
+        assert!(out.contains("watchpoint on cell 1\nwatchpoint on cell 2\n"));
+        assert!(out.contains("cell 2 changed from 0 to 1 by command 9: +\n"));
+        assert!(out.contains("cell 2 changed from 1 to 2 by command 9: +\n"));
+        assert!(out.contains("no watchpoint on cell 1"));
+        assert_eq!(out.matches("changed").count(), 3);
+        assert_eq!(bfi.memory()[..3], [0, 1, 2]);
+        // a change by the last command is reported along with the program finishing
+        let (_, result, out) = session(prog, &[], "w cell 1\nc\nc\n");
+        assert_eq!(result, Some(Ok(())));
+        assert!(out.contains("cell 1 changed from 1 to 0 by command 14: -\nprogram finished\n"));
+    }
+}
@@ -0,0 +1,229 @@
+/*
+    Module for explaining an error a program stopped with, for people rather
+    than scripts: the line it happened on with the failing command picked
+    out, the loops it happened in, and the cells around the data pointer
+
+        error: data pointer overran available memory at command 2 (line 3, column 3)
+        3 |   >+]
+          |   ^
+        note: in the loop opened at line 2, column 2 (1 deep)
+        2 |  [
+          |  ^
+        note: the tape around the data pointer
+           cell | 5 6 7 8 9
+          value | 1 1 1 1 1
+                |         ^
+
+    colors (for a terminal) mark the error in red, the loop in cyan and the
+    data pointer's cell in yellow
+
+        let text = rust_bfi::diagnostics::Diagnostic::new(&e, prog, rust_bfi::Dialect::Brainfuck).with_tape(&bfi).render(true);
+*/
+
+
+use crate::{dtypes, interpreter, parsing};
+
+
+// ANSI escape codes
+const RED: &str = "\x1b[1;31m";
+const CYAN: &str = "\x1b[1;36m";
+const YELLOW: &str = "\x1b[1;33m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+
+// a line of source with the column of one character in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Excerpt {
+    pos: dtypes::SourcePos,
+    line: String,
+}
+
+
+impl Excerpt {
+    fn new (code: &str, pos: dtypes::SourcePos) -> Excerpt {
+        Excerpt { pos, line: code.lines().nth(pos.line.saturating_sub(1)).unwrap_or("").to_string() }
+    }
+
+    // the line with a caret under the column, both in color if given
+    fn render (&self, out: &mut String, color: Option<&str>) {
+        let gutter = self.pos.line.to_string().len();
+        let at = self.pos.column.saturating_sub(1);
+        let (on, off) = color.map_or(("", ""), |color| (color, RESET));
+        let mut line = String::new();
+        for (i, c) in self.line.chars().enumerate() {
+            match i == at {
+                true => line.push_str(&format!("{}{}{}", on, c, off)),
+                false => line.push(c),
+            };
+        }
+        // keep tabs so the caret lines up however they are displayed
+        let pad: String = self.line.chars().take(at).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        out.push_str(&format!("{:>w$} | {}\n{:>w$} | {}{}^{}\n", self.pos.line, line, "", pad, on, off, w = gutter));
+    }
+}
+
+
+// the cells around the data pointer, indexed from where the program started
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Neighborhood {
+    first: isize,
+    cells: Vec<u32>,
+    // which of the cells the data pointer is on
+    pointer: usize,
+}
+
+
+// everything there is to say about an error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    message: String,
+    // where it happened, if the error knows
+    excerpt: Option<Excerpt>,
+    // the [ of each loop the failing command is in, innermost first
+    loops: Vec<Excerpt>,
+    tape: Option<Neighborhood>,
+}
+
+
+impl Diagnostic {
+    // the error with where it happened in code, the program in the given
+    // dialect (anything after the failing command, like embedded input, is
+    // never looked at)
+    pub fn new (error: &dtypes::BFError, code: &str, dialect: dtypes::Dialect) -> Diagnostic {
+        Diagnostic::from_tokens(error, code, &parsing::tokenize(code, dialect))
+    }
+
+    // like new() but for a program already split into commands some other way,
+    // e.g. with a parsing::Mapping
+    pub fn from_tokens (error: &dtypes::BFError, code: &str, tokens: &[parsing::Token]) -> Diagnostic {
+        let excerpt = error.source().map(|pos| Excerpt::new(code, pos));
+        let mut loops = Vec::new();
+        // the unmatched [ of an unbalanced program isn't in a loop of its own
+        if let (Some(pos), false) = (error.source(), matches!(error, dtypes::BFError::UnmatchedBracket { .. })) {
+            if let Some(at) = tokens.iter().position(|token| token.pos == pos) {
+                let mut depth = 0;
+                for token in tokens[..at].iter().rev() {
+                    match token.command {
+                        dtypes::Command::JumpLeftIfNonZero => depth += 1,
+                        dtypes::Command::JumpRightIfZero if depth == 0 => loops.push(Excerpt::new(code, token.pos)),
+                        dtypes::Command::JumpRightIfZero => depth -= 1,
+                        _ => {},
+                    };
+                }
+            }
+        }
+        Diagnostic { message: error.to_string(), excerpt, loops, tape: Option::None }
+    }
+
+    // add the cells around where the data pointer of the interpreter that hit
+    // the error was left
+    pub fn with_tape (mut self, bfi: &interpreter::BFInterpreter) -> Diagnostic {
+        let (pointer, len) = (bfi.data_pointer(), bfi.memory_size());
+        if pointer < len {
+            let radius = interpreter::SUMMARY_RADIUS;
+            let (start, end) = (pointer.saturating_sub(radius), (pointer + radius + 1).min(len));
+            self.tape = Option::Some(Neighborhood {
+                first: start as isize - bfi.tape_origin() as isize,
+                cells: (start..end).map(|i| bfi.cell(i).unwrap_or(0)).collect(),
+                pointer: pointer - start,
+            });
+        }
+        self
+    }
+
+    // the text to show, with ANSI colors if color is true
+    pub fn render (&self, color: bool) -> String {
+        let paint = |code: &'static str| Option::Some(code).filter(|_| color);
+        let label = |text: &str, code: &'static str| match color {
+            true => format!("{}{}:{} ", code, text, RESET),
+            false => format!("{}: ", text),
+        };
+        let mut out = label("error", RED);
+        match color {
+            true => out.push_str(&format!("{}{}{}\n", BOLD, self.message, RESET)),
+            false => out.push_str(&format!("{}\n", self.message)),
+        };
+        if let Some(excerpt) = &self.excerpt {
+            excerpt.render(&mut out, paint(RED));
+        }
+        if let Some(inner) = self.loops.first() {
+            out.push_str(&label("note", CYAN));
+            out.push_str(&format!("in the loop opened at line {}, column {} ({} deep)\n", inner.pos.line, inner.pos.column, self.loops.len()));
+            inner.render(&mut out, paint(CYAN));
+        }
+        if let Some(tape) = &self.tape {
+            out.push_str(&label("note", CYAN));
+            out.push_str("the tape around the data pointer\n");
+            let indexes: Vec<String> = (0..tape.cells.len()).map(|i| (tape.first + i as isize).to_string()).collect();
+            let values: Vec<String> = tape.cells.iter().map(u32::to_string).collect();
+            let widths: Vec<usize> = indexes.iter().zip(&values).map(|(i, v)| i.len().max(v.len())).collect();
+            let row = |name: &str, texts: &[String]| -> String {
+                let cells: Vec<String> = texts.iter().zip(&widths).enumerate().map(|(i, (text, &w))| match (i == tape.pointer, color) {
+                    (true, true) => format!("{}{:>w$}{}", YELLOW, text, RESET, w = w),
+                    _ => format!("{:>w$}", text, w = w),
+                }).collect();
+                format!("  {:>5} | {}\n", name, cells.join(" "))
+            };
+            out.push_str(&row("cell", &indexes));
+            out.push_str(&row("value", &values));
+            // the caret under the right end of the pointer's column
+            let offset: usize = widths[..tape.pointer].iter().map(|w| w + 1).sum::<usize>() + widths[tape.pointer] - 1;
+            let (on, off) = paint(YELLOW).map_or(("", ""), |color| (color, RESET));
+            out.push_str(&format!("  {:>5} | {}{}^{}\n", "", " ".repeat(offset), on, off));
+        }
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // run a program on a small tape and describe the error it stops with
+    fn diagnose (prog: &str) -> Diagnostic {
+        let mut bfi = interpreter::BFInterpreter::new(10);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.set_max_steps(Some(1000));
+        let e = bfi.load_program(prog).and_then(|()| bfi.run()).unwrap_err();
+        Diagnostic::new(&e, prog, dtypes::Dialect::Brainfuck).with_tape(&bfi)
+    }
+
+    #[test]
+    fn diagnostics_render () {
+        let text = diagnose("+\n [\n  >+]").render(false);
+        assert_eq!(text, "\
+error: data pointer overran available memory at command 2 (line 3, column 3)
+3 |   >+]
+  |   ^
+note: in the loop opened at line 2, column 2 (1 deep)
+2 |  [
+  |  ^
+note: the tape around the data pointer
+   cell | 5 6 7 8 9
+  value | 1 1 1 1 1
+        |         ^
+");
+        // loops inside loops, and a short tape
+        let text = diagnose("+[[-]+<]").render(false);
+        assert!(text.contains("note: in the loop opened at line 1, column 2 (1 deep)\n"), "{}", text);
+        assert!(text.contains("   cell | 0 1 2 3 4\n  value | 1 0 0 0 0\n        | ^\n"), "{}", text);
+        let text = diagnose("+[[+[-<]]]").render(false);
+        assert!(text.contains("note: in the loop opened at line 1, column 5 (3 deep)\n"), "{}", text);
+    }
+
+    #[test]
+    fn diagnostics_without_context () {
+        // nothing ran, so there's no tape, and an unmatched [ is in no loop
+        let e = interpreter::BFInterpreter::new(10).load_program("[[]").unwrap_err();
+        let text = Diagnostic::new(&e, "[[]", dtypes::Dialect::Brainfuck).render(false);
+        assert_eq!(text.lines().count(), 3, "{}", text);
+        assert!(!text.contains("note"));
+        // colors only when asked for
+        let colored = diagnose("<").render(true);
+        assert!(colored.contains("\x1b[1;31merror:\x1b[0m") && colored.contains("\x1b[1;33m0\x1b[0m"), "{:?}", colored);
+        assert!(!diagnose("<").render(false).contains('\x1b'));
+    }
+}
@@ -0,0 +1,222 @@
+/*
+    Module with a disassembler that prints the internal representation
+    of a program in a readable textual form, and an explainer that spells
+    out what each instruction does in plain English as a learning aid
+*/
+
+
+use std::fmt::Write;
+
+use crate::dtypes;
+
+
+// the mnemonic for an instruction along with its operands
+fn mnemonic (instr: &dtypes::Instr) -> String {
+    match instr {
+        dtypes::Instr::Add(n) => format!("add {}", n),
+        dtypes::Instr::Move(n) => format!("move {}", n),
+        dtypes::Instr::OutputByte => String::from("out"),
+        dtypes::Instr::InputByte => String::from("in"),
+        dtypes::Instr::JumpIfZero(target) => format!("jz {}", target),
+        dtypes::Instr::JumpIfNonZero(target) => format!("jnz {}", target),
+        dtypes::Instr::Clear => String::from("clear"),
+        dtypes::Instr::AddTo(offset, factor) => format!("add_to {} {}", offset, factor),
+        dtypes::Instr::AddAt(offset, n) => format!("add_at {} {}", offset, n),
+        dtypes::Instr::ClearAt(offset) => format!("clear_at {}", offset),
+        dtypes::Instr::OutputAt(offset) => format!("out_at {}", offset),
+        dtypes::Instr::ScanRight => String::from("scan_right"),
+        dtypes::Instr::ScanLeft => String::from("scan_left"),
+        dtypes::Instr::End => String::from("end"),
+        dtypes::Instr::Store => String::from("store"),
+        dtypes::Instr::Retrieve => String::from("retrieve"),
+        dtypes::Instr::ShiftRight => String::from("shr"),
+        dtypes::Instr::ShiftLeft => String::from("shl"),
+        dtypes::Instr::Not => String::from("not"),
+        dtypes::Instr::Xor => String::from("xor"),
+        dtypes::Instr::And => String::from("and"),
+        dtypes::Instr::Or => String::from("or"),
+        dtypes::Instr::DefineProc(end) => format!("proc {}", end),
+        dtypes::Instr::EndProc => String::from("ret"),
+        dtypes::Instr::CallProc => String::from("call"),
+        dtypes::Instr::Fork => String::from("fork"),
+        dtypes::Instr::Debug => String::from("debug"),
+    }
+}
+
+
+// a cell at an offset from the data pointer
+fn cell_at (offset: isize) -> String {
+    match offset {
+        0 => String::from("the cell"),
+        1 => String::from("the next cell"),
+        -1 => String::from("the previous cell"),
+        n if n > 0 => format!("the cell {} to the right", n),
+        n => format!("the cell {} to the left", n.unsigned_abs()),
+    }
+}
+
+
+// adding n to a cell, in words
+fn add (n: i32, cell: &str) -> String {
+    match n < 0 {
+        true => format!("subtract {} from {}", n.unsigned_abs(), cell),
+        false => format!("add {} to {}", n, cell),
+    }
+}
+
+
+// what an instruction does, in words, where "the cell" is the one under the
+// data pointer
+fn describe (instr: &dtypes::Instr) -> String {
+    match instr {
+        dtypes::Instr::Add(n) => add(*n, "the cell"),
+        dtypes::Instr::Move(n) => {
+            let direction = if *n < 0 { "left" } else { "right" };
+            let cells = if n.unsigned_abs() == 1 { "cell" } else { "cells" };
+            format!("move {} {} {}", direction, n.unsigned_abs(), cells)
+        },
+        dtypes::Instr::OutputByte => String::from("output the cell"),
+        dtypes::Instr::InputByte => String::from("read a byte of input into the cell"),
+        dtypes::Instr::JumpIfZero(_) => String::from("while the cell != 0:"),
+        dtypes::Instr::JumpIfNonZero(_) => String::from("end while"),
+        dtypes::Instr::Clear => String::from("set the cell to 0"),
+        dtypes::Instr::AddTo(offset, 1) => format!("add the cell to {}", cell_at(*offset)),
+        dtypes::Instr::AddTo(offset, -1) => format!("subtract the cell from {}", cell_at(*offset)),
+        dtypes::Instr::AddTo(offset, factor) => add(*factor, &cell_at(*offset)).replacen(' ', " the cell times ", 1),
+        dtypes::Instr::AddAt(offset, n) => add(*n, &cell_at(*offset)),
+        dtypes::Instr::ClearAt(offset) => format!("set {} to 0", cell_at(*offset)),
+        dtypes::Instr::OutputAt(offset) => format!("output {}", cell_at(*offset)),
+        dtypes::Instr::ScanRight => String::from("move right to the nearest cell holding 0"),
+        dtypes::Instr::ScanLeft => String::from("move left to the nearest cell holding 0"),
+        dtypes::Instr::End => String::from("end the program"),
+        dtypes::Instr::Store => String::from("copy the cell into storage"),
+        dtypes::Instr::Retrieve => String::from("copy storage into the cell"),
+        dtypes::Instr::ShiftRight => String::from("shift the cell's bits right by one"),
+        dtypes::Instr::ShiftLeft => String::from("shift the cell's bits left by one"),
+        dtypes::Instr::Not => String::from("flip the cell's bits"),
+        dtypes::Instr::Xor => String::from("xor the cell with storage"),
+        dtypes::Instr::And => String::from("and the cell with storage"),
+        dtypes::Instr::Or => String::from("or the cell with storage"),
+        dtypes::Instr::DefineProc(_) => String::from("define the procedure numbered by the cell:"),
+        dtypes::Instr::EndProc => String::from("end procedure"),
+        dtypes::Instr::CallProc => String::from("call the procedure numbered by the cell"),
+        dtypes::Instr::Fork => String::from("zero the cell and start a thread on the next cell, set to 1"),
+        dtypes::Instr::Debug => String::from("show the data pointer and the first few cells"),
+    }
+}
+
+
+// one line per instruction: its index, the mnemonic (indented by loop and
+// procedure nesting, jumps give the index they go to) and where in the source
+// it came from, as line:column if sources (indexed by command position) has it
+// or the command index if not
+pub fn disassemble (ops: &[dtypes::Op], sources: &[dtypes::SourcePos]) -> String {
+    let mut out = String::new();
+    let width = ops.len().saturating_sub(1).to_string().len();
+    let mut depth: usize = 0;
+    for (i, op) in ops.iter().enumerate() {
+        if let dtypes::Instr::JumpIfNonZero(_) | dtypes::Instr::EndProc = op.instr {
+            depth = depth.saturating_sub(1);
+        }
+        let text = format!("{}{}", "  ".repeat(depth), mnemonic(&op.instr));
+        let origin = match sources.get(op.position) {
+            Some(pos) => pos.to_string(),
+            None => format!("command {}", op.position),
+        };
+        // writing to a String can't fail
+        let _ = writeln!(out, "{:>width$}  {:<24} ; {}", i, text, origin, width = width);
+        if let dtypes::Instr::JumpIfZero(_) | dtypes::Instr::DefineProc(_) = op.instr {
+            depth += 1;
+        }
+    }
+    out
+}
+
+
+// one line per instruction saying what it does, indented by loop and
+// procedure nesting, with where in the source it came from as for
+// disassemble()
+pub fn explain (ops: &[dtypes::Op], sources: &[dtypes::SourcePos]) -> String {
+    let mut out = String::new();
+    let mut depth: usize = 0;
+    for op in ops {
+        if let dtypes::Instr::JumpIfNonZero(_) | dtypes::Instr::EndProc = op.instr {
+            depth = depth.saturating_sub(1);
+        }
+        let text = format!("{}{}", "    ".repeat(depth), describe(&op.instr));
+        let origin = match sources.get(op.position) {
+            Some(pos) => pos.to_string(),
+            None => format!("command {}", op.position),
+        };
+        let _ = writeln!(out, "{:<48} ; {}", text, origin);
+        if let dtypes::Instr::JumpIfZero(_) | dtypes::Instr::DefineProc(_) = op.instr {
+            depth += 1;
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{optimizer, parsing};
+
+    #[test]
+    fn disassemble_optimized () {
+        let prog = "+++[->++<]\n>[.-]";
+        let ops = optimizer::optimize(&parsing::parse_program(prog)).unwrap();
+        let sources = parsing::command_positions(prog);
+        assert_eq!(disassemble(&ops, &sources), "\
+0  add 3                    ; 1:1
+1  add_to 1 2               ; 1:4
+2  clear                    ; 1:4
+3  move 1                   ; 2:1
+4  jz 7                     ; 2:2
+5    out                    ; 2:3
+6    add -1                 ; 2:4
+7  jnz 4                    ; 2:5
+");
+    }
+
+    #[test]
+    fn disassemble_without_sources () {
+        let ops = optimizer::lower(&parsing::parse_program("[[<],]")).unwrap();
+        let listing = disassemble(&ops, &[]);
+        assert_eq!(listing.lines().collect::<Vec<&str>>(), vec![
+            "0  jz 5                     ; command 0",
+            "1    jz 3                   ; command 1",
+            "2      move -1              ; command 2",
+            "3    jnz 1                  ; command 3",
+            "4    in                     ; command 4",
+            "5  jnz 0                    ; command 5",
+        ]);
+        assert_eq!(disassemble(&[], &[]), "");
+    }
+
+    #[test]
+    fn explain_optimized () {
+        let prog = "+++[->++<]\n>[.-<<]>>,[>]";
+        let ops = optimizer::optimize(&parsing::parse_program(prog)).unwrap();
+        let sources = parsing::command_positions(prog);
+        assert_eq!(explain(&ops, &sources), concat!(
+            "add 3 to the cell                                ; 1:1\n",
+            "add the cell times 2 to the next cell            ; 1:4\n",
+            "set the cell to 0                                ; 1:4\n",
+            "move right 1 cell                                ; 2:1\n",
+            "while the cell != 0:                             ; 2:2\n",
+            "    output the cell                              ; 2:3\n",
+            "    subtract 1 from the cell                     ; 2:4\n",
+            "    move left 2 cells                            ; 2:5\n",
+            "end while                                        ; 2:7\n",
+            "move right 2 cells                               ; 2:8\n",
+            "read a byte of input into the cell               ; 2:10\n",
+            "while the cell != 0:                             ; 2:11\n",
+            "    move right to the nearest cell holding 0     ; 2:12\n",
+            "end while                                        ; 2:13\n",
+        ));
+        assert_eq!(describe(&dtypes::Instr::AddTo(-3, -2)), "subtract the cell times 2 from the cell 3 to the left");
+        assert_eq!(describe(&dtypes::Instr::AddAt(-1, 4)), "add 4 to the previous cell");
+    }
+}
@@ -3,7 +3,11 @@
 */
 
 
-#[derive(Debug)]
+use std::{error, fmt};
+use std::time::Duration;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Command {
     MovePointerRight,
     MovePointerLeft,
@@ -13,4 +17,594 @@ pub enum Command {
     InputByte,
     JumpRightIfZero,
     JumpLeftIfNonZero,
+    // Extended Brainfuck Type I commands, only recognized with Dialect::Extended
+    EndProgram,
+    // copy the cell at the data pointer into the storage cell
+    StoreByte,
+    // copy the storage cell into the cell at the data pointer
+    RetrieveByte,
+    ShiftRight,
+    ShiftLeft,
+    BitwiseNot,
+    // these combine the cell at the data pointer with the storage cell
+    BitwiseXor,
+    BitwiseAnd,
+    BitwiseOr,
+    // pbrain commands, only recognized with Dialect::Pbrain
+    // start defining a procedure numbered by the cell at the data pointer
+    DefineProcedure,
+    EndProcedure,
+    // call the procedure numbered by the cell at the data pointer
+    CallProcedure,
+    // Brainfork `Y`, only recognized with Dialect::Brainfork, start another
+    // thread on the next cell
+    Fork,
+    // `#`, dump the start of memory to stderr, only recognized when hash debug
+    // is turned on (see BFInterpreter::set_hash_debug())
+    Debug,
+}
+
+
+impl Command {
+    // the character a command is written with
+    pub fn as_char (&self) -> char {
+        match self {
+            Command::MovePointerRight => '>',
+            Command::MovePointerLeft => '<',
+            Command::IncrementByte => '+',
+            Command::DecrementByte => '-',
+            Command::OutputByte => '.',
+            Command::InputByte => ',',
+            Command::JumpRightIfZero => '[',
+            Command::JumpLeftIfNonZero => ']',
+            Command::EndProgram => '@',
+            Command::StoreByte => '$',
+            Command::RetrieveByte => '!',
+            Command::ShiftRight => '}',
+            Command::ShiftLeft => '{',
+            Command::BitwiseNot => '~',
+            Command::BitwiseXor => '^',
+            Command::BitwiseAnd => '&',
+            Command::BitwiseOr => '|',
+            Command::DefineProcedure => '(',
+            Command::EndProcedure => ')',
+            Command::CallProcedure => ':',
+            Command::Fork => 'Y',
+            Command::Debug => '#',
+        }
+    }
+}
+
+
+// display a command as the character it is written with
+impl fmt::Display for Command {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_char())
+    }
+}
+
+
+// number of cells at the start of memory that the `#` debug command shows
+pub const HASH_DEBUG_CELLS: usize = 16;
+
+
+// instructions of the internal representation that programs are lowered to
+// before being executed, runs of identical commands can be folded into a
+// single Add or Move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    // add to the cell at the data pointer (with rollover)
+    Add(i32),
+    // move the data pointer
+    Move(isize),
+    OutputByte,
+    InputByte,
+    // jump to the index of the matching JumpIfNonZero if the cell is 0
+    JumpIfZero(usize),
+    // jump to the index of the matching JumpIfZero if the cell is not 0
+    JumpIfNonZero(usize),
+    // set the cell at the data pointer to 0, i.e. [-] or [+]
+    Clear,
+    // add the cell at the data pointer times a factor to the cell at an offset
+    // from it, a copy/multiply loop like [->+<] is a run of these then Clear
+    AddTo(isize, i32),
+    // the same as Add, Clear and OutputByte but on the cell at an offset from the
+    // data pointer, so that a move there and back like >+< leaves it where it is
+    AddAt(isize, i32),
+    ClearAt(isize),
+    OutputAt(isize),
+    // move the data pointer right (left) to the nearest cell holding 0, i.e. the
+    // body of [>] or [<]
+    ScanRight,
+    ScanLeft,
+    // Extended Brainfuck Type I, one for each of the commands
+    End,
+    Store,
+    Retrieve,
+    ShiftRight,
+    ShiftLeft,
+    Not,
+    Xor,
+    And,
+    Or,
+    // pbrain, define a procedure whose body runs up to the EndProc at the index
+    DefineProc(usize),
+    EndProc,
+    CallProc,
+    // Brainfork, zero the cell and start another thread with its data pointer
+    // on the next cell, set to 1
+    Fork,
+    // `#`, doesn't change anything but shows the state of the program
+    Debug,
+}
+
+
+// display an instruction like the command(s) it came from, with a count
+// for folded runs (e.g. +5, <3)
+impl fmt::Display for Instr {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instr::Add(1) => write!(f, "+"),
+            Instr::Add(-1) => write!(f, "-"),
+            Instr::Add(n) if *n < 0 => write!(f, "-{}", n.unsigned_abs()),
+            Instr::Add(n) => write!(f, "+{}", n),
+            Instr::Move(1) => write!(f, ">"),
+            Instr::Move(-1) => write!(f, "<"),
+            Instr::Move(n) if *n < 0 => write!(f, "<{}", n.unsigned_abs()),
+            Instr::Move(n) => write!(f, ">{}", n),
+            Instr::OutputByte => write!(f, "."),
+            Instr::InputByte => write!(f, ","),
+            Instr::JumpIfZero(_) => write!(f, "["),
+            Instr::JumpIfNonZero(_) => write!(f, "]"),
+            Instr::Clear => write!(f, "[-]"),
+            Instr::AddTo(offset, factor) => write!(f, "add_to({}, {})", offset, factor),
+            Instr::AddAt(offset, n) => write!(f, "add_at({}, {})", offset, n),
+            Instr::ClearAt(offset) => write!(f, "clear_at({})", offset),
+            Instr::OutputAt(offset) => write!(f, "out_at({})", offset),
+            Instr::ScanRight => write!(f, "scan(>)"),
+            Instr::ScanLeft => write!(f, "scan(<)"),
+            Instr::End => write!(f, "@"),
+            Instr::Store => write!(f, "$"),
+            Instr::Retrieve => write!(f, "!"),
+            Instr::ShiftRight => write!(f, "}}"),
+            Instr::ShiftLeft => write!(f, "{{"),
+            Instr::Not => write!(f, "~"),
+            Instr::Xor => write!(f, "^"),
+            Instr::And => write!(f, "&"),
+            Instr::Or => write!(f, "|"),
+            Instr::DefineProc(_) => write!(f, "("),
+            Instr::EndProc => write!(f, ")"),
+            Instr::CallProc => write!(f, ":"),
+            Instr::Fork => write!(f, "Y"),
+            Instr::Debug => write!(f, "#"),
+        }
+    }
+}
+
+
+// where a command is in the program source, both start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourcePos {
+    pub line: usize,
+    pub column: usize,
+}
+
+
+impl fmt::Display for SourcePos {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+
+// an instruction along with the index of the (first) command it came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Op {
+    pub instr: Instr,
+    pub position: usize,
+}
+
+
+// what to do with the current cell when `,` is executed
+// but the input source has no more bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EofBehavior {
+    // leave the cell unchanged
+    NoChange,
+    // set the cell to 0
+    Zero,
+    // set the cell to its maximum value (-1), i.e. 255 for 8 bit cells
+    MinusOne,
+    // stop the program with BFError::InputExhausted
+    Error,
+}
+
+
+// what happens when the data pointer moves off either end of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeMode {
+    // moving off either end is an error
+    Strict,
+    // moving off one end wraps around to the other
+    Wrapping,
+    // moving off the right end grows memory, moving off the left end is an error
+    Growable,
+    // moving off either end grows memory, cells to the left of where the
+    // program started get indexes from 0 as everything else moves along
+    Bidirectional,
 }
+
+
+// safety caps for running untrusted programs, each one that is hit stops the
+// program with its own error, None for no limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    // commands executed (BFError::StepLimitExceeded)
+    pub max_steps: Option<u64>,
+    // bytes written by `.` (BFError::OutputLimitExceeded)
+    pub max_output_bytes: Option<u64>,
+    // cells a growable tape may grow to hold (BFError::MemoryLimitExceeded), a
+    // bidirectional tape may allocate some spare ones beyond this on the left
+    pub max_mem_cells: Option<usize>,
+    // wall clock time for each call to run() (BFError::Timeout)
+    pub timeout: Option<Duration>,
+    // loops and procedures nested in the program when it's loaded, and pbrain
+    // procedure calls nested while it runs (BFError::LoopDepthExceeded)
+    pub max_loop_depth: Option<usize>,
+}
+
+
+// number of bits in each memory cell, values roll over past the maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellWidth {
+    U8,
+    U16,
+    U32,
+}
+
+
+impl CellWidth {
+    // largest value a cell can hold before rolling over to 0
+    pub fn max_value (&self) -> u32 {
+        match self {
+            CellWidth::U8 => u8::MAX as u32,
+            CellWidth::U16 => u16::MAX as u32,
+            CellWidth::U32 => u32::MAX,
+        }
+    }
+}
+
+
+// languages that are brainfuck with the commands spelled differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Brainfuck,
+    // Ook. Ook? and friends, each command is a pair of words
+    Ook,
+    // like Ook! but with Blub
+    Blub,
+    // brainfuck plus the Extended Brainfuck Type I commands @ $ ! } { ~ ^ & |
+    Extended,
+    // brainfuck plus procedures, ( ) and :
+    Pbrain,
+    // brainfuck plus threads sharing the tape, started with Y
+    Brainfork,
+}
+
+
+impl Dialect {
+    // input can be embedded after a `!` unless it's part of the dialect
+    pub fn allows_embedded_input (&self) -> bool {
+        matches!(self, Dialect::Brainfuck | Dialect::Pbrain | Dialect::Brainfork)
+    }
+}
+
+
+// how the interpreter executes a loaded program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    // execute one instruction at a time
+    Interpreter,
+    // compile the program to machine code when it is loaded
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+
+// a copy of a range of memory cells along with the data pointer location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDump {
+    // index of the first cell in the dump
+    pub start: usize,
+    pub cells: Vec<u32>,
+    pub data_ptr: usize,
+    pub cell_width: CellWidth,
+}
+
+
+// hex/ASCII view of the cells, one row per line with the index of its first cell,
+// the cell at the data pointer is bracketed
+impl fmt::Display for MemoryDump {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (digits, per_row) = match self.cell_width {
+            CellWidth::U8 => (2, 16),
+            CellWidth::U16 => (4, 8),
+            CellWidth::U32 => (8, 8),
+        };
+        for (row, chunk) in self.cells.chunks(per_row).enumerate() {
+            let first = self.start + row * per_row;
+            write!(f, "{:08x} ", first)?;
+            for (i, cell) in chunk.iter().enumerate() {
+                if first + i == self.data_ptr {
+                    write!(f, "[{:0w$x}]", cell, w = digits)?;
+                } else if first + i == self.data_ptr + 1 {
+                    write!(f, "{:0w$x}", cell, w = digits)?;
+                } else {
+                    write!(f, " {:0w$x}", cell, w = digits)?;
+                }
+            }
+            // pad out a short last row (or one not ending in a bracket) so the
+            // ASCII column lines up
+            let pad = (per_row - chunk.len()) * (digits + 1) + usize::from(first + chunk.len() != self.data_ptr + 1);
+            write!(f, "{:pad$} |", "", pad = pad)?;
+            for cell in chunk {
+                match u8::try_from(*cell) {
+                    Ok(b) if b.is_ascii_graphic() || b == b' ' => write!(f, "{}", b as char)?,
+                    _ => write!(f, ".")?,
+                };
+            }
+            writeln!(f, "|")?;
+        }
+        write!(f, "data pointer: {}", self.data_ptr)
+    }
+}
+
+
+// a view of one executed instruction, yielded by BFInterpreter::iter_steps()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionState {
+    // number of instructions executed before this one
+    pub step: u64,
+    // index of the instruction, and the instruction itself
+    pub index: usize,
+    pub instr: Instr,
+    // data pointer once the instruction has executed
+    pub data_ptr: usize,
+    // index and new value of the memory cell the instruction changed, if any
+    pub changed: Option<(usize, u32)>,
+}
+
+
+// why BFInterpreter::run_for() stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    // the program ran to the end
+    Finished,
+    // it used up the instructions it was given, and can be carried on
+    Paused,
+    // a `,` is waiting for input that isn't ready yet
+    NeedsInput,
+    // it stopped on an error
+    Error(BFError),
+}
+
+
+// statistics about a program since it was loaded, returned by BFInterpreter::run_with_report()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunReport {
+    // how the run ended
+    pub result: Result<(), BFError>,
+    pub instructions_executed: u64,
+    // number of distinct cells the program visited or changed
+    pub cells_touched: usize,
+    // furthest right the data pointer went
+    pub max_data_ptr: usize,
+    pub bytes_output: u64,
+    pub bytes_input: u64,
+    // wall clock time of the run_with_report() call
+    pub elapsed: Duration,
+}
+
+
+impl RunReport {
+    // report for a program that never got to run, e.g. because it didn't load
+    pub fn not_run (error: BFError) -> RunReport {
+        RunReport {
+            result: Err(error),
+            instructions_executed: 0,
+            cells_touched: 0,
+            max_data_ptr: 0,
+            bytes_output: 0,
+            bytes_input: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+
+impl fmt::Display for RunReport {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "instructions executed: {}", self.instructions_executed)?;
+        writeln!(f, "cells touched: {}", self.cells_touched)?;
+        writeln!(f, "max data pointer: {}", self.max_data_ptr)?;
+        writeln!(f, "bytes output: {}", self.bytes_output)?;
+        writeln!(f, "bytes input: {}", self.bytes_input)?;
+        write!(f, "time: {:.3?}", self.elapsed)
+    }
+}
+
+
+// errors that can stop a program from loading or running, each carries the
+// index of the command that caused it and, once it is known, the line and
+// column of that command in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BFError {
+    // data pointer moved past the end of memory
+    PointerOverrun { position: usize, source: Option<SourcePos> },
+    // data pointer moved before the start of memory
+    PointerUnderrun { position: usize, source: Option<SourcePos> },
+    // a [ or ] without a partner
+    UnmatchedBracket { bracket: char, position: usize, source: Option<SourcePos> },
+    // `,` executed with nothing left to read and EofBehavior::Error set
+    InputExhausted { position: usize, source: Option<SourcePos> },
+    // reading input or writing output failed
+    Io { position: usize, source: Option<SourcePos>, msg: String },
+    // the program executed the maximum number of commands it was allowed
+    StepLimitExceeded { position: usize, source: Option<SourcePos>, limit: u64 },
+    // the program ran for longer than it was allowed to by run_with_timeout()
+    Timeout { position: usize, source: Option<SourcePos>, limit: Duration },
+    // the interrupt flag was set while the program was running (e.g. by Ctrl-C)
+    Interrupted { position: usize, source: Option<SourcePos> },
+    // pbrain `:` with no procedure defined for the value in the cell
+    UndefinedProcedure { position: usize, source: Option<SourcePos>, id: u32 },
+    // the program tried to output more bytes than it was allowed
+    OutputLimitExceeded { position: usize, source: Option<SourcePos>, limit: u64 },
+    // the program tried to grow memory past the number of cells it was allowed
+    MemoryLimitExceeded { position: usize, source: Option<SourcePos>, limit: usize },
+    // a loop or procedure nested, or a procedure called, more deeply than allowed
+    LoopDepthExceeded { position: usize, source: Option<SourcePos>, limit: usize },
+}
+
+
+impl BFError {
+    // short name for the kind of error, e.g. for machine readable output
+    pub fn kind (&self) -> &'static str {
+        match self {
+            BFError::PointerOverrun { .. } => "pointer_overrun",
+            BFError::PointerUnderrun { .. } => "pointer_underrun",
+            BFError::UnmatchedBracket { .. } => "unmatched_bracket",
+            BFError::InputExhausted { .. } => "input_exhausted",
+            BFError::Io { .. } => "io",
+            BFError::StepLimitExceeded { .. } => "step_limit_exceeded",
+            BFError::Timeout { .. } => "timeout",
+            BFError::Interrupted { .. } => "interrupted",
+            BFError::UndefinedProcedure { .. } => "undefined_procedure",
+            BFError::OutputLimitExceeded { .. } => "output_limit_exceeded",
+            BFError::MemoryLimitExceeded { .. } => "memory_limit_exceeded",
+            BFError::LoopDepthExceeded { .. } => "loop_depth_exceeded",
+        }
+    }
+
+    // exit code for a command line tool stopped by the error, 130 for an
+    // interrupt like a shell gives a process killed by Ctrl-C, otherwise 1
+    pub fn exit_code (&self) -> i32 {
+        match self {
+            BFError::Interrupted { .. } => 130,
+            _ => 1,
+        }
+    }
+
+    // index of the command that caused the error
+    pub fn position (&self) -> usize {
+        match self {
+            BFError::PointerOverrun { position, .. }
+            | BFError::PointerUnderrun { position, .. }
+            | BFError::UnmatchedBracket { position, .. }
+            | BFError::InputExhausted { position, .. }
+            | BFError::Io { position, .. }
+            | BFError::StepLimitExceeded { position, .. }
+            | BFError::Timeout { position, .. }
+            | BFError::Interrupted { position, .. }
+            | BFError::UndefinedProcedure { position, .. }
+            | BFError::OutputLimitExceeded { position, .. }
+            | BFError::MemoryLimitExceeded { position, .. }
+            | BFError::LoopDepthExceeded { position, .. } => *position,
+        }
+    }
+
+    // line and column of the command that caused the error, if known
+    pub fn source (&self) -> Option<SourcePos> {
+        match self {
+            BFError::PointerOverrun { source, .. }
+            | BFError::PointerUnderrun { source, .. }
+            | BFError::UnmatchedBracket { source, .. }
+            | BFError::InputExhausted { source, .. }
+            | BFError::Io { source, .. }
+            | BFError::StepLimitExceeded { source, .. }
+            | BFError::Timeout { source, .. }
+            | BFError::Interrupted { source, .. }
+            | BFError::UndefinedProcedure { source, .. }
+            | BFError::OutputLimitExceeded { source, .. }
+            | BFError::MemoryLimitExceeded { source, .. }
+            | BFError::LoopDepthExceeded { source, .. } => *source,
+        }
+    }
+
+    // fill in the line and column of the error from the positions of every
+    // command in the program (see parsing::command_positions())
+    pub fn locate (mut self, positions: &[SourcePos]) -> BFError {
+        let found = positions.get(self.position()).copied();
+        match &mut self {
+            BFError::PointerOverrun { source, .. }
+            | BFError::PointerUnderrun { source, .. }
+            | BFError::UnmatchedBracket { source, .. }
+            | BFError::InputExhausted { source, .. }
+            | BFError::Io { source, .. }
+            | BFError::StepLimitExceeded { source, .. }
+            | BFError::Timeout { source, .. }
+            | BFError::Interrupted { source, .. }
+            | BFError::UndefinedProcedure { source, .. }
+            | BFError::OutputLimitExceeded { source, .. }
+            | BFError::MemoryLimitExceeded { source, .. }
+            | BFError::LoopDepthExceeded { source, .. } => *source = found,
+        };
+        self
+    }
+}
+
+
+impl fmt::Display for BFError {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BFError::PointerOverrun { position, .. } => {
+                write!(f, "data pointer overran available memory at command {}", position)?;
+            },
+            BFError::PointerUnderrun { position, .. } => {
+                write!(f, "data pointer underran available memory at command {}", position)?;
+            },
+            BFError::UnmatchedBracket { bracket: '[', position, .. } => {
+                write!(f, "could not find closing ] for [ at command {}", position)?;
+            },
+            BFError::UnmatchedBracket { bracket: '(', position, .. } => {
+                write!(f, "could not find closing ) for ( at command {}", position)?;
+            },
+            BFError::UnmatchedBracket { bracket, position, .. } => {
+                write!(f, "unmatched {} at command {}", bracket, position)?;
+            },
+            BFError::InputExhausted { position, .. } => {
+                write!(f, "input exhausted at command {}", position)?;
+            },
+            BFError::Io { position, msg, .. } => {
+                write!(f, "I/O error at command {}: {}", position, msg)?;
+            },
+            BFError::StepLimitExceeded { position, limit, .. } => {
+                write!(f, "step limit of {} exceeded at command {}", limit, position)?;
+            },
+            BFError::Timeout { position, limit, .. } => {
+                write!(f, "timed out after {:?} at command {}", limit, position)?;
+            },
+            BFError::Interrupted { position, .. } => {
+                write!(f, "interrupted at command {}", position)?;
+            },
+            BFError::UndefinedProcedure { position, id, .. } => {
+                write!(f, "call to undefined procedure {} at command {}", id, position)?;
+            },
+            BFError::OutputLimitExceeded { position, limit, .. } => {
+                write!(f, "output limit of {} bytes exceeded at command {}", limit, position)?;
+            },
+            BFError::MemoryLimitExceeded { position, limit, .. } => {
+                write!(f, "memory limit of {} cells exceeded at command {}", limit, position)?;
+            },
+            BFError::LoopDepthExceeded { position, limit, .. } => {
+                write!(f, "nesting deeper than {} at command {}", limit, position)?;
+            },
+        };
+        if let Some(source) = self.source() {
+            write!(f, " (line {}, column {})", source.line, source.column)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl error::Error for BFError {}
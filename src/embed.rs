@@ -0,0 +1,174 @@
+/*
+    Module with the bf! macro for embedding brainfuck programs in rust
+    source, programs are parsed and checked while compiling so that they
+    can be loaded without any errors at runtime
+
+        const HELLO: [rust_bfi::Op; 10] = rust_bfi::bf!("++++++++[>++++++++<-]>+.");
+        let mut bfi = rust_bfi::BFInterpreter::new(30000);
+        bfi.load_instructions(&HELLO);
+        bfi.run()?;
+
+    everything here has to be a const fn, so the work is done by hand with
+    fixed size arrays rather than reusing the parser and optimizer
+*/
+
+
+use crate::dtypes;
+
+
+// lower a brainfuck program into a const array of instructions at compile time,
+// folding runs of identical +, -, >, < commands like the optimizer does (loop
+// idioms are left as loops), characters that aren't commands are comments and
+// unbalanced brackets stop the build
+#[macro_export]
+macro_rules! bf {
+    ($prog:expr) => {{
+        const OPS: [$crate::dtypes::Op; $crate::embed::instruction_count($prog)] = $crate::embed::lower($prog);
+        OPS
+    }};
+}
+
+
+// the instruction a brainfuck command lowers to, None for comments
+const fn lower_byte (byte: u8) -> Option<dtypes::Instr> {
+    match byte {
+        b'>' => Option::Some(dtypes::Instr::Move(1)),
+        b'<' => Option::Some(dtypes::Instr::Move(-1)),
+        b'+' => Option::Some(dtypes::Instr::Add(1)),
+        b'-' => Option::Some(dtypes::Instr::Add(-1)),
+        b'.' => Option::Some(dtypes::Instr::OutputByte),
+        b',' => Option::Some(dtypes::Instr::InputByte),
+        b'[' => Option::Some(dtypes::Instr::JumpIfZero(0)),
+        b']' => Option::Some(dtypes::Instr::JumpIfNonZero(0)),
+        _ => Option::None,
+    }
+}
+
+
+// whether a command continues a run started by the previous one
+const fn folds (prev: Option<u8>, byte: u8) -> bool {
+    match prev {
+        Some(prev) => prev == byte && matches!(byte, b'>' | b'<' | b'+' | b'-'),
+        None => false,
+    }
+}
+
+
+// number of instructions a program lowers to, panics (failing the build when
+// used in a const) if the brackets are unbalanced
+pub const fn instruction_count (prog: &str) -> usize {
+    let bytes = prog.as_bytes();
+    let (mut count, mut depth) = (0_usize, 0_usize);
+    let mut prev: Option<u8> = Option::None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        i += 1;
+        if lower_byte(byte).is_none() {
+            continue;
+        }
+        if byte == b'[' {
+            depth += 1;
+        } else if byte == b']' {
+            if depth == 0 {
+                panic!("bf! program has a ] without a matching [");
+            }
+            depth -= 1;
+        }
+        if !folds(prev, byte) {
+            count += 1;
+        }
+        prev = Option::Some(byte);
+    }
+    if depth != 0 {
+        panic!("bf! program has a [ without a matching ]");
+    }
+    count
+}
+
+
+// lower a program into exactly N instructions with linked jumps, N has to
+// come from instruction_count() for the same program
+pub const fn lower<const N: usize> (prog: &str) -> [dtypes::Op; N] {
+    let bytes = prog.as_bytes();
+    let mut ops = [dtypes::Op { instr: dtypes::Instr::End, position: 0 }; N];
+    // indexes of the [ instructions still waiting for their ]
+    let mut open = [0_usize; N];
+    let (mut n, mut depth, mut position) = (0_usize, 0_usize, 0_usize);
+    let mut prev: Option<u8> = Option::None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        i += 1;
+        let instr = match lower_byte(byte) {
+            Some(instr) => instr,
+            None => continue,
+        };
+        if folds(prev, byte) {
+            ops[n - 1].instr = match ops[n - 1].instr {
+                dtypes::Instr::Add(m) => dtypes::Instr::Add(m.wrapping_add(if byte == b'+' { 1 } else { -1 })),
+                dtypes::Instr::Move(m) => dtypes::Instr::Move(m + if byte == b'>' { 1 } else { -1 }),
+                other => other,
+            };
+        } else {
+            if n == N {
+                panic!("bf! program lowers to more instructions than expected");
+            }
+            ops[n] = dtypes::Op { instr, position };
+            if byte == b'[' {
+                open[depth] = n;
+                depth += 1;
+            } else if byte == b']' {
+                if depth == 0 {
+                    panic!("bf! program has a ] without a matching [");
+                }
+                depth -= 1;
+                ops[n].instr = dtypes::Instr::JumpIfNonZero(open[depth]);
+                ops[open[depth]].instr = dtypes::Instr::JumpIfZero(n);
+            }
+            n += 1;
+        }
+        position += 1;
+        prev = Option::Some(byte);
+    }
+    if depth != 0 {
+        panic!("bf! program has a [ without a matching ]");
+    }
+    if n != N {
+        panic!("bf! program lowers to fewer instructions than expected");
+    }
+    ops
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{optimizer, parsing};
+
+    #[test]
+    fn bf_macro_lowers_at_compile_time () {
+        // the same instructions as optimizing at runtime, for a loop that isn't an idiom
+        const PROG: &str = "++ a comment [->>+<.] >>. <<<";
+        const OPS: [dtypes::Op; 11] = bf!(PROG);
+        assert_eq!(OPS.to_vec(), optimizer::optimize(&parsing::parse_program(PROG)).unwrap());
+        assert_eq!(OPS[1], dtypes::Op { instr: dtypes::Instr::JumpIfZero(7), position: 2 });
+        assert_eq!(OPS[10], dtypes::Op { instr: dtypes::Instr::Move(-3), position: 13 });
+        assert_eq!(bf!("").len(), 0);
+    }
+
+    #[test]
+    fn bf_macro_counts () {
+        assert_eq!(instruction_count("+++>>>---<<<"), 4);
+        assert_eq!(instruction_count("+-+-"), 4);
+        assert_eq!(instruction_count("[[]]..,,"), 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "without a matching [")]
+    fn bf_macro_unbalanced () {
+        // only a runtime panic here, as a const it would fail the build
+        instruction_count("+]");
+    }
+}
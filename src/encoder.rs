@@ -0,0 +1,160 @@
+/*
+    Module for generating a program that prints a given text, reasonably
+    short rather than the shortest possible: a multiplication loop first
+    sets a few cells to values spread across the bytes the text uses, then
+    each byte is printed from whichever cell is cheapest to move to and
+    count up or down from, leaving it holding that byte for the next time
+
+        let prog = rust_bfi::encoder::encode(b"Hello, World!");
+
+    every candidate set up is tried and the one giving the fewest commands
+    wins, the programs only count towards the bytes they print and never
+    wrap around, so they print the same text whatever the cell width
+*/
+
+
+// most cells set up before printing, more than this rarely pays for itself
+const MAX_CELLS: usize = 8;
+
+
+// largest number of times the set up loop goes round
+const MAX_FACTOR: u32 = 20;
+
+
+// the text's distinct bytes split into (at most) count runs of similar values,
+// the middle of each run
+fn centres (text: &[u8], count: usize) -> Vec<u32> {
+    let mut distinct: Vec<u32> = text.iter().map(|&b| b as u32).collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let count = count.min(distinct.len()).max(1);
+    (0..count)
+        .map(|i| {
+            let run = &distinct[i * distinct.len() / count..(i + 1) * distinct.len() / count];
+            run[run.len() / 2]
+        })
+        .collect()
+}
+
+
+// the commands to add n to a cell (n < 0 subtracts)
+fn add (out: &mut String, n: i64) {
+    let c = if n < 0 { '-' } else { '+' };
+    out.extend(std::iter::repeat_n(c, n.unsigned_abs() as usize));
+}
+
+
+// the commands to move the data pointer by n
+fn move_by (out: &mut String, n: i64) {
+    let c = if n < 0 { '<' } else { '>' };
+    out.extend(std::iter::repeat_n(c, n.unsigned_abs() as usize));
+}
+
+
+// a program printing text after setting cells 1 onwards near targets, with a
+// loop going round factor times (or straight out when factor is 1)
+fn encode_with (text: &[u8], targets: &[u32], factor: u32) -> String {
+    let mut out = String::new();
+    // the value of each cell, cell 0 is the loop's counter
+    let mut cells: Vec<i64> = vec![0];
+    let mut at: i64 = 0;
+    if factor > 1 {
+        add(&mut out, factor as i64);
+        out.push('[');
+        for &target in targets {
+            let times = ((target + factor / 2) / factor) as i64;
+            out.push('>');
+            add(&mut out, times);
+            cells.push(times * factor as i64);
+        }
+        move_by(&mut out, -(targets.len() as i64));
+        out.push_str("-]");
+    } else {
+        for &target in targets {
+            out.push('>');
+            add(&mut out, target as i64);
+            cells.push(target as i64);
+        }
+        at = targets.len() as i64;
+    }
+    for &byte in text {
+        let byte = byte as i64;
+        let cost = |(cell, &value): (usize, &i64)| (cell as i64 - at).abs() + (value - byte).abs();
+        let (cell, _) = cells.iter().enumerate().min_by_key(|&entry| cost(entry)).unwrap_or((0, &0));
+        move_by(&mut out, cell as i64 - at);
+        add(&mut out, byte - cells[cell]);
+        out.push('.');
+        cells[cell] = byte;
+        at = cell as i64;
+    }
+    out
+}
+
+
+// a program that prints text (nothing for empty text), counting up to each
+// byte so it never relies on cells wrapping around
+pub fn encode (text: &[u8]) -> String {
+    if text.is_empty() {
+        return String::new();
+    }
+    let mut best = encode_with(text, &[], 1);
+    for count in 1..=MAX_CELLS {
+        let targets = centres(text, count);
+        for factor in 1..=MAX_FACTOR {
+            let prog = encode_with(text, &targets, factor);
+            if prog.len() < best.len() {
+                best = prog;
+            }
+        }
+        // every distinct byte already has a cell of its own
+        if targets.len() < count {
+            break;
+        }
+    }
+    best
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{dtypes, interpreter, optimizer, parsing};
+
+    // what a program prints with cells of the given width
+    fn run (prog: &str, cell_width: dtypes::CellWidth) -> Vec<u8> {
+        let mut bfi = interpreter::BFInterpreter::with_config(100, dtypes::TapeMode::Strict, cell_width);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.load_program(prog).unwrap();
+        bfi.run().unwrap();
+        bfi.output().to_vec()
+    }
+
+    #[test]
+    fn encode_prints_text () {
+        let texts: [&[u8]; 5] = [b"Hello, World!", b"a", b"zzzzzzzzzzzz", b"\x00\x01\xff\x80 \n", b"The quick brown fox jumps over the lazy dog\n"];
+        for text in texts {
+            let prog = encode(text);
+            for cell_width in [dtypes::CellWidth::U8, dtypes::CellWidth::U16, dtypes::CellWidth::U32] {
+                assert_eq!(run(&prog, cell_width), text, "{}", prog);
+            }
+        }
+        assert_eq!(encode(b""), "");
+        // repeats of a byte just print the same cell again
+        assert_eq!(encode(b"AAA"), "++++++++[>++++++++<-]>+...");
+    }
+
+    #[test]
+    fn encode_is_short () {
+        let prog = encode(b"Hello, World!");
+        assert!(prog.len() < 130, "{} commands: {}", prog.len(), prog);
+        // far shorter than counting up to each byte from 0, and the set up loop
+        // becomes a handful of multiplications
+        let text = b"The quick brown fox jumps over the lazy dog\n";
+        let naive: usize = text.iter().map(|&b| b as usize + 3).sum();
+        assert!(encode(text).len() * 4 < naive);
+        let ops = optimizer::optimize(&parsing::parse_program(&prog)).unwrap();
+        assert!(matches!(ops[1].instr, dtypes::Instr::AddTo(..)));
+        assert!(!ops.iter().any(|op| matches!(op.instr, dtypes::Instr::JumpIfZero(_))));
+    }
+}
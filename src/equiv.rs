@@ -0,0 +1,162 @@
+/*
+    Module for checking whether two programs behave the same, e.g. after
+    hand optimizing one: both are run as a batch on each of a set of inputs
+    with the same limits, then their output and the way they finished are
+    compared with the cross-validation engine
+
+        let inputs = rust_bfi::equiv::read_inputs(std::path::Path::new("corpus"))?;
+        let checks = rust_bfi::equiv::check(a, b, &inputs, 1, || rust_bfi::BFInterpreter::new(30000));
+        let same = checks.iter().all(|check| check.divergence.is_none());
+
+    the tapes are not compared, two programs doing the same thing need not use
+    memory the same way, and neither are the positions in errors, which point
+    at different commands in each program
+*/
+
+
+use std::{fs, io};
+use std::path::Path;
+
+use crate::{batch, crossval, interpreter};
+
+
+// step limit programs are checked with unless given another, so a program
+// that never finishes does not stop the check from finishing
+pub const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+
+// how the two programs did on one input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    // name of the input, e.g. its file name
+    pub name: String,
+    pub a: crossval::Outcome,
+    pub b: crossval::Outcome,
+    // the first way b differs from a, None if they behaved the same
+    pub divergence: Option<crossval::Divergence>,
+}
+
+
+// every file in a directory (not its subdirectories) as an input named after
+// the file, sorted by name
+pub fn read_inputs (dir: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut inputs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        inputs.push((name, fs::read(&path)?));
+    }
+    inputs.sort();
+    Ok(inputs)
+}
+
+
+// the outcome of a program run in a batch, without the tape which isn't kept
+fn outcome (engine: &str, result: batch::BatchResult) -> crossval::Outcome {
+    crossval::Outcome {
+        engine: engine.to_string(),
+        result: result.report.result,
+        output: result.output,
+        cells: Vec::new(),
+        origin: 0,
+        data_ptr: 0,
+    }
+}
+
+
+// run programs a and b on each input, on up to threads threads, with an
+// interpreter from setup for every run (neither program is split at a `!`,
+// the input is all given here) and compare how they did, errors are
+// compared by kind, and when both hit the step limit only the output they
+// both got to is compared
+pub fn check<F: Fn() -> interpreter::BFInterpreter + Send + Sync + 'static> (a: &str, b: &str, inputs: &[(String, Vec<u8>)], threads: usize, setup: F) -> Vec<Check> {
+    let mut batch = batch::Batch::new(move || {
+        let mut bfi = setup();
+        bfi.set_split_input(false);
+        bfi
+    });
+    for (name, input) in inputs {
+        batch.add(name, a, Option::Some(input));
+        batch.add(name, b, Option::Some(input));
+    }
+    batch.set_threads(threads);
+    let mut results = batch.run().into_iter();
+    let mut checks = Vec::new();
+    while let (Some(ra), Some(rb)) = (results.next(), results.next()) {
+        let name = ra.name.clone();
+        let (a, b) = (outcome("a", ra), outcome("b", rb));
+        let mut compared = b.clone();
+        if let (Err(expected), Err(e)) = (&a.result, &b.result) {
+            if e.kind() == expected.kind() {
+                compared.result = a.result.clone();
+            }
+        }
+        // a program stopped by the step limit only has its output so far compared,
+        // but the other one finishing (or failing) when it didn't is a difference
+        let divergence = crossval::compare(&a, &compared).or_else(|| {
+            (a.result != compared.result).then(|| crossval::Divergence::Result { expected: a.result.clone(), actual: b.result.clone() })
+        });
+        checks.push(Check { name, a, b, divergence });
+    }
+    checks
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::dtypes;
+
+    fn inputs () -> Vec<(String, Vec<u8>)> {
+        vec![(String::from("empty"), Vec::new()), (String::from("text"), b"hello".to_vec())]
+    }
+
+    fn run (a: &str, b: &str) -> Vec<Option<crossval::Divergence>> {
+        let checks = check(a, b, &inputs(), 2, || {
+            let mut bfi = interpreter::BFInterpreter::new(100);
+            bfi.set_max_steps(Some(10_000));
+            bfi
+        });
+        assert_eq!(checks.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), ["empty", "text"]);
+        checks.into_iter().map(|c| c.divergence).collect()
+    }
+
+    #[test]
+    fn equiv_same_behavior () {
+        // the same thing done with different memory and commands
+        assert_eq!(run(",[.,]", ",[>+>+<<.,]  copies!ignored"), [None, None]);
+        assert_eq!(run("++++++++[>++++++++<-]>+.", "+++++++++++++[>+++++<-]>.").iter().flatten().count(), 0);
+        // both overrun, at different commands
+        assert_eq!(run(",<", ">>,<<<"), [None, None]);
+        // both run forever, printing the same so far
+        assert_eq!(run("+[.]", "+[>+<.]"), [None, None]);
+    }
+
+    #[test]
+    fn equiv_different_behavior () {
+        let diffs = run(",[.,]", ",[+.,]");
+        assert_eq!(diffs[0], None);
+        assert_eq!(diffs[1], Some(crossval::Divergence::Output { index: 0, expected: Some(b'h'), actual: Some(b'i') }));
+        // one stops early
+        assert!(matches!(run(",.,.", ",.")[1], Some(crossval::Divergence::Output { index: 1, expected: Some(b'e'), actual: None })));
+        // one finishes, the other runs forever
+        assert!(matches!(run("+", "+[]")[0], Some(crossval::Divergence::Result { expected: Ok(()), actual: Err(dtypes::BFError::StepLimitExceeded { .. }) })));
+        assert!(matches!(run("+", "[")[0], Some(crossval::Divergence::Result { actual: Err(dtypes::BFError::UnmatchedBracket { .. }), .. })));
+    }
+
+    #[test]
+    fn equiv_read_inputs () {
+        let dir = std::env::temp_dir().join(format!("rust_bfi_equiv_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("b.txt"), "bee").unwrap();
+        fs::write(dir.join("a"), "").unwrap();
+        let inputs = read_inputs(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(inputs.unwrap(), [(String::from("a"), Vec::new()), (String::from("b.txt"), b"bee".to_vec())]);
+        assert!(read_inputs(&dir).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+/*
+    Module with a pretty-printer that lays out program source by loop nesting
+*/
+
+
+use crate::{dtypes, parsing};
+
+
+// how formatted source is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    // spaces per level of loop nesting
+    pub indent: usize,
+    // drop everything that isn't a command
+    pub strip_comments: bool,
+    // wrap lines longer than this (indentation included) where possible
+    pub width: Option<usize>,
+}
+
+
+impl Default for Config {
+    fn default () -> Config {
+        Config { indent: 4, strip_comments: false, width: Option::None }
+    }
+}
+
+
+// builds up formatted source one indented line at a time
+struct Layout<'a> {
+    config: &'a Config,
+    src: String,
+    depth: usize,
+    // text waiting to go out on the current line
+    line: String,
+}
+
+
+impl Layout<'_> {
+    fn push_line (&mut self, text: &str) {
+        self.src.push_str(&" ".repeat(self.config.indent * self.depth));
+        self.src.push_str(text);
+        self.src.push('\n');
+    }
+
+    // write out the pending text, wrapped to the configured width
+    fn flush (&mut self) {
+        let text = self.line.trim().to_string();
+        self.line.clear();
+        // always fit at least one character on a line however deep the nesting
+        let room = self.config.width.map_or(usize::MAX, |w| w.saturating_sub(self.config.indent * self.depth).max(1));
+        let mut rest = text.as_str();
+        while let Some((cut, _)) = rest.char_indices().nth(room) {
+            // break at the last space that fits, or in the middle of a run of
+            // commands if there isn't one
+            let split = if rest[cut..].starts_with(' ') {
+                cut
+            } else {
+                rest[..cut].rfind(' ').filter(|&i| i > 0).unwrap_or(cut)
+            };
+            self.push_line(rest[..split].trim_end());
+            rest = rest[split..].trim_start();
+        }
+        if !rest.is_empty() {
+            self.push_line(rest);
+        }
+    }
+}
+
+
+// lay out a program with every [ and ] on a line of its own and everything
+// between them indented by loop depth, line breaks in the original are kept and
+// blank lines dropped, a program with unmatched brackets is rejected with all
+// of them (as from parsing::find_bracket_errors())
+pub fn format (prog: &str, config: &Config) -> Result<String, Vec<dtypes::BFError>> {
+    let errors = parsing::find_bracket_errors(&parsing::parse_program(prog));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut layout = Layout { config, src: String::new(), depth: 0, line: String::new() };
+    for c in prog.chars() {
+        match c {
+            '[' => {
+                layout.flush();
+                layout.push_line("[");
+                layout.depth += 1;
+            },
+            ']' => {
+                layout.flush();
+                layout.depth -= 1;
+                layout.push_line("]");
+            },
+            '\n' => layout.flush(),
+            _ if c.is_ascii() && parsing::byte_to_command(c as u8).is_some() => layout.line.push(c),
+            _ if !config.strip_comments => layout.line.push(c),
+            _ => {},
+        };
+    }
+    layout.flush();
+    Ok(layout.src)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn format_nesting () {
+        let prog = "++ set up [>+ copy [>+<-]<-]>.\n\n  done";
+        let formatted = format(prog, &Config::default()).unwrap();
+        assert_eq!(formatted, "\
+++ set up
+[
+    >+ copy
+    [
+        >+<-
+    ]
+    <-
+]
+>.
+done
+");
+        // the commands are untouched and formatting again changes nothing
+        assert_eq!(parsing::parse_program(&formatted), parsing::parse_program(prog));
+        assert_eq!(format(&formatted, &Config::default()).unwrap(), formatted);
+        let config = Config { indent: 2, strip_comments: true, width: None };
+        assert_eq!(format(prog, &config).unwrap(), "++\n[\n  >+\n  [\n    >+<-\n  ]\n  <-\n]\n>.\n");
+    }
+
+    #[test]
+    fn format_wrapping () {
+        let config = Config { indent: 2, strip_comments: false, width: Some(8) };
+        assert_eq!(format("++++++++++++ add twelve", &config).unwrap(), "++++++++\n++++ add\ntwelve\n");
+        // nesting deeper than the width still makes progress
+        assert_eq!(format("[[[[+-]]]]", &Config { width: Some(4), ..config }).unwrap(), "\
+[
+  [
+    [
+      [
+        +
+        -
+      ]
+    ]
+  ]
+]
+");
+    }
+
+    #[test]
+    fn format_unbalanced () {
+        assert_eq!(format("+]\n[", &Config::default()), Err(vec![
+            dtypes::BFError::UnmatchedBracket { bracket: ']', position: 1, source: None },
+            dtypes::BFError::UnmatchedBracket { bracket: '[', position: 2, source: None },
+        ]));
+    }
+}
@@ -0,0 +1,131 @@
+/*
+    Module for generating random brainfuck programs that are always
+    syntactically valid (balanced brackets no deeper than a limit), e.g. for
+    property testing or fuzzing other interpreters. The same seed always
+    gives the same program, so a seed strategy from proptest or quickcheck
+    can be mapped straight onto programs:
+
+        let config = rust_bfi::generator::Config { len: 200, ..Default::default() };
+        let prog = rust_bfi::generator::generate(seed, &config);
+
+    the programs are random, so they may well loop forever or run off the
+    end of memory, run them with a step limit
+*/
+
+
+use crate::bfio;
+
+
+// what kind of programs to generate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    // number of commands in each program
+    pub len: usize,
+    // deepest that loops are nested
+    pub max_depth: usize,
+    // whether to use the . and , commands
+    pub io: bool,
+}
+
+
+impl Default for Config {
+    fn default () -> Config {
+        Config { len: 100, max_depth: 4, io: true }
+    }
+}
+
+
+// stream of random programs generated from a seed
+#[derive(Debug, Clone)]
+pub struct Generator {
+    state: u64,
+    config: Config,
+}
+
+
+impl Generator {
+    pub fn new (seed: u64, config: Config) -> Generator {
+        Generator { state: seed, config }
+    }
+
+    // random number below n
+    fn below (&mut self, n: usize) -> usize {
+        (bfio::splitmix64(&mut self.state) % n as u64) as usize
+    }
+
+    // the next program, exactly config.len commands long
+    pub fn program (&mut self) -> String {
+        let ops: &[u8] = if self.config.io { b"+-<>.," } else { b"+-<>" };
+        let len = self.config.len;
+        let mut prog = String::with_capacity(len);
+        let mut depth = 0;
+        let mut prev = b' ';
+        for i in 0..len {
+            let left = len - i;
+            // every open loop has to be closed by the end, and a loop needs room
+            // for at least one command in its body (empty loops never end)
+            let can_open = depth < self.config.max_depth && left >= depth + 3;
+            let can_close = depth > 0 && prev != b'[';
+            let byte = match self.below(10) {
+                _ if can_close && left == depth => b']',
+                0 if can_open => b'[',
+                1 if can_close => b']',
+                _ => ops[self.below(ops.len())],
+            };
+            match byte {
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                _ => {},
+            };
+            prog.push(byte as char);
+            prev = byte;
+        }
+        prog
+    }
+}
+
+
+// a single program generated from a seed
+pub fn generate (seed: u64, config: &Config) -> String {
+    Generator::new(seed, config.clone()).program()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::parsing;
+
+    #[test]
+    fn generated_programs_are_valid () {
+        for seed in 0..200 {
+            let config = Config { len: 1 + seed as usize % 40, max_depth: 1 + seed as usize % 3, io: seed % 2 == 0 };
+            let prog = generate(seed, &config);
+            assert_eq!(prog.len(), config.len);
+            assert!(parsing::find_bracket_errors(&parsing::parse_program(&prog)).is_empty(), "{}", prog);
+            assert!(!prog.contains("[]"), "{}", prog);
+            let (mut depth, mut deepest) = (0_usize, 0_usize);
+            for c in prog.chars() {
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ => {},
+                };
+                deepest = deepest.max(depth);
+            }
+            assert!(deepest <= config.max_depth, "{}", prog);
+        }
+    }
+
+    #[test]
+    fn generated_programs_are_reproducible () {
+        let config = Config::default();
+        assert_eq!(generate(7, &config), generate(7, &config));
+        assert_ne!(generate(7, &config), generate(8, &config));
+        let mut gen = Generator::new(7, config.clone());
+        assert_eq!(gen.program(), generate(7, &config));
+        assert_ne!(gen.program(), generate(7, &config));
+        assert!(!generate(3, &Config { io: false, ..config }).contains(['.', ',']));
+    }
+}
@@ -0,0 +1,118 @@
+/*
+    Module for golden file tests, where a directory holds programs along
+    with the input to give them and the output they should produce:
+
+        hello.bf        the program
+        hello.in        its input (optional, no input if missing)
+        hello.expected  exactly what it should output
+*/
+
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+
+// a program found in a test directory and the files that go with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenTest {
+    // file name of the program
+    pub name: String,
+    pub program: PathBuf,
+    // None if the files don't exist
+    pub input: Option<PathBuf>,
+    pub expected: Option<PathBuf>,
+}
+
+
+// every *.bf program in a directory (not its subdirectories), sorted by name
+pub fn find_tests (dir: &Path) -> io::Result<Vec<GoldenTest>> {
+    let mut tests = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let program = entry?.path();
+        if !program.is_file() || program.extension().is_none_or(|ext| ext != "bf") {
+            continue;
+        }
+        let sibling = |ext: &str| Option::Some(program.with_extension(ext)).filter(|path| path.is_file());
+        tests.push(GoldenTest {
+            name: program.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            input: sibling("in"),
+            expected: sibling("expected"),
+            program,
+        });
+    }
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tests)
+}
+
+
+// line by line differences between the expected and actual output, with the
+// lines that only one of them has prefixed by - (expected) and + (actual), lines
+// they share are left out apart from one either side of each change
+pub fn diff (expected: &str, actual: &str) -> String {
+    let (old, new): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+    // longest common subsequence lengths of every pair of suffixes
+    let mut lcs = vec![vec![0_usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let mut lines: Vec<(char, &str)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    let changed = |k: usize| lines.get(k).is_some_and(|(mark, _)| *mark != ' ');
+    let mut out = String::new();
+    for (k, (mark, line)) in lines.iter().enumerate() {
+        if *mark != ' ' || changed(k + 1) || (k > 0 && changed(k - 1)) {
+            out.push_str(&format!("{} {}\n", mark, line));
+        }
+    }
+    // output that differs only in a missing newline at the end
+    if out.is_empty() && expected != actual {
+        out.push_str(if expected.ends_with('\n') { "- (newline at end of output)\n" } else { "+ (newline at end of output)\n" });
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn golden_find_tests () {
+        let dir = std::env::temp_dir().join(format!("rust_bfi_golden_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub.bf")).unwrap();
+        for file in ["b.bf", "b.expected", "a.bf", "a.in", "a.expected", "notes.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let tests = find_tests(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tests, vec![
+            GoldenTest { name: String::from("a.bf"), program: dir.join("a.bf"), input: Some(dir.join("a.in")), expected: Some(dir.join("a.expected")) },
+            GoldenTest { name: String::from("b.bf"), program: dir.join("b.bf"), input: None, expected: Some(dir.join("b.expected")) },
+        ]);
+        assert!(find_tests(&dir).is_err());
+    }
+
+    #[test]
+    fn golden_diff () {
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nc\n"), "");
+        assert_eq!(diff("1\n2\n3\n4\n5\n", "1\n2\nx\n4\n5\n6\n"), "  2\n- 3\n+ x\n  4\n  5\n+ 6\n");
+        assert_eq!(diff("a\n", ""), "- a\n");
+        assert_eq!(diff("a\n", "a"), "- (newline at end of output)\n");
+    }
+}
@@ -0,0 +1,104 @@
+/*
+    Module for drawing a program's coverage as a heatmap, a standalone HTML
+    page with the source where each command is coloured by how many times it
+    was executed (pale yellow for a few through to dark red for the most, on a
+    log scale) and shows the count when hovered, commands that never ran are
+    grey and comments are faded out
+*/
+
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::{coverage, dtypes};
+
+
+// a string with the characters that mean something in HTML escaped
+fn escape (s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        };
+    }
+    out
+}
+
+
+// background colour for a command executed hits times when the most executed
+// one ran max times
+fn colour (hits: u64, max: u64) -> String {
+    if hits == 0 {
+        return String::from("#ddd");
+    }
+    let heat = (hits as f64).ln_1p() / (max as f64).ln_1p();
+    format!("hsl({:.0},100%,{:.0}%)", 60.0 - 60.0 * heat, 85.0 - 40.0 * heat)
+}
+
+
+// the page, title is shown at the top (e.g. the program's file name) and prog
+// is the source the program was loaded from
+pub fn html (title: &str, prog: &str, cov: &coverage::Coverage) -> String {
+    let hits: BTreeMap<dtypes::SourcePos, u64> = cov.commands.iter().copied().collect();
+    let max = cov.commands.iter().map(|&(_, hits)| hits).max().unwrap_or(0);
+    let mut out = String::new();
+    let _ = write!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{} heatmap</title>\n\
+                         <style>\nbody {{ font-family: sans-serif; }}\n\
+                         pre {{ font-size: 14px; line-height: 1.4; }}\n\
+                         pre span[title] {{ color: #000; }}\n\
+                         .comment {{ color: #aaa; }}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n<p>{}, the most executed \
+                         command ran {} times</p>\n<pre>", escape(title), escape(title), escape(&cov.to_string()), max);
+    let mut pos = dtypes::SourcePos { line: 1, column: 1 };
+    let mut comment = String::new();
+    for c in prog.chars() {
+        match hits.get(&pos) {
+            Some(&n) => {
+                if !comment.is_empty() {
+                    let _ = write!(out, "<span class=\"comment\">{}</span>", escape(&comment));
+                    comment.clear();
+                }
+                let _ = write!(out, "<span style=\"background:{}\" title=\"executions: {}\">{}</span>",
+                               colour(n, max), n, escape(&c.to_string()));
+            },
+            None => comment.push(c),
+        };
+        if c == '\n' {
+            pos = dtypes::SourcePos { line: pos.line + 1, column: 1 };
+        } else {
+            pos.column += 1;
+        }
+    }
+    if !comment.is_empty() {
+        let _ = write!(out, "<span class=\"comment\">{}</span>", escape(&comment));
+    }
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn heatmap_html () {
+        let at = |line, column| dtypes::SourcePos { line, column };
+        let cov = coverage::Coverage {
+            commands: vec![(at(1, 1), 1), (at(1, 2), 100), (at(2, 3), 0)],
+            loops: Vec::new(),
+        };
+        let page = html("<a & b>", "+-\n  >< tag", &cov);
+        assert!(page.contains("<title>&lt;a &amp; b&gt; heatmap</title>"));
+        assert!(page.contains("the most executed command ran 100 times"));
+        assert!(page.contains("<pre><span style=\"background:hsl(51,100%,79%)\" title=\"executions: 1\">+</span>\
+                               <span style=\"background:hsl(0,100%,45%)\" title=\"executions: 100\">-</span>\
+                               <span class=\"comment\">\n  </span>\
+                               <span style=\"background:#ddd\" title=\"executions: 0\">&gt;</span>\
+                               <span class=\"comment\">&lt; tag</span></pre>"));
+    }
+}
@@ -3,280 +3,3515 @@
 */
 
 
-use std::collections::VecDeque;
+use std::any::Any;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use crate::{parsing, dtypes};
+use crate::{analysis, bfio, parsing, dtypes, observer, optimizer, profile, snapshot, tape, trace};
+#[cfg(feature = "jit")]
+use crate::jit;
 
 
+// where bytes produced by Command::OutputByte end up
+pub enum OutputSink {
+    // write each byte to stdout as soon as it is produced
+    Stdout,
+    // write each byte to an arbitrary writer as soon as it is produced
+    Writer(Box<dyn Write>),
+    // collect bytes in the interpreter's output buffer
+    Buffer,
+    // collect bytes in the interpreter's output buffer and write them all to a
+    // writer whenever it fills up, before input is read and at the end
+    BufferedWriter(Box<dyn Write>),
+}
+
+
+// bytes a BufferedWriter sink holds on to if the buffer size hasn't been set
+const DEFAULT_OUT_BUF_SIZE: usize = 8192;
+
+
+// number of cells summaries show on either side of the data pointer
+pub const SUMMARY_RADIUS: usize = 4;
+
+
+// when output is written out and the sink flushed, besides whenever a
+// BufferedWriter sink's buffer fills up and at the end of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    // after every byte, for interactive programs that draw without newlines
+    EveryByte,
+    // before each byte of input is read so that a prompt is visible before
+    // waiting for an answer
+    #[default]
+    BeforeInput,
+    // no more often than that, for throughput when filtering in a pipeline
+    Blocks,
+}
+
+
+impl fmt::Debug for OutputSink {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputSink::Stdout => write!(f, "Stdout"),
+            OutputSink::Writer(_) => write!(f, "Writer"),
+            OutputSink::Buffer => write!(f, "Buffer"),
+            OutputSink::BufferedWriter(_) => write!(f, "BufferedWriter"),
+        }
+    }
+}
+
+
+// where bytes read by Instr::InputByte come from
+enum InputSource {
+    Reader(Box<dyn Read>),
+    // supplied up front, kept apart from other readers so that snapshots can
+    // include whatever hasn't been read yet
+    Bytes(io::Cursor<Vec<u8>>),
+    // given bit by bit with provide_input() as the program runs, running out
+    // before end_input() has been called suspends the program until more comes
+    Provided { queued: VecDeque<u8>, ended: bool },
+}
+
+
+impl Read for InputSource {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            InputSource::Reader(r) => r.read(buf),
+            InputSource::Bytes(c) => c.read(buf),
+            InputSource::Provided { queued, ended } => match queued.read(buf) {
+                Ok(0) if !*ended && !buf.is_empty() => Err(io::ErrorKind::WouldBlock.into()),
+                read => read,
+            },
+        }
+    }
+}
+
+
+// stands in for the I/O backend while run_async() steps a single , or . holding
+// the result of the async read before the step, or the byte written by it
+#[cfg(feature = "tokio")]
+#[derive(Default)]
+struct AsyncBridge {
+    input: Option<io::Result<Option<u8>>>,
+    output: Option<u8>,
+}
+
+
+#[cfg(feature = "tokio")]
+impl bfio::BfIo for AsyncBridge {
+    fn read_byte (&mut self) -> io::Result<Option<u8>> {
+        self.input.take().unwrap_or(Ok(Option::None))
+    }
+
+    fn write_byte (&mut self, byte: u8) -> io::Result<()> {
+        self.output = Option::Some(byte);
+        Ok(())
+    }
+}
+
+
+// a Brainfork thread waiting its turn, all threads share the tape (and input
+// and output) but each has its own pointers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Thread {
+    instr_ptr: usize,
+    data_ptr: usize,
+}
+
+
+// what a single step() changed, enough to put things back the way they were
 #[derive(Debug)]
-pub struct BFInterpreter {
+struct StepDelta {
+    instr_ptr: usize,
+    data_ptr: usize,
+    // memory only ever grows, on the right it is cut back down to this
     mem_size: usize,
-    mem: Vec<u8>,
-    in_buf: VecDeque<u8>,
+    // the cell at the data pointer, and the other cell an Instr::AddTo (or one of
+    // the offset-addressed instructions) changes
+    // (which may be left of the tape until it grows), with their values before
+    // the step
+    cell: (usize, u32),
+    other_cell: Option<(isize, u32)>,
+    storage: u32,
+    // a pbrain procedure that was defined, with its previous start if any
+    procedure: Option<(u32, Option<usize>)>,
+    call_depth: usize,
+    // the return address popped by Instr::EndProc
+    returned: Option<usize>,
+    out_len: usize,
+    bytes_out: u64,
+    // the byte read by Instr::InputByte
+    input: Option<u8>,
+    // the Brainfork threads waiting their turn, if there were any or it forked
+    threads: Option<VecDeque<Thread>>,
+}
+
+
+impl StepDelta {
+    // move the indexes along after the tape grew to the left
+    fn shift (&mut self, n: usize) {
+        self.data_ptr += n;
+        self.mem_size += n;
+        self.cell.0 += n;
+        if let Some((i, _)) = &mut self.other_cell {
+            *i += n as isize;
+        }
+        for thread in self.threads.iter_mut().flatten() {
+            thread.data_ptr += n;
+        }
+    }
+}
+
+
+// iterator over the steps of a program, from BFInterpreter::iter_steps()
+pub struct Steps<'a> {
+    bfi: &'a mut BFInterpreter,
+}
+
+
+impl Iterator for Steps<'_> {
+    type Item = Result<dtypes::ExecutionState, dtypes::BFError>;
+
+    fn next (&mut self) -> Option<Self::Item> {
+        let bfi = &mut *self.bfi;
+        if bfi.is_finished() {
+            return Option::None;
+        }
+        let (step, index, instr, origin) = (bfi.steps, bfi.instr_ptr, bfi.ops[bfi.instr_ptr].instr, bfi.tape.origin());
+        // the only cell an instruction can change, cells off either end of memory
+        // start out as 0 if it grows
+        let cell = match instr {
+            dtypes::Instr::AddTo(offset, _) | dtypes::Instr::AddAt(offset, _) | dtypes::Instr::ClearAt(offset) => {
+                bfi.add_to_target(offset)
+            },
+            _ => bfi.data_ptr as isize,
+        };
+        let before = usize::try_from(cell).ok().and_then(|i| bfi.cell(i)).unwrap_or(0);
+        if let Err(e) = bfi.step() {
+            return Option::Some(Err(e));
+        }
+        // nothing happened, the program is waiting for input
+        if bfi.awaiting_input {
+            return Option::None;
+        }
+        // the cell moved along if the tape grew to the left
+        let cell = usize::try_from(cell + (bfi.tape.origin() - origin) as isize).ok();
+        let changed = cell.map(|i| (i, bfi.cell(i).unwrap_or(0))).filter(|&(_, after)| after != before);
+        Option::Some(Ok(dtypes::ExecutionState { step, index, instr, data_ptr: bfi.data_ptr, changed }))
+    }
+}
+
+
+pub struct BFInterpreter {
+    tape: Box<dyn tape::Tape>,
+    cell_width: dtypes::CellWidth,
+    ops: Vec<dtypes::Op>,
+    n_cmds: usize,
+    // line and column of each command in the program source
+    sources: Vec<dtypes::SourcePos>,
+    optimize: bool,
+    split_input: bool,
+    // whether `#` is the Debug command
+    hash_debug: bool,
+    dialect: dtypes::Dialect,
+    // spellings of the commands used instead of the dialect's
+    mapping: Option<parsing::Mapping>,
+    engine: dtypes::Engine,
+    #[cfg(feature = "jit")]
+    jit: Option<jit::JitProgram>,
+    instr_ptr: usize,
+    steps: u64,
+    limits: dtypes::Limits,
+    // stops run() when set, see set_interrupt()
+    interrupt: Option<Arc<AtomicBool>>,
     out_buf: Vec<u8>,
+    // most bytes out_buf may hold
+    out_buf_size: Option<usize>,
+    // input taken back by step_back(), read again (last first) before anything else
+    unread: Vec<u8>,
+    // recent steps that step_back() can undo, oldest first
+    history: VecDeque<StepDelta>,
+    history_depth: usize,
+    // most steps load_program() runs ahead, see set_precompute()
+    precompute: Option<u64>,
     data_ptr: usize,
+    // proven when the program was loaded that it never takes the data pointer
+    // off the end of a strict tape, so moves needn't be checked
+    in_bounds: bool,
+    // the Extended Type I storage cell
+    storage: u32,
+    // pbrain procedures defined so far, by number, with the index of the first
+    // instruction of their body
+    procedures: BTreeMap<u32, usize>,
+    // where to carry on from when each pbrain procedure being run returns
+    call_stack: Vec<usize>,
+    // Brainfork threads other than the one running, in the order they take
+    // their turns
+    threads: VecDeque<Thread>,
+    // the furthest cell the program has reached, and the cells it has visited
+    // or changed, only kept up while reporting
+    max_data_ptr: usize,
+    touched: HashSet<usize>,
+    bytes_out: u64,
+    bytes_in: u64,
+    // set while run_with_report() needs every instruction to go through step(),
+    // and the cells it touches noted
+    reporting: bool,
+    // set while run_for() is running, so that `,` can wait for input that
+    // isn't ready yet rather than fail
+    slicing: bool,
+    // set by a `,` that is waiting for input, it is tried again next step,
+    // see needs_input()
+    awaiting_input: bool,
     run_flg: bool,
     term_flg: bool,
-    error_flg: bool,
-    error_msg: Option<String>,
-    bracket_state: isize,
-    jump_stack: Vec<u8>,
-    current_byte: Option<u8>,
+    error: Option<dtypes::BFError>,
+    in_src: InputSource,
+    eof_behavior: dtypes::EofBehavior,
+    out_sink: OutputSink,
+    // write out output before blocking on input, so that prompts show
+    flush_policy: FlushPolicy,
+    // takes over from in_src and out_sink when set
+    io: Option<Box<dyn bfio::BfIo>>,
+    // take over from io, in_src and out_sink when set
+    in_hook: Option<Box<dyn FnMut() -> Option<u8>>>,
+    out_hook: Option<Box<dyn FnMut(u8)>>,
+    tracer: Option<Box<dyn trace::Tracer>>,
+    profile: Option<profile::Profile>,
+    observers: Vec<Box<dyn observer::Observer>>,
+}
+
+
+// the input source is not Debug, so list everything else by hand, with the
+// sizes of the tape, program and output rather than all of them
+impl fmt::Debug for BFInterpreter {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BFInterpreter")
+            .field("memory_size", &self.tape.len())
+            .field("tape_origin", &self.tape.origin())
+            .field("cell_width", &self.cell_width)
+            .field("ops", &self.ops.len())
+            .field("n_cmds", &self.n_cmds)
+            .field("optimize", &self.optimize)
+            .field("split_input", &self.split_input)
+            .field("dialect", &self.dialect)
+            .field("mapping", &self.mapping.is_some())
+            .field("engine", &self.engine)
+            .field("instr_ptr", &self.instr_ptr)
+            .field("steps", &self.steps)
+            .field("limits", &self.limits)
+            .field("interrupt", &self.interrupt)
+            .field("out_buf", &self.out_buf.len())
+            .field("out_buf_size", &self.out_buf_size)
+            .field("unread", &self.unread.len())
+            .field("history", &self.history.len())
+            .field("history_depth", &self.history_depth)
+            .field("precompute", &self.precompute)
+            .field("data_ptr", &self.data_ptr)
+            .field("in_bounds", &self.in_bounds)
+            .field("storage", &self.storage)
+            .field("procedures", &self.procedures)
+            .field("call_stack", &self.call_stack)
+            .field("threads", &self.threads)
+            .field("max_data_ptr", &self.max_data_ptr)
+            .field("touched", &self.touched.len())
+            .field("bytes_out", &self.bytes_out)
+            .field("bytes_in", &self.bytes_in)
+            .field("awaiting_input", &self.awaiting_input)
+            .field("run_flg", &self.run_flg)
+            .field("term_flg", &self.term_flg)
+            .field("error", &self.error)
+            .field("eof_behavior", &self.eof_behavior)
+            .field("out_sink", &self.out_sink)
+            .field("flush_policy", &self.flush_policy)
+            .field("custom_io", &self.io.is_some())
+            .field("input_hook", &self.in_hook.is_some())
+            .field("output_hook", &self.out_hook.is_some())
+            .field("tracing", &self.tracer.is_some())
+            .field("profile", &self.profile)
+            .field("observers", &self.observers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+
+// a few lines on where the program is and what it has done, see summary()
+// for a single line
+impl fmt::Display for BFInterpreter {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.current_instruction() {
+            Some(instr) => write!(f, "command {} of {}: {}", self.instr_ptr, self.ops.len(), instr)?,
+            None => write!(f, "command {} of {}: <end of program>", self.instr_ptr, self.ops.len())?,
+        };
+        if let Some(pos) = self.sources.get(self.position()) {
+            write!(f, " (line {}, column {})", pos.line, pos.column)?;
+        }
+        let dump = self.window();
+        writeln!(f)?;
+        writeln!(f, "data pointer: {}  cells {} to {}:{}", self.data_ptr, dump.start, (dump.start + dump.cells.len()).saturating_sub(1), self.window_text(&dump))?;
+        writeln!(f, "steps: {}  output: {} bytes  input: {} bytes", self.steps, self.bytes_out, self.bytes_in)?;
+        if !self.threads.is_empty() {
+            let others: Vec<String> = self.threads.iter().map(|thread| format!("command {} on cell {}", thread.instr_ptr, thread.data_ptr)).collect();
+            writeln!(f, "threads: {}  waiting: {}", self.threads(), others.join(", "))?;
+        }
+        match &self.error {
+            Some(e) => write!(f, "state: stopped by an error: {}", e),
+            None => write!(f, "state: {}", self.state()),
+        }
+    }
 }
 
 
 impl BFInterpreter {
+    // new interpreter with a strict tape of 8 bit cells
     pub fn new (mem_size: usize) -> BFInterpreter {
-        let in_buf: VecDeque<u8> = VecDeque::new();
+        BFInterpreter::with_config(mem_size, dtypes::TapeMode::Strict, dtypes::CellWidth::U8)
+    }
+
+    // new interpreter with a particular behavior at the ends of memory and cell width
+    pub fn with_config (mem_size: usize, tape_mode: dtypes::TapeMode, cell_width: dtypes::CellWidth) -> BFInterpreter {
+        BFInterpreter::with_tape(tape::new_tape(tape_mode, mem_size), cell_width)
+    }
+
+    // new interpreter running on a particular tape backend, e.g. a tape::SparseTape
+    // for programs that spread out over a huge amount of memory
+    pub fn with_tape (tape: Box<dyn tape::Tape>, cell_width: dtypes::CellWidth) -> BFInterpreter {
         let out_buf: Vec<u8> = Vec::new();
-        let jump_stack: Vec<u8> = Vec::new();
-        let bfi = BFInterpreter {
-            mem_size,
-            mem: vec![0; mem_size],
-            in_buf,
+        BFInterpreter {
+            tape,
+            cell_width,
+            ops: Vec::new(),
+            n_cmds: 0,
+            sources: Vec::new(),
+            optimize: true,
+            split_input: true,
+            hash_debug: false,
+            dialect: dtypes::Dialect::Brainfuck,
+            mapping: Option::None,
+            engine: dtypes::Engine::Interpreter,
+            #[cfg(feature = "jit")]
+            jit: Option::None,
+            instr_ptr: 0,
+            steps: 0,
+            limits: dtypes::Limits::default(),
+            interrupt: Option::None,
             out_buf,
+            out_buf_size: Option::None,
+            unread: Vec::new(),
+            history: VecDeque::new(),
+            history_depth: 0,
+            precompute: Option::None,
             data_ptr: 0,
+            in_bounds: false,
+            storage: 0,
+            procedures: BTreeMap::new(),
+            call_stack: Vec::new(),
+            threads: VecDeque::new(),
+            max_data_ptr: 0,
+            touched: HashSet::new(),
+            bytes_out: 0,
+            bytes_in: 0,
+            reporting: false,
+            slicing: false,
+            awaiting_input: false,
             run_flg: false,
             term_flg: false,
-            error_flg: false,
-            error_msg: Option::None,
-            bracket_state: 0,
-            jump_stack,
-            current_byte: Option::None,
+            error: Option::None,
+            in_src: InputSource::Reader(Box::new(io::stdin())),
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            out_sink: OutputSink::Stdout,
+            flush_policy: FlushPolicy::BeforeInput,
+            io: Option::None,
+            in_hook: Option::None,
+            out_hook: Option::None,
+            tracer: Option::None,
+            profile: Option::None,
+            observers: Vec::new(),
+        }
+    }
+
+    // replace the source that Command::InputByte reads from (stdin by default)
+    pub fn set_input_source (&mut self, src: Box<dyn Read>) {
+        self.in_src = InputSource::Reader(src);
+        self.unread.clear();
+    }
+
+    // have Command::InputByte read from a copy of these bytes instead of the input source
+    pub fn set_input (&mut self, input: &[u8]) {
+        self.in_src = InputSource::Bytes(io::Cursor::new(input.to_vec()));
+        self.unread.clear();
+    }
+
+    // give the program more input as it runs, e.g. for embeddings that can't block
+    // waiting for it, the first call replaces the input source, from then on a
+    // `,` with nothing left to read suspends the program (see needs_input())
+    // rather than reaching the end of input, until end_input() is called
+    pub fn provide_input (&mut self, input: &[u8]) {
+        match &mut self.in_src {
+            InputSource::Provided { queued, .. } => queued.extend(input),
+            _ => {
+                self.in_src = InputSource::Provided { queued: input.iter().copied().collect(), ended: false };
+                self.unread.clear();
+            },
         };
-        bfi
+    }
+
+    // no more input is coming after what has been given with provide_input(),
+    // once it has been read `,` reaches the end of input as usual
+    pub fn end_input (&mut self) {
+        if let InputSource::Provided { ended, .. } = &mut self.in_src {
+            *ended = true;
+        }
+    }
+
+    // true while the program is suspended on a `,` waiting for input, which
+    // carries on when it is run again once some has been given
+    pub fn needs_input (&self) -> bool {
+        self.awaiting_input
+    }
+
+    // replace the sink that Command::OutputByte writes to (stdout by default)
+    pub fn set_output_sink (&mut self, sink: OutputSink) {
+        self.out_sink = sink;
+    }
+
+    // how often output is written out (and the sink flushed), before input is
+    // read by default
+    pub fn set_flush_policy (&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    // write out any output the sink is holding on to and flush it, e.g. before
+    // handing a writer back after stepping through part of a program
+    pub fn flush (&mut self) -> io::Result<()> {
+        match (&mut self.io, &mut self.out_sink) {
+            (Some(io), _) => io.flush(),
+            (None, OutputSink::Stdout) => io::stdout().flush(),
+            (None, OutputSink::Writer(w)) => w.flush(),
+            (None, OutputSink::Buffer) => Ok(()),
+            (None, OutputSink::BufferedWriter(w)) => {
+                let result = w.write_all(&self.out_buf).and_then(|()| w.flush());
+                self.out_buf.clear();
+                result
+            },
+        }
+    }
+
+    // most bytes the output buffer holds (None for no limit), once it is full the
+    // OutputSink::Buffer sink stops the program with BFError::OutputLimitExceeded
+    // and the OutputSink::BufferedWriter sink writes it out so that it can be
+    // reused, which otherwise happens every 8 KiB
+    pub fn set_output_buffer_size (&mut self, size: Option<usize>) {
+        self.out_buf_size = size;
+    }
+
+    // do all input and output through a BfIo backend rather than the input source
+    // and output sink (None to go back to them), input embedded in a program and
+    // snapshots of unread input only work with the input source
+    pub fn set_io (&mut self, io: Option<Box<dyn bfio::BfIo>>) {
+        self.io = io;
+    }
+
+    // call a closure with each byte output instead of writing it anywhere else,
+    // it takes over from the output sink and any BfIo backend so nothing is
+    // collected in the output buffer
+    pub fn on_output<F: FnMut(u8) + 'static> (&mut self, hook: F) {
+        self.out_hook = Option::Some(Box::new(hook));
+    }
+
+    // call a closure for each byte of input instead of reading it from anywhere
+    // else (None at the end of input), it takes over from the input source
+    // (including any input embedded in the program) and any BfIo backend, input
+    // put back by step_back() is still read again first
+    pub fn on_input<F: FnMut() -> Option<u8> + 'static> (&mut self, hook: F) {
+        self.in_hook = Option::Some(Box::new(hook));
+    }
+
+    // remove the closures set by on_output() and on_input(), going back to the
+    // input source and output sink (or BfIo backend)
+    pub fn clear_hooks (&mut self) {
+        self.in_hook = Option::None;
+        self.out_hook = Option::None;
+    }
+
+    // the BfIo backend, if one of type T is set
+    pub fn io<T: bfio::BfIo> (&self) -> Option<&T> {
+        self.io.as_deref().and_then(|io| (io as &dyn Any).downcast_ref::<T>())
+    }
+
+    // the BfIo backend, if one of type T is set
+    pub fn io_mut<T: bfio::BfIo> (&mut self) -> Option<&mut T> {
+        self.io.as_deref_mut().and_then(|io| (io as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    // set what Command::InputByte does to the current cell once the input source is exhausted
+    pub fn set_eof_behavior (&mut self, eof_behavior: dtypes::EofBehavior) {
+        self.eof_behavior = eof_behavior;
+    }
+
+    // stop the program with BFError::StepLimitExceeded once it has executed
+    // this many commands (None for no limit), the same as setting
+    // Limits::max_steps
+    pub fn set_max_steps (&mut self, max_steps: Option<u64>) {
+        self.limits.max_steps = max_steps;
+    }
+
+    // set every safety cap at once, replacing any set before, the nesting of
+    // loops is checked by load_program() so this has to be set before that
+    pub fn set_limits (&mut self, limits: dtypes::Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits (&self) -> dtypes::Limits {
+        self.limits
+    }
+
+    // fold runs of repeated commands when loading programs (on by default), this
+    // must be set before load_program() to have any effect
+    pub fn set_optimize (&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    // treat anything after the first `!` outside of a loop as input for the program
+    // when loading it (on by default), this must be set before load_program() to
+    // have any effect
+    pub fn set_split_input (&mut self, split_input: bool) {
+        self.split_input = split_input;
+    }
+
+    // treat `#` as a command that prints the data pointer and the first few cells
+    // to stderr rather than as a comment (off by default), this must be set
+    // before load_program() to have any effect
+    pub fn set_hash_debug (&mut self, hash_debug: bool) {
+        self.hash_debug = hash_debug;
+    }
+
+    // language programs are written in (brainfuck by default), this must be set
+    // before load_program() to have any effect, input can't be embedded in
+    // dialects that use `!` themselves
+    pub fn set_dialect (&mut self, dialect: dtypes::Dialect) {
+        self.dialect = dialect;
+    }
+
+    // spell the commands the way a trivial substitution dialect does rather than
+    // the way the dialect does (None to go back to it), this must be set before
+    // load_program() too, and input is never embedded in a mapped program
+    pub fn set_mapping (&mut self, mapping: Option<parsing::Mapping>) {
+        self.mapping = mapping;
+    }
+
+    // report every instruction executed to a tracer (None to stop tracing)
+    pub fn set_tracer (&mut self, tracer: Option<Box<dyn trace::Tracer>>) {
+        self.tracer = tracer;
+    }
+
+    // tell an observer about everything the program does from now on, on top of
+    // any attached already, compiled code can't report to observers so the
+    // interpreter is used while any are attached
+    pub fn add_observer (&mut self, observer: Box<dyn observer::Observer>) {
+        self.observers.push(observer);
+    }
+
+    // detach every observer
+    pub fn clear_observers (&mut self) {
+        self.observers.clear();
+    }
+
+    // count executions of each instruction and time each loop (off by default),
+    // turning it on starts a fresh profile
+    pub fn set_profiling (&mut self, profiling: bool) {
+        self.profile = if profiling { Option::Some(profile::Profile::new()) } else { Option::None };
+    }
+
+    // like set_profiling(), but the profile also keeps a timeline of every loop
+    // execution, see profile::Profile::chrome_trace()
+    pub fn set_span_profiling (&mut self, profiling: bool) {
+        self.profile = if profiling { Option::Some(profile::Profile::with_spans()) } else { Option::None };
+    }
+
+    // remember what the last depth steps changed so that step_back() can undo
+    // them (0, the default, to remember nothing), the oldest are forgotten first,
+    // compiled code keeps no history so the interpreter is used while this is on
+    pub fn set_history_depth (&mut self, depth: usize) {
+        self.history_depth = depth;
+        while self.history.len() > depth {
+            self.history.pop_front();
+        }
+    }
+
+    // have load_program() run the start of a program up to its first input or
+    // output (at most budget steps, None to not do this, the default), leaving
+    // it to carry on from there with memory already set up, many programs spend
+    // a while setting up constants first, the steps count towards the step limit
+    // as usual, memory has to be set before the program is loaded for the
+    // precomputed part to see it, and nothing is precomputed while tracing,
+    // profiling or keeping history (which all need to see every step) or on a
+    // sparse tape
+    pub fn set_precompute (&mut self, budget: Option<u64>) {
+        self.precompute = budget;
+    }
+
+    // have run() stop with BFError::Interrupted before the next instruction once
+    // flag is set, e.g. from a Ctrl-C handler, the flag is cleared again and the
+    // program isn't terminated so it can be carried on, compiled code only sees
+    // the flag when it hands an instruction back to the interpreter
+    pub fn set_interrupt (&mut self, flag: Option<Arc<AtomicBool>>) {
+        self.interrupt = flag;
+    }
+
+    // pick how run() executes programs (the interpreter by default), this must be
+    // set before load_program() to have any effect, compiled code does not count
+    // steps, report to tracers, observers or profile so the interpreter is used
+    // regardless while any of those are on
+    pub fn set_engine (&mut self, engine: dtypes::Engine) {
+        self.engine = engine;
+    }
+
+    // index of the source command behind the current instruction (or the
+    // number of commands once the program has run off the end)
+    fn position (&self) -> usize {
+        self.ops.get(self.instr_ptr).map_or(self.n_cmds, |op| op.position)
     }
 
     // return value at current data pointer location
-    fn ptr_val (&mut self) -> u8 {
-        self.mem[self.data_ptr]
+    fn ptr_val (&mut self) -> u32 {
+        self.tape.get(self.data_ptr)
     }
 
-    // handler for Command::MovePointerRight
-    fn move_pointer_right (&mut self) {
-        self.data_ptr += 1;
-        // ensure data pointer did not overrun available memory
-        if self.data_ptr >= self.mem_size {
-            self.error_flg = true;
-            self.error_msg = Option::Some(String::from("data pointer overran available memory"));
+    // the index of the cell offset cells away from the data pointer, growing the
+    // tape according to its mode (moving everything along if it grows to the left),
+    // None if it is off the end
+    fn tape_offset (&mut self, offset: isize) -> Option<usize> {
+        if self.in_bounds {
+            return Option::Some(self.data_ptr.wrapping_add_signed(offset));
+        }
+        if let Some(limit) = self.limits.max_mem_cells {
+            if self.cells_needed(offset) > limit.max(self.tape.len()) {
+                self.error = Option::Some(dtypes::BFError::MemoryLimitExceeded { position: self.position(), source: Option::None, limit });
+                return Option::None;
+            }
+        }
+        let origin = self.tape.origin();
+        let index = self.tape.offset(self.data_ptr, offset);
+        let grown = self.tape.origin() - origin;
+        if grown > 0 {
+            self.data_ptr += grown;
+            self.max_data_ptr += grown;
+            self.touched = self.touched.drain().map(|i| i + grown).collect();
+            for delta in &mut self.history {
+                delta.shift(grown);
+            }
+            for thread in &mut self.threads {
+                thread.data_ptr += grown;
+            }
         }
+        index
     }
 
-    // handler for Command::MovePointerLeft
-    fn move_pointer_left (&mut self) {
-        // ensure data pointer did not underrun available memory
-        if self.data_ptr == 0 {
-            self.error_flg = true;
-            self.error_msg = Option::Some(String::from("data pointer underran available memory"));
-        } else {
-            self.data_ptr -= 1;
+    // number of cells memory has to grow to for the cell offset cells away from
+    // the data pointer
+    fn cells_needed (&self, offset: isize) -> usize {
+        let (target, len) = (self.data_ptr as isize + offset, self.tape.len());
+        match self.tape.mode() {
+            dtypes::TapeMode::Growable | dtypes::TapeMode::Bidirectional if target >= len as isize => target as usize + 1,
+            dtypes::TapeMode::Bidirectional if target < 0 => len + target.unsigned_abs(),
+            _ => len,
         }
     }
 
-    // handler for Command::IncrementByte
-    fn increment_byte (&mut self) {
-        // increment byte at data pointer location
-        // with rollover
-        if self.ptr_val() == 255 {
-            self.mem[self.data_ptr] = 0;
-        } else {
-            self.mem[self.data_ptr] += 1;
+    // handler for Instr::Move
+    fn move_pointer (&mut self, n: isize) {
+        // handle the data pointer moving off either end of available memory
+        // according to the tape mode
+        match self.tape_offset(n) {
+            Some(index) => self.data_ptr = index,
+            // stopped by the memory limit
+            None if self.error.is_some() => {},
+            // a folded move is a run of consecutive commands, so stop at the edge
+            // and blame the command that would have stepped over it
+            None if n < 0 => {
+                let position = self.position() + self.data_ptr;
+                self.data_ptr = 0;
+                self.error = Option::Some(dtypes::BFError::PointerUnderrun { position, source: Option::None });
+            },
+            None => {
+                let position = self.position() + (self.tape.len() - 1 - self.data_ptr);
+                self.data_ptr = self.tape.len() - 1;
+                self.error = Option::Some(dtypes::BFError::PointerOverrun { position, source: Option::None });
+            },
+        };
+    }
+
+    // handler for Instr::Add
+    fn add (&mut self, n: i32) {
+        // add to cell at data pointer location with rollover, cells narrower
+        // than 32 bits are masked back down to their width
+        let val = self.ptr_val().wrapping_add_signed(n) & self.cell_width.max_value();
+        self.tape.set(self.data_ptr, val);
+    }
+
+    // resolve the memory index at an offset from the data pointer according to the
+    // tape mode (growing memory if needed), sets an error and returns None if the
+    // index is off the end of a strict tape
+    fn offset_index (&mut self, offset: isize) -> Option<usize> {
+        let index = self.tape_offset(offset);
+        if index.is_none() && self.error.is_none() {
+            let position = self.position();
+            self.error = Option::Some(match offset < 0 {
+                true => dtypes::BFError::PointerUnderrun { position, source: Option::None },
+                false => dtypes::BFError::PointerOverrun { position, source: Option::None },
+            });
         }
+        index
     }
 
-    // handler for Command::DecrementByte
-    fn decrement_byte (&mut self) {
-        // increment byte at data pointer location
-        // with rollover
-        if self.ptr_val() == 0 {
-            self.mem[self.data_ptr] = 255;
-        } else {
-            self.mem[self.data_ptr] -= 1;
+    // note that the program visited or changed a cell, when it's being reported on
+    fn touch (&mut self, index: usize) {
+        if self.reporting {
+            self.touched.insert(index);
         }
     }
 
-    // handler for Command::OutputByte
-    fn output_byte (&mut self) {
+    // handler for Instr::Clear
+    fn clear (&mut self) {
+        self.tape.set(self.data_ptr, 0);
+    }
+
+    // handler for Instr::AddTo
+    fn add_to (&mut self, offset: isize, factor: i32) {
+        // the loop this came from never runs (or touches the other cell) if
+        // the cell at the data pointer is 0, if the other cell is off the end
+        // of a strict tape the error is blamed on the loop's [ and no cells change
         let val = self.ptr_val();
-        self.out_buf.push(val);
+        if val == 0 {
+            return;
+        }
+        if let Some(i) = self.offset_index(offset) {
+            self.touch(i);
+            let product = val.wrapping_mul(factor as u32);
+            self.tape.set(i, self.tape.get(i).wrapping_add(product) & self.cell_width.max_value());
+        }
     }
 
-    // handler for Command::InputByte
-    fn input_byte (&mut self) {
-       panic!("not implemented");
-    }
-
-    // handler for Command::JumpRightIfZero
-    fn jump_right_if_zero (&mut self) {
-        // if byte at the current data pointer location is 0
-        // skip all commands until a matching closing bracket is reached
-        // and push everything (including that closing bracket) onto the jump stack
-        let pre_bracket_state = self.bracket_state;
-        if self.ptr_val() == 0 {
-            // jump right
-            while self.in_buf.len() > 0 && self.bracket_state != pre_bracket_state {
-                if let Some(cmd) = parsing::byte_to_command(self.in_buf[0]) {
-                    match cmd {
-                        dtypes::Command::JumpRightIfZero => {
-                            self.bracket_state += 1;
-                        },
-                        dtypes::Command::JumpLeftIfNonZero => {
-                            self.bracket_state -= 1;
-                        },
-                        // don't do anything with other commands
-                        _ => {},
-                    };
-                };
-                // push whatever byte was there onto the jump stack
-                // can unwrap() because already know there are bytes
-                // in the input buffer from while loop condition
-                self.jump_stack.push(self.in_buf.pop_front().unwrap());
-            }
-            // detect an error condition
-            if self.bracket_state != pre_bracket_state {
-                self.error_flg = true;
-                self.error_msg = Option::Some(String::from("could not find closing ]"));
-            }
+    // the index of the cell an offset-addressed instruction works on, these stand
+    // in for a move there and back so running off the end of a strict tape stops
+    // at the edge and blames the same command the move would have
+    fn offset_cell (&mut self, offset: isize) -> Option<usize> {
+        let index = self.tape_offset(offset);
+        if index.is_none() && self.error.is_none() {
+            self.move_pointer(offset);
         }
+        index
     }
 
-    // handler for Command::JumpLeftIfNonZero
-    fn jump_left_if_non_zero (&mut self) {
-        // check for unbalanced ]
-        if self.bracket_state == 0 {
-            self.error_flg = true;
-            self.error_msg = Option::Some(String::from("unmatched ]"));
-        } else {
-            // if byte at the current data pointer location is not 0
-            // jump back to the matching opening bracket [
-            // by popping from the jump stack and inserting at the front 
-            // of the input buffer
-            let pre_bracket_state = self.bracket_state;
-            self.bracket_state -= 1;
-            if self.ptr_val() > 0 {
-                // jump left
-                // put the ] back in the input buffer first
-                // can use unwrap here since this should not be reached unless
-                // at least 1 byte has been read from input buffer (i.e. self.current_byte
-                // cannot be Option::None)
-                self.in_buf.push_front(self.current_byte.unwrap());
-                while self.bracket_state != pre_bracket_state {
-                    // pop everything (except matching [) off of jump stack
-                    if let Some(cmd) = parsing::byte_to_command(self.jump_stack[self.jump_stack.len() - 1]) {
-                        match cmd {
-                            dtypes::Command::JumpRightIfZero => {
-                                self.bracket_state += 1;
-                            },
-                            dtypes::Command::JumpLeftIfNonZero => {
-                                self.bracket_state -= 1;
-                            },
-                            // don't do anything with other commands
-                            _ => {},
-                        };
-                    };
-                    self.in_buf.push_front(self.jump_stack.pop().unwrap());
-                }
-                // put the [ into self.current_byte, it will get pushed back onto the jump stack
-                self.current_byte = self.in_buf.pop_front();
-            }
+    // handler for Instr::AddAt
+    fn add_at (&mut self, offset: isize, n: i32) {
+        if let Some(i) = self.offset_cell(offset) {
+            self.touch(i);
+            self.tape.set(i, self.tape.get(i).wrapping_add_signed(n) & self.cell_width.max_value());
         }
     }
 
-    pub fn run (&mut self) {
-        // set running flag while interpreter is running
-        self.run_flg = true;
-        // consume 1 byte at a time from input buffer
-        // ignore any bytes that are not recognized commands
-        // continue while there are still bytes in the input buffer
-        // and the error flag has not been set
-        while self.in_buf.len() > 0 && !self.error_flg {
-            self.current_byte = self.in_buf.pop_front();
-            if let Some(cmd) = parsing::byte_to_command(self.current_byte.unwrap()) {
-                match cmd {
-                    dtypes::Command::MovePointerRight => self.move_pointer_right(),
-                    dtypes::Command::MovePointerLeft => self.move_pointer_left(),
-                    dtypes::Command::IncrementByte => self.increment_byte(),
-                    dtypes::Command::DecrementByte => self.decrement_byte(),
-                    dtypes::Command::OutputByte => self.output_byte(),
-                    dtypes::Command::InputByte => self.input_byte(),
-                    dtypes::Command::JumpRightIfZero => self.jump_right_if_zero(),
-                    dtypes::Command::JumpLeftIfNonZero => self.jump_left_if_non_zero(),
-                };
-            };
-            // after every loop cycle push the byte that was just processed onto the jump stack
-            //self.__jump_stack.insert(0, self.__byte)
-            self.jump_stack.push(self.current_byte.unwrap());
+    // handler for Instr::ClearAt
+    fn clear_at (&mut self, offset: isize) {
+        if let Some(i) = self.offset_cell(offset) {
+            self.touch(i);
+            self.tape.set(i, 0);
         }
-        // after executing reset run flag and set terminated flag
-        // to signal execution has completed
-        self.run_flg = false;
-        self.term_flg = true;
     }
 
-    pub fn fill_in_buff (&mut self, prog: String) {
-        for c in prog.as_bytes() {
-            self.in_buf.push_back(*c);
+    // handler for Instr::OutputAt
+    fn output_at (&mut self, offset: isize) {
+        if let Some(i) = self.offset_cell(offset) {
+            self.touch(i);
+            self.output_byte(i);
         }
     }
 
-}
+    // handler for Instr::ScanRight and Instr::ScanLeft, jump straight to the nearest
+    // cell holding 0, past the end of memory the tape mode decides what happens
+    // like it would for a single move, on a wrapping tape with no 0 anywhere the
+    // data pointer goes all the way round and the loop it is in carries on
+    fn scan (&mut self, right: bool) {
+        if let Some(i) = self.tape.find_zero(self.data_ptr, right) {
+            self.data_ptr = i;
+            return;
+        }
+        let (start, end) = match right {
+            true => (0, self.tape.len() - 1),
+            false => (self.tape.len() - 1, 0),
+        };
+        match self.tape.mode() {
+            dtypes::TapeMode::Wrapping => {
+                if let Some(i) = self.tape.find_zero(start, right) {
+                    self.data_ptr = i;
+                }
+            },
+            _ => {
+                self.data_ptr = end;
+                self.move_pointer(if right { 1 } else { -1 });
+            },
+        };
+    }
 
+    // handler for the Extended Type I instructions that change the cell at the
+    // data pointer, results are cut down to the cell width
+    fn bitwise (&mut self, instr: dtypes::Instr) {
+        let val = self.tape.get(self.data_ptr);
+        let result = match instr {
+            dtypes::Instr::Retrieve => self.storage,
+            dtypes::Instr::ShiftRight => val >> 1,
+            dtypes::Instr::ShiftLeft => val << 1,
+            dtypes::Instr::Not => !val,
+            dtypes::Instr::Xor => val ^ self.storage,
+            dtypes::Instr::And => val & self.storage,
+            dtypes::Instr::Or => val | self.storage,
+            _ => val,
+        };
+        self.tape.set(self.data_ptr, result & self.cell_width.max_value());
+    }
 
-#[cfg(test)]
-mod tests {
+    // handler for Instr::DefineProc, remember where the body starts then skip over it
+    fn define_procedure (&mut self, end: usize) {
+        if end >= self.ops.len() {
+            self.unmatched('(');
+            return;
+        }
+        self.procedures.insert(self.tape.get(self.data_ptr), self.instr_ptr + 1);
+        self.instr_ptr = end;
+    }
 
-    use super::*;
+    // handler for Instr::CallProc
+    fn call_procedure (&mut self) {
+        let id = self.tape.get(self.data_ptr);
+        match self.procedures.get(&id) {
+            Some(&start) => match self.limits.max_loop_depth.filter(|&limit| self.call_stack.len() >= limit) {
+                Some(limit) => {
+                    self.error = Option::Some(dtypes::BFError::LoopDepthExceeded { position: self.position(), source: Option::None, limit });
+                },
+                None => {
+                    self.call_stack.push(self.instr_ptr);
+                    // step() moves the instruction pointer on by one afterwards
+                    self.instr_ptr = start - 1;
+                },
+            },
+            None => {
+                self.error = Option::Some(dtypes::BFError::UndefinedProcedure { position: self.position(), source: Option::None, id });
+            },
+        };
+    }
 
-    #[test]
-    fn new_interpreter_no_errors () {
-        let bfi = BFInterpreter::new(8);
-        println!("\n--------------------");
-        println!("bfi: {:?}", bfi);
+    // handler for Instr::EndProc, return to just after the call
+    fn end_procedure (&mut self) {
+        if let Some(ret) = self.call_stack.pop() {
+            self.instr_ptr = ret;
+        }
     }
 
-    #[test]
-    fn test_fill_in_buf () {
-        // fill input buffer, no errors
-        let mut bfi = BFInterpreter::new(8);
-        bfi.fill_in_buff(String::from("++++"));
-        //println!("\n--------------------");
-        //println!("bfi: {:?}", bfi);
+    // handler for Instr::Fork, the new thread starts on the next cell (which
+    // moves like a >) set to 1 and takes its turn straight after this one,
+    // from the next instruction, this one's cell is set to 0
+    fn fork (&mut self) {
+        let Some(index) = self.offset_index(1) else {
+            return;
+        };
+        self.touch(index);
+        if self.reporting {
+            self.max_data_ptr = self.max_data_ptr.max(index);
+        }
+        self.tape.set(self.data_ptr, 0);
+        self.tape.set(index, 1);
+        self.threads.push_front(Thread { instr_ptr: self.instr_ptr + 1, data_ptr: index });
     }
 
-    #[test]
-    fn interpterter_run_inc_dec () {
-        let progs: Vec<(String, u8)> = vec![
-            // program, expected value
-            (String::from("+++"), 3),
-            (String::from("+++---"), 0),
-        ];
-        for (prog, exp_value) in progs {
-            let mut bfi = BFInterpreter::new(8);
-            bfi.fill_in_buff(prog);
-            //println!("\n--------------------");
-            //println!("bfi: {:?}", bfi);
-            bfi.run();
-            //println!("bfi: {:?}", bfi);
-            //println!("expected_value: {}", exp_value);
-            assert_eq!(bfi.mem[0], exp_value);
+    // once a step is done, hand over to the next Brainfork thread (if there
+    // are others) putting this one at the back of the queue, threads that have
+    // run off the end of the program are dropped, the last one left running
+    // off the end finishes the program
+    fn next_thread (&mut self) {
+        if self.threads.is_empty() {
+            return;
+        }
+        if self.instr_ptr < self.ops.len() {
+            self.threads.push_back(Thread { instr_ptr: self.instr_ptr, data_ptr: self.data_ptr });
+        }
+        while let Some(thread) = self.threads.pop_front() {
+            self.instr_ptr = thread.instr_ptr;
+            self.data_ptr = thread.data_ptr;
+            if self.instr_ptr < self.ops.len() {
+                break;
+            }
         }
     }
 
-    fn interpreter_run_mv_ptr () {
-        let progs: Vec<(String, usize)> = vec![
-            // program, expected pointer value
-            (String::from("+++>"), 1),
-            (String::from("++>+-<--"), 0),
-        ];
-        for (prog, exp_value) in progs {
-            let mut bfi = BFInterpreter::new(8);
-            bfi.fill_in_buff(prog);
-            //println!("\n--------------------");
-            //println!("bfi: {:?}", bfi);
-            bfi.run();
-            //println!("bfi: {:?}", bfi);
-            //println!("expected_value: {}", exp_value);
-            assert_eq!(bfi.data_ptr, exp_value);
+    // handler for Instr::End, skip to the end of the program
+    fn end (&mut self) {
+        // step() moves the instruction pointer on by one afterwards
+        self.instr_ptr = self.ops.len() - 1;
+    }
+
+    // handler for Instr::OutputByte, writing the cell at an index
+    fn output_byte (&mut self, index: usize) {
+        if let Some(limit) = self.limits.max_output_bytes.filter(|&limit| self.bytes_out >= limit) {
+            self.error = Option::Some(dtypes::BFError::OutputLimitExceeded { position: self.position(), source: Option::None, limit });
+            return;
+        }
+        // cells wider than 8 bits are output as their low byte
+        let val = self.tape.get(index) as u8;
+        for observer in &mut self.observers {
+            observer.on_output(val);
+        }
+        let result = match (&mut self.out_hook, &mut self.io, &mut self.out_sink) {
+            (Some(hook), _, _) => {
+                hook(val);
+                Ok(())
+            },
+            (None, Some(io), _) => io.write_byte(val),
+            (None, None, OutputSink::Stdout) => io::stdout().write_all(&[val]),
+            (None, None, OutputSink::Writer(w)) => w.write_all(&[val]),
+            (None, None, OutputSink::Buffer) => match self.out_buf_size {
+                Some(size) if self.out_buf.len() >= size => {
+                    self.error = Option::Some(dtypes::BFError::OutputLimitExceeded { position: self.position(), source: Option::None, limit: size as u64 });
+                    return;
+                },
+                _ => {
+                    self.out_buf.push(val);
+                    Ok(())
+                },
+            },
+            (None, None, OutputSink::BufferedWriter(w)) => {
+                self.out_buf.push(val);
+                if self.out_buf.len() >= self.out_buf_size.unwrap_or(DEFAULT_OUT_BUF_SIZE) {
+                    let result = w.write_all(&self.out_buf);
+                    self.out_buf.clear();
+                    result
+                } else {
+                    Ok(())
+                }
+            },
+        };
+        match result {
+            Ok(()) => {
+                self.bytes_out += 1;
+                if self.flush_policy == FlushPolicy::EveryByte {
+                    self.flush_output();
+                }
+            },
+            Err(e) => {
+                self.error = Option::Some(dtypes::BFError::Io {
+                    position: self.position(),
+                    source: Option::None,
+                    msg: format!("unable to write output: {}", e),
+                });
+            },
+        };
+    }
+
+    // flush any output that a streaming sink is holding on to
+    fn flush_output (&mut self) {
+        let result = self.flush();
+        // don't clobber an error that stopped the program
+        if let (Err(e), None) = (result, &self.error) {
+            self.error = Option::Some(dtypes::BFError::Io {
+                position: self.position(),
+                source: Option::None,
+                msg: format!("unable to write output: {}", e),
+            });
+        }
+    }
+
+    // handler for Instr::InputByte
+    fn input_byte (&mut self) {
+        // make sure any prompt the program printed is visible before blocking on input
+        if self.flush_policy == FlushPolicy::BeforeInput {
+            self.flush_output();
+        }
+        // read a single byte from the input source and store it at the data pointer
+        // location, if the source is exhausted apply the configured EOF behavior
+        let mut byte = [0_u8; 1];
+        loop {
+            let read = match (self.unread.pop(), &mut self.in_hook, &mut self.io) {
+                (Some(val), _, _) => Ok(Option::Some(val)),
+                (None, Some(hook), _) => Ok(hook()),
+                (None, None, Some(io)) => io.read_byte(),
+                (None, None, None) => {
+                    self.in_src.read(&mut byte).map(|n| if n == 0 { Option::None } else { Option::Some(byte[0]) })
+                },
+            };
+            match read {
+                Ok(None) => {
+                    match self.eof_behavior {
+                        dtypes::EofBehavior::NoChange => {},
+                        dtypes::EofBehavior::Zero => self.tape.set(self.data_ptr, 0),
+                        dtypes::EofBehavior::MinusOne => self.tape.set(self.data_ptr, self.cell_width.max_value()),
+                        dtypes::EofBehavior::Error => {
+                            self.error = Option::Some(dtypes::BFError::InputExhausted { position: self.position(), source: Option::None });
+                        },
+                    };
+                    return;
+                },
+                Ok(Some(val)) => {
+                    self.tape.set(self.data_ptr, val as u32);
+                    self.bytes_in += 1;
+                    return;
+                },
+                // retry reads that were interrupted before any data was read
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock
+                    && (self.slicing || matches!(self.in_src, InputSource::Provided { .. })) => {
+                    self.awaiting_input = true;
+                    return;
+                },
+                Err(e) => {
+                    self.error = Option::Some(dtypes::BFError::Io {
+                        position: self.position(),
+                        source: Option::None,
+                        msg: format!("unable to read input: {}", e),
+                    });
+                    return;
+                },
+            }
         }
     }
+
+    // handler for Instr::JumpIfZero
+    fn jump_right_if_zero (&mut self, target: usize) {
+        // if cell at the current data pointer location is 0 jump right to
+        // the matching closing bracket, the instruction pointer is left on
+        // the ] so that execution resumes right after it
+        if target >= self.ops.len() {
+            self.unmatched('[');
+        } else if self.ptr_val() == 0 {
+            self.instr_ptr = target;
+        }
+    }
+
+    // handler for Instr::JumpIfNonZero
+    fn jump_left_if_non_zero (&mut self, target: usize) {
+        // if cell at the current data pointer location is not 0 jump back
+        // to the matching opening bracket, the instruction pointer is left
+        // on the [ so that execution resumes with the loop body
+        if target >= self.ops.len() {
+            self.unmatched(']');
+        } else if self.ptr_val() > 0 {
+            self.instr_ptr = target;
+        }
+    }
+
+    // stop on a jump to past the end of the program, which can only come from
+    // instructions given to load_instructions() rather than parsed
+    fn unmatched (&mut self, bracket: char) {
+        self.error = Option::Some(dtypes::BFError::UnmatchedBracket { position: self.position(), source: Option::None, bracket });
+    }
+
+    // handler for Instr::Debug
+    fn debug_dump (&mut self) {
+        // keep the dump in order with anything the program has output
+        self.flush_output();
+        eprintln!("{}", self.hash_debug_line());
+    }
+
+    // what the `#` debug command shows, the data pointer and the first
+    // HASH_DEBUG_CELLS cells with the one under the data pointer in brackets
+    fn hash_debug_line (&self) -> String {
+        let mut line = format!("#  data pointer: {}  cells:", self.data_ptr);
+        for i in 0..dtypes::HASH_DEBUG_CELLS.min(self.tape.len()) {
+            if i == self.data_ptr {
+                line.push_str(&format!(" [{}]", self.tape.get(i)));
+            } else {
+                line.push_str(&format!(" {}", self.tape.get(i)));
+            }
+        }
+        line
+    }
+
+    // execute a just loaded program up to its first instruction that does input
+    // or output (or the end, or budget steps), going back to the start if it
+    // stops on an error so that the error comes up when it is run instead
+    fn run_ahead (&mut self, budget: u64) {
+        if self.tracer.is_some() || self.profile.is_some() || self.history_depth > 0 || !self.observers.is_empty() {
+            return;
+        }
+        let Some(cells) = self.tape.cells().map(<[u32]>::to_vec) else {
+            return;
+        };
+        let (origin, data_ptr, storage) = (self.tape.origin(), self.data_ptr, self.storage);
+        let pure = |instr: dtypes::Instr| !matches!(instr,
+            dtypes::Instr::OutputByte | dtypes::Instr::InputByte | dtypes::Instr::OutputAt(_) | dtypes::Instr::End | dtypes::Instr::Debug);
+        while self.steps < budget && self.current_instruction().is_some_and(pure) {
+            if self.step().is_err() {
+                self.tape.load(cells, origin);
+                self.data_ptr = data_ptr;
+                self.storage = storage;
+                self.start_program();
+                return;
+            }
+        }
+    }
+
+    // wrap up once the program has finished or stopped on an error
+    fn terminate (&mut self) {
+        self.flush_output();
+        self.term_flg = true;
+        // errors are raised knowing only the command index, point them at the source
+        self.error = self.error.take().map(|e| e.locate(&self.sources));
+        if let Some(tracer) = &mut self.tracer {
+            tracer.finish();
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.finish();
+        }
+    }
+
+    // BFError::Interrupted if the interrupt flag has been set (clearing it
+    // again), with the output so far flushed
+    fn check_interrupt (&mut self) -> Result<(), dtypes::BFError> {
+        if !self.interrupt.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed) && flag.swap(false, Ordering::Relaxed)) {
+            return Ok(());
+        }
+        self.flush_output();
+        let e = dtypes::BFError::Interrupted { position: self.position(), source: Option::None };
+        Err(e.locate(&self.sources))
+    }
+
+    // true once the instruction pointer has run off the end of the program
+    // or an error has stopped it
+    pub fn is_finished (&self) -> bool {
+        self.instr_ptr >= self.ops.len() || self.error.is_some()
+    }
+
+    // execute the single instruction at the instruction pointer, stepping a program
+    // that has already finished does nothing (or returns the error that stopped it),
+    // as does stepping a `,` that is still waiting for input (see needs_input())
+    pub fn step (&mut self) -> Result<(), dtypes::BFError> {
+        self.awaiting_input = false;
+        // guard against programs that never finish
+        if let (false, Some(limit)) = (self.is_finished(), self.limits.max_steps) {
+            if self.steps >= limit {
+                self.error = Option::Some(dtypes::BFError::StepLimitExceeded { position: self.position(), source: Option::None, limit });
+                self.terminate();
+            }
+        }
+        // there is no cell for the data pointer to be on without any memory
+        if !self.is_finished() && self.tape.is_empty() {
+            self.error = Option::Some(dtypes::BFError::PointerOverrun { position: self.position(), source: Option::None });
+            self.terminate();
+        }
+        if !self.is_finished() {
+            let (index, data_ptr, before) = (self.instr_ptr, self.data_ptr, self.ptr_val());
+            let (bytes_in, call_depth, origin) = (self.bytes_in, self.call_stack.len(), self.tape.origin());
+            let delta = (self.history_depth > 0).then(|| self.record_step());
+            let watched = (!self.observers.is_empty()).then(|| self.watched_cells());
+            self.steps += 1;
+            match self.ops[self.instr_ptr].instr {
+                dtypes::Instr::Add(n) => self.add(n),
+                dtypes::Instr::Move(n) => self.move_pointer(n),
+                dtypes::Instr::OutputByte => self.output_byte(self.data_ptr),
+                dtypes::Instr::InputByte => self.input_byte(),
+                dtypes::Instr::JumpIfZero(target) => self.jump_right_if_zero(target),
+                dtypes::Instr::JumpIfNonZero(target) => self.jump_left_if_non_zero(target),
+                dtypes::Instr::Clear => self.clear(),
+                dtypes::Instr::AddTo(offset, factor) => self.add_to(offset, factor),
+                dtypes::Instr::AddAt(offset, n) => self.add_at(offset, n),
+                dtypes::Instr::ClearAt(offset) => self.clear_at(offset),
+                dtypes::Instr::OutputAt(offset) => self.output_at(offset),
+                dtypes::Instr::ScanRight => self.scan(true),
+                dtypes::Instr::ScanLeft => self.scan(false),
+                dtypes::Instr::End => self.end(),
+                dtypes::Instr::Store => self.storage = self.tape.get(self.data_ptr),
+                dtypes::Instr::DefineProc(end) => self.define_procedure(end),
+                dtypes::Instr::CallProc => self.call_procedure(),
+                dtypes::Instr::EndProc => self.end_procedure(),
+                dtypes::Instr::Fork => self.fork(),
+                dtypes::Instr::Debug => self.debug_dump(),
+                instr @ (dtypes::Instr::Retrieve | dtypes::Instr::ShiftRight | dtypes::Instr::ShiftLeft
+                         | dtypes::Instr::Not | dtypes::Instr::Xor | dtypes::Instr::And | dtypes::Instr::Or) => {
+                    self.bitwise(instr)
+                },
+            };
+            // a `,` waiting for input hasn't happened yet
+            if self.awaiting_input {
+                self.steps -= 1;
+                return Ok(());
+            }
+            // everything moved along if the tape grew to the left
+            let grown = self.tape.origin() - origin;
+            let data_ptr = data_ptr + grown;
+            if let Some(mut delta) = delta {
+                delta.shift(grown);
+                if self.bytes_in > bytes_in {
+                    delta.input = Option::Some(self.tape.get(data_ptr) as u8);
+                }
+                if self.call_stack.len() < call_depth {
+                    delta.returned = Option::Some(self.instr_ptr);
+                }
+                if self.history.len() == self.history_depth {
+                    self.history.pop_front();
+                }
+                self.history.push_back(delta);
+            }
+            if self.reporting {
+                self.touch(data_ptr);
+                self.touch(self.data_ptr);
+                self.max_data_ptr = self.max_data_ptr.max(self.data_ptr);
+            }
+            if let Some(watched) = watched {
+                self.notify_observers(index, watched, grown);
+            }
+            if self.tracer.is_some() || self.profile.is_some() || !self.observers.is_empty() {
+                let event = trace::TraceEvent {
+                    step: self.steps - 1,
+                    index,
+                    position: self.ops[index].position,
+                    instr: self.ops[index].instr,
+                    data_ptr,
+                    before,
+                    // memory can only have grown since
+                    after: self.tape.get(data_ptr),
+                    input: (self.bytes_in > bytes_in).then(|| self.tape.get(data_ptr) as u8),
+                };
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.trace(&event);
+                }
+                if let Some(profile) = &mut self.profile {
+                    profile.record(&event);
+                }
+                for observer in &mut self.observers {
+                    observer.on_step(&event);
+                }
+            }
+            // move on to the next instruction (of the next thread), unless this
+            // one failed so that the instruction pointer is left on the culprit
+            if self.error.is_none() {
+                self.instr_ptr += 1;
+                self.next_thread();
+            }
+            // set terminated flag to signal execution has completed
+            if self.is_finished() {
+                self.terminate();
+            }
+        }
+        match &self.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    // the cell an Instr::AddTo (or AddAt, ClearAt) with this offset changes, which
+    // may be off either end of memory
+    fn add_to_target (&self, offset: isize) -> isize {
+        let target = self.data_ptr as isize + offset;
+        match self.tape.mode() {
+            dtypes::TapeMode::Wrapping => target.rem_euclid(self.tape.len() as isize),
+            _ => target,
+        }
+    }
+
+    // the cells the instruction at the instruction pointer could write, with
+    // their values before it is executed, the second is the cell an
+    // offset-addressed instruction works on (which may be off either end of memory)
+    fn watched_cells (&self) -> [(isize, u32); 2] {
+        let value = |i: isize| usize::try_from(i).ok().and_then(|i| self.cell(i)).unwrap_or(0);
+        let other = match self.ops[self.instr_ptr].instr {
+            dtypes::Instr::AddTo(offset, _) | dtypes::Instr::AddAt(offset, _) | dtypes::Instr::ClearAt(offset) => {
+                self.add_to_target(offset)
+            },
+            dtypes::Instr::Fork => self.add_to_target(1),
+            _ => self.data_ptr as isize,
+        };
+        [(self.data_ptr as isize, value(self.data_ptr as isize)), (other, value(other))]
+    }
+
+    // tell observers which cells the instruction at index changed and whether it
+    // went into or out of a loop, before the instruction pointer moves on
+    fn notify_observers (&mut self, index: usize, watched: [(isize, u32); 2], grown: usize) {
+        let [first, second] = watched;
+        let cells = if second.0 == first.0 { &watched[..1] } else { &watched[..] };
+        for &(i, before) in cells {
+            // moved along if the tape grew to the left
+            let Some(i) = usize::try_from(i + grown as isize).ok().filter(|&i| i < self.tape.len()) else {
+                continue;
+            };
+            let after = self.tape.get(i);
+            if after != before {
+                for observer in &mut self.observers {
+                    observer.on_memory_write(i, before, after);
+                }
+            }
+        }
+        // a jump that isn't taken goes into or out of the loop
+        if self.error.is_some() || self.instr_ptr != index {
+            return;
+        }
+        match self.ops[index].instr {
+            dtypes::Instr::JumpIfZero(_) => {
+                for observer in &mut self.observers {
+                    observer.on_loop_enter(index);
+                }
+            },
+            dtypes::Instr::JumpIfNonZero(start) => {
+                for observer in &mut self.observers {
+                    observer.on_loop_exit(start);
+                }
+            },
+            _ => {},
+        };
+    }
+
+    // what the instruction at the instruction pointer is about to change, before
+    // it is executed
+    fn record_step (&self) -> StepDelta {
+        let other_offset = match self.ops[self.instr_ptr].instr {
+            dtypes::Instr::AddTo(offset, _) | dtypes::Instr::AddAt(offset, _) | dtypes::Instr::ClearAt(offset) => Option::Some(offset),
+            // the new thread's cell
+            dtypes::Instr::Fork => Option::Some(1),
+            _ => Option::None,
+        };
+        // cells past the end of memory are dropped when it is cut back down,
+        // ones left of it start out as 0
+        let other_cell = other_offset.and_then(|offset| {
+            let target = self.add_to_target(offset);
+            match usize::try_from(target) {
+                Ok(i) if i < self.tape.len() => Option::Some((target, self.tape.get(i))),
+                Ok(_) => Option::None,
+                Err(_) => Option::Some((target, 0)),
+            }
+        });
+        let procedure = match self.ops[self.instr_ptr].instr {
+            dtypes::Instr::DefineProc(_) => {
+                let id = self.tape.get(self.data_ptr);
+                Option::Some((id, self.procedures.get(&id).copied()))
+            },
+            _ => Option::None,
+        };
+        StepDelta {
+            instr_ptr: self.instr_ptr,
+            data_ptr: self.data_ptr,
+            mem_size: self.tape.len(),
+            cell: (self.data_ptr, self.tape.get(self.data_ptr)),
+            other_cell,
+            storage: self.storage,
+            procedure,
+            call_depth: self.call_stack.len(),
+            returned: Option::None,
+            out_len: self.out_buf.len(),
+            bytes_out: self.bytes_out,
+            input: Option::None,
+            threads: (!self.threads.is_empty() || self.ops[self.instr_ptr].instr == dtypes::Instr::Fork).then(|| self.threads.clone()),
+        }
+    }
+
+    // undo the last step, putting back the memory, pointers, the output collected
+    // by OutputSink::Buffer and any input read (which is read again next time),
+    // output already written to a streaming sink can't be taken back, returns
+    // false if there is no history left to undo (see set_history_depth())
+    pub fn step_back (&mut self) -> bool {
+        let delta = match self.history.pop_back() {
+            Some(delta) => delta,
+            None => return false,
+        };
+        // cells added to the left stay, everything else has been moved along to match
+        self.tape.truncate(delta.mem_size);
+        self.tape.set(delta.cell.0, delta.cell.1);
+        if let Some((i, val)) = delta.other_cell {
+            self.tape.set(i as usize, val);
+        }
+        self.instr_ptr = delta.instr_ptr;
+        self.data_ptr = delta.data_ptr;
+        self.storage = delta.storage;
+        if let Some((id, start)) = delta.procedure {
+            match start {
+                Some(start) => self.procedures.insert(id, start),
+                None => self.procedures.remove(&id),
+            };
+        }
+        self.call_stack.truncate(delta.call_depth);
+        if let Some(ret) = delta.returned {
+            self.call_stack.push(ret);
+        }
+        if let Some(threads) = delta.threads {
+            self.threads = threads;
+        }
+        self.out_buf.truncate(delta.out_len);
+        self.bytes_out = delta.bytes_out;
+        if let Some(byte) = delta.input {
+            self.unread.push(byte);
+            self.bytes_in -= 1;
+        }
+        self.steps -= 1;
+        self.error = Option::None;
+        self.term_flg = false;
+        true
+    }
+
+    // execute the program one instruction at a time as an iterator, yielding what
+    // each one did, or the error that stopped the program (after which it ends),
+    // stopping iterating early leaves the program where it got to
+    pub fn iter_steps (&mut self) -> Steps<'_> {
+        Steps { bfi: self }
+    }
+
+    // run the loaded program until it finishes or hits an error, picking up
+    // wherever a previous step() left off, or until it suspends waiting for
+    // input given with provide_input() (see needs_input())
+    pub fn run (&mut self) -> Result<(), dtypes::BFError> {
+        if let Some(timeout) = self.limits.timeout {
+            return self.run_with_timeout(timeout);
+        }
+        // set running flag while interpreter is running
+        self.run_flg = true;
+        self.awaiting_input = false;
+        // execute 1 instruction at a time from the program
+        // continue while the instruction pointer has not run off the end
+        // of the program and no error has been hit
+        let mut result = Ok(());
+        #[cfg(feature = "jit")]
+        if let Some(jit) = self.jit.take_if(|_| {
+            self.limits.max_steps.is_none() && self.limits.max_output_bytes.is_none() && self.limits.max_mem_cells.is_none()
+                && self.tracer.is_none() && self.profile.is_none() && !self.reporting && self.observers.is_empty()
+                && self.history_depth == 0 && self.tape.cells().is_some()
+        }) {
+            // run compiled code until it stops on an instruction that it can't
+            // handle, then execute that one instruction here and carry on
+            while !self.is_finished() && !self.awaiting_input {
+                if let Some(cells) = self.tape.cells_mut() {
+                    self.instr_ptr = jit.run(cells, &mut self.data_ptr, self.instr_ptr);
+                }
+                result = self.check_interrupt();
+                if result.is_err() {
+                    break;
+                }
+                if !self.is_finished() {
+                    result = self.step();
+                }
+            }
+            if self.is_finished() && !self.term_flg {
+                self.terminate();
+            }
+            self.jit = Option::Some(jit);
+        }
+        while result.is_ok() && !self.is_finished() && !self.awaiting_input {
+            result = self.check_interrupt().and_then(|()| self.step());
+        }
+        // after executing reset run flag
+        self.run_flg = false;
+        result
+    }
+
+    // like run() but give up with BFError::Timeout once the program has run for
+    // longer than timeout, a timeout doesn't stop the program so it can be carried
+    // on with another call, this always uses the interpreter engine
+    pub fn run_with_timeout (&mut self, timeout: Duration) -> Result<(), dtypes::BFError> {
+        // a deadline too far away to represent is as good as none
+        let deadline = Instant::now().checked_add(timeout);
+        self.run_flg = true;
+        self.awaiting_input = false;
+        let mut result = Ok(());
+        let mut n: u32 = 0;
+        while !self.is_finished() && !self.awaiting_input {
+            // checking the clock is slow compared to a step, so only do it now and then
+            if n.is_multiple_of(1024) && deadline.is_some_and(|d| Instant::now() >= d) {
+                let e = dtypes::BFError::Timeout { position: self.position(), source: Option::None, limit: timeout };
+                result = Err(e.locate(&self.sources));
+                break;
+            }
+            n = n.wrapping_add(1);
+            result = self.check_interrupt().and_then(|()| self.step());
+            if result.is_err() && !self.is_finished() {
+                break;
+            }
+        }
+        self.run_flg = false;
+        result
+    }
+
+    // execute at most n instructions and say why it stopped, so that a frame loop
+    // can carry a program on a slice at a time, each call picks up exactly where
+    // the last left off (even in the middle of a loop), a `,` whose input source
+    // or BfIo backend has nothing ready yet (failing with io::ErrorKind::WouldBlock,
+    // or input given with provide_input() running out) stops with
+    // StepOutcome::NeedsInput and is tried again by the next call, this
+    // always uses the interpreter engine so that every slice is n instructions
+    pub fn run_for (&mut self, n: u64) -> dtypes::StepOutcome {
+        self.run_flg = true;
+        self.slicing = true;
+        let mut outcome = dtypes::StepOutcome::Paused;
+        for _ in 0..n {
+            if self.is_finished() {
+                break;
+            }
+            if let Err(e) = self.check_interrupt() {
+                outcome = dtypes::StepOutcome::Error(e);
+                break;
+            }
+            // errors are kept in self.error
+            let _ = self.step();
+            if self.awaiting_input {
+                outcome = dtypes::StepOutcome::NeedsInput;
+                break;
+            }
+        }
+        self.slicing = false;
+        self.run_flg = false;
+        match (&self.error, self.is_finished()) {
+            (Some(e), _) => dtypes::StepOutcome::Error(e.clone()),
+            (None, true) => dtypes::StepOutcome::Finished,
+            (None, false) => outcome,
+        }
+    }
+
+    // like run() but on an async executor, `,` awaits a byte from input and `.`
+    // writes to output (flushed before waiting on input and once the program
+    // stops), control goes back to the executor every yield_every instructions
+    // so that a long running program doesn't hold up other tasks, this always
+    // uses the interpreter engine and ignores any BfIo backend, input source and
+    // output sink
+    #[cfg(feature = "tokio")]
+    pub async fn run_async<R, W> (&mut self, input: &mut R, output: &mut W, yield_every: u32) -> Result<(), dtypes::BFError>
+    where R: tokio::io::AsyncRead + Unpin, W: tokio::io::AsyncWrite + Unpin {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        self.run_flg = true;
+        let mut n: u32 = 0;
+        while !self.is_finished() {
+            n = n.wrapping_add(1);
+            if yield_every > 0 && n.is_multiple_of(yield_every) {
+                tokio::task::yield_now().await;
+            }
+            let mut bridge = AsyncBridge::default();
+            match self.current_instruction() {
+                Some(dtypes::Instr::InputByte) => {
+                    let mut byte = [0_u8; 1];
+                    let read = match output.flush().await {
+                        Ok(()) => input.read(&mut byte).await.map(|n| if n == 0 { Option::None } else { Option::Some(byte[0]) }),
+                        Err(e) => Err(e),
+                    };
+                    bridge.input = Option::Some(read);
+                },
+                // including a . folded together with the moves around it
+                Some(dtypes::Instr::OutputByte | dtypes::Instr::OutputAt(_)) => {},
+                _ => {
+                    // errors are kept in self.error
+                    let _ = self.step();
+                    continue;
+                },
+            };
+            // step the instruction with the bridge in place of the usual I/O
+            let position = self.position();
+            let saved = self.io.replace(Box::new(bridge));
+            let _ = self.step();
+            let written = self.io_mut::<AsyncBridge>().and_then(|bridge| bridge.output);
+            self.io = saved;
+            if let Some(byte) = written {
+                if let Err(e) = output.write_all(&[byte]).await {
+                    let e = dtypes::BFError::Io { position, source: Option::None, msg: format!("unable to write output: {}", e) };
+                    self.error = Option::Some(e.locate(&self.sources));
+                }
+            }
+        }
+        if !self.term_flg {
+            self.terminate();
+        }
+        // don't clobber an error that stopped the program
+        if let (Err(e), None) = (output.flush().await, &self.error) {
+            let e = dtypes::BFError::Io { position: self.position(), source: Option::None, msg: format!("unable to write output: {}", e) };
+            self.error = Option::Some(e.locate(&self.sources));
+        }
+        self.run_flg = false;
+        match &self.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    // run() the program and collect statistics about it since it was loaded, the
+    // cells touched and the furthest the data pointer went are only counted
+    // while one of these is running
+    pub fn run_with_report (&mut self) -> dtypes::RunReport {
+        let start = Instant::now();
+        self.reporting = true;
+        let result = self.run();
+        self.reporting = false;
+        dtypes::RunReport {
+            result,
+            instructions_executed: self.steps,
+            cells_touched: self.touched.len(),
+            max_data_ptr: self.max_data_ptr,
+            bytes_output: self.bytes_out,
+            bytes_input: self.bytes_in,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    // save the memory, data and instruction pointers and any input given with
+    // set_input() or provide_input() that hasn't been read yet, so that the program can be carried
+    // on later with restore()
+    pub fn snapshot (&self) -> Vec<u8> {
+        let input = match &self.in_src {
+            InputSource::Bytes(c) => {
+                let rest = c.get_ref().get(c.position() as usize..).unwrap_or_default();
+                Option::Some(self.unread.iter().rev().chain(rest).copied().collect())
+            },
+            InputSource::Provided { queued, .. } => Option::Some(self.unread.iter().rev().chain(queued).copied().collect()),
+            InputSource::Reader(_) => Option::None,
+        };
+        snapshot::Snapshot {
+            cell_width: self.cell_width,
+            tape_mode: self.tape.mode(),
+            program: snapshot::program_fingerprint(&self.ops),
+            data_ptr: self.data_ptr,
+            instr_ptr: self.instr_ptr,
+            storage: self.storage,
+            procedures: self.procedures.clone(),
+            call_stack: self.call_stack.clone(),
+            origin: self.tape.origin(),
+            threads: self.threads.iter().map(|thread| (thread.instr_ptr, thread.data_ptr)).collect(),
+            cells: (0..self.tape.len()).map(|i| self.tape.get(i)).collect(),
+            input,
+        }.to_bytes()
+    }
+
+    // preload memory with bytes, starting from the cell under the data pointer,
+    // e.g. data for a program to work on without reading it as input, growable
+    // tapes grow to fit them, otherwise BFError::MemoryLimitExceeded is returned
+    // and memory isn't changed, loading a program leaves memory as it is
+    pub fn set_memory (&mut self, bytes: &[u8]) -> Result<(), dtypes::BFError> {
+        let Some(last) = bytes.len().checked_sub(1) else {
+            return Ok(());
+        };
+        let grows = matches!(self.tape.mode(), dtypes::TapeMode::Growable | dtypes::TapeMode::Bidirectional);
+        let available = self.tape.len() - self.data_ptr;
+        if (!grows && bytes.len() > available) || self.tape_offset(last as isize).is_none() {
+            let limit = self.limits.max_mem_cells.unwrap_or(available);
+            return Err(self.error.take().unwrap_or(dtypes::BFError::MemoryLimitExceeded { position: 0, source: Option::None, limit }));
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.tape.set(self.data_ptr + i, byte as u32);
+        }
+        Ok(())
+    }
+
+    // carry on from a snapshot, the same program has to be loaded (with the same
+    // optimization setting) into an interpreter with the same cell width and tape
+    // mode, clears any error and starts counting steps again from 0
+    pub fn restore (&mut self, bytes: &[u8]) -> Result<(), snapshot::SnapshotError> {
+        let snap = snapshot::Snapshot::from_bytes(bytes)?;
+        if snap.cell_width != self.cell_width || snap.tape_mode != self.tape.mode() {
+            return Err(snapshot::SnapshotError::ConfigMismatch);
+        }
+        // procedure bodies start after their ( and calls return to a :, so a
+        // call can't land before the program or return off the end of it
+        if snap.program != snapshot::program_fingerprint(&self.ops) || snap.instr_ptr > self.ops.len()
+           || snap.threads.iter().any(|&(instr_ptr, _)| instr_ptr > self.ops.len())
+           || snap.procedures.values().any(|&start| start == 0 || start > self.ops.len())
+           || snap.call_stack.iter().any(|&ret| ret >= self.ops.len()) {
+            return Err(snapshot::SnapshotError::ProgramMismatch);
+        }
+        self.tape.load(snap.cells, snap.origin);
+        self.data_ptr = snap.data_ptr;
+        // the proof was for running the program from the start
+        self.in_bounds = false;
+        self.instr_ptr = snap.instr_ptr;
+        self.storage = snap.storage;
+        self.procedures = snap.procedures;
+        self.call_stack = snap.call_stack;
+        self.threads = snap.threads.into_iter().map(|(instr_ptr, data_ptr)| Thread { instr_ptr, data_ptr }).collect();
+        self.history.clear();
+        self.unread.clear();
+        self.steps = 0;
+        self.max_data_ptr = self.data_ptr;
+        self.touched.clear();
+        self.error = Option::None;
+        self.term_flg = false;
+        if let Some(input) = snap.input {
+            self.set_input(&input);
+        }
+        Ok(())
+    }
+
+    // index of the next instruction to be executed
+    pub fn instruction_pointer (&self) -> usize {
+        self.instr_ptr
+    }
+
+    // next instruction to be executed (None once the program has run off the end)
+    pub fn current_instruction (&self) -> Option<dtypes::Instr> {
+        self.ops.get(self.instr_ptr).map(|op| op.instr)
+    }
+
+    // the loaded program, as lowered for execution
+    pub fn instructions (&self) -> &[dtypes::Op] {
+        &self.ops
+    }
+
+    // line and column of each command in the loaded program's source (empty
+    // if it was loaded as instructions)
+    pub fn sources (&self) -> &[dtypes::SourcePos] {
+        &self.sources
+    }
+
+    // number of instructions executed so far
+    pub fn steps_executed (&self) -> u64 {
+        self.steps
+    }
+
+    // current data pointer location
+    pub fn data_pointer (&self) -> usize {
+        self.data_ptr
+    }
+
+    // true if it was proven when the program was loaded that it can't take the
+    // data pointer off the end of memory (only done for strict tapes), in which
+    // case moves aren't checked as it runs
+    pub fn bounds_proven (&self) -> bool {
+        self.in_bounds
+    }
+
+    // index of the cell the program started on, which is above 0 once a
+    // bidirectional tape has grown to the left
+    pub fn tape_origin (&self) -> usize {
+        self.tape.origin()
+    }
+
+    // the memory cells, empty for tapes that don't hold them all in one place
+    // like a tape::SparseTape (see cell() and memory_size())
+    pub fn memory (&self) -> &[u32] {
+        self.tape.cells().unwrap_or_default()
+    }
+
+    // number of memory cells
+    pub fn memory_size (&self) -> usize {
+        self.tape.len()
+    }
+
+    // value of the memory cell at index, None if it is past the end of memory
+    pub fn cell (&self, index: usize) -> Option<u32> {
+        (index < self.tape.len()).then(|| self.tape.get(index))
+    }
+
+    // copy of the cells in a range (clamped to the end of memory) along with the
+    // data pointer, e.g. dump_memory(..) for all of memory
+    pub fn dump_memory<R: RangeBounds<usize>> (&self, range: R) -> dtypes::MemoryDump {
+        let start = match range.start_bound() {
+            Bound::Included(&i) => i,
+            Bound::Excluded(&i) => i.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&i) => i.saturating_add(1),
+            Bound::Excluded(&i) => i,
+            Bound::Unbounded => self.tape.len(),
+        };
+        let end = end.min(self.tape.len());
+        let start = start.min(end);
+        dtypes::MemoryDump {
+            start,
+            cells: (start..end).map(|i| self.tape.get(i)).collect(),
+            data_ptr: self.data_ptr,
+            cell_width: self.cell_width,
+        }
+    }
+
+    // output collected by the OutputSink::Buffer sink (or held on to by the
+    // OutputSink::BufferedWriter sink and not written yet)
+    pub fn output (&self) -> &[u8] {
+        &self.out_buf
+    }
+
+    // hand over the output collected by the OutputSink::Buffer sink, leaving
+    // the buffer empty
+    pub fn take_output (&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out_buf)
+    }
+
+    // true while run() is executing
+    pub fn is_running (&self) -> bool {
+        self.run_flg
+    }
+
+    // true once the program has run to completion or stopped on an error
+    pub fn is_terminated (&self) -> bool {
+        self.term_flg
+    }
+
+    // counters collected so far, if profiling is on
+    pub fn profile (&self) -> Option<&profile::Profile> {
+        self.profile.as_ref()
+    }
+
+    // the error that stopped the program, if any
+    pub fn error (&self) -> Option<&dtypes::BFError> {
+        self.error.as_ref()
+    }
+
+    // number of threads still running, more than 1 once a Brainfork program
+    // has forked, the pointers and current_instruction() are those of the one
+    // whose turn is next
+    pub fn threads (&self) -> usize {
+        self.threads.len() + 1
+    }
+
+    // one line on where the program is: the next command, the data pointer
+    // and the cells around it with the one under it in brackets, and whether
+    // it has stopped, e.g.
+    //     command 2: +  data pointer: 1  cells: 1 [0] 0 0 0 0  (paused)
+    pub fn summary (&self) -> String {
+        let mut line = match self.current_instruction() {
+            Some(instr) => format!("command {}: {}", self.instr_ptr, instr),
+            None => format!("command {}: <end of program>", self.instr_ptr),
+        };
+        line.push_str(&format!("  data pointer: {}  cells:{}  ({}", self.data_ptr, self.window_text(&self.window()), self.state()));
+        match self.threads() {
+            1 => line.push(')'),
+            n => line.push_str(&format!(", {} threads)", n)),
+        };
+        line
+    }
+
+    // the cells within SUMMARY_RADIUS of the data pointer
+    fn window (&self) -> dtypes::MemoryDump {
+        self.dump_memory(self.data_ptr.saturating_sub(SUMMARY_RADIUS)..=self.data_ptr.saturating_add(SUMMARY_RADIUS))
+    }
+
+    // cells as a summary shows them, the one under the data pointer in brackets
+    fn window_text (&self, dump: &dtypes::MemoryDump) -> String {
+        dump.cells.iter().enumerate().map(|(i, cell)| match dump.start + i == self.data_ptr {
+            true => format!(" [{}]", cell),
+            false => format!(" {}", cell),
+        }).collect()
+    }
+
+    // a word or two on whether the program has started, stopped or is waiting
+    fn state (&self) -> &'static str {
+        if self.error.is_some() {
+            "stopped by an error"
+        } else if self.instr_ptr >= self.ops.len() {
+            "finished"
+        } else if self.awaiting_input {
+            "waiting for input"
+        } else if self.run_flg {
+            "running"
+        } else if self.steps == 0 {
+            "ready"
+        } else {
+            "paused"
+        }
+    }
+
+    // load instructions that have already been lowered, e.g. by the bf! macro,
+    // replacing any previously loaded program, jumps must point at their partners
+    pub fn load_instructions (&mut self, ops: &[dtypes::Op]) {
+        self.ops = ops.to_vec();
+        self.sources = Vec::new();
+        // folded runs cover one command per unit
+        self.n_cmds = ops.last().map_or(0, |op| op.position + match op.instr {
+            dtypes::Instr::Add(n) => n.unsigned_abs() as usize,
+            dtypes::Instr::Move(n) => n.unsigned_abs(),
+            _ => 1,
+        });
+        // nothing is known about where they take the data pointer (or that their
+        // jumps can be followed to find out)
+        self.in_bounds = false;
+        self.start_program();
+    }
+
+    // get ready to run a newly loaded program from the start
+    fn start_program (&mut self) {
+        // fall back to interpreting the program if it can't be compiled, compiled
+        // code would run one Brainfork thread without giving the others a turn
+        #[cfg(feature = "jit")]
+        {
+            let forks = self.ops.iter().any(|op| op.instr == dtypes::Instr::Fork);
+            self.jit = match self.engine {
+                dtypes::Engine::Jit if !forks => jit::JitProgram::compile(&self.ops, self.cell_width).ok(),
+                _ => Option::None,
+            };
+        }
+        self.instr_ptr = 0;
+        self.history.clear();
+        self.unread.clear();
+        self.procedures.clear();
+        self.call_stack.clear();
+        self.threads.clear();
+        self.steps = 0;
+        self.max_data_ptr = self.data_ptr;
+        self.touched.clear();
+        self.bytes_out = 0;
+        self.bytes_in = 0;
+        self.error = Option::None;
+        self.term_flg = false;
+        self.profile = self.profile.as_ref().map(profile::Profile::restart);
+    }
+
+    // parse program source into commands and lower them into instructions (folding
+    // runs of repeated commands unless optimization is off), replacing any previously
+    // loaded program, unbalanced brackets are reported here before anything gets executed,
+    // input embedded after a `!` replaces the input source
+    pub fn load_program (&mut self, prog: &str) -> Result<(), dtypes::BFError> {
+        let (prog, input) = if self.split_input && self.mapping.is_none() && self.dialect.allows_embedded_input() {
+            parsing::split_program_input(prog)
+        } else {
+            (prog, Option::None)
+        };
+        let tokens = match &self.mapping {
+            Some(mapping) => mapping.tokenize(prog),
+            None => parsing::tokenize_with_debug(prog, self.dialect, self.hash_debug),
+        };
+        let program: Vec<dtypes::Command> = tokens.iter().map(|token| token.command).collect();
+        let sources: Vec<dtypes::SourcePos> = tokens.iter().map(|token| token.pos).collect();
+        if let Some(limit) = self.limits.max_loop_depth {
+            parsing::check_depth(&program, limit).map_err(|e| e.locate(&sources))?;
+        }
+        // Brainfork threads take turns a command at a time, so folding runs of
+        // them would change how they interleave
+        let lowered = if self.optimize && self.dialect != dtypes::Dialect::Brainfork {
+            optimizer::optimize_for(&program, self.tape.mode(), self.tape.len(), self.data_ptr)
+        } else {
+            optimizer::lower(&program)
+        };
+        self.ops = lowered.map_err(|e| e.locate(&sources))?;
+        self.sources = sources;
+        self.n_cmds = program.len();
+        self.in_bounds = self.tape.mode() == dtypes::TapeMode::Strict
+                         && analysis::check_bounds(&self.ops, self.data_ptr, self.tape.len()).is_ok();
+        self.start_program();
+        if let Some(budget) = self.precompute {
+            self.run_ahead(budget);
+        }
+        if let Some(input) = input {
+            self.set_input(input.as_bytes());
+        }
+        Ok(())
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    // writer that keeps its bytes somewhere the test can still see them
+    struct SharedWriter(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush (&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // where a command is in a program written on a single line
+    fn at (column: usize) -> Option<dtypes::SourcePos> {
+        Some(dtypes::SourcePos { line: 1, column })
+    }
+
+    #[test]
+    fn new_interpreter_no_errors () {
+        let bfi = BFInterpreter::new(8);
+        println!("\n--------------------");
+        println!("bfi: {:?}", bfi);
+    }
+
+    #[test]
+    fn test_load_program () {
+        // load a program, comment characters are dropped
+        let mut bfi = BFInterpreter::new(8);
+        bfi.load_program("++ comment ++").unwrap();
+        assert_eq!(bfi.instructions(), [dtypes::Op { instr: dtypes::Instr::Add(4), position: 0 }]);
+        // one instruction per command without optimization
+        bfi.set_optimize(false);
+        bfi.load_program("++ comment ++").unwrap();
+        assert_eq!(bfi.instructions().len(), 4);
+        //println!("\n--------------------");
+        //println!("bfi: {:?}", bfi);
+    }
+
+    #[test]
+    fn interpterter_run_inc_dec () {
+        let progs: Vec<(String, u32)> = vec![
+            // program, expected value
+            (String::from("+++"), 3),
+            (String::from("+++---"), 0),
+        ];
+        for (prog, exp_value) in progs {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.load_program(&prog).unwrap();
+            //println!("\n--------------------");
+            //println!("bfi: {:?}", bfi);
+            bfi.run().unwrap();
+            //println!("bfi: {:?}", bfi);
+            //println!("expected_value: {}", exp_value);
+            assert_eq!(bfi.memory()[0], exp_value);
+        }
+    }
+
+    fn interpreter_run_mv_ptr () {
+        let progs: Vec<(String, usize)> = vec![
+            // program, expected pointer value
+            (String::from("+++>"), 1),
+            (String::from("++>+-<--"), 0),
+        ];
+        for (prog, exp_value) in progs {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.load_program(&prog).unwrap();
+            //println!("\n--------------------");
+            //println!("bfi: {:?}", bfi);
+            bfi.run().unwrap();
+            //println!("bfi: {:?}", bfi);
+            //println!("expected_value: {}", exp_value);
+            assert_eq!(bfi.data_pointer(), exp_value);
+        }
+    }
+
+    #[test]
+    fn interpreter_run_input_byte () {
+        let progs: Vec<(String, &[u8], Vec<u8>)> = vec![
+            // program, input, expected output
+            (String::from(",."), b"A", vec![65]),
+            (String::from(",.,.,."), b"abc", vec![97, 98, 99]),
+            (String::from(",+."), b"a", vec![98]),
+        ];
+        for (prog, input, exp_out) in progs {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.set_input_source(Box::new(Cursor::new(input.to_vec())));
+            bfi.set_output_sink(OutputSink::Buffer);
+            bfi.load_program(&prog).unwrap();
+            bfi.run().unwrap();
+            assert_eq!(bfi.output(), exp_out);
+        }
+    }
+
+    #[test]
+    fn interpreter_run_set_input () {
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+        bfi.set_input(b"scripted");
+        bfi.load_program(",[.,]").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), b"scripted");
+    }
+
+    #[test]
+    fn interpreter_run_input_byte_eof () {
+        let behaviors: Vec<(dtypes::EofBehavior, u32)> = vec![
+            // EOF behavior, expected value of cell 0
+            (dtypes::EofBehavior::NoChange, 3),
+            (dtypes::EofBehavior::Zero, 0),
+            (dtypes::EofBehavior::MinusOne, 255),
+        ];
+        for (eof_behavior, exp_value) in behaviors {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.set_input_source(Box::new(Cursor::new(Vec::new())));
+            bfi.set_eof_behavior(eof_behavior);
+            bfi.load_program("+++,").unwrap();
+            bfi.run().unwrap();
+            assert_eq!(bfi.memory()[0], exp_value);
+        }
+    }
+
+    #[test]
+    fn interpreter_run_eof_conventions () {
+        use dtypes::EofBehavior::{MinusOne, NoChange, Zero};
+        // cat written for each convention, and the behaviors it copies its input
+        // with, under any other it never sees the end of the input
+        let programs = [
+            // clears the cell before each read, so an unchanged cell is 0
+            (",[.[-],]", vec![NoChange, Zero]),
+            (",[.,]", vec![Zero]),
+            (",+[-.,+]", vec![MinusOne]),
+        ];
+        for (prog, works) in programs {
+            for eof_behavior in [NoChange, Zero, MinusOne] {
+                let mut bfi = BFInterpreter::new(8);
+                bfi.set_output_sink(OutputSink::Buffer);
+                bfi.set_input(b"cat");
+                bfi.set_eof_behavior(eof_behavior);
+                bfi.set_limits(dtypes::Limits { max_steps: Some(1000), ..Default::default() });
+                bfi.load_program(prog).unwrap();
+                match works.contains(&eof_behavior) {
+                    true => {
+                        assert_eq!(bfi.run(), Ok(()), "{} with {:?}", prog, eof_behavior);
+                        assert_eq!(bfi.output(), b"cat", "{} with {:?}", prog, eof_behavior);
+                    },
+                    false => assert!(matches!(bfi.run(), Err(dtypes::BFError::StepLimitExceeded { .. })),
+                                     "{} with {:?}", prog, eof_behavior),
+                };
+            }
+        }
+    }
+
+    #[test]
+    fn interpreter_iter_steps () {
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program("++[->+<]>.").unwrap();
+        let states: Vec<dtypes::ExecutionState> = bfi.iter_steps().map(Result::unwrap).collect();
+        assert_eq!(states, vec![
+            dtypes::ExecutionState { step: 0, index: 0, instr: dtypes::Instr::Add(2), data_ptr: 0, changed: Some((0, 2)) },
+            dtypes::ExecutionState { step: 1, index: 1, instr: dtypes::Instr::AddTo(1, 1), data_ptr: 0, changed: Some((1, 2)) },
+            dtypes::ExecutionState { step: 2, index: 2, instr: dtypes::Instr::Clear, data_ptr: 0, changed: Some((0, 0)) },
+            dtypes::ExecutionState { step: 3, index: 3, instr: dtypes::Instr::Move(1), data_ptr: 1, changed: None },
+            dtypes::ExecutionState { step: 4, index: 4, instr: dtypes::Instr::OutputByte, data_ptr: 1, changed: None },
+        ]);
+        assert!(bfi.is_finished());
+        assert_eq!(bfi.iter_steps().count(), 0);
+        // iteration ends with the error that stopped the program
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program("+<+").unwrap();
+        let results: Vec<_> = bfi.iter_steps().collect();
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[1], Err(dtypes::BFError::PointerUnderrun { .. })));
+        // stopping early leaves the program part way through
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_optimize(false);
+        bfi.load_program("+[+]").unwrap();
+        assert_eq!(bfi.iter_steps().take_while(|state| state.as_ref().unwrap().step < 100).count(), 100);
+        assert!(!bfi.is_finished());
+    }
+
+    #[test]
+    fn interpreter_precompute () {
+        let prog = "++++++++[>++++++++<-]>+.,.";
+        let run = |budget| {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.set_output_sink(OutputSink::Buffer);
+            bfi.set_precompute(budget);
+            bfi.set_input(b"x");
+            bfi.load_program(prog).unwrap();
+            let ahead = (bfi.steps_executed(), bfi.current_instruction(), bfi.memory()[1]);
+            bfi.run().unwrap();
+            assert_eq!(bfi.output(), b"Ax");
+            (ahead, bfi.steps_executed())
+        };
+        let (ahead, steps) = run(None);
+        assert_eq!(ahead, (0, Some(dtypes::Instr::Add(8)), 0));
+        // everything up to the first output has already run, and counts
+        assert_eq!(run(Some(1000)), ((steps - 3, Some(dtypes::Instr::OutputByte), 65), steps));
+        // or as much as the budget allows
+        assert_eq!(run(Some(2)).0, (2, Some(dtypes::Instr::Clear), 64));
+        // errors come up when the program is run, from the start
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_precompute(Some(1000));
+        bfi.load_program("+++<").unwrap();
+        assert_eq!((bfi.steps_executed(), bfi.memory()[0]), (0, 0));
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerUnderrun { position: 3, source: at(4) }));
+        // as does running out of steps
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_precompute(Some(1000));
+        bfi.set_max_steps(Some(10));
+        bfi.load_program("+[]").unwrap();
+        assert_eq!(bfi.steps_executed(), 0);
+        // and nothing is precomputed while keeping history
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_precompute(Some(1000));
+        bfi.set_history_depth(10);
+        bfi.load_program("+++").unwrap();
+        assert_eq!(bfi.steps_executed(), 0);
+    }
+
+    #[test]
+    fn interpreter_proven_bounds () {
+        let mut bfi = BFInterpreter::new(3);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_program("++[>+++[>+<-]<-]>>.").unwrap();
+        assert!(bfi.bounds_proven());
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [6]);
+        // everything is still checked when it can't be proven
+        let mut bfi = BFInterpreter::new(3);
+        bfi.load_program("+[>+]").unwrap();
+        assert!(!bfi.bounds_proven());
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerOverrun { position: 2, source: at(3) }));
+        // or when the tape isn't strict
+        let mut bfi = BFInterpreter::with_config(3, dtypes::TapeMode::Wrapping, dtypes::CellWidth::U8);
+        bfi.load_program(">").unwrap();
+        assert!(!bfi.bounds_proven());
+        // and after restoring a snapshot, which can be anywhere in the program
+        let mut bfi = BFInterpreter::new(3);
+        bfi.load_program(">>").unwrap();
+        let snap = bfi.snapshot();
+        assert!(bfi.bounds_proven());
+        bfi.restore(&snap).unwrap();
+        assert!(!bfi.bounds_proven());
+    }
+
+    #[test]
+    fn interpreter_state_accessors () {
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program("+++>++>+<").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.memory(), [3, 2, 1, 0]);
+        assert_eq!((bfi.cell(0), bfi.cell(2), bfi.cell(4)), (Some(3), Some(1), None));
+        assert_eq!(bfi.data_pointer(), 1);
+    }
+
+    #[test]
+    fn interpreter_run_output_sinks () {
+        // the buffer sink collects output to be read back or taken
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_program("+++.+.").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), vec![3, 4]);
+        assert_eq!(bfi.take_output(), vec![3, 4]);
+        assert!(bfi.output().is_empty());
+        // a writer sink receives the output and leaves the buffer empty
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Writer(Box::new(SharedWriter(Rc::clone(&written)))));
+        bfi.load_program("+++.+.").unwrap();
+        bfi.run().unwrap();
+        assert!(bfi.output().is_empty());
+        assert_eq!(*written.borrow(), vec![3, 4]);
+    }
+
+    #[test]
+    fn interpreter_output_buffer_size () {
+        // a full buffer stops the program rather than growing forever
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_output_buffer_size(Some(4));
+        bfi.load_program("+[.]").unwrap();
+        assert_eq!(bfi.run(), Err(dtypes::BFError::OutputLimitExceeded { position: 2, source: at(3), limit: 4 }));
+        assert_eq!(bfi.output(), &[1, 1, 1, 1]);
+        // a buffered writer is written to each time the buffer fills up instead
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::BufferedWriter(Box::new(SharedWriter(Rc::clone(&written)))));
+        bfi.set_output_buffer_size(Some(4));
+        bfi.set_input(b"a");
+        bfi.load_program("+.+.+.+.+.+.,.").unwrap();
+        assert_eq!(bfi.iter_steps().take(10).count(), 10);
+        assert_eq!(*written.borrow(), vec![1, 2, 3, 4]);
+        assert_eq!(bfi.output(), &[5]);
+        // and with everything left before input is read and at the end
+        assert_eq!(bfi.iter_steps().take(3).count(), 3);
+        assert_eq!(*written.borrow(), vec![1, 2, 3, 4, 5, 6]);
+        bfi.run().unwrap();
+        assert_eq!(*written.borrow(), vec![1, 2, 3, 4, 5, 6, b'a']);
+        assert!(bfi.output().is_empty());
+        // or only when it fills up and at the end, for filtering in a pipeline
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::BufferedWriter(Box::new(SharedWriter(Rc::clone(&written)))));
+        bfi.set_flush_policy(FlushPolicy::Blocks);
+        bfi.set_input(b"ab");
+        bfi.load_program(",.,.").unwrap();
+        assert_eq!(bfi.iter_steps().take(3).count(), 3);
+        assert!(written.borrow().is_empty());
+        bfi.run().unwrap();
+        assert_eq!(*written.borrow(), b"ab");
+        // or after every byte, for interactive programs
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::BufferedWriter(Box::new(SharedWriter(Rc::clone(&written)))));
+        bfi.set_flush_policy(FlushPolicy::EveryByte);
+        bfi.load_program("+.+.+.").unwrap();
+        assert_eq!(bfi.iter_steps().take(2).count(), 2);
+        assert_eq!(*written.borrow(), [1]);
+        // and whenever it is asked to
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::BufferedWriter(Box::new(SharedWriter(Rc::clone(&written)))));
+        bfi.load_program("+.+.+.").unwrap();
+        assert_eq!(bfi.iter_steps().take(4).count(), 4);
+        assert!(written.borrow().is_empty());
+        bfi.flush().unwrap();
+        assert_eq!(*written.borrow(), [1, 2]);
+        assert!(bfi.output().is_empty());
+    }
+
+    #[test]
+    fn interpreter_run_custom_io () {
+        // a buffer backend takes over from the input source and output sink
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_input(b"zzz");
+        bfi.set_io(Some(Box::new(bfio::BufferIo::new(b"ab"))));
+        bfi.load_program(",+.,+.,.").unwrap();
+        bfi.run().unwrap();
+        assert!(bfi.output().is_empty());
+        assert_eq!(bfi.io::<bfio::BufferIo>().unwrap().output(), b"bcc");
+        assert!(bfi.io::<bfio::StdIo>().is_none());
+        bfio::BfIo::write_byte(bfi.io_mut::<bfio::BufferIo>().unwrap(), b'!').unwrap();
+        assert_eq!(bfi.io::<bfio::BufferIo>().unwrap().output(), b"bcc!");
+        // a program on its own thread talking over channels
+        let (in_tx, in_rx) = std::sync::mpsc::channel();
+        let (out_tx, out_rx) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+            bfi.set_io(Some(Box::new(bfio::ChannelIo::new(in_rx, out_tx))));
+            bfi.load_program(",[+.,]").unwrap();
+            bfi.run()
+        });
+        for byte in [1, 2, 3] {
+            in_tx.send(byte).unwrap();
+            assert_eq!(out_rx.recv().unwrap(), byte + 1);
+        }
+        drop(in_tx);
+        assert_eq!(handle.join().unwrap(), Ok(()));
+        assert!(out_rx.recv().is_err());
+    }
+
+    #[test]
+    fn interpreter_hash_debug () {
+        // # is a comment unless hash debug is on
+        let mut bfi = BFInterpreter::new(20);
+        bfi.load_program("+#>").unwrap();
+        assert_eq!(bfi.instructions().len(), 2);
+        bfi.set_hash_debug(true);
+        bfi.set_optimize(false);
+        bfi.load_program("+#>++#").unwrap();
+        assert_eq!(bfi.instructions()[1].instr, dtypes::Instr::Debug);
+        assert_eq!(bfi.iter_steps().take(4).count(), 4);
+        assert_eq!(bfi.hash_debug_line(), "#  data pointer: 1  cells: 1 [1] 0 0 0 0 0 0 0 0 0 0 0 0 0 0");
+        bfi.run().unwrap();
+        // it only shows things
+        assert_eq!(bfi.memory()[..3], [1, 2, 0]);
+        assert_eq!(bfi.data_pointer(), 1);
+        let mut bfi = BFInterpreter::new(3);
+        bfi.set_hash_debug(true);
+        bfi.load_program(">>+").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.hash_debug_line(), "#  data pointer: 2  cells: 0 0 [1]");
+    }
+
+    #[test]
+    fn interpreter_io_hooks () {
+        // hooks take over from the sink, source and any BfIo backend
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&written);
+        let mut input = b"ab".to_vec().into_iter();
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_input(b"zzz");
+        bfi.set_io(Some(Box::new(bfio::BufferIo::new(b"yyy"))));
+        bfi.on_output(move |byte| sink.borrow_mut().push(byte));
+        bfi.on_input(move || input.next());
+        bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+        bfi.load_program(",+.,+.,.").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(*written.borrow(), b"bc\0");
+        assert!(bfi.output().is_empty());
+        assert!(bfi.io::<bfio::BufferIo>().unwrap().output().is_empty());
+        // input taken back is read again before asking the hook
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_history_depth(4);
+        let mut next = 0;
+        bfi.on_input(move || {
+            next += 1;
+            Some(next)
+        });
+        bfi.load_program(",.").unwrap();
+        bfi.step().unwrap();
+        assert!(bfi.step_back());
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), &[1]);
+        // and without the hooks it's back to the sink and source
+        bfi.clear_hooks();
+        bfi.set_input(b"x");
+        bfi.load_program(",.").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.take_output(), b"\x01x");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn interpreter_run_async () {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        // echo input back through an in-memory duplex pipe, alongside another task
+        let (mut ours, mut theirs) = tokio::io::duplex(16);
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+        bfi.load_program(",[+.,]").unwrap();
+        let client = runtime.spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let mut echoed = Vec::new();
+            for byte in [1, 2, 3] {
+                ours.write_all(&[byte]).await.unwrap();
+                echoed.push(ours.read_u8().await.unwrap());
+            }
+            ours.shutdown().await.unwrap();
+            echoed
+        });
+        let (mut input, mut output) = tokio::io::split(&mut theirs);
+        assert_eq!(runtime.block_on(bfi.run_async(&mut input, &mut output, 4)), Ok(()));
+        let echoed = runtime.block_on(client).unwrap();
+        assert_eq!(echoed, [2, 3, 4]);
+        assert!(bfi.is_terminated());
+        // a long program yields, and errors are reported as usual
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program("+[>+<]").unwrap();
+        bfi.set_max_steps(Some(10000));
+        let (mut input, mut output) = (tokio::io::empty(), tokio::io::sink());
+        let result = runtime.block_on(bfi.run_async(&mut input, &mut output, 1));
+        assert!(matches!(result, Err(dtypes::BFError::StepLimitExceeded { limit: 10000, .. })));
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_eof_behavior(dtypes::EofBehavior::Error);
+        bfi.load_program("+.,").unwrap();
+        let mut out = Vec::new();
+        let result = runtime.block_on(bfi.run_async(&mut input, &mut out, 1));
+        assert_eq!(result, Err(dtypes::BFError::InputExhausted { position: 2, source: at(3) }));
+        assert_eq!(out, [1]);
+        // output at an offset from the data pointer goes the same way
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_program("++++++++[>++++++++<-]>+<>.<").unwrap();
+        assert!(bfi.instructions().iter().any(|op| matches!(op.instr, dtypes::Instr::OutputAt(_))));
+        let mut out = Vec::new();
+        assert_eq!(runtime.block_on(bfi.run_async(&mut input, &mut out, 1)), Ok(()));
+        assert_eq!(out, b"A");
+        assert!(bfi.output().is_empty());
+    }
+
+    #[test]
+    fn interpreter_run_jumps () {
+        let progs: Vec<(String, u8)> = vec![
+            // program, expected first output byte
+            (String::from("+[++>]<."), 3),
+            (String::from("[+++]."), 0),
+            (String::from("++++>[]<."), 4),
+            (String::from("+[->+<]>."), 1),
+            (String::from("++++[->+<]>."), 4),
+            (String::from("+++>[[]]<."), 3),
+            // this one outputs "Hello World!\n"
+            (String::from("++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++."), 72),
+        ];
+        for (prog, exp_value) in progs {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.set_output_sink(OutputSink::Buffer);
+            bfi.load_program(&prog).unwrap();
+            assert_eq!(bfi.run(), Ok(()), "{}", prog);
+            assert_eq!(bfi.output()[0], exp_value);
+        }
+    }
+
+    #[test]
+    fn interpreter_load_unbalanced () {
+        let progs: Vec<(String, dtypes::BFError)> = vec![
+            // program, expected error
+            (String::from("]"), dtypes::BFError::UnmatchedBracket { bracket: ']', position: 0, source: at(1) }),
+            (String::from("[+++"), dtypes::BFError::UnmatchedBracket { bracket: '[', position: 0, source: at(1) }),
+        ];
+        for (prog, exp_err) in progs {
+            let mut bfi = BFInterpreter::new(8);
+            assert_eq!(bfi.load_program(&prog), Err(exp_err));
+            assert!(bfi.instructions().is_empty());
+        }
+    }
+
+    #[test]
+    fn interpreter_run_errors () {
+        let progs: Vec<(String, dtypes::BFError)> = vec![
+            // program, expected error
+            (String::from(">>>>>>>>"), dtypes::BFError::PointerOverrun { position: 7, source: at(8) }),
+            (String::from("+<"), dtypes::BFError::PointerUnderrun { position: 1, source: at(2) }),
+            (String::from("><<"), dtypes::BFError::PointerUnderrun { position: 2, source: at(3) }),
+            (String::from("+,"), dtypes::BFError::InputExhausted { position: 1, source: at(2) }),
+        ];
+        for (prog, exp_err) in progs {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.set_input_source(Box::new(Cursor::new(Vec::new())));
+            bfi.set_eof_behavior(dtypes::EofBehavior::Error);
+            bfi.load_program(&prog).unwrap();
+            assert_eq!(bfi.run(), Err(exp_err));
+            assert!(bfi.term_flg && !bfi.run_flg);
+        }
+    }
+
+    #[test]
+    fn interpreter_run_tape_modes () {
+        // wrapping moves off either end onto the other
+        let mut bfi = BFInterpreter::with_config(4, dtypes::TapeMode::Wrapping, dtypes::CellWidth::U8);
+        bfi.load_program("<+>>>>+").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.data_pointer(), 3);
+        assert_eq!(bfi.memory(), vec![0, 0, 0, 2]);
+        // growable extends memory on the right but not the left
+        let mut bfi = BFInterpreter::with_config(2, dtypes::TapeMode::Growable, dtypes::CellWidth::U8);
+        bfi.load_program(">>>>+").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.data_pointer(), 4);
+        assert_eq!(bfi.memory(), vec![0, 0, 0, 0, 1]);
+        let mut bfi = BFInterpreter::with_config(2, dtypes::TapeMode::Growable, dtypes::CellWidth::U8);
+        bfi.load_program("<").unwrap();
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerUnderrun { position: 0, source: at(1) }));
+        // bidirectional extends memory on both sides, moving the cells along on the left
+        let mut bfi = BFInterpreter::with_config(2, dtypes::TapeMode::Bidirectional, dtypes::CellWidth::U8);
+        bfi.load_program("+<<<++>>>>>+++").unwrap();
+        bfi.run().unwrap();
+        assert_eq!((bfi.tape_origin(), bfi.data_pointer()), (3, 5));
+        assert_eq!(bfi.memory(), vec![2, 0, 0, 1, 0, 3]);
+    }
+
+    #[test]
+    fn interpreter_bidirectional_tape () {
+        // copy loops and undo keep track of cells as the tape grows to the left
+        let mut bfi = BFInterpreter::with_config(1, dtypes::TapeMode::Bidirectional, dtypes::CellWidth::U8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_history_depth(100);
+        bfi.load_program("+++[-<<+>>]<<.").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [3]);
+        assert_eq!((bfi.tape_origin(), bfi.cell(bfi.tape_origin())), (2, Some(0)));
+        while bfi.step_back() {}
+        assert_eq!((bfi.instruction_pointer(), bfi.data_pointer()), (0, bfi.tape_origin()));
+        assert!(bfi.memory().iter().all(|&cell| cell == 0));
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [3]);
+        // a snapshot carries the cells on either side of where the program started
+        let snap = bfi.snapshot();
+        let mut other = BFInterpreter::with_config(1, dtypes::TapeMode::Bidirectional, dtypes::CellWidth::U8);
+        other.load_program("+++[-<<+>>]<<.").unwrap();
+        other.restore(&snap).unwrap();
+        assert_eq!((other.tape_origin(), other.memory()), (2, bfi.memory()));
+        // the unoptimized program gets to the same place
+        let mut unoptimized = BFInterpreter::with_config(1, dtypes::TapeMode::Bidirectional, dtypes::CellWidth::U8);
+        unoptimized.set_optimize(false);
+        unoptimized.load_program("+++[-<<+>>]<<").unwrap();
+        unoptimized.run().unwrap();
+        assert_eq!(unoptimized.cell(unoptimized.data_pointer()), Some(3));
+    }
+
+    #[test]
+    fn interpreter_sparse_tape () {
+        // runs the same as the vec backed tape without allocating all of memory
+        let prog = "+++[->>>>>>>>>>+<<<<<<<<<<]>>>>>>>>>>[<<<+>>>-]<<<.<<<<<<<<<<<<<<<<<<<+";
+        for mode in [dtypes::TapeMode::Strict, dtypes::TapeMode::Bidirectional] {
+            let mut dense = BFInterpreter::with_config(20, mode, dtypes::CellWidth::U8);
+            let mut sparse = BFInterpreter::with_tape(Box::new(tape::SparseTape::new(mode, 20)), dtypes::CellWidth::U8);
+            let mut results = Vec::new();
+            for bfi in [&mut dense, &mut sparse] {
+                bfi.set_output_sink(OutputSink::Buffer);
+                bfi.load_program(prog).unwrap();
+                let result = bfi.run();
+                let origin = bfi.tape_origin();
+                let cells: Vec<u32> = (origin..bfi.memory_size()).map(|i| bfi.cell(i).unwrap()).collect();
+                results.push((result, bfi.output().to_vec(), cells, bfi.data_pointer() as isize - origin as isize));
+            }
+            assert_eq!(results[0], results[1], "{:?}", mode);
+        }
+        let mut bfi = BFInterpreter::with_tape(Box::new(tape::SparseTape::new(dtypes::TapeMode::Strict, 1 << 40)), dtypes::CellWidth::U8);
+        bfi.load_program("+>-").unwrap();
+        bfi.run().unwrap();
+        assert_eq!((bfi.memory(), bfi.memory_size()), (&[][..], 1 << 40));
+        assert_eq!((bfi.cell(0), bfi.cell(1), bfi.cell(1 << 40)), (Some(1), Some(255), None));
+    }
+
+    #[test]
+    fn interpreter_step () {
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_optimize(false);
+        bfi.load_program("++[->+<]>.").unwrap();
+        // step through the first pass over the loop
+        for exp_ip in [1, 2, 3, 4, 5, 6, 7, 3] {
+            bfi.step().unwrap();
+            assert_eq!(bfi.instruction_pointer(), exp_ip);
+        }
+        assert_eq!(bfi.current_instruction(), Some(dtypes::Instr::Add(-1)));
+        assert_eq!(bfi.memory()[..2], [1, 1]);
+        assert!(!bfi.is_finished() && !bfi.term_flg);
+        // run picks up where step left off
+        bfi.run().unwrap();
+        assert!(bfi.is_finished() && bfi.term_flg);
+        assert_eq!(bfi.output(), vec![2]);
+        // stepping a finished program does nothing
+        bfi.step().unwrap();
+        assert_eq!(bfi.instruction_pointer(), 10);
+    }
+
+    #[test]
+    fn interpreter_step_error () {
+        // the instruction pointer stays on the command that failed
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_optimize(false);
+        bfi.load_program("+<+").unwrap();
+        bfi.step().unwrap();
+        let err = dtypes::BFError::PointerUnderrun { position: 1, source: at(2) };
+        assert_eq!(bfi.step(), Err(err.clone()));
+        assert_eq!(bfi.instruction_pointer(), 1);
+        assert!(bfi.is_finished());
+        assert_eq!(bfi.step(), Err(err));
+    }
+
+    #[test]
+    fn interpreter_error_source () {
+        // comments and line breaks are skipped over when finding the culprit
+        let prog = "a comment +\n  >>>> more\n\t<<<<<\n";
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_optimize(false);
+        bfi.load_program(prog).unwrap();
+        let err = bfi.run().unwrap_err();
+        assert_eq!(err.position(), 9);
+        assert_eq!(err.source(), Some(dtypes::SourcePos { line: 3, column: 6 }));
+        assert!(err.to_string().ends_with("at command 9 (line 3, column 6)"));
+        assert_eq!(parsing::source_snippet(prog, err.source().unwrap()), "3 | \t<<<<<\n  | \t    ^");
+        // the same command is blamed when the moves are folded together
+        let mut bfi = BFInterpreter::new(8);
+        bfi.load_program(prog).unwrap();
+        assert_eq!(bfi.run().unwrap_err(), err);
+        // and errors found while loading
+        let mut bfi = BFInterpreter::new(8);
+        let err = bfi.load_program("+\n +]").unwrap_err();
+        assert_eq!(err.source(), Some(dtypes::SourcePos { line: 2, column: 3 }));
+    }
+
+    #[test]
+    fn interpreter_run_dialects () {
+        // prints an A, and doesn't take the ! as the start of embedded input
+        let ook = "Ook. Ook. ".repeat(65) + "Ook! Ook.";
+        let blub = ook.replace("Ook", "Blub");
+        for (prog, dialect) in [(ook, dtypes::Dialect::Ook), (blub, dtypes::Dialect::Blub)] {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.set_output_sink(OutputSink::Buffer);
+            bfi.set_dialect(dialect);
+            bfi.load_program(&prog).unwrap();
+            bfi.run().unwrap();
+            assert_eq!(bfi.output(), b"A");
+        }
+        // errors point at the start of the offending command
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_dialect(dtypes::Dialect::Ook);
+        let err = bfi.load_program("Ook. Ook.\n  Ook? Ook!").unwrap_err();
+        assert_eq!(err.source(), Some(dtypes::SourcePos { line: 2, column: 3 }));
+    }
+
+    #[test]
+    fn interpreter_run_mapping () {
+        // the mapping wins over the dialect, and a ! is just a comment
+        let mapping = parsing::Mapping::parse("+=up,.=out,[=while,]=end,-=down,>=right,<=left,,=in").unwrap();
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_dialect(dtypes::Dialect::Ook);
+        bfi.set_mapping(Some(mapping));
+        bfi.load_program("up up while right up up left down end right out! in out").unwrap();
+        bfi.set_input(b"x");
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), b"\x04x");
+        let err = bfi.load_program("up\n  end").unwrap_err();
+        assert_eq!(err.source(), Some(dtypes::SourcePos { line: 2, column: 3 }));
+        // back to the dialect
+        bfi.set_mapping(None);
+        bfi.set_dialect(dtypes::Dialect::Brainfuck);
+        bfi.load_program("+.").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), b"\x04xy");
+    }
+
+    #[test]
+    fn interpreter_run_extended () {
+        // 12 in storage, then 10 shifted and combined with it, @ stops before the last +
+        let prog = "++++++++++++$>++++++++++.{.}}.~.^.&.|.>!.@+";
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_dialect(dtypes::Dialect::Extended);
+        bfi.load_program(prog).unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [10, 20, 5, 250, 246, 4, 12, 12]);
+        assert!(bfi.is_finished());
+        assert_eq!(bfi.instruction_pointer(), bfi.instructions().len());
+        // without the extension they are comments (and ! starts embedded input)
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_program(prog).unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [10; 7]);
+        // wider cells keep their high bits
+        let mut bfi = BFInterpreter::with_config(4, dtypes::TapeMode::Strict, dtypes::CellWidth::U16);
+        bfi.set_dialect(dtypes::Dialect::Extended);
+        bfi.load_program("-{~").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.memory()[0], 1);
+    }
+
+    #[test]
+    fn interpreter_run_procedures () {
+        // procedure 0 prints and adds one, procedure 1 calls 0 twice then adds two
+        let prog = "(.+)+(-:-:++):.";
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_dialect(dtypes::Dialect::Pbrain);
+        bfi.load_program(prog).unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [0, 0, 3]);
+        assert_eq!(bfi.memory()[..2], [3, 0]);
+        // calling something that was never defined
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_dialect(dtypes::Dialect::Pbrain);
+        bfi.load_program("(-)++:").unwrap();
+        let err = bfi.run().unwrap_err();
+        assert_eq!(err, dtypes::BFError::UndefinedProcedure { position: 5, source: at(6), id: 2 });
+        assert_eq!(err.to_string(), "call to undefined procedure 2 at command 5 (line 1, column 6)");
+        // procedures survive a snapshot
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_dialect(dtypes::Dialect::Pbrain);
+        bfi.set_max_steps(Some(4));
+        bfi.load_program(prog).unwrap();
+        assert!(bfi.run().is_err());
+        let snap = bfi.snapshot();
+        bfi.load_program(prog).unwrap();
+        bfi.restore(&snap).unwrap();
+        bfi.set_max_steps(None);
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [0, 0, 3]);
+        // but not ones that start or return outside the program
+        let ops = bfi.ops.len();
+        let mut snap = snapshot::Snapshot::from_bytes(&snap).unwrap();
+        for (procedures, call_stack) in [(vec![(0, 0)], vec![]), (vec![(0, ops + 1)], vec![]), (vec![], vec![ops])] {
+            let corrupt = snapshot::Snapshot { procedures: procedures.into_iter().collect(), call_stack, ..snap.clone() };
+            assert_eq!(bfi.restore(&corrupt.to_bytes()), Err(snapshot::SnapshotError::ProgramMismatch));
+        }
+        snap.procedures.insert(0, ops);
+        snap.call_stack.push(ops - 1);
+        assert_eq!(bfi.restore(&snap.to_bytes()), Ok(()));
+    }
+
+    #[test]
+    fn interpreter_run_threads () {
+        // the new thread goes into the loop (its cell is 1) and prints twice,
+        // taking turns with the first which skips it (its cell is 0) and prints once
+        let prog = "Y[..-].";
+        let brainfork = |prog: &str| {
+            let mut bfi = BFInterpreter::new(4);
+            bfi.set_output_sink(OutputSink::Buffer);
+            bfi.set_dialect(dtypes::Dialect::Brainfork);
+            bfi.set_history_depth(16);
+            bfi.load_program(prog).unwrap();
+            bfi
+        };
+        let mut bfi = brainfork(prog);
+        bfi.step().unwrap();
+        assert_eq!((bfi.threads(), bfi.instruction_pointer(), bfi.data_pointer()), (2, 1, 1));
+        assert_eq!(bfi.memory()[..2], [0, 1]);
+        assert!(bfi.to_string().contains("\nthreads: 2  waiting: command 1 on cell 0\n"), "{}", bfi);
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [1, 0, 1, 0]);
+        assert_eq!((bfi.threads(), bfi.steps_executed()), (1, 9));
+        // back through the fork
+        while bfi.step_back() {}
+        assert_eq!((bfi.threads(), bfi.instruction_pointer(), bfi.output()), (1, 0, &[][..]));
+        assert_eq!(bfi.memory()[..2], [0, 0]);
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [1, 0, 1, 0]);
+        // threads survive a snapshot
+        let mut bfi = brainfork(prog);
+        bfi.set_max_steps(Some(4));
+        assert!(bfi.run().is_err());
+        let snap = bfi.snapshot();
+        bfi.load_program(prog).unwrap();
+        bfi.restore(&snap).unwrap();
+        assert_eq!(bfi.threads(), 2);
+        bfi.set_max_steps(None);
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [1, 0, 1, 0]);
+        // a command a turn, however the program is optimized
+        assert_eq!(brainfork("Y++").instructions().len(), 3);
+        // the new thread's cell has to be on the tape
+        let mut bfi = BFInterpreter::new(1);
+        bfi.set_dialect(dtypes::Dialect::Brainfork);
+        bfi.load_program("Y").unwrap();
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerOverrun { position: 0, source: at(1) }));
+        // Y is a comment elsewhere
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program("Y+").unwrap();
+        assert_eq!(bfi.instructions().len(), 1);
+    }
+
+    #[test]
+    fn interpreter_run_cell_widths () {
+        let widths: Vec<(dtypes::CellWidth, u32)> = vec![
+            // cell width, value of a cell after decrementing from 0
+            (dtypes::CellWidth::U8, 255),
+            (dtypes::CellWidth::U16, 65535),
+            (dtypes::CellWidth::U32, 4294967295),
+        ];
+        for (cell_width, exp_value) in widths {
+            let mut bfi = BFInterpreter::with_config(8, dtypes::TapeMode::Strict, cell_width);
+            bfi.set_output_sink(OutputSink::Buffer);
+            bfi.load_program("->-+.").unwrap();
+            bfi.run().unwrap();
+            assert_eq!(bfi.memory()[0], exp_value);
+            // rolls back over to 0
+            assert_eq!(bfi.memory()[1], 0);
+            // output is the low byte of the cell
+            assert_eq!(bfi.output(), vec![0]);
+        }
+        // 256 increments only rolls over 8 bit cells
+        let mut bfi = BFInterpreter::with_config(8, dtypes::TapeMode::Strict, dtypes::CellWidth::U16);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_program(&format!("{}+.", "+".repeat(256))).unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.memory()[0], 257);
+        assert_eq!(bfi.output(), vec![1]);
+    }
+
+    #[test]
+    fn interpreter_run_max_steps () {
+        // an infinite loop is stopped once the limit is reached
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_max_steps(Some(100));
+        bfi.load_program("+[]").unwrap();
+        assert_eq!(bfi.run(), Err(dtypes::BFError::StepLimitExceeded { position: 2, source: at(3), limit: 100 }));
+        assert_eq!(bfi.steps_executed(), 100);
+        assert!(bfi.is_terminated());
+        // a program that needs exactly the limit is fine
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_optimize(false);
+        bfi.set_max_steps(Some(3));
+        bfi.load_program("+++").unwrap();
+        assert_eq!(bfi.run(), Ok(()));
+        assert_eq!(bfi.steps_executed(), 3);
+        // steps count instructions, so folded runs use fewer of them
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_max_steps(Some(3));
+        bfi.load_program("+++>>>---").unwrap();
+        assert_eq!(bfi.run(), Ok(()));
+    }
+
+    #[test]
+    fn interpreter_optimized_matches_unoptimized () {
+        let progs: Vec<(&str, dtypes::TapeMode)> = vec![
+            // program, tape mode
+            ("++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.", dtypes::TapeMode::Strict),
+            ("+++[>+++++<-]>[>++<-]>.<<<<<<<<<<<<++.", dtypes::TapeMode::Wrapping),
+            (">>>>>>>>>>>>+.<<<<<<<<<<<<<<<<<<<<<<<<.", dtypes::TapeMode::Growable),
+            ("-----.---------[>+<---------]>.", dtypes::TapeMode::Strict),
+            ("++++++[>+++++<-]>[->++>+++<<]>.>.<<[-]+++[->>>>>>>>>+<<<<<<<<<]>>>>>>>>>.", dtypes::TapeMode::Wrapping),
+            ("+++++[->>>>>>>>>>>>++<<<<<<<<<<<<]>>>>>>>>>>>>.", dtypes::TapeMode::Growable),
+        ];
+        for (prog, tape_mode) in progs {
+            let mut results = Vec::new();
+            for optimize in [false, true] {
+                let mut bfi = BFInterpreter::with_config(8, tape_mode, dtypes::CellWidth::U8);
+                bfi.set_output_sink(OutputSink::Buffer);
+                bfi.set_optimize(optimize);
+                bfi.load_program(prog).unwrap();
+                let result = bfi.run();
+                results.push((result, bfi.output().to_vec(), bfi.memory().to_vec(), bfi.data_pointer()));
+            }
+            assert_eq!(results[0], results[1], "{}", prog);
+        }
+    }
+
+    #[test]
+    fn interpreter_run_loop_idioms () {
+        // copy/multiply loops with wider cells
+        let mut bfi = BFInterpreter::with_config(8, dtypes::TapeMode::Strict, dtypes::CellWidth::U16);
+        bfi.load_program("++++++++++[>++++++++++<-]>[->+++>---<<]").unwrap();
+        assert!(bfi.instructions().iter().any(|op| matches!(op.instr, dtypes::Instr::AddTo(..))));
+        bfi.run().unwrap();
+        assert_eq!(bfi.memory()[..4], [0, 0, 300, 65536 - 300]);
+        // a copy loop that would step off the tape only fails if it runs
+        let mut bfi = BFInterpreter::new(8);
+        bfi.load_program("[-<+>]+[-<+>]").unwrap();
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerUnderrun { position: 7, source: at(8) }));
+    }
+
+    #[test]
+    fn interpreter_dump_memory () {
+        let mut bfi = BFInterpreter::new(20);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_program("++++++++[>++++++++<-]>+>++>>>>>>>>>>>>>>>>>-").unwrap();
+        bfi.run().unwrap();
+        let dump = bfi.dump_memory(1..3);
+        assert_eq!(dump, dtypes::MemoryDump {
+            start: 1,
+            cells: vec![65, 2],
+            data_ptr: 19,
+            cell_width: dtypes::CellWidth::U8,
+        });
+        assert_eq!(bfi.dump_memory(..).cells.len(), 20);
+        assert_eq!(bfi.dump_memory(18..=100).cells, vec![0, 255]);
+        assert!(bfi.dump_memory(30..40).cells.is_empty());
+        assert_eq!(bfi.dump_memory(..).to_string(), "\
+00000000  00 41 02 00 00 00 00 00 00 00 00 00 00 00 00 00  |.A..............|
+00000010  00 00 00[ff]                                     |....|
+data pointer: 19");
+        let mut bfi = BFInterpreter::with_config(4, dtypes::TapeMode::Strict, dtypes::CellWidth::U16);
+        bfi.load_program("-").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.dump_memory(..).to_string(), "\
+00000000 [ffff]0000 0000 0000                      |....|
+data pointer: 0");
+    }
+
+    #[test]
+    fn interpreter_run_tracer () {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&events);
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_tracer(Some(Box::new(move |event: &trace::TraceEvent| sink.borrow_mut().push(*event))));
+        bfi.load_program("++>+<<").unwrap();
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerUnderrun { position: 5, source: at(6) }));
+        let events = events.borrow();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0], trace::TraceEvent {
+            step: 0,
+            index: 0,
+            position: 0,
+            instr: dtypes::Instr::Add(2),
+            data_ptr: 0,
+            before: 0,
+            after: 2,
+            input: None,
+        });
+        assert_eq!((events[1].instr, events[1].data_ptr, events[1].after), (dtypes::Instr::Move(1), 0, 2));
+        assert_eq!((events[2].index, events[2].position, events[2].data_ptr, events[2].after), (2, 3, 1, 1));
+        // the failing instruction is traced too
+        assert_eq!((events[3].step, events[3].instr, events[3].position), (3, dtypes::Instr::Move(-2), 4));
+    }
+
+    // observer writing down what it is told
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl observer::Observer for Recorder {
+        fn on_step (&mut self, event: &trace::TraceEvent) {
+            self.0.borrow_mut().push(format!("step {}", event.index));
+        }
+
+        fn on_output (&mut self, byte: u8) {
+            self.0.borrow_mut().push(format!("output {}", byte));
+        }
+
+        fn on_memory_write (&mut self, index: usize, before: u32, after: u32) {
+            self.0.borrow_mut().push(format!("write {} {}->{}", index, before, after));
+        }
+
+        fn on_loop_enter (&mut self, index: usize) {
+            self.0.borrow_mut().push(format!("enter {}", index));
+        }
+
+        fn on_loop_exit (&mut self, index: usize) {
+            self.0.borrow_mut().push(format!("exit {}", index));
+        }
+    }
+
+    #[test]
+    fn interpreter_observers () {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_optimize(false);
+        bfi.add_observer(Box::new(Recorder(Rc::clone(&events))));
+        bfi.load_program("++[>+<-]>.").unwrap();
+        bfi.run().unwrap();
+        let told: Vec<String> = events.borrow().iter().filter(|e| !e.starts_with("step")).cloned().collect();
+        assert_eq!(told, [
+            "write 0 0->1", "write 0 1->2", "enter 2", "write 1 0->1", "write 0 2->1",
+            "write 1 1->2", "write 0 1->0", "exit 2", "output 2",
+        ]);
+        assert_eq!(events.borrow().iter().filter(|e| e.starts_with("step")).count() as u64, bfi.steps);
+        // the callbacks for an instruction come before its step
+        assert_eq!(&events.borrow()[..3], ["write 0 0->1", "step 0", "write 0 1->2"]);
+        // loops lowered to single instructions just write cells
+        events.borrow_mut().clear();
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.add_observer(Box::new(Recorder(Rc::clone(&events))));
+        bfi.load_program("++[>+<-]>.").unwrap();
+        bfi.run().unwrap();
+        let told: Vec<String> = events.borrow().iter().filter(|e| !e.starts_with("step")).cloned().collect();
+        assert_eq!(told, ["write 0 0->2", "write 1 0->2", "write 0 2->0", "output 2"]);
+        bfi.clear_observers();
+        events.borrow_mut().clear();
+        bfi.load_program("+.").unwrap();
+        bfi.run().unwrap();
+        assert!(events.borrow().is_empty());
+    }
+
+    #[test]
+    fn interpreter_run_profile () {
+        let mut bfi = BFInterpreter::new(8);
+        assert!(bfi.profile().is_none());
+        bfi.set_profiling(true);
+        bfi.set_optimize(false);
+        bfi.load_program("++[>+++[-]<-]").unwrap();
+        bfi.run().unwrap();
+        let profile = bfi.profile().unwrap();
+        // the inner loop body runs 3 times for each of the 2 outer iterations
+        assert_eq!(profile.hits[&0], 1);
+        assert_eq!(profile.hits[&2], 1);
+        assert_eq!(profile.hits[&8], 6);
+        assert_eq!(profile.hits[&12], 2);
+        assert_eq!(profile.loops[&2].entries, 1);
+        assert_eq!(profile.loops[&2].iterations, 2);
+        assert_eq!(profile.loops[&7].entries, 2);
+        assert_eq!(profile.loops[&7].iterations, 6);
+        assert!(profile.loops[&2].time >= profile.loops[&7].time);
+        let report = profile.to_string();
+        assert!(report.starts_with("hottest commands:\n   command           hits\n         8              6\n"));
+        assert!(report.contains("slowest loops:\n"));
+        assert!(profile.spans().is_empty());
+        // the timeline has each execution of the inner loop inside the outer one
+        bfi.set_span_profiling(true);
+        bfi.load_program("++[>+++[-]<-]").unwrap();
+        bfi.run().unwrap();
+        let spans = bfi.profile().unwrap().spans();
+        assert_eq!(spans.iter().map(|span| span.position).collect::<Vec<_>>(), [7, 7, 2]);
+        assert!(spans[0].start >= spans[2].start && spans[1].start + spans[1].duration <= spans[2].start + spans[2].duration);
+    }
+
+    #[test]
+    fn interpreter_run_embedded_input () {
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_input_source(Box::new(Cursor::new(b"stdin".to_vec())));
+        bfi.load_program(",[.[-],]!hi!").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), b"hi!");
+        // with splitting off the ! is a comment and input comes from the source
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_split_input(false);
+        bfi.set_input_source(Box::new(Cursor::new(b"stdin".to_vec())));
+        bfi.load_program(",[.[-],]!hi!").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), b"stdin");
+    }
+
+    #[test]
+    fn interpreter_run_with_report () {
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_input(b"ab");
+        bfi.load_program(",>,>++[->>+<<]>>.").unwrap();
+        let report = bfi.run_with_report();
+        assert_eq!(report.result, Ok(()));
+        assert_eq!(report.instructions_executed, 9);
+        // cells 0 to 2 visited, cell 4 changed from cell 2
+        assert_eq!(report.cells_touched, 4);
+        assert_eq!(report.max_data_ptr, 4);
+        assert_eq!(report.bytes_output, 1);
+        assert_eq!(report.bytes_input, 2);
+        assert!(report.to_string().starts_with("instructions executed: 9\ncells touched: 4\n"));
+        // errors are reported along with everything up to them
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program(">>>>").unwrap();
+        let report = bfi.run_with_report();
+        assert_eq!(report.result, Err(dtypes::BFError::PointerOverrun { position: 3, source: at(4) }));
+        assert_eq!(report.max_data_ptr, 3);
+        // a plain run doesn't keep track of the cells
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program(">>+>[-]").unwrap();
+        bfi.run().unwrap();
+        assert!(bfi.touched.is_empty());
+        assert_eq!(bfi.max_data_ptr, 0);
+    }
+
+    #[test]
+    fn interpreter_snapshot_restore () {
+        let prog = ",[.,]++++++++[>++++++++<-]>+.";
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+        bfi.set_input(b"abc");
+        bfi.set_max_steps(Some(4));
+        bfi.load_program(prog).unwrap();
+        assert!(matches!(bfi.run(), Err(dtypes::BFError::StepLimitExceeded { .. })));
+        assert_eq!(bfi.output(), b"a");
+        let snap = bfi.snapshot();
+        // carry on in a fresh interpreter, the unread input comes along
+        let mut resumed = BFInterpreter::new(8);
+        resumed.set_output_sink(OutputSink::Buffer);
+        resumed.set_eof_behavior(dtypes::EofBehavior::Zero);
+        resumed.load_program(prog).unwrap();
+        resumed.restore(&snap).unwrap();
+        assert_eq!(resumed.data_pointer(), 0);
+        resumed.run().unwrap();
+        assert_eq!(resumed.output(), b"bcA");
+        // only into the same program and configuration
+        let mut other = BFInterpreter::new(8);
+        other.load_program("+").unwrap();
+        assert_eq!(other.restore(&snap), Err(snapshot::SnapshotError::ProgramMismatch));
+        let mut other = BFInterpreter::with_config(8, dtypes::TapeMode::Strict, dtypes::CellWidth::U16);
+        other.load_program(prog).unwrap();
+        assert_eq!(other.restore(&snap), Err(snapshot::SnapshotError::ConfigMismatch));
+    }
+
+    #[test]
+    fn interpreter_load_instructions () {
+        const HELLO: [dtypes::Op; 10] = crate::bf!("++++++++[>++++++++<-]>+.");
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_instructions(&HELLO);
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), b"A");
+        // errors are blamed on the command, there is no source to point at
+        let mut bfi = BFInterpreter::new(8);
+        bfi.load_instructions(&crate::bf!("<"));
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerUnderrun { position: 0, source: None }));
+        bfi.load_instructions(&crate::bf!(","));
+        bfi.set_input(&[]);
+        bfi.set_eof_behavior(dtypes::EofBehavior::Error);
+        assert_eq!(bfi.run(), Err(dtypes::BFError::InputExhausted { position: 0, source: None }));
+        // malformed instructions stop with an error rather than a panic, on either engine
+        let op = |instr, position| dtypes::Op { instr, position };
+        for config in crate::bench::engines() {
+            let mut bfi = BFInterpreter::new(8);
+            bfi.set_engine(config.engine);
+            bfi.load_instructions(&[op(dtypes::Instr::Add(1), 0), op(dtypes::Instr::JumpIfNonZero(9), 1)]);
+            assert_eq!(bfi.run(), Err(dtypes::BFError::UnmatchedBracket { bracket: ']', position: 1, source: None }));
+            bfi.load_instructions(&[op(dtypes::Instr::JumpIfZero(9), 0), op(dtypes::Instr::Add(1), 1)]);
+            assert_eq!(bfi.run(), Err(dtypes::BFError::UnmatchedBracket { bracket: '[', position: 0, source: None }));
+            bfi.load_instructions(&[op(dtypes::Instr::DefineProc(9), 0)]);
+            assert_eq!(bfi.run(), Err(dtypes::BFError::UnmatchedBracket { bracket: '(', position: 0, source: None }));
+        }
+        // moves aren't left unchecked on the word of a program loaded before them
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program("+").unwrap();
+        assert!(bfi.bounds_proven());
+        bfi.load_instructions(&[op(dtypes::Instr::Move(10), 0), op(dtypes::Instr::Add(1), 10)]);
+        assert!(!bfi.bounds_proven());
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerOverrun { position: 3, source: None }));
+        // and so does running without any memory
+        let mut bfi = BFInterpreter::new(0);
+        bfi.load_instructions(&crate::bf!("+"));
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerOverrun { position: 0, source: None }));
+    }
+
+    #[test]
+    fn interpreter_step_back () {
+        // undoing everything gets back to the start, then running again reads
+        // the same input and gives the same output
+        let prog = ",.>+++[->>+<<]>>.";
+        let mut bfi = BFInterpreter::with_config(2, dtypes::TapeMode::Growable, dtypes::CellWidth::U8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_input(b"xy");
+        bfi.set_history_depth(100);
+        bfi.load_program(prog).unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [b'x', 3]);
+        assert_eq!(bfi.memory(), [b'x' as u32, 0, 0, 3]);
+        let steps = bfi.steps_executed();
+        assert!(bfi.step_back());
+        assert!(!bfi.is_terminated());
+        assert_eq!(bfi.output(), b"x");
+        while bfi.step_back() {}
+        assert_eq!((bfi.instruction_pointer(), bfi.data_pointer(), bfi.steps_executed()), (0, 0, 0));
+        assert_eq!(bfi.memory(), [0, 0]);
+        assert_eq!(bfi.output(), b"");
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [b'x', 3]);
+        assert_eq!(bfi.steps_executed(), steps);
+        // an error is undone along with the step that caused it
+        let mut bfi = BFInterpreter::new(2);
+        bfi.set_history_depth(2);
+        bfi.load_program(">+>").unwrap();
+        assert!(bfi.run().is_err());
+        assert!(bfi.step_back());
+        assert!(bfi.error().is_none());
+        assert_eq!(bfi.instruction_pointer(), 2);
+        // only as far back as the history goes
+        assert!(bfi.step_back());
+        assert!(!bfi.step_back());
+        assert_eq!((bfi.instruction_pointer(), bfi.memory()), (1, &[0, 0][..]));
+        // pbrain procedures and calls are undone too
+        let mut bfi = BFInterpreter::new(4);
+        bfi.set_dialect(dtypes::Dialect::Pbrain);
+        bfi.set_history_depth(100);
+        bfi.load_program("+(+):").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.memory()[0], 2);
+        while bfi.step_back() {}
+        assert!(bfi.procedures.is_empty() && bfi.call_stack.is_empty());
+        // nothing to undo without any history
+        let mut bfi = BFInterpreter::new(2);
+        bfi.load_program("+").unwrap();
+        bfi.step().unwrap();
+        assert!(!bfi.step_back());
+    }
+
+    #[test]
+    fn interpreter_run_with_timeout () {
+        let mut bfi = BFInterpreter::new(8);
+        bfi.load_program("+[]").unwrap();
+        assert_eq!(bfi.run_with_timeout(Duration::from_millis(20)),
+                   Err(dtypes::BFError::Timeout { position: 2, source: at(3), limit: Duration::from_millis(20) }));
+        assert!(!bfi.is_finished());
+        assert!(bfi.error().is_none());
+        // a timed out program picks up where it left off
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_program("++++++++[>++++++++<-]>+.").unwrap();
+        assert!(matches!(bfi.run_with_timeout(Duration::ZERO), Err(dtypes::BFError::Timeout { .. })));
+        assert_eq!(bfi.instruction_pointer(), 0);
+        bfi.run_with_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(bfi.output(), b"A");
+    }
+
+    // reader giving whatever bytes have been queued, failing with WouldBlock
+    // while there are none
+    struct PendingReader(Rc<RefCell<Vec<u8>>>);
+
+    impl Read for PendingReader {
+        fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut queued = self.0.borrow_mut();
+            if queued.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(queued.len());
+            buf[..n].copy_from_slice(&queued[..n]);
+            queued.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn interpreter_run_for () {
+        let prog = "++++++++[>++++++++<-]>+.";
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.load_program(prog).unwrap();
+        // slices stop part way round the loop and carry on from there
+        assert_eq!(bfi.run_for(0), dtypes::StepOutcome::Paused);
+        assert_eq!(bfi.run_for(5), dtypes::StepOutcome::Paused);
+        assert_eq!((bfi.instruction_pointer(), bfi.steps), (5, 5));
+        let mut slices = 1;
+        while bfi.run_for(7) == dtypes::StepOutcome::Paused {
+            slices += 1;
+        }
+        assert_eq!(bfi.output(), b"A");
+        assert_eq!(bfi.run_for(7), dtypes::StepOutcome::Finished);
+        let mut whole = BFInterpreter::new(8);
+        whole.set_output_sink(OutputSink::Buffer);
+        whole.load_program(prog).unwrap();
+        whole.run().unwrap();
+        assert_eq!(slices, (whole.steps - 5).div_ceil(7));
+        // a `,` with nothing to read yet waits for it
+        let queued = Rc::new(RefCell::new(Vec::new()));
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_input_source(Box::new(PendingReader(Rc::clone(&queued))));
+        bfi.load_program("+,+.").unwrap();
+        assert_eq!(bfi.run_for(100), dtypes::StepOutcome::NeedsInput);
+        assert_eq!(bfi.run_for(100), dtypes::StepOutcome::NeedsInput);
+        assert_eq!((bfi.instruction_pointer(), bfi.steps), (1, 1));
+        queued.borrow_mut().push(b'a');
+        assert_eq!(bfi.run_for(100), dtypes::StepOutcome::Finished);
+        assert_eq!(bfi.output(), b"b");
+        // outside run_for() it is an error as before
+        bfi.load_program(",").unwrap();
+        assert!(matches!(bfi.run(), Err(dtypes::BFError::Io { .. })));
+        bfi.load_program(">>>>>>>>>").unwrap();
+        assert_eq!(bfi.run_for(100), dtypes::StepOutcome::Error(dtypes::BFError::PointerOverrun { position: 7, source: at(8) }));
+    }
+
+    #[test]
+    fn interpreter_provide_input () {
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+        bfi.load_program(",[.,]+.").unwrap();
+        bfi.provide_input(b"a");
+        // run() and step() hand back control rather than block
+        bfi.run().unwrap();
+        assert!(bfi.needs_input() && !bfi.is_finished());
+        assert_eq!((bfi.output(), bfi.instruction_pointer()), (&b"a"[..], 3));
+        bfi.step().unwrap();
+        assert!(bfi.needs_input());
+        assert_eq!(bfi.iter_steps().count(), 0);
+        bfi.provide_input(b"bc");
+        assert_eq!(bfi.run_for(4), dtypes::StepOutcome::Paused);
+        assert!(!bfi.needs_input());
+        assert_eq!(bfi.run_for(100), dtypes::StepOutcome::NeedsInput);
+        assert!(bfi.needs_input());
+        assert_eq!(bfi.output(), b"abc");
+        // then the end of input is reached as usual
+        bfi.end_input();
+        bfi.run().unwrap();
+        assert!(bfi.is_finished() && !bfi.needs_input());
+        assert_eq!(bfi.output(), b"abc\x01");
+    }
+
+    #[test]
+    fn interpreter_interrupt () {
+        // a program that never stops is interrupted from another thread
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_interrupt(Some(Arc::clone(&flag)));
+        bfi.load_program("+[>+<]").unwrap();
+        let setter = Arc::clone(&flag);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            setter.store(true, Ordering::Relaxed);
+        });
+        assert!(matches!(bfi.run(), Err(dtypes::BFError::Interrupted { .. })));
+        assert!(!flag.load(Ordering::Relaxed));
+        assert!(!bfi.is_finished());
+        // and carries on where it stopped when run again
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_interrupt(Some(Arc::clone(&flag)));
+        bfi.load_program("++++++++[>++++++++<-]>+.").unwrap();
+        flag.store(true, Ordering::Relaxed);
+        assert_eq!(bfi.run(), Err(dtypes::BFError::Interrupted { position: 0, source: at(1) }));
+        assert_eq!(bfi.instruction_pointer(), 0);
+        flag.store(true, Ordering::Relaxed);
+        assert!(matches!(bfi.run_with_timeout(Duration::from_secs(10)), Err(dtypes::BFError::Interrupted { .. })));
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), b"A");
+    }
+
+    #[test]
+    fn interpreter_limits () {
+        let limited = |prog: &str, tape_mode: dtypes::TapeMode, limits: dtypes::Limits| {
+            let mut bfi = BFInterpreter::with_config(4, tape_mode, dtypes::CellWidth::U8);
+            bfi.set_output_sink(OutputSink::Buffer);
+            bfi.set_limits(limits);
+            bfi.load_program(prog).unwrap();
+            let result = bfi.run();
+            (result, bfi)
+        };
+        let none = dtypes::Limits::default();
+        let (result, bfi) = limited("+[.]", dtypes::TapeMode::Strict, dtypes::Limits { max_output_bytes: Some(3), ..none });
+        assert_eq!(result, Err(dtypes::BFError::OutputLimitExceeded { position: 2, source: at(3), limit: 3 }));
+        assert_eq!(bfi.output(), &[1, 1, 1]);
+        let (result, bfi) = limited("+[>+]", dtypes::TapeMode::Growable, dtypes::Limits { max_mem_cells: Some(10), ..none });
+        assert_eq!(result, Err(dtypes::BFError::MemoryLimitExceeded { position: 2, source: at(3), limit: 10 }));
+        assert_eq!(bfi.memory_size(), 10);
+        assert_eq!(bfi.data_pointer(), 9);
+        let (result, bfi) = limited("+[<+]", dtypes::TapeMode::Bidirectional, dtypes::Limits { max_mem_cells: Some(6), ..none });
+        assert!(matches!(result, Err(dtypes::BFError::MemoryLimitExceeded { limit: 6, .. })));
+        // growing left made room for 4 more cells, the limit is hit once they're used up
+        assert_eq!(bfi.data_pointer() as isize - bfi.tape_origin() as isize, -4);
+        // a limit smaller than the starting memory doesn't stop it being used
+        let (result, _) = limited(">>>+", dtypes::TapeMode::Growable, dtypes::Limits { max_mem_cells: Some(2), ..none });
+        assert_eq!(result, Ok(()));
+        let (result, bfi) = limited("+[]", dtypes::TapeMode::Strict, dtypes::Limits { max_steps: Some(100), ..none });
+        assert!(matches!(result, Err(dtypes::BFError::StepLimitExceeded { limit: 100, .. })));
+        assert_eq!(bfi.limits().max_steps, Some(100));
+        let timeout = Duration::from_millis(20);
+        let (result, _) = limited("+[]", dtypes::TapeMode::Strict, dtypes::Limits { timeout: Some(timeout), ..none });
+        assert!(matches!(result, Err(dtypes::BFError::Timeout { limit, .. }) if limit == timeout));
+        // programs inside every limit run as normal
+        let all = dtypes::Limits {
+            max_steps: Some(1000),
+            max_output_bytes: Some(1),
+            max_mem_cells: Some(4),
+            timeout: Some(Duration::from_secs(10)),
+            max_loop_depth: Some(1),
+        };
+        let (result, bfi) = limited("++++++++[>++++++++<-]>+.", dtypes::TapeMode::Growable, all);
+        assert_eq!(result, Ok(()));
+        assert_eq!(bfi.output(), b"A");
+    }
+
+    #[test]
+    fn interpreter_loop_depth_limit () {
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_limits(dtypes::Limits { max_loop_depth: Some(2), ..Default::default() });
+        bfi.load_program("+[>[-]<-]").unwrap();
+        assert_eq!(bfi.load_program("+[[\n[-]]]"),
+                   Err(dtypes::BFError::LoopDepthExceeded { position: 3, source: Some(dtypes::SourcePos { line: 2, column: 1 }), limit: 2 }));
+        // pbrain procedures nest while the program runs
+        bfi.set_dialect(dtypes::Dialect::Pbrain);
+        bfi.load_program("(>:):").unwrap();
+        assert_eq!(bfi.run(), Err(dtypes::BFError::LoopDepthExceeded { position: 2, source: at(3), limit: 2 }));
+        assert_eq!(bfi.data_pointer(), 2);
+        // calls that don't nest are fine however many there are
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_dialect(dtypes::Dialect::Pbrain);
+        bfi.set_limits(dtypes::Limits { max_loop_depth: Some(1), ..Default::default() });
+        bfi.load_program("(>+<)::::").unwrap();
+        assert_eq!(bfi.run(), Ok(()));
+        assert_eq!(bfi.memory()[1], 4);
+    }
+
+    #[test]
+    fn interpreter_scan_loops () {
+        // scans end up in the same place (or fail the same way) as the loops would
+        let cases = [
+            ("+>+>+>+<<<[>]", dtypes::TapeMode::Strict),
+            ("+>+>+>+<<<[>]", dtypes::TapeMode::Growable),
+            ("+>+>>+>+<[>]", dtypes::TapeMode::Wrapping),
+            ("+<+<+>>[<]", dtypes::TapeMode::Bidirectional),
+            ("+>+>+>+[<]+[>]>", dtypes::TapeMode::Growable),
+            ("+>+>+>+[>]", dtypes::TapeMode::Wrapping),
+        ];
+        for (prog, mode) in cases {
+            let mut outcomes = Vec::new();
+            for optimize in [false, true] {
+                let mut bfi = BFInterpreter::with_config(4, mode, dtypes::CellWidth::U8);
+                bfi.set_optimize(optimize);
+                bfi.set_max_steps(Some(1000));
+                outcomes.push(crate::crossval::run_engine("engine", &mut bfi, prog, None));
+            }
+            assert_eq!(crate::crossval::compare(&outcomes[0], &outcomes[1]), None, "{}", prog);
+        }
+        // running off a strict tape blames the >, and a wrapping tape with no 0
+        // on it goes round forever
+        let mut bfi = BFInterpreter::new(4);
+        bfi.load_program("+>+>+>+<<<[>]").unwrap();
+        assert_eq!(bfi.run(), Err(dtypes::BFError::PointerOverrun { position: 11, source: at(12) }));
+        assert_eq!(bfi.data_pointer(), 3);
+        let mut bfi = BFInterpreter::with_config(4, dtypes::TapeMode::Wrapping, dtypes::CellWidth::U8);
+        bfi.set_max_steps(Some(1000));
+        bfi.load_program("+>+>+>+[>]").unwrap();
+        assert!(matches!(bfi.run(), Err(dtypes::BFError::StepLimitExceeded { .. })));
+    }
+
+    #[test]
+    fn interpreter_set_memory () {
+        // a program that prints its preloaded cells
+        let mut bfi = BFInterpreter::new(8);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_memory(b"Hi!").unwrap();
+        bfi.load_program("[.>]").unwrap();
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), b"Hi!");
+        // too much for a strict tape leaves memory as it was
+        let mut bfi = BFInterpreter::new(2);
+        assert_eq!(bfi.set_memory(&[1, 2, 3]), Err(dtypes::BFError::MemoryLimitExceeded { position: 0, source: None, limit: 2 }));
+        assert_eq!(bfi.memory(), [0, 0]);
+        // growable tapes grow, up to the memory limit
+        let mut bfi = BFInterpreter::with_config(2, dtypes::TapeMode::Growable, dtypes::CellWidth::U8);
+        bfi.set_memory(&[1, 2, 3]).unwrap();
+        assert_eq!(bfi.memory(), [1, 2, 3]);
+        bfi.set_limits(dtypes::Limits { max_mem_cells: Some(4), ..Default::default() });
+        assert!(matches!(bfi.set_memory(&[9; 5]), Err(dtypes::BFError::MemoryLimitExceeded { limit: 4, .. })));
+        assert_eq!(bfi.error(), None);
+        assert_eq!(bfi.memory(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn interpreter_summary () {
+        let mut bfi = BFInterpreter::new(20);
+        bfi.set_output_sink(OutputSink::Buffer);
+        bfi.set_optimize(false);
+        bfi.load_program(">>>>>>+>,.<<<<<<<<").unwrap();
+        assert_eq!(bfi.summary(), "command 0: >  data pointer: 0  cells: [0] 0 0 0 0  (ready)");
+        bfi.provide_input(b"");
+        bfi.run().unwrap();
+        assert_eq!(bfi.summary(), "command 8: ,  data pointer: 7  cells: 0 0 0 1 [0] 0 0 0 0  (waiting for input)");
+        bfi.provide_input(b"A");
+        bfi.run().unwrap_err();
+        assert_eq!(bfi.to_string(), "\
+command 17 of 18: < (line 1, column 18)
+data pointer: 0  cells 0 to 4: [0] 0 0 0 0
+steps: 18  output: 1 bytes  input: 1 bytes
+state: stopped by an error: data pointer underran available memory at command 17 (line 1, column 18)");
+        // none of the tape or output in the Debug view
+        bfi.load_program("+[.]").unwrap();
+        bfi.set_max_steps(Some(100));
+        bfi.run().unwrap_err();
+        assert!(format!("{:?}", bfi).len() < 2000);
+    }
 }
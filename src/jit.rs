@@ -0,0 +1,250 @@
+/*
+    Module with a JIT that compiles lowered programs into x86_64 machine code
+
+    The compiled code only handles the instructions that can run without help:
+    arithmetic, in-bounds pointer moves and loops. Anything else (I/O, moving
+    off the end of memory) exits the machine code with the index of the
+    instruction, the interpreter executes that one instruction the usual way and
+    then re-enters the machine code right after it, so errors, tape modes and
+    output sinks all behave exactly as they do in the interpreter.
+*/
+
+
+#[cfg(not(target_arch = "x86_64"))]
+compile_error!("the jit feature is only supported on x86_64");
+
+
+use std::{io, mem};
+
+use dynasmrt::{dynasm, x64, AssemblyOffset, DynasmApi, DynasmLabelApi, ExecutableBuffer};
+
+use crate::dtypes;
+
+
+// signature of the compiled code: (memory, memory length, data pointer, entry address),
+// returns the index of the instruction it stopped at with the data pointer updated
+type Entry = extern "sysv64" fn(*mut u32, usize, *mut usize, *const u8) -> usize;
+
+
+// a program compiled to machine code
+pub struct JitProgram {
+    code: ExecutableBuffer,
+    // offset of the code for each instruction, plus one for the end of the program
+    offsets: Vec<AssemblyOffset>,
+}
+
+
+impl JitProgram {
+    // compile lowered instructions for cells of a particular width
+    pub fn compile (ops: &[dtypes::Op], cell_width: dtypes::CellWidth) -> io::Result<JitProgram> {
+        let mut asm = x64::Assembler::new()?;
+        // registers: rdi = memory, rsi = memory length, r8 = where to store the
+        // data pointer on exit, r9 = data pointer, rax and rcx are scratch
+        dynasm!(asm
+            ; .arch x64
+            ; mov r8, rdx
+            ; mov r9, [rdx]
+            ; jmp rcx
+        );
+        let labels: Vec<_> = (0..=ops.len()).map(|_| asm.new_dynamic_label()).collect();
+        // (label, instruction index) for conditional exits, emitted out of line
+        let mut exits = Vec::new();
+        let mut offsets = Vec::with_capacity(ops.len() + 1);
+        // narrower cells get masked back down after arithmetic
+        let mask = match cell_width {
+            dtypes::CellWidth::U32 => Option::None,
+            width => Option::Some(width.max_value() as i32),
+        };
+        for (i, op) in ops.iter().enumerate() {
+            dynasm!(asm ; =>labels[i]);
+            offsets.push(asm.offset());
+            match op.instr {
+                dtypes::Instr::Add(n) => {
+                    dynasm!(asm ; add DWORD [rdi + r9 * 4], n);
+                    if let Some(mask) = mask {
+                        dynasm!(asm ; and DWORD [rdi + r9 * 4], mask);
+                    }
+                },
+                dtypes::Instr::Move(n) => match i32::try_from(n) {
+                    // leave moves off either end of memory to the interpreter (the
+                    // unsigned compare catches negative pointers too)
+                    Ok(n) => {
+                        let exit = asm.new_dynamic_label();
+                        exits.push((exit, i));
+                        dynasm!(asm
+                            ; lea rax, [r9 + n]
+                            ; cmp rax, rsi
+                            ; jae =>exit
+                            ; mov r9, rax
+                        );
+                    },
+                    Err(_) => dynasm!(asm ; mov rax, QWORD i as i64 ; jmp ->done),
+                },
+                dtypes::Instr::Clear => dynasm!(asm ; mov DWORD [rdi + r9 * 4], 0),
+                dtypes::Instr::AddTo(offset, factor) => match i32::try_from(offset) {
+                    Ok(offset) => {
+                        let exit = asm.new_dynamic_label();
+                        exits.push((exit, i));
+                        dynasm!(asm
+                            ; mov eax, [rdi + r9 * 4]
+                            ; test eax, eax
+                            ; jz =>labels[i + 1]
+                            ; lea rcx, [r9 + offset]
+                            ; cmp rcx, rsi
+                            ; jae =>exit
+                            ; imul eax, eax, factor
+                            ; add [rdi + rcx * 4], eax
+                        );
+                        if let Some(mask) = mask {
+                            dynasm!(asm ; and DWORD [rdi + rcx * 4], mask);
+                        }
+                    },
+                    Err(_) => dynasm!(asm ; mov rax, QWORD i as i64 ; jmp ->done),
+                },
+                // cells off either end of memory are left to the interpreter
+                dtypes::Instr::AddAt(offset, n) => match i32::try_from(offset) {
+                    Ok(offset) => {
+                        let exit = asm.new_dynamic_label();
+                        exits.push((exit, i));
+                        dynasm!(asm
+                            ; lea rcx, [r9 + offset]
+                            ; cmp rcx, rsi
+                            ; jae =>exit
+                            ; add DWORD [rdi + rcx * 4], n
+                        );
+                        if let Some(mask) = mask {
+                            dynasm!(asm ; and DWORD [rdi + rcx * 4], mask);
+                        }
+                    },
+                    Err(_) => dynasm!(asm ; mov rax, QWORD i as i64 ; jmp ->done),
+                },
+                dtypes::Instr::ClearAt(offset) => match i32::try_from(offset) {
+                    Ok(offset) => {
+                        let exit = asm.new_dynamic_label();
+                        exits.push((exit, i));
+                        dynasm!(asm
+                            ; lea rcx, [r9 + offset]
+                            ; cmp rcx, rsi
+                            ; jae =>exit
+                            ; mov DWORD [rdi + rcx * 4], 0
+                        );
+                    },
+                    Err(_) => dynasm!(asm ; mov rax, QWORD i as i64 ; jmp ->done),
+                },
+                // a jump lands right after the matching bracket, like the interpreter,
+                // ones to past the end of the program are left to it to report
+                dtypes::Instr::JumpIfZero(target) if target < ops.len() => dynasm!(asm
+                    ; cmp DWORD [rdi + r9 * 4], 0
+                    ; je =>labels[target + 1]
+                ),
+                dtypes::Instr::JumpIfNonZero(target) if target < ops.len() => dynasm!(asm
+                    ; cmp DWORD [rdi + r9 * 4], 0
+                    ; jne =>labels[target + 1]
+                ),
+                // I/O and the Extended Type I instructions (which need the storage
+                // cell) are left to the interpreter
+                _ => dynasm!(asm ; mov rax, QWORD i as i64 ; jmp ->done),
+            };
+        }
+        dynasm!(asm ; =>labels[ops.len()]);
+        offsets.push(asm.offset());
+        dynasm!(asm ; mov rax, QWORD ops.len() as i64);
+        for (exit, i) in exits {
+            dynasm!(asm
+                ; jmp ->done
+                ; =>exit
+                ; mov rax, QWORD i as i64
+            );
+        }
+        dynasm!(asm
+            ; ->done:
+            ; mov [r8], r9
+            ; ret
+        );
+        asm.commit().map_err(|e| io::Error::other(e.to_string()))?;
+        let code = asm.finalize()
+            .map_err(|_| io::Error::other("unable to finalize compiled code"))?;
+        Ok(JitProgram { code, offsets })
+    }
+
+    // run from the instruction at start until the program finishes (returns the
+    // number of instructions) or reaches an instruction the interpreter has to
+    // handle (returns its index), memory is the whole tape and the data pointer
+    // must point into it
+    pub fn run (&self, memory: &mut [u32], data_ptr: &mut usize, start: usize) -> usize {
+        let Some(&offset) = self.offsets.get(start) else {
+            return start;
+        };
+        if *data_ptr >= memory.len() {
+            return start;
+        }
+        // SAFETY: the code starts with the prologue at offset 0 and was compiled
+        // for the sysv64 signature, every memory access it makes is either at the
+        // data pointer or checked against the memory length first
+        unsafe {
+            let entry: Entry = mem::transmute(self.code.ptr(AssemblyOffset(0)));
+            entry(memory.as_mut_ptr(), memory.len(), data_ptr, self.code.ptr(offset))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{interpreter, optimizer, parsing};
+
+    // run a program under both engines with the output buffered and compare
+    // everything that can be observed afterwards
+    fn assert_engines_match (prog: &str, tape_mode: dtypes::TapeMode, cell_width: dtypes::CellWidth, input: &str) {
+        let mut results = Vec::new();
+        for engine in [dtypes::Engine::Interpreter, dtypes::Engine::Jit] {
+            let mut bfi = interpreter::BFInterpreter::with_config(8, tape_mode, cell_width);
+            bfi.set_engine(engine);
+            bfi.set_output_sink(interpreter::OutputSink::Buffer);
+            bfi.set_input_source(Box::new(io::Cursor::new(input.as_bytes().to_vec())));
+            bfi.load_program(prog).unwrap();
+            let result = bfi.run();
+            assert!(bfi.is_terminated());
+            results.push((result, bfi.output().to_vec(), bfi.memory().to_vec(), bfi.data_pointer()));
+        }
+        assert_eq!(results[0], results[1], "engines differ on {}", prog);
+    }
+
+    #[test]
+    fn jit_compile () {
+        let ops = optimizer::optimize(&parsing::parse_program("+[->+<]>.")).unwrap();
+        let jit = JitProgram::compile(&ops, dtypes::CellWidth::U8).unwrap();
+        assert_eq!(jit.offsets.len(), ops.len() + 1);
+        let mut memory = vec![0; 4];
+        let mut data_ptr = 0;
+        // stops on the output for the interpreter to handle
+        assert_eq!(jit.run(&mut memory, &mut data_ptr, 0), ops.len() - 1);
+        assert_eq!(memory, vec![0, 1, 0, 0]);
+        assert_eq!(data_ptr, 1);
+        assert_eq!(jit.run(&mut memory, &mut data_ptr, ops.len()), ops.len());
+    }
+
+    #[test]
+    fn jit_matches_interpreter () {
+        let strict = dtypes::TapeMode::Strict;
+        let u8 = dtypes::CellWidth::U8;
+        let hello = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.";
+        assert_engines_match(hello, strict, u8, "");
+        assert_engines_match(",[.[-],]", strict, u8, "echo");
+        assert_engines_match(",+[-->++<]>.", strict, u8, "a");
+        assert_engines_match("-[>+>++<<-]>.", strict, dtypes::CellWidth::U16, "");
+        assert_engines_match("-->+++[<++++>-]<[>>>>-<<<<-]", strict, dtypes::CellWidth::U32, "");
+        // errors at the ends of a strict tape
+        assert_engines_match("+.>>>>>>>>>>>>", strict, u8, "");
+        assert_engines_match("+[<+>-]", strict, u8, "");
+        assert_engines_match(">>>>>>>+[->>+<<]", strict, u8, "");
+        // offset-addressed instructions, including off the end
+        assert_engines_match("+++[>>-<<->+[-]<]>>.>>>>>>>>+<<<<<<<<", strict, u8, "");
+        // other tape modes
+        assert_engines_match("+<+<<<<<<<<<++[->>>+<<<]", dtypes::TapeMode::Wrapping, u8, "");
+        assert_engines_match("+[>>>>>>>>>+<<<<<<<<<-]<<<[-]>>>", dtypes::TapeMode::Wrapping, u8, "");
+        assert_engines_match(">>>>>>>>>>+++[->>>>>+<<<<<]", dtypes::TapeMode::Growable, u8, "");
+    }
+}
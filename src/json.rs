@@ -0,0 +1,350 @@
+/*
+    Module for writing the result of running a program as a JSON document,
+    e.g. for web services and CI pipelines. Each document is one line:
+
+        {"program":"hello.bf","exit_code":0,
+         "output":{"text":"A","base64":"QQ=="},
+         "error":null,
+         "stats":{"instructions_executed":9,"cells_touched":2,"max_data_ptr":1,
+                  "bytes_output":1,"bytes_input":0,"elapsed_seconds":0.000012}}
+
+    errors are {"kind":"pointer_underrun","message":"...","position":7,
+    "line":3,"column":6} with a null line and column if they aren't known
+
+    JSON can also be read back in, e.g. requests to the playground server
+*/
+
+
+use std::fmt::Write;
+
+use crate::dtypes;
+
+
+// a string as a quoted JSON string
+pub fn string (s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        };
+    }
+    out.push('"');
+    out
+}
+
+
+// standard base64 (with padding) of some bytes
+pub fn base64 (bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0_u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+
+// a parsed JSON value, objects keep their keys in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+
+impl Value {
+    // value of a key in an object (the last one if it's repeated), None for
+    // missing keys and anything that isn't an object
+    pub fn get (&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => Option::None,
+        }
+    }
+
+    pub fn as_str (&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Option::Some(s),
+            _ => Option::None,
+        }
+    }
+}
+
+
+// reads one value at a time from JSON text
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+
+impl Parser<'_> {
+    fn error (&self, expected: &str) -> String {
+        format!("invalid JSON at byte {}: expected {}", self.pos, expected)
+    }
+
+    fn skip_whitespace (&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek (&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    // skip past a literal if it's next
+    fn eat (&mut self, literal: &str) -> bool {
+        let found = self.text[self.pos..].starts_with(literal);
+        if found {
+            self.pos += literal.len();
+        }
+        found
+    }
+
+    fn value (&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(b'{') => self.object()?,
+            Some(b'[') => self.array()?,
+            Some(b'"') => Value::String(self.string()?),
+            Some(b'-' | b'0'..=b'9') => self.number()?,
+            _ if self.eat("null") => Value::Null,
+            _ if self.eat("true") => Value::Bool(true),
+            _ if self.eat("false") => Value::Bool(false),
+            _ => return Err(self.error("a value")),
+        };
+        self.skip_whitespace();
+        Ok(value)
+    }
+
+    fn object (&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.eat("}") {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Option::Some(b'"') {
+                return Err(self.error("a key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(":") {
+                return Err(self.error("':'"));
+            }
+            fields.push((key, self.value()?));
+            if self.eat("}") {
+                return Ok(Value::Object(fields));
+            }
+            if !self.eat(",") {
+                return Err(self.error("',' or '}'"));
+            }
+        }
+    }
+
+    fn array (&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.eat("]") {
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if self.eat("]") {
+                return Ok(Value::Array(items));
+            }
+            if !self.eat(",") {
+                return Err(self.error("',' or ']'"));
+            }
+        }
+    }
+
+    // four hex digits of a \u escape
+    fn hex4 (&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()));
+        let n = digits.and_then(|d| u32::from_str_radix(d, 16).ok()).ok_or_else(|| self.error("4 hex digits"))?;
+        self.pos += 4;
+        Ok(n)
+    }
+
+    fn string (&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let plain = rest.find(|c: char| c == '"' || c == '\\' || c < ' ').unwrap_or(rest.len());
+            out.push_str(&rest[..plain]);
+            self.pos += plain;
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                },
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = self.peek().ok_or_else(|| self.error("an escape"))?;
+                    self.pos += 1;
+                    match escaped {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{8}'),
+                        b'f' => out.push('\u{c}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+                        b'u' => {
+                            let mut c = self.hex4()?;
+                            // characters past the BMP come as a surrogate pair
+                            if (0xd800..0xdc00).contains(&c) && self.eat("\\u") {
+                                let low = self.hex4()?;
+                                if (0xdc00..0xe000).contains(&low) {
+                                    c = 0x10000 + ((c - 0xd800) << 10) + (low - 0xdc00);
+                                }
+                            }
+                            out.push(char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        },
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("an escape"));
+                        },
+                    };
+                },
+                _ => return Err(self.error("'\"'")),
+            };
+        }
+    }
+
+    fn number (&mut self) -> Result<Value, String> {
+        let rest = &self.text[self.pos..];
+        let len = rest.find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')).unwrap_or(rest.len());
+        let n = rest[..len].parse::<f64>().map_err(|_| self.error("a number"))?;
+        self.pos += len;
+        Ok(Value::Number(n))
+    }
+}
+
+
+// parse a JSON document
+pub fn parse (text: &str) -> Result<Value, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value()?;
+    match parser.pos == text.len() {
+        true => Ok(value),
+        false => Err(parser.error("the end")),
+    }
+}
+
+
+// an error as a JSON object
+fn error (e: &dtypes::BFError) -> String {
+    let (line, column) = match e.source() {
+        Some(pos) => (pos.line.to_string(), pos.column.to_string()),
+        None => (String::from("null"), String::from("null")),
+    };
+    format!("{{\"kind\":{},\"message\":{},\"position\":{},\"line\":{},\"column\":{}}}",
+            string(e.kind()), string(&e.to_string()), e.position(), line, column)
+}
+
+
+// document describing how a program ran, output is everything it wrote (as
+// text, invalid UTF-8 replaced, and as base64 for the exact bytes)
+pub fn run_document (program: &str, output: &[u8], report: &dtypes::RunReport) -> String {
+    let (exit_code, err) = match &report.result {
+        Ok(()) => (0, String::from("null")),
+        Err(e) => (e.exit_code(), error(e)),
+    };
+    format!("{{\"program\":{},\"exit_code\":{},\"output\":{{\"text\":{},\"base64\":{}}},\"error\":{},\
+             \"stats\":{{\"instructions_executed\":{},\"cells_touched\":{},\"max_data_ptr\":{},\
+             \"bytes_output\":{},\"bytes_input\":{},\"elapsed_seconds\":{}}}}}",
+            string(program), exit_code, string(&String::from_utf8_lossy(output)), string(&base64(output)), err,
+            report.instructions_executed, report.cells_touched, report.max_data_ptr,
+            report.bytes_output, report.bytes_input, report.elapsed.as_secs_f64())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn json_strings () {
+        assert_eq!(string("plain"), "\"plain\"");
+        assert_eq!(string("a \"q\" \\ b\n\u{1}é"), "\"a \\\"q\\\" \\\\ b\\n\\u0001é\"");
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0x00, 0x80]), "/wCA");
+    }
+
+    #[test]
+    fn json_run_document () {
+        let report = dtypes::RunReport {
+            result: Ok(()),
+            instructions_executed: 9,
+            cells_touched: 2,
+            max_data_ptr: 1,
+            bytes_output: 1,
+            bytes_input: 0,
+            elapsed: Duration::from_millis(500),
+        };
+        assert_eq!(run_document("hello.bf", b"A", &report),
+                   "{\"program\":\"hello.bf\",\"exit_code\":0,\"output\":{\"text\":\"A\",\"base64\":\"QQ==\"},\"error\":null,\
+                    \"stats\":{\"instructions_executed\":9,\"cells_touched\":2,\"max_data_ptr\":1,\
+                    \"bytes_output\":1,\"bytes_input\":0,\"elapsed_seconds\":0.5}}");
+        let source = Some(dtypes::SourcePos { line: 3, column: 6 });
+        let report = dtypes::RunReport::not_run(dtypes::BFError::PointerUnderrun { position: 7, source });
+        let doc = run_document("a.bf", b"", &report);
+        assert!(doc.contains("\"exit_code\":1"));
+        assert!(doc.contains("\"error\":{\"kind\":\"pointer_underrun\",\"message\":\"data pointer underran available memory at command 7 (line 3, column 6)\",\
+                              \"position\":7,\"line\":3,\"column\":6}"));
+    }
+
+    #[test]
+    fn json_parse () {
+        let doc = parse(" {\"program\": \"+[-]\\n\", \"input\":\"a\\u00e9\\ud83d\\ude00\\\"\", \"n\": -1.5e1,
+                          \"list\": [true, false, null, {}], \"empty\": []} ").unwrap();
+        assert_eq!(doc.get("program").and_then(Value::as_str), Some("+[-]\n"));
+        assert_eq!(doc.get("input").and_then(Value::as_str), Some("aé😀\""));
+        assert_eq!(doc.get("n"), Some(&Value::Number(-15.0)));
+        assert_eq!(doc.get("list"), Some(&Value::Array(vec![Value::Bool(true), Value::Bool(false), Value::Null, Value::Object(Vec::new())])));
+        assert_eq!(doc.get("empty"), Some(&Value::Array(Vec::new())));
+        assert_eq!(doc.get("missing"), None);
+        // anything written can be read back
+        assert_eq!(parse(&string("a \"q\" \\ b\n\u{1}é")), Ok(Value::String(String::from("a \"q\" \\ b\n\u{1}é"))));
+        assert_eq!(parse("{\"a\": 1,}"), Err(String::from("invalid JSON at byte 8: expected a key")));
+        assert_eq!(parse("[1 2]"), Err(String::from("invalid JSON at byte 3: expected ',' or ']'")));
+        assert_eq!(parse("\"abc"), Err(String::from("invalid JSON at byte 4: expected '\"'")));
+        assert_eq!(parse("\"\\x\""), Err(String::from("invalid JSON at byte 2: expected an escape")));
+        assert_eq!(parse("{} x"), Err(String::from("invalid JSON at byte 3: expected the end")));
+        assert!(parse("nul").is_err());
+        assert!(parse("").is_err());
+        assert!(parse("-").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+/*
+    toy brainfart interpreter, library crate
+
+    embed the interpreter by loading a program into a BFInterpreter
+    and running it:
+
+        let mut bfi = rust_bfi::BFInterpreter::new(30000);
+        bfi.set_output_sink(rust_bfi::OutputSink::Buffer);
+        bfi.load_program("++++++++[>++++++++<-]>+.")?;
+        bfi.run()?;
+        assert_eq!(bfi.output(), b"A");
+*/
+
+
+pub mod dtypes;
+pub mod parsing;
+pub mod bfio;
+pub mod optimizer;
+pub mod interpreter;
+pub mod selfmod;
+pub mod debugger;
+pub mod diagnostics;
+pub mod compiler;
+pub mod formatter;
+pub mod minifier;
+pub mod disasm;
+pub mod embed;
+pub mod trace;
+pub mod observer;
+pub mod profile;
+pub mod snapshot;
+pub mod tape;
+pub mod config;
+pub mod batch;
+pub mod crossval;
+pub mod equiv;
+pub mod golden;
+pub mod json;
+pub mod generator;
+pub mod encoder;
+pub mod obfuscator;
+pub mod bench;
+pub mod coverage;
+pub mod heatmap;
+pub mod pool;
+pub mod server;
+pub mod replay;
+pub mod analysis;
+pub mod untrusted;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+
+pub use batch::{Batch, BatchResult};
+pub use bfio::{BfIo, BufferIo, ChannelIo, RandomInput, StdIo, StreamIo};
+pub use config::BFConfig;
+pub use dtypes::{BFError, CellWidth, Command, Dialect, EofBehavior, Engine, ExecutionState, Instr, Limits, MemoryDump, Op, RunReport, SourcePos, StepOutcome, TapeMode};
+pub use interpreter::{BFInterpreter, FlushPolicy, OutputSink, Steps};
+pub use observer::Observer;
+pub use pool::{EvalPool, EvalResult};
+pub use profile::Profile;
+pub use trace::{TraceEvent, Tracer};
+pub use untrusted::{parse_untrusted, run_untrusted_with_limits};
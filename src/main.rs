@@ -3,12 +3,1131 @@
 */
 
 
-mod dtypes;
-mod parsing;
-mod interpreter;
+use std::{env, fs, io, process};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rust_bfi::{analysis, batch, bench, bfio, compiler, coverage, crossval, debugger, diagnostics, disasm, dtypes, encoder, equiv, formatter, generator, golden, heatmap, interpreter, json, minifier, obfuscator, optimizer, parsing, pool, replay, selfmod, server, tape, trace};
+
+mod cli;
+mod completions;
+#[cfg(not(target_arch = "wasm32"))]
+mod terminal;
+#[cfg(feature = "tui")]
+mod tui;
+
+
+// read the program source, bail out with a sensible message if the file
+// is missing or cannot be read
+fn read_program (path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(prog) => prog,
+        Err(e) => {
+            eprintln!("error: unable to read program file '{}': {}", path, e);
+            process::exit(1);
+        },
+    }
+}
+
+
+// input given up front as text or a file, None if neither was given
+fn read_input (text: &Option<String>, path: &Option<String>) -> Option<Vec<u8>> {
+    match (text, path) {
+        (Some(text), _) => Option::Some(text.clone().into_bytes()),
+        (None, Some(path)) => match fs::read(path) {
+            Ok(input) => Option::Some(input),
+            Err(e) => {
+                eprintln!("error: unable to read input file '{}': {}", path, e);
+                process::exit(1);
+            },
+        },
+        (None, None) => Option::None,
+    }
+}
+
+
+// bytes to preload memory with, from the command line or a file, None if
+// neither was given
+fn read_init_mem (bytes: &Option<Vec<u8>>, path: &Option<String>) -> Option<Vec<u8>> {
+    match (bytes, path) {
+        (Some(bytes), _) => Option::Some(bytes.clone()),
+        (None, Some(path)) => match fs::read(path) {
+            Ok(bytes) => Option::Some(bytes),
+            Err(e) => {
+                eprintln!("error: unable to read memory file '{}': {}", path, e);
+                process::exit(1);
+            },
+        },
+        (None, None) => Option::None,
+    }
+}
+
+
+// spellings of the commands from the command line or a file, None if neither
+// was given
+fn read_mapping (map: &Option<parsing::Mapping>, path: &Option<String>) -> Option<parsing::Mapping> {
+    match (map, path) {
+        (Some(map), _) => Option::Some(map.clone()),
+        (None, Some(path)) => match fs::read_to_string(path).map(|spec| parsing::Mapping::parse(&spec)) {
+            Ok(Ok(map)) => Option::Some(map),
+            Ok(Err(e)) => {
+                eprintln!("error: invalid mapping in '{}': {}", path, e);
+                process::exit(1);
+            },
+            Err(e) => {
+                eprintln!("error: unable to read mapping file '{}': {}", path, e);
+                process::exit(1);
+            },
+        },
+        (None, None) => Option::None,
+    }
+}
+
+
+// explain an error in a program split into commands the same way it was loaded
+fn diagnose (e: &dtypes::BFError, prog: &str, dialect: dtypes::Dialect, mapping: &Option<parsing::Mapping>) -> diagnostics::Diagnostic {
+    match mapping {
+        Some(mapping) => diagnostics::Diagnostic::from_tokens(e, prog, &mapping.tokenize(prog)),
+        None => diagnostics::Diagnostic::new(e, prog, dialect),
+    }
+}
+
+
+// preload memory, bail out if it doesn't fit
+fn init_memory (bfi: &mut interpreter::BFInterpreter, bytes: &[u8]) {
+    if let Err(e) = bfi.set_memory(bytes) {
+        eprintln!("error: unable to preload memory: {}", e);
+        process::exit(1);
+    }
+}
+
+
+// print an error along with the line of source it came from, if that's known
+fn report_error (prog: &str, e: &dtypes::BFError) {
+    eprintln!("error: {}", e);
+    if let Some(pos) = e.source() {
+        eprintln!("{}", parsing::source_snippet(prog, pos));
+    }
+}
+
+
+// whether to show errors in color, by default only on a terminal, see
+// https://no-color.org
+fn color (choice: cli::ColorChoice) -> bool {
+    match choice {
+        cli::ColorChoice::Always => true,
+        cli::ColorChoice::Never => false,
+        cli::ColorChoice::Auto => io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+    }
+}
+
+
+// bytes of output a filter collects before writing them out
+const FILTER_BUFFER_SIZE: usize = 1 << 16;
+
+
+// interpreter with the tape asked for, a sparse one only stores the memory the
+// program uses
+fn new_interpreter (mem_size: usize, tape_mode: dtypes::TapeMode, sparse: bool, cell_width: dtypes::CellWidth) -> interpreter::BFInterpreter {
+    match sparse {
+        true => interpreter::BFInterpreter::with_tape(Box::new(tape::SparseTape::new(tape_mode, mem_size)), cell_width),
+        false => interpreter::BFInterpreter::with_config(mem_size, tape_mode, cell_width),
+    }
+}
+
+
+// step a loaded program in the full screen view, then print its output once the
+// terminal is back to normal
+#[cfg(feature = "tui")]
+fn watch (bfi: &mut interpreter::BFInterpreter, prog: &str, opts: &cli::Options) -> Result<(), dtypes::BFError> {
+    let split = opts.embedded_input && opts.dialect.allows_embedded_input();
+    let code = if split { parsing::split_program_input(prog).0 } else { prog };
+    let positions: Vec<dtypes::SourcePos> = parsing::tokenize_with_debug(code, opts.dialect, opts.hash_debug).iter().map(|token| token.pos).collect();
+    let result = match tui::run(bfi, code, &positions) {
+        Ok(Some(result)) => result,
+        // quit before the program finished
+        Ok(None) => Ok(()),
+        Err(e) => {
+            eprintln!("error: terminal I/O failed: {}", e);
+            process::exit(1);
+        },
+    };
+    let _ = io::Write::write_all(&mut io::stdout(), bfi.output());
+    result
+}
+
+
+// --tui is rejected when parsing arguments without the tui feature
+#[cfg(not(feature = "tui"))]
+fn watch (_bfi: &mut interpreter::BFInterpreter, _prog: &str, _opts: &cli::Options) -> Result<(), dtypes::BFError> {
+    unreachable!("--tui requires the tui feature")
+}
+
+
+// run several programs, each with the same input, then print what each of them
+// output (and their stats) in turn
+fn run_batch (opts: cli::Options) {
+    let input = read_input(&opts.input, &opts.input_file);
+    let init_mem = read_init_mem(&opts.init_mem, &opts.init_mem_file).unwrap_or_default();
+    let (mem_size, tape_mode, sparse, cell_width) = (opts.mem_size, opts.tape_mode, opts.sparse, opts.cell_width);
+    // every program gets the same memory, so check it fits once up front
+    init_memory(&mut new_interpreter(mem_size, tape_mode, sparse, cell_width), &init_mem);
+    let (eof_behavior, limits, engine) = (opts.eof_behavior, opts.limits, opts.engine);
+    let (optimize, precompute, embedded_input, dialect, hash_debug) = (opts.optimize, opts.precompute, opts.embedded_input, opts.dialect, opts.hash_debug);
+    let mapping = read_mapping(&opts.map, &opts.map_file);
+    let mut batch = batch::Batch::new(move || {
+        let mut bfi = new_interpreter(mem_size, tape_mode, sparse, cell_width);
+        bfi.set_eof_behavior(eof_behavior);
+        bfi.set_limits(limits);
+        bfi.set_engine(engine);
+        bfi.set_optimize(optimize);
+        bfi.set_precompute(precompute);
+        bfi.set_split_input(embedded_input);
+        bfi.set_dialect(dialect);
+        bfi.set_hash_debug(hash_debug);
+        bfi.set_mapping(mapping.clone());
+        // already known to fit
+        let _ = bfi.set_memory(&init_mem);
+        bfi
+    });
+    let paths: Vec<&String> = std::iter::once(&opts.path).chain(&opts.more_paths).collect();
+    let progs: Vec<String> = paths.iter().map(|path| read_program(path)).collect();
+    for (path, prog) in paths.iter().zip(&progs) {
+        batch.add(path, prog, input.as_deref());
+    }
+    batch.set_threads(opts.jobs);
+    let results = batch.run();
+    let failed = results.iter().any(|result| result.report.result.is_err());
+    if opts.output_format == cli::OutputFormat::Json {
+        let docs: Vec<String> = results.iter().map(|result| json::run_document(&result.name, &result.output, &result.report)).collect();
+        println!("[{}]", docs.join(",\n "));
+        process::exit(if failed { 1 } else { 0 });
+    }
+    for (result, prog) in results.iter().zip(&progs) {
+        println!("==> {} <==", result.name);
+        let _ = io::Write::write_all(&mut io::stdout(), &result.output);
+        if !result.output.is_empty() && !result.output.ends_with(b"\n") {
+            println!();
+        }
+        if opts.stats {
+            eprintln!("{}", result.report);
+        }
+        if let Err(e) = &result.report.result {
+            report_error(prog, e);
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+
+// run a program, either straight through, under the debugger or in the full screen view
+fn run (opts: cli::Options) {
+    if !opts.more_paths.is_empty() {
+        return run_batch(opts);
+    }
+    let prog = read_program(&opts.path);
+    let mut bfi = new_interpreter(opts.mem_size, opts.tape_mode, opts.sparse, opts.cell_width);
+    bfi.set_eof_behavior(opts.eof_behavior);
+    bfi.set_limits(opts.limits);
+    bfi.set_engine(opts.engine);
+    // coverage is worked out from the profile's counters
+    let covering = opts.coverage || opts.lcov.is_some();
+    match opts.profile_trace {
+        Some(_) => bfi.set_span_profiling(true),
+        None => bfi.set_profiling(opts.profile || covering),
+    };
+    if opts.trace {
+        let out: Box<dyn io::Write> = match &opts.trace_file {
+            Some(path) => match fs::File::create(path) {
+                Ok(file) => Box::new(io::BufWriter::new(file)),
+                Err(e) => {
+                    eprintln!("error: unable to create trace file '{}': {}", path, e);
+                    process::exit(1);
+                },
+            },
+            None => Box::new(io::stderr()),
+        };
+        bfi.set_tracer(Some(Box::new(trace::WriteTracer::new(out))));
+    }
+    let recorder = opts.record.as_ref().map(|_| replay::Recorder::new());
+    if let Some(recorder) = &recorder {
+        bfi.set_tracer(Some(Box::new(recorder.clone())));
+    }
+    // the debugger steps (and sets breakpoints on) individual commands, and
+    // coverage is of individual commands rather than the loops they fold into
+    bfi.set_optimize(opts.optimize && !opts.debug && !covering);
+    bfi.set_precompute(opts.precompute);
+    // everything a filter reads comes from stdin
+    bfi.set_split_input(opts.embedded_input && !opts.filter);
+    bfi.set_dialect(opts.dialect);
+    bfi.set_hash_debug(opts.hash_debug);
+    let mapping = read_mapping(&opts.map, &opts.map_file);
+    bfi.set_mapping(mapping.clone());
+    // Ctrl-C stops the program cleanly before its next instruction, pressing it
+    // again gives up straight away (compiled code can't stop in the middle of a
+    // loop), the debugger and full screen view deal with keys themselves
+    #[cfg(not(target_arch = "wasm32"))]
+    if !opts.debug && !opts.tui {
+        let flag = Arc::new(AtomicBool::new(false));
+        let pressed = Arc::clone(&flag);
+        let handled = ctrlc::set_handler(move || {
+            if pressed.swap(true, Ordering::Relaxed) {
+                terminal::restore();
+                process::exit(130);
+            }
+        });
+        if handled.is_ok() {
+            bfi.set_interrupt(Option::Some(flag));
+        }
+    }
+    if let Some(bytes) = read_init_mem(&opts.init_mem, &opts.init_mem_file) {
+        init_memory(&mut bfi, &bytes);
+    }
+    // the full screen view owns the terminal, so the program can't have stdin
+    // and its output is shown rather than printed
+    if opts.tui {
+        bfi.set_input(&[]);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+    }
+    // the output goes in the JSON document rather than straight to stdout
+    let json = opts.output_format == cli::OutputFormat::Json;
+    if json {
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+    }
+    // output is written in big blocks rather than a line (or a byte read) at a
+    // time, stdin and stdout are plain bytes either way
+    if opts.filter {
+        bfi.set_output_sink(interpreter::OutputSink::BufferedWriter(Box::new(io::stdout())));
+        bfi.set_output_buffer_size(Option::Some(opts.output_buffer.unwrap_or(FILTER_BUFFER_SIZE)));
+        bfi.set_flush_policy(interpreter::FlushPolicy::Blocks);
+    } else if let Some(size) = opts.output_buffer {
+        // fewer, bigger writes, though a prompt is still shown before input is read
+        bfi.set_output_sink(interpreter::OutputSink::BufferedWriter(Box::new(io::stdout())));
+        bfi.set_output_buffer_size(Option::Some(size));
+    }
+    // for programs that draw without newlines, e.g. a progress bar or animation
+    if opts.flush_every_byte {
+        bfi.set_flush_policy(interpreter::FlushPolicy::EveryByte);
+    }
+    if let Some(ms) = opts.delay_ms {
+        let slow = bfio::SlowWriter::new(io::stdout(), std::time::Duration::from_millis(ms));
+        bfi.set_output_sink(interpreter::OutputSink::Writer(Box::new(slow)));
+    }
+    if let Err(e) = bfi.load_program(&prog) {
+        match json {
+            true => println!("{}", json::run_document(&opts.path, &[], &dtypes::RunReport::not_run(e))),
+            false => eprint!("{}", diagnose(&e, &prog, opts.dialect, &mapping).render(color(opts.color))),
+        };
+        process::exit(1);
+    }
+    // input given on the command line wins over any embedded in the program
+    if let Some(text) = &opts.input {
+        bfi.set_input(text.as_bytes());
+    }
+    if let Some(path) = &opts.input_file {
+        match fs::read(path) {
+            Ok(input) => bfi.set_input(&input),
+            Err(e) => {
+                eprintln!("error: unable to read input file '{}': {}", path, e);
+                process::exit(1);
+            },
+        };
+    }
+    if let Some(seed) = opts.input_random {
+        bfi.set_input_source(Box::new(bfio::RandomInput::new(seed, Option::None)));
+    }
+    if let Some(path) = &opts.resume {
+        let restored = fs::read(path).map_err(|e| e.to_string())
+            .and_then(|snap| bfi.restore(&snap).map_err(|e| e.to_string()));
+        if let Err(e) = restored {
+            eprintln!("error: unable to resume from '{}': {}", path, e);
+            process::exit(1);
+        }
+    }
+    // keys go to the program as they are pressed, until it stops
+    #[cfg(not(target_arch = "wasm32"))]
+    let raw_input = match opts.raw_input {
+        true => match terminal::RawInput::enable() {
+            Ok(raw) => Option::Some(raw),
+            Err(e) => {
+                eprintln!("error: unable to switch to raw input: {}", e);
+                process::exit(1);
+            },
+        },
+        false => Option::None,
+    };
+    let result = if opts.debug {
+        let mut dbg = debugger::Debugger::new();
+        // explicit breakpoints plus any # characters in brainfuck source, which the
+        // debugger pauses on by itself when they are commands
+        let code = if opts.embedded_input { parsing::split_program_input(&prog).0 } else { &prog };
+        let marks = match opts.dialect {
+            dtypes::Dialect::Brainfuck if !opts.hash_debug && mapping.is_none() => parsing::find_debug_marks(code),
+            _ => Vec::new(),
+        };
+        for index in opts.breakpoints.iter().copied().chain(marks) {
+            dbg.add_breakpoint(index);
+        }
+        for &index in &opts.watchpoints {
+            dbg.add_watchpoint(index);
+        }
+        bfi.set_history_depth(opts.history);
+        // the program reads its input from stdin too, so don't let the debugger
+        // buffer anything past the end of its own command lines
+        let mut input = io::BufReader::with_capacity(1, io::stdin());
+        match dbg.run(&mut bfi, &mut input, &mut io::stderr()) {
+            Ok(Some(result)) => result,
+            // quit before the program finished
+            Ok(None) => Ok(()),
+            Err(e) => {
+                eprintln!("error: debugger I/O failed: {}", e);
+                process::exit(1);
+            },
+        }
+    } else if opts.tui {
+        watch(&mut bfi, &prog, &opts)
+    } else if json {
+        let report = bfi.run_with_report();
+        println!("{}", json::run_document(&opts.path, &bfi.take_output(), &report));
+        report.result
+    } else if opts.stats {
+        let report = bfi.run_with_report();
+        eprintln!("{}", report);
+        report.result
+    } else {
+        bfi.run()
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    drop(raw_input);
+    // show the cells up to the last one in use (non-zero or under the data pointer)
+    if opts.dump_mem {
+        let used = bfi.memory().iter().rposition(|&cell| cell != 0).map_or(0, |i| i + 1);
+        eprintln!("{}", bfi.dump_memory(..used.max(bfi.data_pointer() + 1)));
+    }
+    // save where a program that ran out of steps or was interrupted got to so
+    // it can be resumed
+    if let (Some(path), Err(dtypes::BFError::StepLimitExceeded { .. } | dtypes::BFError::Interrupted { .. })) = (&opts.checkpoint, &result) {
+        match fs::write(path, bfi.snapshot()) {
+            Ok(()) => eprintln!("checkpoint saved to '{}'", path),
+            Err(e) => eprintln!("error: unable to save checkpoint to '{}': {}", path, e),
+        };
+    }
+    // recorded however the program stopped, so errors can be replayed too
+    if let (Some(path), Some(recorder)) = (&opts.record, &recorder) {
+        let settings = replay::Settings {
+            mem_size: opts.mem_size,
+            tape_mode: opts.tape_mode,
+            sparse: opts.sparse,
+            cell_width: opts.cell_width,
+            eof_behavior: opts.eof_behavior,
+            dialect: opts.dialect,
+            optimize: opts.optimize,
+            embedded_input: opts.embedded_input,
+            hash_debug: opts.hash_debug,
+        };
+        let recording = recorder.recording(settings, bfi.instructions());
+        if let Err(e) = fs::write(path, recording.to_bytes()) {
+            eprintln!("error: unable to save recording to '{}': {}", path, e);
+            process::exit(1);
+        }
+    }
+    if let (true, Some(profile)) = (opts.profile, bfi.profile()) {
+        eprintln!("{}", profile);
+    }
+    if let (Some(path), Some(profile)) = (&opts.profile_trace, bfi.profile()) {
+        if let Err(e) = fs::write(path, profile.chrome_trace(&opts.path, bfi.sources())) {
+            eprintln!("error: unable to write profile trace to '{}': {}", path, e);
+        }
+    }
+    if let (true, Some(profile)) = (covering, bfi.profile()) {
+        let cov = coverage::Coverage::new(bfi.sources(), bfi.instructions(), profile);
+        if opts.coverage {
+            eprint!("{}", cov.annotate(&prog));
+            eprintln!("{}", cov);
+        }
+        if let Some(path) = &opts.lcov {
+            if let Err(e) = fs::write(path, cov.lcov(&opts.path)) {
+                eprintln!("error: unable to write coverage to '{}': {}", path, e);
+            }
+        }
+    }
+    if let Err(e) = result {
+        // the error is already in the JSON document
+        if !json {
+            let diagnostic = diagnose(&e, &prog, opts.dialect, &mapping).with_tape(&bfi);
+            eprint!("{}", diagnostic.render(color(opts.color)));
+        }
+        process::exit(e.exit_code());
+    }
+    // hand the cell back to the shell, which only sees the low 8 bits
+    if let Some(index) = opts.exit_cell {
+        // cells past the end of a grown tape were never reached, so are 0
+        let value = bfi.tape_origin().checked_add(index).and_then(|i| bfi.cell(i)).unwrap_or(0);
+        process::exit((value & 0xff) as i32);
+    }
+}
+
+
+// compile a program to source code for another language
+fn compile (opts: cli::CompileOptions) {
+    let prog = read_program(&opts.path);
+    // compiled programs always read stdin, so any embedded input is dropped
+    let split = opts.embedded_input && opts.dialect.allows_embedded_input();
+    let code = if split { parsing::split_program_input(&prog).0 } else { &prog };
+    let tokens = parsing::tokenize_with_debug(code, opts.dialect, opts.hash_debug);
+    let cmds: Vec<dtypes::Command> = tokens.iter().map(|token| token.command).collect();
+    let lowered = if opts.optimize { optimizer::optimize(&cmds) } else { optimizer::lower(&cmds) };
+    let ops = match lowered {
+        Ok(ops) => ops,
+        Err(e) => {
+            let positions: Vec<dtypes::SourcePos> = tokens.iter().map(|token| token.pos).collect();
+            report_error(&prog, &e.locate(&positions));
+            process::exit(1);
+        },
+    };
+    if cmds.contains(&dtypes::Command::DefineProcedure) {
+        eprintln!("error: pbrain procedures can't be compiled");
+        process::exit(1);
+    }
+    if cmds.contains(&dtypes::Command::Fork) {
+        eprintln!("error: brainfork threads can't be compiled");
+        process::exit(1);
+    }
+    let config = compiler::Config {
+        mem_size: opts.mem_size,
+        cell_width: opts.cell_width,
+        eof_behavior: opts.eof_behavior,
+    };
+    let src = compiler::compile(&ops, opts.target, &config);
+    let written = match &opts.output {
+        Some(path) => fs::write(path, src),
+        None => io::Write::write_all(&mut io::stdout(), &src),
+    };
+    if let Err(e) = written {
+        eprintln!("error: unable to write compiled program: {}", e);
+        process::exit(1);
+    }
+}
+
+
+// print bracket errors found in a program file prefixed with where they are
+fn report_bracket_errors (path: &str, code: &str, dialect: dtypes::Dialect, errors: &[dtypes::BFError]) {
+    let tokens = parsing::tokenize(code, dialect);
+    for e in errors {
+        if let Some(token) = tokens.get(e.position()) {
+            eprintln!("{}:{}: error: {}", path, token.pos, e);
+            eprintln!("{}", parsing::source_snippet(code, token.pos));
+        }
+    }
+}
+
+
+// report every unmatched bracket in a program with its line and column,
+// without running anything
+fn check (opts: cli::CheckOptions) {
+    let prog = read_program(&opts.path);
+    let split = opts.embedded_input && opts.dialect.allows_embedded_input();
+    let code = if split { parsing::split_program_input(&prog).0 } else { &prog };
+    let cmds = parsing::parse_dialect(code, opts.dialect);
+    let errors = parsing::find_bracket_errors(&cmds);
+    if !errors.is_empty() {
+        report_bracket_errors(&opts.path, code, opts.dialect, &errors);
+        process::exit(1);
+    }
+    if opts.dead_code {
+        for (pos, count) in dead_code(code, opts.dialect) {
+            let what = if count == 1 { String::from("this command never has") } else { format!("these {} commands never have", count) };
+            eprintln!("{}:{}: warning: {} an effect", opts.path, pos, what);
+            eprintln!("{}", parsing::source_snippet(code, pos));
+        }
+    }
+    if !opts.bounds {
+        println!("{}: ok, {} commands", opts.path, cmds.len());
+        return;
+    }
+    if let Some(pos) = unproven_bounds(code, opts.dialect, opts.mem_size) {
+        eprintln!("{}:{}: error: can't prove that the data pointer stays within {} cells from here", opts.path, pos, opts.mem_size);
+        eprintln!("{}", parsing::source_snippet(code, pos));
+        process::exit(1);
+    }
+    println!("{}: ok, {} commands, the data pointer stays within {} cells", opts.path, cmds.len(), opts.mem_size);
+}
+
+
+// where a program (whose brackets match) might first take the data pointer out
+// of mem_size cells, None if it's proven that it can't
+fn unproven_bounds (code: &str, dialect: dtypes::Dialect, mem_size: usize) -> Option<dtypes::SourcePos> {
+    let tokens = parsing::tokenize(code, dialect);
+    let cmds: Vec<dtypes::Command> = tokens.iter().map(|token| token.command).collect();
+    // every command, so it holds whether or not the program is run optimized
+    let ops = optimizer::lower(&cmds).ok()?;
+    let unproven = analysis::check_bounds(&ops, 0, mem_size).err()?;
+    tokens.get(unproven.position).map(|token| token.pos)
+}
+
+
+// where each run of commands the optimizer drops as dead code starts, and how
+// many commands it has, for a program whose brackets match
+fn dead_code (code: &str, dialect: dtypes::Dialect) -> Vec<(dtypes::SourcePos, usize)> {
+    let tokens = parsing::tokenize(code, dialect);
+    let cmds: Vec<dtypes::Command> = tokens.iter().map(|token| token.command).collect();
+    optimizer::eliminated(&cmds).unwrap_or_default().into_iter()
+        .filter_map(|range| tokens.get(range.start).map(|token| (token.pos, range.len())))
+        .collect()
+}
+
+
+// report metrics of a program without running it
+fn analyze (opts: cli::CheckOptions) {
+    let prog = read_program(&opts.path);
+    let split = opts.embedded_input && opts.dialect.allows_embedded_input();
+    let code = if split { parsing::split_program_input(&prog).0 } else { &prog };
+    let errors = parsing::find_bracket_errors(&parsing::parse_dialect(code, opts.dialect));
+    if !errors.is_empty() {
+        report_bracket_errors(&opts.path, code, opts.dialect, &errors);
+        process::exit(1);
+    }
+    match analysis::Analysis::new(code, opts.dialect) {
+        Ok(analysis) => println!("{}", analysis),
+        Err(e) => {
+            report_bracket_errors(&opts.path, code, opts.dialect, &[e]);
+            process::exit(1);
+        },
+    };
+    if opts.bounds {
+        match unproven_bounds(code, opts.dialect, opts.mem_size) {
+            Some(pos) => println!("bounds: might leave {} cells, first at {}", opts.mem_size, pos),
+            None => println!("bounds: stays within {} cells", opts.mem_size),
+        };
+    }
+    if opts.dead_code {
+        let regions = dead_code(code, opts.dialect);
+        let total: usize = regions.iter().map(|(_, count)| count).sum();
+        let at: Vec<String> = regions.iter().map(|(pos, count)| format!("{} at {}", count, pos)).collect();
+        match regions.is_empty() {
+            true => println!("dead code: none"),
+            false => println!("dead code: {} commands ({})", total, at.join(", ")),
+        };
+    }
+}
+
+
+// re-indent a program by loop nesting
+fn fmt (opts: cli::FmtOptions) {
+    let prog = read_program(&opts.path);
+    let (code, input) = if opts.embedded_input { parsing::split_program_input(&prog) } else { (prog.as_str(), Option::None) };
+    let mut src = match formatter::format(code, &opts.config) {
+        Ok(src) => src,
+        Err(errors) => {
+            report_bracket_errors(&opts.path, code, dtypes::Dialect::Brainfuck, &errors);
+            process::exit(1);
+        },
+    };
+    if let Some(input) = input {
+        src.push('!');
+        src.push_str(input);
+    }
+    let written = match &opts.output {
+        Some(path) => fs::write(path, src),
+        None => io::Write::write_all(&mut io::stdout(), src.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("error: unable to write formatted program: {}", e);
+        process::exit(1);
+    }
+}
+
+
+// strip a program down to its commands and cancel out what it can
+fn minify (opts: cli::MinifyOptions) {
+    let prog = read_program(&opts.path);
+    let (code, input) = if opts.embedded_input { parsing::split_program_input(&prog) } else { (prog.as_str(), Option::None) };
+    let mut src = match minifier::minify(&parsing::parse_program(code)) {
+        Ok(src) => src,
+        Err(e) => {
+            report_bracket_errors(&opts.path, code, dtypes::Dialect::Brainfuck, &[e]);
+            process::exit(1);
+        },
+    };
+    match input {
+        Some(input) => {
+            src.push('!');
+            src.push_str(input);
+        },
+        None => src.push('\n'),
+    };
+    let written = match &opts.output {
+        Some(path) => fs::write(path, src),
+        None => io::Write::write_all(&mut io::stdout(), src.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("error: unable to write minified program: {}", e);
+        process::exit(1);
+    }
+}
+
+
+// print the instructions a program is lowered (and optimized) into
+fn ir (opts: cli::IrOptions) {
+    let (ops, positions) = lower_program(&opts);
+    print!("{}", disasm::disassemble(&ops, &positions));
+}
+
+
+// print what each instruction of a program does in plain English
+fn explain (opts: cli::IrOptions) {
+    let (ops, positions) = lower_program(&opts);
+    print!("{}", disasm::explain(&ops, &positions));
+}
+
+
+// the instructions a program runs as and the positions of its source
+// commands, bail out if the program doesn't have matching brackets
+fn lower_program (opts: &cli::IrOptions) -> (Vec<dtypes::Op>, Vec<dtypes::SourcePos>) {
+    let prog = read_program(&opts.path);
+    let split = opts.embedded_input && opts.dialect.allows_embedded_input();
+    let code = if split { parsing::split_program_input(&prog).0 } else { &prog };
+    let tokens = parsing::tokenize(code, opts.dialect);
+    let cmds: Vec<dtypes::Command> = tokens.iter().map(|token| token.command).collect();
+    let positions: Vec<dtypes::SourcePos> = tokens.iter().map(|token| token.pos).collect();
+    let lowered = if opts.optimize { optimizer::optimize(&cmds) } else { optimizer::lower(&cmds) };
+    match lowered {
+        Ok(ops) => (ops, positions),
+        Err(e) => {
+            report_error(&prog, &e.locate(&positions));
+            process::exit(1);
+        },
+    }
+}
+
+
+// run every program in a directory of golden file tests, reporting the ones
+// that don't output what they should along with the differences
+fn test (opts: cli::TestOptions) {
+    let tests = match golden::find_tests(Path::new(&opts.dir)) {
+        Ok(tests) => tests,
+        Err(e) => {
+            eprintln!("error: unable to read test directory '{}': {}", opts.dir, e);
+            process::exit(1);
+        },
+    };
+    let (eof_behavior, max_steps) = (opts.eof_behavior, opts.max_steps);
+    let mut batch = batch::Batch::new(move || {
+        let mut bfi = interpreter::BFInterpreter::new(cli::DEFAULT_MEM_SIZE);
+        bfi.set_eof_behavior(eof_behavior);
+        bfi.set_max_steps(max_steps);
+        bfi
+    });
+    let mut expected: Vec<Option<Vec<u8>>> = Vec::new();
+    for test in &tests {
+        let read = |path: &Path| fs::read(path).unwrap_or_else(|e| {
+            eprintln!("error: unable to read '{}': {}", path.display(), e);
+            process::exit(1);
+        });
+        let prog = String::from_utf8_lossy(&read(&test.program)).into_owned();
+        let input = test.input.as_deref().map(read).unwrap_or_default();
+        batch.add(&test.name, &prog, Option::Some(&input));
+        expected.push(test.expected.as_deref().map(read));
+    }
+    batch.set_threads(opts.jobs);
+    let mut failed = 0;
+    for (result, expected) in batch.run().iter().zip(&expected) {
+        let problem = match (&result.report.result, expected) {
+            (Err(e), _) => format!("  error: {}\n", e),
+            (Ok(()), None) => String::from("  no .expected file\n"),
+            (Ok(()), Some(expected)) if *expected != result.output => {
+                let diff = golden::diff(&String::from_utf8_lossy(expected), &String::from_utf8_lossy(&result.output));
+                diff.lines().map(|line| format!("  {}\n", line)).collect()
+            },
+            (Ok(()), Some(_)) => {
+                println!("PASS {}", result.name);
+                continue;
+            },
+        };
+        println!("FAIL {}\n{}", result.name, problem.trim_end());
+        failed += 1;
+    }
+    println!("{} passed, {} failed", tests.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+
+// run a program through the unoptimized interpreter and the optimized engines
+// with the same input, reporting the first place any of them differs from it
+fn diff (opts: cli::DiffOptions) {
+    let prog = read_program(&opts.path);
+    let input = read_input(&opts.input, &opts.input_file);
+    let engines = [
+        ("unoptimized", false, dtypes::Engine::Interpreter),
+        ("optimized", true, dtypes::Engine::Interpreter),
+        #[cfg(feature = "jit")]
+        ("jit", true, dtypes::Engine::Jit),
+    ];
+    let outcomes: Vec<crossval::Outcome> = engines.into_iter().map(|(name, optimize, engine)| {
+        let mut bfi = interpreter::BFInterpreter::with_config(opts.mem_size, opts.tape_mode, opts.cell_width);
+        bfi.set_eof_behavior(opts.eof_behavior);
+        bfi.set_max_steps(Option::Some(opts.max_steps));
+        bfi.set_optimize(optimize);
+        bfi.set_engine(engine);
+        bfi.set_split_input(opts.embedded_input);
+        bfi.set_dialect(opts.dialect);
+        crossval::run_engine(name, &mut bfi, &prog, input.as_deref())
+    }).collect();
+    for outcome in &outcomes {
+        match &outcome.result {
+            Ok(()) => println!("{}: finished, {} bytes output", outcome.engine, outcome.output.len()),
+            Err(dtypes::BFError::StepLimitExceeded { limit, .. }) => {
+                println!("{}: did not finish within {} steps, {} bytes output", outcome.engine, limit, outcome.output.len());
+            },
+            Err(e) => println!("{}: {}, {} bytes output", outcome.engine, e, outcome.output.len()),
+        };
+    }
+    let reference = &outcomes[0];
+    let mut diverged = false;
+    for outcome in &outcomes[1..] {
+        if let Some(divergence) = crossval::compare(reference, outcome) {
+            println!("DIVERGED {} from {}: {}", outcome.engine, reference.engine, divergence);
+            diverged = true;
+        }
+    }
+    if outcomes.iter().any(|o| matches!(o.result, Err(dtypes::BFError::StepLimitExceeded { .. }))) {
+        println!("note: stopped by the step limit, only the output so far was compared");
+    }
+    if diverged {
+        process::exit(1);
+    }
+    println!("all engines agree");
+}
+
+
+// run two programs on every input in a directory with the same limits,
+// reporting each input they behave differently on
+fn equiv (opts: cli::EquivOptions) {
+    let (a, b) = (read_program(&opts.a), read_program(&opts.b));
+    let inputs = match &opts.inputs {
+        Some(dir) => match equiv::read_inputs(Path::new(dir)) {
+            Ok(inputs) => inputs,
+            Err(e) => {
+                eprintln!("error: unable to read input directory '{}': {}", dir, e);
+                process::exit(1);
+            },
+        },
+        None => vec![(String::from("(no input)"), Vec::new())],
+    };
+    let (mem_size, tape_mode, cell_width) = (opts.mem_size, opts.tape_mode, opts.cell_width);
+    let (eof_behavior, max_steps, dialect) = (opts.eof_behavior, opts.max_steps, opts.dialect);
+    let checks = equiv::check(&a, &b, &inputs, opts.jobs, move || {
+        let mut bfi = interpreter::BFInterpreter::with_config(mem_size, tape_mode, cell_width);
+        bfi.set_eof_behavior(eof_behavior);
+        bfi.set_max_steps(Option::Some(max_steps));
+        bfi.set_dialect(dialect);
+        bfi
+    });
+    let result = |outcome: &crossval::Outcome| match &outcome.result {
+        Ok(()) => format!("finished, {} bytes output", outcome.output.len()),
+        Err(e) => format!("{}, {} bytes output", e, outcome.output.len()),
+    };
+    let mut differ = 0;
+    for check in &checks {
+        match &check.divergence {
+            Some(divergence) => {
+                println!("DIFFER {}: {}", check.name, divergence);
+                println!("  {}: {}", opts.a, result(&check.a));
+                println!("  {}: {}", opts.b, result(&check.b));
+                differ += 1;
+            },
+            None => println!("SAME {}", check.name),
+        };
+    }
+    println!("{} same, {} differ", checks.len() - differ, differ);
+    if differ > 0 {
+        process::exit(1);
+    }
+}
+
+
+// print a random program, e.g. for fuzzing other interpreters
+fn gen (opts: cli::GenOptions) {
+    let mut prog = generator::generate(opts.seed, &opts.config);
+    prog.push('\n');
+    let written = match &opts.output {
+        Some(path) => fs::write(path, prog),
+        None => io::Write::write_all(&mut io::stdout(), prog.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("error: unable to write generated program: {}", e);
+        process::exit(1);
+    }
+}
+
+
+// write a program that prints the text given
+fn encode (opts: cli::EncodeOptions) {
+    let text = match &opts.file {
+        Some(path) => match fs::read(path) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("error: unable to read '{}': {}", path, e);
+                process::exit(1);
+            },
+        },
+        None => opts.text.clone().unwrap_or_default().into_bytes(),
+    };
+    let mut prog = encoder::encode(&text);
+    prog.push('\n');
+    let written = match &opts.output {
+        Some(path) => fs::write(path, prog),
+        None => io::Write::write_all(&mut io::stdout(), prog.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("error: unable to write generated program: {}", e);
+        process::exit(1);
+    }
+}
+
+
+// reshape a program with noise that doesn't change what it does, checking that
+// it really doesn't by running both versions
+fn obfuscate (opts: cli::ObfuscateOptions) {
+    let prog = read_program(&opts.path);
+    let (code, embedded) = if opts.embedded_input { parsing::split_program_input(&prog) } else { (prog.as_str(), Option::None) };
+    let mut src = match obfuscator::obfuscate(&parsing::parse_program(code), opts.seed) {
+        Ok(src) => src,
+        Err(e) => {
+            report_bracket_errors(&opts.path, code, dtypes::Dialect::Brainfuck, &[e]);
+            process::exit(1);
+        },
+    };
+    let input = read_input(&opts.input, &opts.input_file).unwrap_or_else(|| embedded.unwrap_or_default().as_bytes().to_vec());
+    if let Some(divergence) = obfuscator::verify(code, &src, &input, opts.max_steps) {
+        eprintln!("error: the obfuscated program behaves differently: {}", divergence);
+        process::exit(1);
+    }
+    match embedded {
+        Some(input) => {
+            src.push('!');
+            src.push_str(input);
+        },
+        None => src.push('\n'),
+    };
+    let written = match &opts.output {
+        Some(path) => fs::write(path, src),
+        None => io::Write::write_all(&mut io::stdout(), src.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("error: unable to write obfuscated program: {}", e);
+        process::exit(1);
+    }
+}
+
+
+// time the bundled programs, and any others given, on every engine and print
+// how they compare
+fn bench (opts: cli::BenchOptions) {
+    let mut programs = bench::bundled();
+    let input = read_input(&opts.input, &opts.input_file).unwrap_or_default();
+    for path in &opts.paths {
+        programs.push(bench::Program { name: path.clone(), source: read_program(path), input: input.clone() });
+    }
+    let mut timings = Vec::new();
+    for program in &programs {
+        for engine in bench::engines() {
+            timings.push(bench::time(program, &engine, opts.runs, opts.max_steps));
+        }
+    }
+    print!("{}", bench::table(&timings));
+    if timings.iter().any(|t| t.report.result.is_err()) {
+        process::exit(1);
+    }
+}
+
+
+// run a program again exactly as it ran when it was recorded, with the same
+// settings and input, checking it executes the same instructions or stepping
+// through it in the debugger
+fn replay (opts: cli::ReplayOptions) {
+    let read = fs::read(&opts.recording).map_err(|e| e.to_string())
+        .and_then(|bytes| replay::Recording::from_bytes(&bytes).map_err(|e| e.to_string()));
+    let recording = match read {
+        Ok(recording) => recording,
+        Err(e) => {
+            eprintln!("error: unable to read recording '{}': {}", opts.recording, e);
+            process::exit(1);
+        },
+    };
+    let prog = read_program(&opts.path);
+    let settings = recording.settings;
+    let mut bfi = new_interpreter(settings.mem_size, settings.tape_mode, settings.sparse, settings.cell_width);
+    settings.apply(&mut bfi);
+    if let Err(e) = bfi.load_program(&prog) {
+        report_error(&prog, &e);
+        process::exit(1);
+    }
+    let result = if opts.debug {
+        if let Err(e) = replay::check_program(&bfi, &recording) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        bfi.set_input(&recording.input);
+        let mut input = io::BufReader::with_capacity(1, io::stdin());
+        match debugger::Debugger::new().run(&mut bfi, &mut input, &mut io::stderr()) {
+            Ok(result) => result.unwrap_or(Ok(())),
+            Err(e) => {
+                eprintln!("error: debugger I/O failed: {}", e);
+                process::exit(1);
+            },
+        }
+    } else {
+        if let Err(divergence) = replay::replay(&mut bfi, &recording) {
+            eprintln!("error: replay diverged from the recording: {}", divergence);
+            process::exit(1);
+        }
+        match bfi.is_finished() {
+            true => eprintln!("replayed {} steps, matching the recording", recording.steps),
+            false => eprintln!("replayed {} steps, matching the recording up to where it stopped", recording.steps),
+        };
+        bfi.error().cloned().map_or(Ok(()), Err)
+    };
+    if let Err(e) = result {
+        report_error(&prog, &e);
+        process::exit(e.exit_code());
+    }
+}
+
+
+// run a program unoptimized with profiling on and draw how many times each
+// command ran, the program's own output is thrown away
+fn heatmap (opts: cli::HeatmapOptions) {
+    let prog = read_program(&opts.path);
+    let mut bfi = interpreter::BFInterpreter::new(opts.mem_size);
+    bfi.set_eof_behavior(opts.eof_behavior);
+    bfi.set_max_steps(opts.max_steps);
+    bfi.set_optimize(false);
+    bfi.set_profiling(true);
+    bfi.set_split_input(opts.embedded_input);
+    bfi.set_dialect(opts.dialect);
+    bfi.set_output_sink(interpreter::OutputSink::Buffer);
+    if let Err(e) = bfi.load_program(&prog) {
+        report_error(&prog, &e);
+        process::exit(1);
+    }
+    if let Some(input) = read_input(&opts.input, &opts.input_file) {
+        bfi.set_input(&input);
+    }
+    // a program stopped part way still shows where it spent its time
+    let result = bfi.run();
+    let page = match bfi.profile() {
+        Some(profile) => heatmap::html(&opts.path, &prog, &coverage::Coverage::new(bfi.sources(), bfi.instructions(), profile)),
+        None => unreachable!("profiling is on"),
+    };
+    match &opts.output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, page) {
+                eprintln!("error: unable to write heatmap to '{}': {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => print!("{}", page),
+    };
+    if let Err(e) = result {
+        report_error(&prog, &e);
+        process::exit(e.exit_code());
+    }
+}
+
+
+// run a program stored on the tape it works on, so it can rewrite itself
+fn smbf (opts: cli::SmbfOptions) {
+    let prog = read_program(&opts.path);
+    let tape = tape::new_tape(opts.tape_mode, opts.mem_size);
+    let mut smbf = match selfmod::SelfModifying::new(&prog, tape, opts.cell_width) {
+        Ok(smbf) => smbf,
+        Err(_) => {
+            eprintln!("error: the program doesn't fit on a tape of {} cells", opts.mem_size);
+            process::exit(1);
+        },
+    };
+    smbf.set_eof_behavior(opts.eof_behavior);
+    smbf.set_max_steps(opts.max_steps);
+    if let Some(input) = read_input(&opts.input, &opts.input_file) {
+        smbf.set_io(Box::new(bfio::StreamIo::new(io::Cursor::new(input), io::stdout())));
+    }
+    let result = smbf.run();
+    if opts.dump_mem {
+        eprintln!("{}", smbf.dump_memory());
+    }
+    if let Err(e) = result {
+        report_error(&prog, &e);
+        process::exit(e.exit_code());
+    }
+}
+
+
+// answer requests to run programs over HTTP until killed
+fn serve (opts: cli::ServeOptions) {
+    let limits = dtypes::Limits {
+        max_steps: Option::Some(opts.max_steps),
+        max_output_bytes: Option::Some(opts.max_output),
+        max_mem_cells: Option::Some(opts.mem_size),
+        timeout: Option::Some(std::time::Duration::from_millis(opts.timeout_ms)),
+        max_loop_depth: Option::None,
+    };
+    let (mem_size, eof_behavior) = (opts.mem_size, opts.eof_behavior);
+    let pool = pool::EvalPool::new(opts.jobs, limits, move || {
+        let mut bfi = interpreter::BFInterpreter::new(mem_size);
+        bfi.set_eof_behavior(eof_behavior);
+        bfi
+    });
+    let listener = match std::net::TcpListener::bind((opts.host.as_str(), opts.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("error: unable to listen on {}:{}: {}", opts.host, opts.port, e);
+            process::exit(1);
+        },
+    };
+    eprintln!("listening on http://{}:{}", opts.host, opts.port);
+    if let Err(e) = server::serve(listener, Arc::new(pool), opts.max_body) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
 
 
 fn main() {
-    let mut bfi = interpreter::BFInterpreter::new(8);
-    bfi.run();
+    // skip the executable name, env::vars() would panic on variables that aren't
+    // unicode, which can't be settings anyway
+    let vars = env::vars_os().filter_map(|(name, value)| Option::Some((name.into_string().ok()?, value.into_string().ok()?)));
+    match cli::parse_args(env::args().skip(1), vars) {
+        Ok(cli::Subcommand::Run(opts)) => run(*opts),
+        Ok(cli::Subcommand::Test(opts)) => test(opts),
+        Ok(cli::Subcommand::Compile(opts)) => compile(opts),
+        Ok(cli::Subcommand::Check(opts)) => check(opts),
+        Ok(cli::Subcommand::Analyze(opts)) => analyze(opts),
+        Ok(cli::Subcommand::Fmt(opts)) => fmt(opts),
+        Ok(cli::Subcommand::Minify(opts)) => minify(opts),
+        Ok(cli::Subcommand::Ir(opts)) => ir(opts),
+        Ok(cli::Subcommand::Explain(opts)) => explain(opts),
+        Ok(cli::Subcommand::Gen(opts)) => gen(opts),
+        Ok(cli::Subcommand::Encode(opts)) => encode(opts),
+        Ok(cli::Subcommand::Obfuscate(opts)) => obfuscate(opts),
+        Ok(cli::Subcommand::Diff(opts)) => diff(opts),
+        Ok(cli::Subcommand::Equiv(opts)) => equiv(opts),
+        Ok(cli::Subcommand::Bench(opts)) => bench(opts),
+        Ok(cli::Subcommand::Replay(opts)) => replay(opts),
+        Ok(cli::Subcommand::Heatmap(opts)) => heatmap(opts),
+        Ok(cli::Subcommand::Smbf(opts)) => smbf(opts),
+        Ok(cli::Subcommand::Serve(opts)) => serve(opts),
+        Ok(cli::Subcommand::Completions(shell)) => print!("{}", completions::script(shell)),
+        Err(msg) => {
+            eprintln!("error: {}", msg);
+            eprintln!("{}", cli::USAGE);
+            process::exit(2);
+        },
+    };
 }
@@ -0,0 +1,130 @@
+/*
+    Module with a minifier that shrinks program source without changing
+    what it does
+*/
+
+
+use crate::{dtypes, optimizer};
+
+
+// add an instruction to the end of a minified program, merging it with the one
+// before where they combine and dropping anything that can never run
+fn push (out: &mut Vec<dtypes::Instr>, instr: dtypes::Instr) {
+    match (out.last_mut(), instr) {
+        (Some(dtypes::Instr::Add(m)), dtypes::Instr::Add(n)) => {
+            *m = m.wrapping_add(n);
+            if *m == 0 {
+                out.pop();
+            }
+        },
+        (Some(dtypes::Instr::Move(m)), dtypes::Instr::Move(n)) => {
+            *m += n;
+            if *m == 0 {
+                out.pop();
+            }
+        },
+        _ => out.push(instr),
+    };
+}
+
+
+// the commands for a run of instructions
+fn to_source (instrs: &[dtypes::Instr]) -> String {
+    let mut src = String::new();
+    for instr in instrs {
+        match *instr {
+            dtypes::Instr::Add(n) if n > 0 => src.push_str(&"+".repeat(n as usize)),
+            dtypes::Instr::Add(n) => src.push_str(&"-".repeat(n.unsigned_abs() as usize)),
+            dtypes::Instr::Move(n) if n > 0 => src.push_str(&">".repeat(n as usize)),
+            dtypes::Instr::Move(n) => src.push_str(&"<".repeat(n.unsigned_abs())),
+            dtypes::Instr::OutputByte => src.push('.'),
+            dtypes::Instr::InputByte => src.push(','),
+            dtypes::Instr::JumpIfZero(_) => src.push('['),
+            dtypes::Instr::JumpIfNonZero(_) => src.push(']'),
+            // lower() never produces these
+            dtypes::Instr::Clear | dtypes::Instr::AddTo(..) | dtypes::Instr::AddAt(..)
+            | dtypes::Instr::ClearAt(_) | dtypes::Instr::OutputAt(_) | dtypes::Instr::ScanRight | dtypes::Instr::ScanLeft => {},
+            instr => src.push_str(&instr.to_string()),
+        };
+    }
+    src
+}
+
+
+// strip everything but commands from a program and make it smaller where that's
+// safe: adjacent +/- and >/< cancel out, and loops that can never be entered
+// (at the very start, when every cell is still zero, or straight after another
+// loop, which always leaves a zero cell) are dropped, unbalanced brackets are
+// reported
+//
+// moves that cancel are dropped even if they would have stepped off the end of
+// the tape and back, so on a strict tape a program that failed might not, the
+// optimizer knows the tape a program runs on and only drops them where they
+// can't, a minified program could be run on any
+pub fn minify (program: &[dtypes::Command]) -> Result<String, dtypes::BFError> {
+    let ops = optimizer::lower(program)?;
+    let mut out: Vec<dtypes::Instr> = Vec::with_capacity(ops.len());
+    let mut i = 0;
+    while i < ops.len() {
+        match ops[i].instr {
+            // the cell under the data pointer is known to be zero, skip to the
+            // matching bracket
+            dtypes::Instr::JumpIfZero(target) if matches!(out.last(), None | Some(dtypes::Instr::JumpIfNonZero(_))) => {
+                i = target;
+            },
+            instr => push(&mut out, instr),
+        };
+        i += 1;
+    }
+    Ok(to_source(&out))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{interpreter, parsing};
+
+    fn minify_str (prog: &str) -> String {
+        minify(&parsing::parse_program(prog)).unwrap()
+    }
+
+    #[test]
+    fn minify_cancels_and_strips () {
+        assert_eq!(minify_str("++ two\n+- >>< done."), "++>.");
+        assert_eq!(minify_str(">+-<"), "");
+        assert_eq!(minify_str("+++---"), "");
+        assert_eq!(minify_str("-[>+<-]"), "-[>+<-]");
+    }
+
+    #[test]
+    fn minify_dead_loops () {
+        // a comment loop at the start never runs, nor does one after another loop
+        assert_eq!(minify_str("[this is a comment, with commands. in it]+[-][.][>]>"), "+[-]>");
+        assert_eq!(minify_str("+-[.]<>[.],"), ",");
+        // a loop after anything else might run
+        assert_eq!(minify_str(",[.]"), ",[.]");
+    }
+
+    #[test]
+    fn minify_keeps_behavior () {
+        let prog = "[comment.]++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.";
+        let mut outputs = Vec::new();
+        for src in [String::from(prog), minify_str(prog)] {
+            let mut bfi = interpreter::BFInterpreter::new(16);
+            bfi.set_output_sink(interpreter::OutputSink::Buffer);
+            bfi.load_program(&src).unwrap();
+            bfi.run().unwrap();
+            outputs.push(bfi.output().to_vec());
+        }
+        assert_eq!(outputs[0], b"Hello World!");
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[test]
+    fn minify_unbalanced () {
+        assert_eq!(minify(&parsing::parse_program("+]")),
+                   Err(dtypes::BFError::UnmatchedBracket { bracket: ']', position: 1, source: None }));
+    }
+}
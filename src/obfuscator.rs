@@ -0,0 +1,165 @@
+/*
+    Module for reshaping a program's source without changing what it does,
+    e.g. for golf or teaching demos. Noise is sprinkled between the commands:
+
+        +- or -+    cancel out anywhere
+        <> or ><    after a > or a < respectively, so the pointer only goes
+                    back to a cell it was just on and never off the tape
+        [...]       after a ], which always leaves a zero cell, so the loop
+                    is never entered whatever is in it
+        words       comments, which are skipped
+
+    and the result is broken into lines of varying length
+
+        let noisy = rust_bfi::obfuscator::obfuscate(&rust_bfi::parsing::parse_program(prog), seed)?;
+
+    the same seed always gives the same program, verify() runs a program and
+    its obfuscated version through the cross-validation engine to check that
+    they behave the same
+*/
+
+
+use crate::{bfio, crossval, dtypes, interpreter, optimizer};
+
+
+// comment words, none of them has a command in it
+const WORDS: [&str; 16] = ["noise", "nothing", "to", "see", "here", "move", "along", "just", "a",
+                           "loop", "cell", "tape", "byte", "maybe", "not", "really"];
+
+
+// each command is followed by at most one piece of noise, which executes at most
+// two commands, so an obfuscated program runs at most this many times as many
+pub const STEP_FACTOR: u64 = 3;
+
+
+// number of memory cells programs are verified with, per the classic spec
+const MEM_SIZE: usize = 30000;
+
+
+// step limit programs are verified with unless given another
+pub const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+
+// lines are broken somewhere between these many characters long
+const MIN_WIDTH: usize = 24;
+const MAX_WIDTH: usize = 72;
+
+
+// source for the random choices, from a seed
+struct Noise {
+    state: u64,
+}
+
+
+impl Noise {
+    // random number below n
+    fn below (&mut self, n: usize) -> usize {
+        (bfio::splitmix64(&mut self.state) % n as u64) as usize
+    }
+
+    // one of a few characters at random
+    fn pick (&mut self, chars: &str) -> char {
+        chars.as_bytes()[self.below(chars.len())] as char
+    }
+
+    // what to put after a command, if anything
+    fn after (&mut self, cmd: dtypes::Command) -> String {
+        match self.below(8) {
+            0 => String::from(if self.below(2) == 0 { "+-" } else { "-+" }),
+            1 => match cmd {
+                dtypes::Command::MovePointerRight => String::from("<>"),
+                dtypes::Command::MovePointerLeft => String::from("><"),
+                _ => String::from("+-"),
+            },
+            2 if cmd == dtypes::Command::JumpLeftIfNonZero => {
+                let body: String = (0..1 + self.below(6)).map(|_| self.pick("+-<>.,")).collect();
+                format!("[{}]", body)
+            },
+            3 => format!(" {} ", WORDS[self.below(WORDS.len())]),
+            _ => String::new(),
+        }
+    }
+}
+
+
+// the commands of a program with noise mixed in, unbalanced brackets are reported
+pub fn obfuscate (program: &[dtypes::Command], seed: u64) -> Result<String, dtypes::BFError> {
+    optimizer::lower(program)?;
+    let mut noise = Noise { state: seed };
+    let mut out = String::new();
+    let mut line = String::new();
+    let mut width = MIN_WIDTH + noise.below(MAX_WIDTH - MIN_WIDTH);
+    for &cmd in program {
+        line.push(cmd.as_char());
+        line.push_str(&noise.after(cmd));
+        if line.len() >= width {
+            out.push_str(line.trim());
+            out.push('\n');
+            line.clear();
+            width = MIN_WIDTH + noise.below(MAX_WIDTH - MIN_WIDTH);
+        }
+    }
+    out.push_str(line.trim());
+    Ok(out)
+}
+
+
+// run a program and its obfuscated version with the same input (neither is
+// split at a `!`) on the plain interpreter, the obfuscated one allowed
+// STEP_FACTOR times as many steps, and give the first way it differs, errors
+// are compared by kind since the commands they blame have moved
+pub fn verify (original: &str, obfuscated: &str, input: &[u8], max_steps: u64) -> Option<crossval::Divergence> {
+    let run = |engine: &str, prog: &str, max_steps: u64| {
+        let mut bfi = interpreter::BFInterpreter::new(MEM_SIZE);
+        bfi.set_optimize(false);
+        bfi.set_split_input(false);
+        bfi.set_max_steps(Option::Some(max_steps));
+        crossval::run_engine(engine, &mut bfi, prog, Option::Some(input))
+    };
+    let reference = run("original", original, max_steps);
+    let mut actual = run("obfuscated", obfuscated, max_steps.saturating_mul(STEP_FACTOR));
+    if let (Err(expected), Err(e)) = (&reference.result, &actual.result) {
+        if e.kind() == expected.kind() {
+            actual.result = reference.result.clone();
+        }
+    }
+    crossval::compare(&reference, &actual)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{generator, parsing};
+
+    #[test]
+    fn obfuscate_keeps_behavior () {
+        let prog = "++++++++[>++++++++<-]>+.[-]<<<,[.,]";
+        let noisy = obfuscate(&parsing::parse_program(prog), 1).unwrap();
+        assert!(noisy.len() > prog.len() && noisy.contains('\n'), "{}", noisy);
+        assert_eq!(verify(prog, &noisy, b"hi", 10_000), None);
+        // same seed, same program
+        assert_eq!(obfuscate(&parsing::parse_program(prog), 1).unwrap(), noisy);
+        assert_ne!(obfuscate(&parsing::parse_program(prog), 2).unwrap(), noisy);
+        // random programs behave the same, errors and all
+        let config = generator::Config { len: 60, ..Default::default() };
+        for seed in 0..50 {
+            let prog = generator::generate(seed, &config);
+            let noisy = obfuscate(&parsing::parse_program(&prog), seed).unwrap();
+            assert_eq!(verify(&prog, &noisy, b"input", 10_000), None, "{}\n{}", prog, noisy);
+        }
+        assert!(matches!(obfuscate(&parsing::parse_program("[["), 0), Err(dtypes::BFError::UnmatchedBracket { .. })));
+    }
+
+    #[test]
+    fn verify_finds_differences () {
+        assert_eq!(verify("+.", "+\n noise +-.", b"", 100), None);
+        assert!(matches!(verify("+.", "++.", b"", 100), Some(crossval::Divergence::Output { index: 0, .. })));
+        // a move that is only neutral away from the end of the tape
+        assert_eq!(verify("+.", "<>+.", b"", 100), Some(crossval::Divergence::Output { index: 0, expected: Some(1), actual: None }));
+        assert!(matches!(verify("+", "<>+", b"", 100), Some(crossval::Divergence::Result { .. })));
+        // a ! is just a comment
+        assert_eq!(verify(",.!", ",.", b"x", 100), None);
+    }
+}
@@ -0,0 +1,35 @@
+/*
+    Module with the observer interface, for tools built outside the
+    interpreter (tracers, profilers, debuggers, visualizers) that want to
+    follow a program as it runs
+
+        let mut bfi = rust_bfi::BFInterpreter::new(30000);
+        bfi.add_observer(Box::new(MyVisualizer::default()));
+
+    every callback does nothing by default, so an observer only implements
+    the ones it cares about
+*/
+
+
+use crate::trace;
+
+
+// told about what the interpreter does, attach one with BFInterpreter::add_observer(),
+// the callbacks for an instruction come before on_step() for it
+pub trait Observer {
+    // an instruction was executed
+    fn on_step (&mut self, _event: &trace::TraceEvent) {}
+
+    // the program output a byte
+    fn on_output (&mut self, _byte: u8) {}
+
+    // an instruction changed the value of a memory cell
+    fn on_memory_write (&mut self, _index: usize, _before: u32, _after: u32) {}
+
+    // the [ at index went into its loop body, which happens once each time the
+    // loop is run rather than once each time round
+    fn on_loop_enter (&mut self, _index: usize) {}
+
+    // the loop starting with the [ at index finished, by its ] falling through
+    fn on_loop_exit (&mut self, _index: usize) {}
+}
@@ -0,0 +1,914 @@
+/*
+    Module with passes that lower parsed commands into the
+    internal representation executed by the interpreter
+*/
+
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use crate::{analysis, dtypes, parsing};
+
+
+// the instruction a single command lowers to (jump targets are filled in later)
+fn lower_command (cmd: &dtypes::Command) -> dtypes::Instr {
+    match cmd {
+        dtypes::Command::MovePointerRight => dtypes::Instr::Move(1),
+        dtypes::Command::MovePointerLeft => dtypes::Instr::Move(-1),
+        dtypes::Command::IncrementByte => dtypes::Instr::Add(1),
+        dtypes::Command::DecrementByte => dtypes::Instr::Add(-1),
+        dtypes::Command::OutputByte => dtypes::Instr::OutputByte,
+        dtypes::Command::InputByte => dtypes::Instr::InputByte,
+        dtypes::Command::JumpRightIfZero => dtypes::Instr::JumpIfZero(0),
+        dtypes::Command::JumpLeftIfNonZero => dtypes::Instr::JumpIfNonZero(0),
+        dtypes::Command::EndProgram => dtypes::Instr::End,
+        dtypes::Command::StoreByte => dtypes::Instr::Store,
+        dtypes::Command::RetrieveByte => dtypes::Instr::Retrieve,
+        dtypes::Command::ShiftRight => dtypes::Instr::ShiftRight,
+        dtypes::Command::ShiftLeft => dtypes::Instr::ShiftLeft,
+        dtypes::Command::BitwiseNot => dtypes::Instr::Not,
+        dtypes::Command::BitwiseXor => dtypes::Instr::Xor,
+        dtypes::Command::BitwiseAnd => dtypes::Instr::And,
+        dtypes::Command::BitwiseOr => dtypes::Instr::Or,
+        dtypes::Command::DefineProcedure => dtypes::Instr::DefineProc(0),
+        dtypes::Command::EndProcedure => dtypes::Instr::EndProc,
+        dtypes::Command::CallProcedure => dtypes::Instr::CallProc,
+        dtypes::Command::Fork => dtypes::Instr::Fork,
+        dtypes::Command::Debug => dtypes::Instr::Debug,
+    }
+}
+
+
+// try to fold next into last, returns false if they can't be combined
+fn fold (last: &mut dtypes::Instr, next: dtypes::Instr) -> bool {
+    match (last, next) {
+        (dtypes::Instr::Add(m), dtypes::Instr::Add(n)) => {
+            *m = m.wrapping_add(n);
+            true
+        },
+        (dtypes::Instr::Move(m), dtypes::Instr::Move(n)) => {
+            *m += n;
+            true
+        },
+        _ => false,
+    }
+}
+
+
+// combine neighbouring Adds (e.g. +++--), dropping them if they cancel out,
+// and neighbouring Moves (e.g. >><) the same way when safe_moves says no move
+// can run off the end of the tape, otherwise Moves are left alone even when they
+// cancel since the first of them can still run off the end of a strict tape, and
+// a Move that does blames one of the commands in a run that all go the same way
+fn cancel_opposites (ops: Vec<dtypes::Op>, safe_moves: bool) -> Vec<dtypes::Op> {
+    let mut cancelled: Vec<dtypes::Op> = Vec::with_capacity(ops.len());
+    for op in ops {
+        let merged = match (cancelled.last().map(|last| last.instr), op.instr) {
+            (Some(dtypes::Instr::Add(m)), dtypes::Instr::Add(n)) => Option::Some(dtypes::Instr::Add(m.wrapping_add(n))),
+            (Some(dtypes::Instr::Move(m)), dtypes::Instr::Move(n)) if safe_moves => Option::Some(dtypes::Instr::Move(m + n)),
+            _ => Option::None,
+        };
+        match merged {
+            Some(dtypes::Instr::Add(0) | dtypes::Instr::Move(0)) => {
+                cancelled.pop();
+            },
+            // the combined instruction keeps the position of the first one
+            Some(instr) => {
+                if let Some(last) = cancelled.last_mut() {
+                    last.instr = instr;
+                }
+            },
+            None => cancelled.push(op),
+        };
+    }
+    cancelled
+}
+
+
+// the commands the op at i came from, up to where the next op starts (program
+// has len commands)
+fn span (ops: &[dtypes::Op], i: usize, len: usize) -> Range<usize> {
+    ops[i].position..ops.get(i + 1).map_or(len, |next| next.position)
+}
+
+
+// the key for the cell at offset from some data pointer, on a wrapping tape of
+// length len offsets that are a multiple of len apart are the same cell
+fn cell (offset: isize, wrap: Option<usize>) -> isize {
+    match wrap {
+        Some(len) => offset.rem_euclid(len.max(1) as isize),
+        None => offset,
+    }
+}
+
+
+// drop loops that can never run because the cell is already 0 when they are
+// reached, e.g. straight after another loop (or a Clear), or on a cell cleared
+// earlier that nothing has changed since (as far as straight-line code shows),
+// along with Clears and AddTos there (which do nothing to a cell that is 0), the
+// commands dropped are added to removed, wrap is the length of a wrapping tape
+fn remove_dead_loops (ops: Vec<dtypes::Op>, len: usize, wrap: Option<usize>, removed: &mut Vec<Range<usize>>) -> Vec<dtypes::Op> {
+    let mut live: Vec<dtypes::Op> = Vec::with_capacity(ops.len());
+    // the cells known to be 0, as offsets from where the data pointer was when
+    // they were last forgotten, and where it is now
+    let mut zeros: BTreeSet<isize> = BTreeSet::new();
+    let mut at: isize = 0;
+    let mut i = 0;
+    while i < ops.len() {
+        let zero = zeros.contains(&cell(at, wrap));
+        match ops[i].instr {
+            dtypes::Instr::JumpIfZero(_) if zero => {
+                // skip to just past the matching ]
+                let start = ops[i].position;
+                let mut depth: usize = 0;
+                while i < ops.len() {
+                    match ops[i].instr {
+                        dtypes::Instr::JumpIfZero(_) => depth += 1,
+                        dtypes::Instr::JumpIfNonZero(_) => depth -= 1,
+                        _ => {},
+                    };
+                    i += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                removed.push(start..span(&ops, i - 1, len).end);
+                continue;
+            },
+            dtypes::Instr::Clear | dtypes::Instr::AddTo(..) if zero => {
+                removed.push(span(&ops, i, len));
+                i += 1;
+                continue;
+            },
+            _ => {},
+        };
+        match ops[i].instr {
+            dtypes::Instr::Add(_) | dtypes::Instr::InputByte => {
+                zeros.remove(&cell(at, wrap));
+            },
+            dtypes::Instr::AddTo(offset, _) => {
+                zeros.remove(&cell(at + offset, wrap));
+            },
+            dtypes::Instr::Clear => {
+                zeros.insert(cell(at, wrap));
+            },
+            dtypes::Instr::Move(n) => at += n,
+            dtypes::Instr::OutputByte | dtypes::Instr::Debug => {},
+            // a loop (or scan) only leaves the cell it stopped on known, and
+            // nothing is known inside one
+            dtypes::Instr::JumpIfNonZero(_) | dtypes::Instr::ScanRight | dtypes::Instr::ScanLeft => {
+                (zeros, at) = (BTreeSet::from([0]), 0);
+            },
+            _ => (zeros, at) = (BTreeSet::new(), 0),
+        };
+        live.push(ops[i]);
+        i += 1;
+    }
+    live
+}
+
+
+// drop Adds and Clears of a cell that is cleared again before anything reads
+// it, and AddTos into one, e.g. the +++ of +++>.<[-], working back through
+// straight-line code from each Clear, the commands dropped are added to removed,
+// wrap is the length of a wrapping tape, and the walk only goes back past a move
+// if safe_moves says it can't run off the end of the tape (if it did the Clear
+// would never run, leaving the cell as it was)
+fn remove_dead_stores (ops: Vec<dtypes::Op>, len: usize, wrap: Option<usize>, safe_moves: bool, removed: &mut Vec<Range<usize>>) -> Vec<dtypes::Op> {
+    let mut dead = vec![false; ops.len()];
+    // the cells that get cleared before they're read, as offsets from where the
+    // data pointer was when they were last forgotten, and where it is (working
+    // backwards)
+    let mut doomed: BTreeSet<isize> = BTreeSet::new();
+    let mut at: isize = 0;
+    for (i, op) in ops.iter().enumerate().rev() {
+        match op.instr {
+            dtypes::Instr::Add(_) | dtypes::Instr::Clear if doomed.contains(&cell(at, wrap)) => dead[i] = true,
+            dtypes::Instr::AddTo(offset, _) if doomed.contains(&cell(at + offset, wrap)) => dead[i] = true,
+            dtypes::Instr::Add(_) => {},
+            dtypes::Instr::Clear => {
+                doomed.insert(cell(at, wrap));
+            },
+            // reads the cell (an input at the end of input can leave it as it was)
+            dtypes::Instr::AddTo(..) | dtypes::Instr::OutputByte | dtypes::Instr::InputByte => {
+                doomed.remove(&cell(at, wrap));
+            },
+            dtypes::Instr::Move(n) if safe_moves => at -= n,
+            _ => (doomed, at) = (BTreeSet::new(), 0),
+        };
+    }
+    let mut live: Vec<dtypes::Op> = Vec::with_capacity(ops.len());
+    for (i, &op) in ops.iter().enumerate() {
+        match dead[i] {
+            true => removed.push(span(&ops, i, len)),
+            false => live.push(op),
+        };
+    }
+    live
+}
+
+
+// what one trip around a loop body does to a cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CellEffect {
+    // adds a constant to whatever was there
+    Add(i32),
+    // leaves it set to a constant
+    Set(i32),
+    // depends on the value of another cell
+    Unknown,
+}
+
+
+// the effect of one trip around a loop body on each cell it changes (relative to
+// the data pointer at the start of the body), given the cells known to hold a
+// constant when it starts, or None if the body does more than move and change
+// cells or doesn't end up where it started
+fn loop_effects (body: &[dtypes::Instr], known: &BTreeMap<isize, i32>) -> Option<BTreeMap<isize, CellEffect>> {
+    let mut cells: BTreeMap<isize, CellEffect> = known.iter().map(|(&offset, &v)| (offset, CellEffect::Set(v))).collect();
+    let mut offset: isize = 0;
+    for instr in body {
+        match *instr {
+            dtypes::Instr::Add(n) => {
+                let cell = cells.entry(offset).or_insert(CellEffect::Add(0));
+                *cell = match *cell {
+                    CellEffect::Add(d) => CellEffect::Add(d.wrapping_add(n)),
+                    CellEffect::Set(v) => CellEffect::Set(v.wrapping_add(n)),
+                    CellEffect::Unknown => CellEffect::Unknown,
+                };
+            },
+            dtypes::Instr::Move(n) => offset += n,
+            dtypes::Instr::Clear => {
+                cells.insert(offset, CellEffect::Set(0));
+            },
+            // only adds a constant if the cell it multiplies holds one
+            dtypes::Instr::AddTo(to, factor) => {
+                let source = cells.get(&offset).copied().unwrap_or(CellEffect::Add(0));
+                let cell = cells.entry(offset + to).or_insert(CellEffect::Add(0));
+                *cell = match (source, *cell) {
+                    (CellEffect::Set(v), CellEffect::Add(d)) => CellEffect::Add(d.wrapping_add(v.wrapping_mul(factor))),
+                    (CellEffect::Set(v), CellEffect::Set(w)) => CellEffect::Set(w.wrapping_add(v.wrapping_mul(factor))),
+                    _ => CellEffect::Unknown,
+                };
+            },
+            _ => return Option::None,
+        };
+    }
+    (offset == 0).then_some(cells)
+}
+
+
+// replace a loop that ends up where it started and counts the cell down by one
+// each trip (once any cells it clears hold their constants) with straight-line
+// instructions, or None if it doesn't fit
+//
+// when every trip adds the same to each cell it is a copy/multiply loop, a run
+// of AddTo (each cell gets its delta times the starting value) then Clear,
+// otherwise (it clears cells, the first trip reads cells the later ones have
+// cleared, like the outer loop of ++[>++++[>++++<-]<-], or it moves further than
+// the cells it changes, like [<>-]) the first trip is kept in a loop that runs
+// once, with the AddTos for the rest after it, on a wrapping tape of length wrap
+// loops that reach as many cells as there are on it are left alone since
+// different offsets in them can be the same cell
+fn lower_loop (open: dtypes::Op, body: &[dtypes::Op], close: dtypes::Op, wrap: Option<usize>) -> Option<Vec<dtypes::Op>> {
+    let instrs: Vec<dtypes::Instr> = body.iter().map(|op| op.instr).collect();
+    let at_open = |instr| dtypes::Op { instr, position: open.position };
+    // [-] and [+] clear the cell
+    if let [dtypes::Instr::Add(1 | -1)] = instrs[..] {
+        return Option::Some(vec![at_open(dtypes::Instr::Clear)]);
+    }
+    // [>] and [<] scan for a 0, the move's position is kept so that running off
+    // the end blames it, and the loop is kept for a wrapping tape with no 0 on it
+    if let [dtypes::Instr::Move(n @ (1 | -1))] = instrs[..] {
+        let instr = if n > 0 { dtypes::Instr::ScanRight } else { dtypes::Instr::ScanLeft };
+        return Option::Some(vec![open, dtypes::Op { instr, position: body[0].position }, close]);
+    }
+    if let Some(len) = wrap {
+        let mut offset: isize = 0;
+        let (mut min, mut max) = (0, 0);
+        for instr in &instrs {
+            let touched = match *instr {
+                dtypes::Instr::Move(n) => {
+                    offset += n;
+                    offset
+                },
+                dtypes::Instr::AddTo(to, _) => offset + to,
+                _ => offset,
+            };
+            (min, max) = (min.min(touched), max.max(touched));
+        }
+        if max - min >= len as isize {
+            return Option::None;
+        }
+    }
+    let first = loop_effects(&instrs, &BTreeMap::new())?;
+    let cleared: BTreeMap<isize, i32> = first.iter()
+        .filter_map(|(&offset, &cell)| match cell {
+            CellEffect::Set(v) => Option::Some((offset, v)),
+            _ => Option::None,
+        })
+        .collect();
+    let later = loop_effects(&instrs, &cleared)?;
+    let settled = later.iter().all(|(offset, cell)| match cell {
+        CellEffect::Add(_) => true,
+        CellEffect::Set(v) => cleared.get(offset) == Option::Some(v),
+        CellEffect::Unknown => false,
+    });
+    if !settled || later.get(&0) != Option::Some(&CellEffect::Add(-1)) {
+        return Option::None;
+    }
+    let add_tos = later.iter().filter_map(|(&offset, &cell)| match cell {
+        CellEffect::Add(delta) if offset != 0 && delta != 0 => Option::Some(at_open(dtypes::Instr::AddTo(offset, delta))),
+        _ => Option::None,
+    });
+    // the AddTos only reach the cells they change, the first trip has to run
+    // the moves past them in case they run off the end of a strict tape
+    let reach = instrs.iter().scan(0, |offset, instr| {
+        if let dtypes::Instr::Move(n) = instr {
+            *offset += n;
+        }
+        Option::Some(*offset)
+    });
+    let changed = |offset: &isize| *offset == 0 || matches!(later.get(offset), Some(CellEffect::Add(delta)) if *delta != 0);
+    let covered = reach.clone().min().is_none_or(|min| changed(&min)) && reach.max().is_none_or(|max| changed(&max));
+    let mut lowered: Vec<dtypes::Op> = Vec::new();
+    if first == later && cleared.is_empty() && covered {
+        lowered.extend(add_tos);
+        lowered.push(at_open(dtypes::Instr::Clear));
+    } else {
+        lowered.push(open);
+        lowered.extend_from_slice(body);
+        lowered.extend(add_tos);
+        lowered.push(at_open(dtypes::Instr::Clear));
+        lowered.push(close);
+    }
+    Option::Some(lowered)
+}
+
+
+// replace recognized loop idioms (clear loops, scan loops, copy/multiply loops,
+// and other loops that count down by one) with dedicated instructions, inner loops first
+// so the loops around them can be too, jump targets are left for link_jumps(),
+// wrap is the length of a wrapping tape
+fn optimize_loops (ops: Vec<dtypes::Op>, wrap: Option<usize>) -> Vec<dtypes::Op> {
+    let mut optimized: Vec<dtypes::Op> = Vec::with_capacity(ops.len());
+    // indexes of the loops still open in optimized, and whether each has a loop
+    // left in it (which it can't be lowered with, so isn't worth looking at)
+    let mut open: Vec<(usize, bool)> = Vec::new();
+    for op in ops {
+        match op.instr {
+            dtypes::Instr::JumpIfZero(_) => {
+                open.push((optimized.len(), false));
+                optimized.push(op);
+            },
+            dtypes::Instr::JumpIfNonZero(_) => {
+                let lowered = match open.pop() {
+                    Some((start, false)) => lower_loop(optimized[start], &optimized[start + 1..], op, wrap).map(|lowered| (start, lowered)),
+                    _ => Option::None,
+                };
+                match lowered {
+                    Some((start, lowered)) => {
+                        optimized.truncate(start);
+                        optimized.extend(lowered);
+                    },
+                    None => optimized.push(op),
+                };
+                if let (Some(dtypes::Op { instr: dtypes::Instr::JumpIfNonZero(_), .. }), Some(outer)) = (optimized.last(), open.last_mut()) {
+                    outer.1 = true;
+                }
+            },
+            _ => optimized.push(op),
+        };
+    }
+    optimized
+}
+
+
+// the offset-addressed version of an instruction that works on the cell at the
+// data pointer, if it has one
+fn at_offset (instr: dtypes::Instr, offset: isize) -> Option<dtypes::Instr> {
+    match instr {
+        dtypes::Instr::Add(n) => Option::Some(dtypes::Instr::AddAt(offset, n)),
+        dtypes::Instr::Clear => Option::Some(dtypes::Instr::ClearAt(offset)),
+        dtypes::Instr::OutputByte => Option::Some(dtypes::Instr::OutputAt(offset)),
+        _ => Option::None,
+    }
+}
+
+
+// fuse a move to another cell, some adds, clears or outputs there, and a move
+// straight back (e.g. >+< or <<[-].>>) into offset-addressed instructions so the
+// data pointer stays put, they take the position of the first move so running
+// off the end of a strict tape blames the same command
+fn fuse_offsets (ops: Vec<dtypes::Op>) -> Vec<dtypes::Op> {
+    let mut fused: Vec<dtypes::Op> = Vec::with_capacity(ops.len());
+    let mut i = 0;
+    while i < ops.len() {
+        if let dtypes::Instr::Move(offset) = ops[i].instr {
+            let body_len = ops[i + 1..].iter()
+                .take_while(|op| at_offset(op.instr, offset).is_some())
+                .count();
+            let end = i + 1 + body_len;
+            if body_len > 0 && ops.get(end).map(|op| op.instr) == Option::Some(dtypes::Instr::Move(-offset)) {
+                let position = ops[i].position;
+                fused.extend(ops[i + 1..end].iter().filter_map(|op| {
+                    at_offset(op.instr, offset).map(|instr| dtypes::Op { instr, position })
+                }));
+                i = end + 1;
+                continue;
+            }
+        }
+        fused.push(ops[i]);
+        i += 1;
+    }
+    fused
+}
+
+
+// point every jump at its partner and every procedure definition at its end,
+// the brackets must already be balanced
+fn link_jumps (ops: &mut [dtypes::Op]) {
+    let mut open: Vec<usize> = Vec::new();
+    for i in 0..ops.len() {
+        match ops[i].instr {
+            dtypes::Instr::JumpIfZero(_) | dtypes::Instr::DefineProc(_) => open.push(i),
+            dtypes::Instr::EndProc => {
+                if let Some(j) = open.pop() {
+                    ops[j].instr = dtypes::Instr::DefineProc(i);
+                }
+            },
+            dtypes::Instr::JumpIfNonZero(_) => {
+                if let Some(j) = open.pop() {
+                    ops[i].instr = dtypes::Instr::JumpIfNonZero(j);
+                    ops[j].instr = dtypes::Instr::JumpIfZero(i);
+                }
+            },
+            _ => {},
+        };
+    }
+}
+
+
+// lower each command into exactly one instruction, unbalanced brackets are reported
+pub fn lower (program: &[dtypes::Command]) -> Result<Vec<dtypes::Op>, dtypes::BFError> {
+    parsing::build_jump_table(program)?;
+    let mut ops: Vec<dtypes::Op> = program.iter()
+        .enumerate()
+        .map(|(position, cmd)| dtypes::Op { instr: lower_command(cmd), position })
+        .collect();
+    link_jumps(&mut ops);
+    Ok(ops)
+}
+
+
+// lower commands into instructions, folding runs of +, -, >, < commands into a
+// single Add (or nothing if they cancel out) or Move, replacing clear,
+// copy/multiply and other loops that count down by one with Clear and AddTo,
+// scan loops with ScanRight and ScanLeft, dropping loops that can't run and
+// changes to cells that are cleared before they are read, and fusing moves
+// there and back into AddAt, ClearAt and OutputAt, unbalanced brackets are
+// reported, the tape is taken not to wrap
+pub fn optimize (program: &[dtypes::Command]) -> Result<Vec<dtypes::Op>, dtypes::BFError> {
+    optimize_removing(program, Option::None, false, &mut Vec::new())
+}
+
+
+// optimize() for a tape of len cells in mode with the data pointer starting on
+// cell start, on a wrapping tape different offsets from the data pointer can be
+// the same cell, and moves that cancel out are dropped where none of them can
+// run off the end (on a wrapping or bidirectional tape, or when the program is
+// proven to stay on the tape)
+pub fn optimize_for (program: &[dtypes::Command], mode: dtypes::TapeMode, len: usize, start: usize) -> Result<Vec<dtypes::Op>, dtypes::BFError> {
+    let wrap = (mode == dtypes::TapeMode::Wrapping).then_some(len);
+    let safe_moves = match mode {
+        dtypes::TapeMode::Wrapping | dtypes::TapeMode::Bidirectional => true,
+        dtypes::TapeMode::Strict | dtypes::TapeMode::Growable => analysis::check_bounds(&lower(program)?, start, len).is_ok(),
+    };
+    optimize_removing(program, wrap, safe_moves, &mut Vec::new())
+}
+
+
+// the commands optimize() drops because they can never have an effect (loops
+// that can't run and changes to cells that are cleared before they are read),
+// as ranges of indexes into program in order, neighbouring ones joined
+pub fn eliminated (program: &[dtypes::Command]) -> Result<Vec<Range<usize>>, dtypes::BFError> {
+    let mut removed: Vec<Range<usize>> = Vec::new();
+    optimize_removing(program, Option::None, false, &mut removed)?;
+    removed.sort_by_key(|range| range.start);
+    let mut joined: Vec<Range<usize>> = Vec::with_capacity(removed.len());
+    for range in removed.into_iter().filter(|range| !range.is_empty()) {
+        match joined.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => joined.push(range),
+        };
+    }
+    Ok(joined)
+}
+
+
+// optimize(), on a wrapping tape of length wrap, cancelling moves if safe_moves,
+// adding the commands dropped as dead code to removed
+fn optimize_removing (program: &[dtypes::Command], wrap: Option<usize>, safe_moves: bool, removed: &mut Vec<Range<usize>>) -> Result<Vec<dtypes::Op>, dtypes::BFError> {
+    parsing::build_jump_table(program)?;
+    let mut ops: Vec<dtypes::Op> = Vec::new();
+    let mut prev: Option<dtypes::Command> = Option::None;
+    for (position, cmd) in program.iter().enumerate() {
+        let instr = lower_command(cmd);
+        let folded = match ops.last_mut() {
+            Some(last) if prev == Option::Some(*cmd) => fold(&mut last.instr, instr),
+            _ => false,
+        };
+        if !folded {
+            ops.push(dtypes::Op { instr, position });
+        }
+        prev = Option::Some(*cmd);
+    }
+    let live = remove_dead_loops(optimize_loops(cancel_opposites(ops, safe_moves), wrap), program.len(), wrap, removed);
+    // dropping an add between others can leave them to combine
+    let mut ops = fuse_offsets(cancel_opposites(remove_dead_stores(live, program.len(), wrap, safe_moves, removed), safe_moves));
+    link_jumps(&mut ops);
+    Ok(ops)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // instructions without their positions
+    fn instrs (ops: &[dtypes::Op]) -> Vec<dtypes::Instr> {
+        ops.iter().map(|op| op.instr).collect()
+    }
+
+    #[test]
+    fn lower_one_to_one () {
+        let ops = lower(&parsing::parse_program("+-[>.]<,")).unwrap();
+        assert_eq!(instrs(&ops), vec![
+            dtypes::Instr::Add(1),
+            dtypes::Instr::Add(-1),
+            dtypes::Instr::JumpIfZero(5),
+            dtypes::Instr::Move(1),
+            dtypes::Instr::OutputByte,
+            dtypes::Instr::JumpIfNonZero(2),
+            dtypes::Instr::Move(-1),
+            dtypes::Instr::InputByte,
+        ]);
+        assert!(ops.iter().enumerate().all(|(i, op)| op.position == i));
+    }
+
+    #[test]
+    fn optimize_folds_runs () {
+        let ops = optimize(&parsing::parse_program("+++>>>--<[-..]")).unwrap();
+        assert_eq!(ops, vec![
+            dtypes::Op { instr: dtypes::Instr::Add(3), position: 0 },
+            dtypes::Op { instr: dtypes::Instr::Move(3), position: 3 },
+            dtypes::Op { instr: dtypes::Instr::Add(-2), position: 6 },
+            dtypes::Op { instr: dtypes::Instr::Move(-1), position: 8 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfZero(8), position: 9 },
+            dtypes::Op { instr: dtypes::Instr::Add(-1), position: 10 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 11 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 12 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfNonZero(4), position: 13 },
+        ]);
+    }
+
+    #[test]
+    fn optimize_does_not_fold_across_brackets () {
+        let ops = optimize(&parsing::parse_program("+[]+[[]]")).unwrap();
+        assert_eq!(instrs(&ops), vec![
+            dtypes::Instr::Add(1),
+            dtypes::Instr::JumpIfZero(2),
+            dtypes::Instr::JumpIfNonZero(1),
+            dtypes::Instr::Add(1),
+            dtypes::Instr::JumpIfZero(7),
+            dtypes::Instr::JumpIfZero(6),
+            dtypes::Instr::JumpIfNonZero(5),
+            dtypes::Instr::JumpIfNonZero(4),
+        ]);
+    }
+
+    #[test]
+    fn unbalanced_brackets_reported () {
+        let err = dtypes::BFError::UnmatchedBracket { bracket: ']', position: 1, source: None };
+        assert_eq!(lower(&parsing::parse_program("+]")), Err(err.clone()));
+        assert_eq!(optimize(&parsing::parse_program("+]")), Err(err));
+    }
+
+    #[test]
+    fn optimize_clear_loops () {
+        let ops = optimize(&parsing::parse_program("+++.[-]>[+]<,[--]")).unwrap();
+        assert_eq!(ops, vec![
+            dtypes::Op { instr: dtypes::Instr::Add(3), position: 0 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 3 },
+            dtypes::Op { instr: dtypes::Instr::Clear, position: 4 },
+            dtypes::Op { instr: dtypes::Instr::ClearAt(1), position: 7 },
+            dtypes::Op { instr: dtypes::Instr::InputByte, position: 12 },
+            // not a clear loop (it may never finish)
+            dtypes::Op { instr: dtypes::Instr::JumpIfZero(7), position: 13 },
+            dtypes::Op { instr: dtypes::Instr::Add(-2), position: 14 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfNonZero(5), position: 16 },
+        ]);
+    }
+
+    #[test]
+    fn optimize_copy_loops () {
+        // copy to the right, multiply into two cells, and a loop inside another loop
+        let ops = optimize(&parsing::parse_program("[->+<]>[<+++>>--<-]+[>[-<<+>>]<]")).unwrap();
+        assert_eq!(instrs(&ops), vec![
+            dtypes::Instr::AddTo(1, 1),
+            dtypes::Instr::Clear,
+            dtypes::Instr::Move(1),
+            dtypes::Instr::AddTo(-1, 3),
+            dtypes::Instr::AddTo(1, -2),
+            dtypes::Instr::Clear,
+            dtypes::Instr::Add(1),
+            dtypes::Instr::JumpIfZero(12),
+            dtypes::Instr::Move(1),
+            dtypes::Instr::AddTo(-2, 1),
+            dtypes::Instr::Clear,
+            dtypes::Instr::Move(-1),
+            dtypes::Instr::JumpIfNonZero(7),
+        ]);
+    }
+
+    #[test]
+    fn optimize_nested_multiply_loops () {
+        // the first trip of the outer loop runs as it is, the rest add 16 each
+        let ops = optimize(&parsing::parse_program("++[>++++[>++++<-]<-]>>.")).unwrap();
+        assert_eq!(ops, vec![
+            dtypes::Op { instr: dtypes::Instr::Add(2), position: 0 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfZero(10), position: 2 },
+            dtypes::Op { instr: dtypes::Instr::Move(1), position: 3 },
+            dtypes::Op { instr: dtypes::Instr::Add(4), position: 4 },
+            dtypes::Op { instr: dtypes::Instr::AddTo(1, 4), position: 8 },
+            dtypes::Op { instr: dtypes::Instr::Clear, position: 8 },
+            dtypes::Op { instr: dtypes::Instr::Move(-1), position: 17 },
+            dtypes::Op { instr: dtypes::Instr::Add(-1), position: 18 },
+            dtypes::Op { instr: dtypes::Instr::AddTo(2, 16), position: 2 },
+            dtypes::Op { instr: dtypes::Instr::Clear, position: 2 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfNonZero(1), position: 19 },
+            dtypes::Op { instr: dtypes::Instr::Move(2), position: 20 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 22 },
+        ]);
+        // a loop that sets a cell only does so if it runs at all (and it runs
+        // once, so its - is cleared away on a tape it can't run off)
+        let ops = optimize_for(&parsing::parse_program("[->[-]+<]"), dtypes::TapeMode::Strict, 2, 0).unwrap();
+        assert_eq!(instrs(&ops), vec![
+            dtypes::Instr::JumpIfZero(4),
+            dtypes::Instr::ClearAt(1),
+            dtypes::Instr::AddAt(1, 1),
+            dtypes::Instr::Clear,
+            dtypes::Instr::JumpIfNonZero(0),
+        ]);
+        // the number of steps doesn't depend on how many times the loops go round
+        let steps = |count: usize| {
+            let mut bfi = crate::interpreter::BFInterpreter::with_config(8, dtypes::TapeMode::Strict, dtypes::CellWidth::U16);
+            bfi.load_program(&format!("{}[>++++++++[>+++<-]>[>>+<<-]<<-]", "+".repeat(count))).unwrap();
+            bfi.run().unwrap();
+            assert_eq!(bfi.cell(4), Some(24 * count as u32));
+            bfi.steps_executed()
+        };
+        assert_eq!(steps(10), steps(1000));
+    }
+
+    #[test]
+    fn optimize_scan_loops () {
+        let ops = optimize(&parsing::parse_program("+[>]<[<][>>]")).unwrap();
+        assert_eq!(ops, vec![
+            dtypes::Op { instr: dtypes::Instr::Add(1), position: 0 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfZero(3), position: 1 },
+            dtypes::Op { instr: dtypes::Instr::ScanRight, position: 2 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfNonZero(1), position: 3 },
+            dtypes::Op { instr: dtypes::Instr::Move(-1), position: 4 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfZero(7), position: 5 },
+            dtypes::Op { instr: dtypes::Instr::ScanLeft, position: 6 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfNonZero(5), position: 7 },
+        ]);
+        // a scan that moves more than one cell at a time is left as a loop
+        let ops = optimize(&parsing::parse_program("+[>>]")).unwrap();
+        assert!(ops.iter().all(|op| !matches!(op.instr, dtypes::Instr::ScanRight)));
+    }
+
+    #[test]
+    fn optimize_leaves_other_loops () {
+        // net pointer movement, counter not decremented by one, and I/O in the body
+        for prog in ["[->+]", "[-->+<]", "[>+<+]", "[-.>+<]"] {
+            let ops = optimize(&parsing::parse_program(prog)).unwrap();
+            assert!(ops.iter().all(|op| !matches!(op.instr, dtypes::Instr::Clear | dtypes::Instr::AddTo(..))), "{}", prog);
+        }
+    }
+
+    #[test]
+    fn optimize_cancels_opposites () {
+        let ops = optimize(&parsing::parse_program("+++--.+-.>>><<<.>><.")).unwrap();
+        assert_eq!(ops, vec![
+            dtypes::Op { instr: dtypes::Instr::Add(1), position: 0 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 5 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 8 },
+            // moves are kept apart even when they cancel, the first can still
+            // run off the end of a strict tape
+            dtypes::Op { instr: dtypes::Instr::Move(3), position: 9 },
+            dtypes::Op { instr: dtypes::Instr::Move(-3), position: 12 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 15 },
+            dtypes::Op { instr: dtypes::Instr::Move(2), position: 16 },
+            dtypes::Op { instr: dtypes::Instr::Move(-1), position: 18 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 19 },
+        ]);
+        assert!(optimize(&parsing::parse_program("+-")).unwrap().is_empty());
+        assert_eq!(instrs(&optimize(&parsing::parse_program("+-<>")).unwrap()), vec![dtypes::Instr::Move(-1), dtypes::Instr::Move(1)]);
+        // they do cancel where none of them can run off the end of the tape
+        let cmds = parsing::parse_program("+.>>><<<.>><.<>");
+        let cancelled = vec![dtypes::Instr::Add(1), dtypes::Instr::OutputByte, dtypes::Instr::OutputByte, dtypes::Instr::Move(1), dtypes::Instr::OutputByte];
+        for mode in [dtypes::TapeMode::Wrapping, dtypes::TapeMode::Bidirectional] {
+            assert_eq!(instrs(&optimize_for(&cmds, mode, 2, 0).unwrap()), cancelled, "{:?}", mode);
+        }
+        // on a strict (or growable) tape only once the program is proven to stay on it
+        assert_eq!(instrs(&optimize_for(&cmds, dtypes::TapeMode::Strict, 4, 0).unwrap()), cancelled);
+        assert_eq!(instrs(&optimize_for(&cmds, dtypes::TapeMode::Growable, 4, 0).unwrap()), cancelled);
+        assert_eq!(optimize_for(&cmds, dtypes::TapeMode::Strict, 3, 0).unwrap(), optimize(&cmds).unwrap());
+        assert_eq!(optimize_for(&cmds, dtypes::TapeMode::Growable, 4, 1).unwrap().len(), optimize(&cmds).unwrap().len());
+    }
+
+    #[test]
+    fn optimize_removes_dead_loops () {
+        // loops straight after a loop or clear never run
+        let ops = optimize(&parsing::parse_program("+[-.][.[,]][-][->+<]>+<")).unwrap();
+        assert_eq!(instrs(&ops), vec![
+            dtypes::Instr::Add(1),
+            dtypes::Instr::JumpIfZero(4),
+            dtypes::Instr::Add(-1),
+            dtypes::Instr::OutputByte,
+            dtypes::Instr::JumpIfNonZero(1),
+            dtypes::Instr::AddAt(1, 1),
+        ]);
+        // but not once something else has happened in between
+        let ops = optimize(&parsing::parse_program("[-]+[.]")).unwrap();
+        assert_eq!(instrs(&ops), vec![
+            dtypes::Instr::Clear,
+            dtypes::Instr::Add(1),
+            dtypes::Instr::JumpIfZero(4),
+            dtypes::Instr::OutputByte,
+            dtypes::Instr::JumpIfNonZero(2),
+        ]);
+        // a cleared cell is still known to be 0 after moving away and back, one
+        // that has been added to isn't
+        let ops = optimize(&parsing::parse_program("[-]>+.<[.]>[.]")).unwrap();
+        assert_eq!(instrs(&ops), vec![
+            dtypes::Instr::Clear,
+            dtypes::Instr::AddAt(1, 1),
+            dtypes::Instr::OutputAt(1),
+            dtypes::Instr::Move(1),
+            dtypes::Instr::JumpIfZero(6),
+            dtypes::Instr::OutputByte,
+            dtypes::Instr::JumpIfNonZero(4),
+        ]);
+    }
+
+    #[test]
+    fn optimize_removes_dead_stores () {
+        // changes to a cell that is cleared before anything reads it
+        let cmds = parsing::parse_program("+++>.<[-]");
+        let ops = optimize_for(&cmds, dtypes::TapeMode::Strict, 2, 0).unwrap();
+        assert_eq!(ops, vec![
+            dtypes::Op { instr: dtypes::Instr::OutputAt(1), position: 3 },
+            dtypes::Op { instr: dtypes::Instr::Clear, position: 6 },
+        ]);
+        // unless a move in between might run off the end of the tape, the clear
+        // would never happen then
+        assert_eq!(instrs(&optimize(&cmds).unwrap())[0], dtypes::Instr::Add(3));
+        assert_eq!(instrs(&optimize_for(&cmds, dtypes::TapeMode::Strict, 1, 0).unwrap())[0], dtypes::Instr::Add(3));
+        // copying into one, the moves left behind cancel around the clear
+        let ops = optimize_for(&parsing::parse_program(">++<[->+<]>[-]"), dtypes::TapeMode::Strict, 2, 0).unwrap();
+        assert_eq!(ops, vec![
+            dtypes::Op { instr: dtypes::Instr::Clear, position: 4 },
+            dtypes::Op { instr: dtypes::Instr::Move(1), position: 10 },
+            dtypes::Op { instr: dtypes::Instr::Clear, position: 11 },
+        ]);
+        assert_eq!(instrs(&optimize(&parsing::parse_program("[-]+[-]")).unwrap()), vec![dtypes::Instr::Clear]);
+        // but not ones that are read (or might be kept by input at the end) first
+        let ops = optimize(&parsing::parse_program("+>+<[-.]+.[-]+,[-]")).unwrap();
+        assert_eq!(instrs(&ops)[..2], [dtypes::Instr::Add(1), dtypes::Instr::AddAt(1, 1)]);
+        assert_eq!(instrs(&ops)[6..], [
+            dtypes::Instr::Add(1),
+            dtypes::Instr::OutputByte,
+            dtypes::Instr::Clear,
+            dtypes::Instr::Add(1),
+            dtypes::Instr::InputByte,
+            dtypes::Instr::Clear,
+        ]);
+        // the - before the << that underruns the tape is still there when it fails
+        let prog = "-.+,.-<<-++>,>[-]>,-,[,.->->>>]+[<],[>+[>[,+,<],...<,<.>],[-+.],,>-<[[<<+[-.+,<..<<,<++].<<+-++>]-<][[[>+++<]>[,->-.]]]]";
+        let mut outcomes = Vec::new();
+        for optimize in [false, true] {
+            let mut bfi = crate::interpreter::BFInterpreter::with_config(8, dtypes::TapeMode::Strict, dtypes::CellWidth::U8);
+            bfi.set_optimize(optimize);
+            bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+            outcomes.push(crate::crossval::run_engine("engine", &mut bfi, prog, Some(b"xyz")));
+        }
+        assert!(outcomes[0].result.is_err());
+        assert_eq!(crate::crossval::compare(&outcomes[0], &outcomes[1]), None);
+    }
+
+    #[test]
+    fn optimize_eliminated () {
+        // the +++ stays, the > before its clear could run off a strict tape
+        let cmds = parsing::parse_program("+++>.<[-][.[-]]>>++[-]<<.");
+        assert_eq!(eliminated(&cmds), Ok(vec![9..15, 17..19]));
+        // neighbouring ones are joined
+        assert_eq!(eliminated(&parsing::parse_program(">[-]+[-][.]")), Ok(vec![1..5, 8..11]));
+        assert_eq!(eliminated(&parsing::parse_program("+[-.]>,.")), Ok(vec![]));
+        assert!(eliminated(&parsing::parse_program("[")).is_err());
+    }
+
+    #[test]
+    fn optimize_fuses_offsets () {
+        let ops = optimize(&parsing::parse_program("+>+<.<<[-]--.>>[>+<-<.>]")).unwrap();
+        assert_eq!(ops, vec![
+            dtypes::Op { instr: dtypes::Instr::Add(1), position: 0 },
+            dtypes::Op { instr: dtypes::Instr::AddAt(1, 1), position: 1 },
+            dtypes::Op { instr: dtypes::Instr::OutputByte, position: 4 },
+            // the clear loop takes the position of the move too
+            dtypes::Op { instr: dtypes::Instr::ClearAt(-2), position: 5 },
+            dtypes::Op { instr: dtypes::Instr::AddAt(-2, -2), position: 5 },
+            dtypes::Op { instr: dtypes::Instr::OutputAt(-2), position: 5 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfZero(10), position: 15 },
+            dtypes::Op { instr: dtypes::Instr::AddAt(1, 1), position: 16 },
+            dtypes::Op { instr: dtypes::Instr::Add(-1), position: 19 },
+            dtypes::Op { instr: dtypes::Instr::OutputAt(-1), position: 20 },
+            dtypes::Op { instr: dtypes::Instr::JumpIfNonZero(6), position: 23 },
+        ]);
+        // moves that don't come straight back are left alone
+        let ops = optimize(&parsing::parse_program(">+>-<<<.")).unwrap();
+        assert!(ops.iter().all(|op| !matches!(op.instr, dtypes::Instr::AddAt(..) | dtypes::Instr::OutputAt(_))));
+        // running off the end of a strict tape fails the same way as the moves
+        for prog in ["+>>.<<", "<+>", "+>+<>>>[-]<<<", "<>+++.", "+>>><<<.", "+[<>-].", ">><<", "+>+-<<>>>[-]<<<"] {
+            let mut outcomes = Vec::new();
+            for optimize in [false, true] {
+                let mut bfi = crate::interpreter::BFInterpreter::with_config(2, dtypes::TapeMode::Strict, dtypes::CellWidth::U8);
+                bfi.set_optimize(optimize);
+                outcomes.push(crate::crossval::run_engine("engine", &mut bfi, prog, None));
+            }
+            assert!(outcomes[0].result.is_err(), "{}", prog);
+            assert_eq!(crate::crossval::compare(&outcomes[0], &outcomes[1]), None, "{}", prog);
+        }
+    }
+
+    #[test]
+    fn optimize_matches_unoptimized () {
+        // programs that use the cancelled and dead code, and random ones, behave the
+        // same (output, memory and data pointer) optimized or not
+        let mut corpus: Vec<String> = [
+            "++++++++[>++++++++<-]>+.+-.><.[-][.].",
+            "+++[>+++<-][>>+<<-]>[<+>-]<.>>+<<<>>+--+-.",
+            ",[.,]+-[-]",
+            "++>+++++[<+>-]++++++++[<++++++>-]<.",
+            "+[[>+<-]>[<+>-]<-]>>><<<+.",
+            "++++[>++++[>+>+<<-]<-]>>[>]>[<+<+>>-]<<.",
+            "+++++[>+<<[-]-.>-]>.<<<++.>>>[-]<+",
+            "++[>++++[>++++<-]<-]>>.<<+++[->[-]+>+++[>+<-]>[<+>-]<<<]>>.",
+        ].iter().map(|prog| prog.to_string()).collect();
+        let config = crate::generator::Config { len: 60, max_depth: 3, io: true };
+        corpus.extend((0..100).map(|seed| crate::generator::generate(seed, &config)));
+        for prog in &corpus {
+            let mut outcomes = Vec::new();
+            for optimize in [false, true] {
+                let mut bfi = crate::interpreter::BFInterpreter::with_config(16, dtypes::TapeMode::Bidirectional, dtypes::CellWidth::U8);
+                bfi.set_optimize(optimize);
+                bfi.set_eof_behavior(dtypes::EofBehavior::Zero);
+                bfi.set_max_steps(Some(10000));
+                outcomes.push(crate::crossval::run_engine("engine", &mut bfi, prog, Some(b"input")));
+            }
+            assert_eq!(crate::crossval::compare(&outcomes[0], &outcomes[1]), None, "{}", prog);
+        }
+    }
+
+    #[test]
+    fn optimize_tiny_wrapping_tapes () {
+        // different offsets are the same cell on a wrapping tape this short, so
+        // cells cleared or written at one offset can't be tracked apart from another
+        let cases = [
+            // program, tape length, cell width, input, output
+            ("[]>+<[>>>[+>,+.>++<,.>]]", 1, dtypes::CellWidth::U16, vec![3, 1, 2, 0], vec![4, 1, 3, 0]),
+            ("[--<.]++>.,>.--<<[+]", 1, dtypes::CellWidth::U8, vec![3], vec![2, 3]),
+            ("-<+>>.<,-><<+-+[-]>", 2, dtypes::CellWidth::U8, vec![], vec![1]),
+        ];
+        for (prog, size, width, input, expected) in cases {
+            let mut outcomes = Vec::new();
+            for optimize in [false, true] {
+                let mut bfi = crate::interpreter::BFInterpreter::with_config(size, dtypes::TapeMode::Wrapping, width);
+                bfi.set_optimize(optimize);
+                bfi.set_max_steps(Some(1000));
+                outcomes.push(crate::crossval::run_engine("engine", &mut bfi, prog, Some(&input)));
+            }
+            assert_eq!(crate::crossval::compare(&outcomes[0], &outcomes[1]), None, "{}", prog);
+            assert_eq!(outcomes[1].output, expected, "{}", prog);
+        }
+        // a copy loop onto the cell it counts down never ends, so it is only
+        // lowered once the tape is long enough for them to be different cells
+        let cmds = parsing::parse_program("+[->+<]");
+        let lowered = |len| instrs(&optimize_for(&cmds, dtypes::TapeMode::Wrapping, len, 0).unwrap()).contains(&dtypes::Instr::AddTo(1, 1));
+        assert!(!lowered(1));
+        assert!(lowered(2));
+    }
+}
@@ -32,6 +32,424 @@ pub fn byte_to_command (byte: u8) -> Option<dtypes::Command> {
 }
 
 
+// a command found in program source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub command: dtypes::Command,
+    // where the command starts
+    pub pos: dtypes::SourcePos,
+}
+
+
+// how each command is spelled in a dialect, a space stands for any run of whitespace
+fn spellings (dialect: dtypes::Dialect) -> Vec<(String, dtypes::Command)> {
+    // Ook! style dialects spell each command with two words that differ only in
+    // their punctuation
+    let word_pairs = |word: &str| {
+        [
+            (".", "?", dtypes::Command::MovePointerRight),
+            ("?", ".", dtypes::Command::MovePointerLeft),
+            (".", ".", dtypes::Command::IncrementByte),
+            ("!", "!", dtypes::Command::DecrementByte),
+            ("!", ".", dtypes::Command::OutputByte),
+            (".", "!", dtypes::Command::InputByte),
+            ("!", "?", dtypes::Command::JumpRightIfZero),
+            ("?", "!", dtypes::Command::JumpLeftIfNonZero),
+        ].into_iter()
+            .map(|(a, b, cmd)| (format!("{}{} {}{}", word, a, word, b), cmd))
+            .collect()
+    };
+    match dialect {
+        dtypes::Dialect::Brainfuck => {
+            b"+,-.<>[]".iter()
+                .filter_map(|&byte| byte_to_command(byte).map(|cmd| ((byte as char).to_string(), cmd)))
+                .collect()
+        },
+        dtypes::Dialect::Extended => {
+            let mut spellings = spellings(dtypes::Dialect::Brainfuck);
+            spellings.extend([
+                ("@", dtypes::Command::EndProgram),
+                ("$", dtypes::Command::StoreByte),
+                ("!", dtypes::Command::RetrieveByte),
+                ("}", dtypes::Command::ShiftRight),
+                ("{", dtypes::Command::ShiftLeft),
+                ("~", dtypes::Command::BitwiseNot),
+                ("^", dtypes::Command::BitwiseXor),
+                ("&", dtypes::Command::BitwiseAnd),
+                ("|", dtypes::Command::BitwiseOr),
+            ].map(|(token, cmd)| (String::from(token), cmd)));
+            spellings
+        },
+        dtypes::Dialect::Pbrain => {
+            let mut spellings = spellings(dtypes::Dialect::Brainfuck);
+            spellings.extend([
+                ("(", dtypes::Command::DefineProcedure),
+                (")", dtypes::Command::EndProcedure),
+                (":", dtypes::Command::CallProcedure),
+            ].map(|(token, cmd)| (String::from(token), cmd)));
+            spellings
+        },
+        dtypes::Dialect::Brainfork => {
+            let mut spellings = spellings(dtypes::Dialect::Brainfuck);
+            spellings.push((String::from("Y"), dtypes::Command::Fork));
+            spellings
+        },
+        dtypes::Dialect::Ook => word_pairs("Ook"),
+        dtypes::Dialect::Blub => word_pairs("Blub"),
+    }
+}
+
+
+// length in bytes of the token at the start of src, or None if src doesn't
+// start with it
+fn match_token (src: &str, token: &str) -> Option<usize> {
+    let mut rest = src;
+    for (i, part) in token.split(' ').enumerate() {
+        if i > 0 {
+            let trimmed = rest.trim_start();
+            if trimmed.len() == rest.len() {
+                return Option::None;
+            }
+            rest = trimmed;
+        }
+        rest = rest.strip_prefix(part)?;
+    }
+    Option::Some(src.len() - rest.len())
+}
+
+
+// split program source in a dialect into commands with their line and column,
+// anything that isn't a command is a comment, columns count characters not bytes
+pub fn tokenize (prog: &str, dialect: dtypes::Dialect) -> Vec<Token> {
+    tokenize_with_debug(prog, dialect, false)
+}
+
+
+// like tokenize() but with `#` as the Debug command rather than a comment if
+// hash_debug is set
+pub fn tokenize_with_debug (prog: &str, dialect: dtypes::Dialect, hash_debug: bool) -> Vec<Token> {
+    let mut spellings = spellings(dialect);
+    if hash_debug {
+        spellings.push((String::from("#"), dtypes::Command::Debug));
+    }
+    tokenize_spellings(prog, &spellings)
+}
+
+
+// split program source into commands spelled any of the ways given, the first
+// spelling that matches wins
+fn tokenize_spellings (prog: &str, spellings: &[(String, dtypes::Command)]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut pos = dtypes::SourcePos { line: 1, column: 1 };
+    let mut rest = prog;
+    while let Some(c) = rest.chars().next() {
+        let matched = spellings.iter().find_map(|(token, command)| {
+            match_token(rest, token).map(|len| (len, *command))
+        });
+        let len = match matched {
+            Some((len, command)) => {
+                tokens.push(Token { command, pos });
+                len
+            },
+            None => c.len_utf8(),
+        };
+        for c in rest[..len].chars() {
+            if c == '\n' {
+                pos = dtypes::SourcePos { line: pos.line + 1, column: 1 };
+            } else {
+                pos.column += 1;
+            }
+        }
+        rest = &rest[len..];
+    }
+    tokens
+}
+
+
+// the words a trivial substitution of brainfuck spells its eight commands
+// with, for running such dialects without a built in one for each
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    // longest first, so a token isn't matched in place of a longer one it starts
+    spellings: Vec<(String, dtypes::Command)>,
+}
+
+
+impl Mapping {
+    // mapping from entries like `+=up` separated by commas or newlines, a space
+    // in a token stands for any run of whitespace as in the built in dialects,
+    // a command that isn't given can't be written
+    pub fn parse (spec: &str) -> Result<Mapping, String> {
+        let mut spellings: Vec<(String, dtypes::Command)> = Vec::new();
+        for line in spec.lines() {
+            let mut rest = line.trim_start();
+            while let Some(key) = rest.chars().next() {
+                let after = rest[key.len_utf8()..].strip_prefix('=')
+                    .ok_or_else(|| format!("expected '=' after '{}' in mapping", key))?;
+                let (token, next) = after.split_once(',').unwrap_or((after, ""));
+                let command = u8::try_from(key).ok().and_then(byte_to_command)
+                    .ok_or_else(|| format!("'{}' in mapping is not a brainfuck command", key))?;
+                let token = token.split_whitespace().collect::<Vec<&str>>().join(" ");
+                if token.is_empty() {
+                    return Err(format!("no token given for '{}' in mapping", key));
+                }
+                if spellings.iter().any(|&(_, cmd)| cmd == command) {
+                    return Err(format!("'{}' is mapped more than once", key));
+                }
+                if spellings.iter().any(|(other, _)| *other == token) {
+                    return Err(format!("'{}' is mapped to more than one command", token));
+                }
+                spellings.push((token, command));
+                rest = next.trim_start();
+            }
+        }
+        if spellings.is_empty() {
+            return Err(String::from("no commands mapped"));
+        }
+        spellings.sort_by_key(|(token, _)| std::cmp::Reverse(token.len()));
+        Ok(Mapping { spellings })
+    }
+
+    // like tokenize() but with the commands spelled by the mapping
+    pub fn tokenize (&self, prog: &str) -> Vec<Token> {
+        tokenize_spellings(prog, &self.spellings)
+    }
+}
+
+
+// parse program source in a dialect into a vector of commands, dropping
+// anything that isn't a command (i.e. comments)
+pub fn parse_dialect (prog: &str, dialect: dtypes::Dialect) -> Vec<dtypes::Command> {
+    tokenize(prog, dialect).into_iter().map(|token| token.command).collect()
+}
+
+
+// number of characters of program source in a dialect that are comments,
+// i.e. neither part of a command nor whitespace
+pub fn comment_chars (prog: &str, dialect: dtypes::Dialect) -> usize {
+    let spellings = spellings(dialect);
+    let mut count = 0;
+    let mut rest = prog;
+    while let Some(c) = rest.chars().next() {
+        match spellings.iter().find_map(|(token, _)| match_token(rest, token)) {
+            Some(len) => rest = &rest[len..],
+            None => {
+                count += !c.is_whitespace() as usize;
+                rest = &rest[c.len_utf8()..];
+            },
+        };
+    }
+    count
+}
+
+
+// parse brainfuck source into a vector of commands, dropping any
+// bytes that are not recognized commands (i.e. comments)
+pub fn parse_program (prog: &str) -> Vec<dtypes::Command> {
+    parse_dialect(prog, dtypes::Dialect::Brainfuck)
+}
+
+
+// line and column of every command in brainfuck source, indexed the same way
+// as the commands returned by parse_program()
+pub fn command_positions (prog: &str) -> Vec<dtypes::SourcePos> {
+    tokenize(prog, dtypes::Dialect::Brainfuck).into_iter().map(|token| token.pos).collect()
+}
+
+
+// the source line a command is on with a caret under its column, for showing
+// where an error came from:
+//
+//     2 |   -]]
+//       |     ^
+pub fn source_snippet (prog: &str, pos: dtypes::SourcePos) -> String {
+    let line = prog.lines().nth(pos.line.saturating_sub(1)).unwrap_or("");
+    // keep tabs so the caret lines up however they are displayed
+    let pad: String = line.chars()
+        .take(pos.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let gutter = pos.line.to_string().len();
+    format!("{:>w$} | {}\n{:>w$} | {}^", pos.line, line, "", pad, w = gutter)
+}
+
+
+// split program source at the first `!` outside of any loop, returning the program
+// and the input embedded after the `!` (None if there isn't one)
+pub fn split_program_input (prog: &str) -> (&str, Option<&str>) {
+    let mut depth: usize = 0;
+    for (i, byte) in prog.bytes().enumerate() {
+        match byte {
+            b'[' => depth += 1,
+            b']' => depth = depth.saturating_sub(1),
+            b'!' if depth == 0 => return (&prog[..i], Option::Some(&prog[i + 1..])),
+            _ => {},
+        };
+    }
+    (prog, Option::None)
+}
+
+
+// find the `#` debug characters in program source, returned as the index of
+// the command that follows each one (i.e. where execution should pause)
+pub fn find_debug_marks (prog: &str) -> Vec<usize> {
+    let mut marks: Vec<usize> = Vec::new();
+    let mut n_cmds: usize = 0;
+    for byte in prog.bytes() {
+        if byte == b'#' {
+            marks.push(n_cmds);
+        } else if byte_to_command(byte).is_some() {
+            n_cmds += 1;
+        }
+    }
+    marks
+}
+
+
+// the opening bracket for a closing one ([ for ], ( for pbrain's ))
+fn opener (cmd: dtypes::Command) -> Option<dtypes::Command> {
+    match cmd {
+        dtypes::Command::JumpLeftIfNonZero => Option::Some(dtypes::Command::JumpRightIfZero),
+        dtypes::Command::EndProcedure => Option::Some(dtypes::Command::DefineProcedure),
+        _ => Option::None,
+    }
+}
+
+
+// a loop (or pbrain procedure) whose brackets have been matched up, as the
+// indices of its opening and closing commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopFrame {
+    pub start: usize,
+    pub end: usize,
+}
+
+
+// the brackets still waiting on their partner while going through a program
+// in order, a closing bracket only matches the innermost one of its own kind
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlStack {
+    // (index, command) of each open bracket, innermost last
+    open: Vec<(usize, dtypes::Command)>,
+}
+
+
+impl ControlStack {
+    pub fn new () -> ControlStack {
+        ControlStack::default()
+    }
+
+    // an opening bracket ([ or pbrain's () at index
+    pub fn open (&mut self, index: usize, cmd: dtypes::Command) {
+        self.open.push((index, cmd));
+    }
+
+    // a closing bracket (] or pbrain's )) at index, the frame it closes if it
+    // matches the innermost open bracket, otherwise None and nothing changes
+    pub fn close (&mut self, index: usize, cmd: dtypes::Command) -> Option<LoopFrame> {
+        match self.open.last() {
+            Some(&(start, open)) if Option::Some(open) == opener(cmd) => {
+                self.open.pop();
+                Option::Some(LoopFrame { start, end: index })
+            },
+            _ => Option::None,
+        }
+    }
+
+    // number of brackets still open
+    pub fn depth (&self) -> usize {
+        self.open.len()
+    }
+
+    // index of the innermost bracket still open
+    pub fn innermost (&self) -> Option<usize> {
+        self.open.last().map(|&(index, _)| index)
+    }
+
+    // indices of the brackets still open, outermost first
+    pub fn unclosed (&self) -> impl Iterator<Item = usize> + '_ {
+        self.open.iter().map(|&(index, _)| index)
+    }
+}
+
+
+// every unmatched bracket in a parsed program in the order they appear, unlike
+// build_jump_table() this doesn't stop at the first one
+pub fn find_bracket_errors (program: &[dtypes::Command]) -> Vec<dtypes::BFError> {
+    // (position, bracket) for each bracket without a partner
+    let mut unmatched: Vec<(usize, char)> = Vec::new();
+    let mut stack = ControlStack::new();
+    for (i, &cmd) in program.iter().enumerate() {
+        match cmd {
+            dtypes::Command::JumpRightIfZero | dtypes::Command::DefineProcedure => stack.open(i, cmd),
+            // closing brackets that match are taken off the stack by the guard
+            dtypes::Command::JumpLeftIfNonZero | dtypes::Command::EndProcedure if stack.close(i, cmd).is_none() => {
+                unmatched.push((i, cmd.as_char()));
+            },
+            _ => {},
+        };
+    }
+    unmatched.extend(stack.unclosed().map(|j| (j, program[j].as_char())));
+    unmatched.sort();
+    unmatched.into_iter()
+        .map(|(position, bracket)| dtypes::BFError::UnmatchedBracket { bracket, position, source: Option::None })
+        .collect()
+}
+
+
+// check that no loop or procedure in a parsed program is nested more than limit
+// deep, pointing the error at the first bracket that goes too deep
+pub fn check_depth (program: &[dtypes::Command], limit: usize) -> Result<(), dtypes::BFError> {
+    let mut depth: usize = 0;
+    for (position, &cmd) in program.iter().enumerate() {
+        match cmd {
+            dtypes::Command::JumpRightIfZero | dtypes::Command::DefineProcedure => {
+                depth += 1;
+                if depth > limit {
+                    return Err(dtypes::BFError::LoopDepthExceeded { position, source: Option::None, limit });
+                }
+            },
+            dtypes::Command::JumpLeftIfNonZero | dtypes::Command::EndProcedure => depth = depth.saturating_sub(1),
+            _ => {},
+        };
+    }
+    Ok(())
+}
+
+
+// match up the brackets in a parsed program, the returned table has an entry for
+// every command: the index of the matching bracket for [ ] ( and ), 0 for anything else
+pub fn build_jump_table (program: &[dtypes::Command]) -> Result<Vec<usize>, dtypes::BFError> {
+    let mut jump_table: Vec<usize> = vec![0; program.len()];
+    let mut stack = ControlStack::new();
+    for (i, &cmd) in program.iter().enumerate() {
+        match cmd {
+            dtypes::Command::JumpRightIfZero | dtypes::Command::DefineProcedure => stack.open(i, cmd),
+            dtypes::Command::JumpLeftIfNonZero | dtypes::Command::EndProcedure => {
+                match stack.close(i, cmd) {
+                    Some(frame) => {
+                        jump_table[frame.start] = frame.end;
+                        jump_table[frame.end] = frame.start;
+                    },
+                    None => {
+                        let bracket = cmd.as_char();
+                        return Err(dtypes::BFError::UnmatchedBracket { bracket, position: i, source: Option::None });
+                    },
+                };
+            },
+            _ => {},
+        };
+    }
+    // anything left over never found its partner
+    match stack.innermost() {
+        Some(j) => {
+            Err(dtypes::BFError::UnmatchedBracket { bracket: program[j].as_char(), position: j, source: Option::None })
+        },
+        None => Ok(jump_table),
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
 
@@ -41,4 +459,169 @@ mod tests {
     fn no_tests_implemented () {
         assert!(false, "no tests implemented");
     }
+
+    #[test]
+    fn parse_program_strips_comments () {
+        let cmds = parse_program("+ comment -\n>< .,[]");
+        assert_eq!(cmds, vec![
+            dtypes::Command::IncrementByte,
+            dtypes::Command::DecrementByte,
+            dtypes::Command::MovePointerRight,
+            dtypes::Command::MovePointerLeft,
+            dtypes::Command::OutputByte,
+            dtypes::Command::InputByte,
+            dtypes::Command::JumpRightIfZero,
+            dtypes::Command::JumpLeftIfNonZero,
+        ]);
+        assert!(parse_program("no commands here").is_empty());
+    }
+
+    #[test]
+    fn control_stack_frames () {
+        use dtypes::Command::{DefineProcedure, EndProcedure, JumpLeftIfNonZero, JumpRightIfZero};
+        let mut stack = ControlStack::new();
+        assert_eq!(stack.close(0, JumpLeftIfNonZero), None);
+        stack.open(1, JumpRightIfZero);
+        stack.open(2, DefineProcedure);
+        stack.open(3, JumpRightIfZero);
+        assert_eq!(stack.depth(), 3);
+        assert_eq!(stack.close(4, JumpLeftIfNonZero), Some(LoopFrame { start: 3, end: 4 }));
+        // a ] can't close a ( and leaves it open
+        assert_eq!(stack.close(5, JumpLeftIfNonZero), None);
+        assert_eq!(stack.innermost(), Some(2));
+        assert_eq!(stack.close(6, EndProcedure), Some(LoopFrame { start: 2, end: 6 }));
+        assert_eq!(stack.unclosed().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(stack.close(7, JumpLeftIfNonZero), Some(LoopFrame { start: 1, end: 7 }));
+        assert_eq!(stack.depth(), 0);
+        assert_eq!(stack.innermost(), None);
+    }
+
+    #[test]
+    fn build_jump_table_matches_brackets () {
+        let table = build_jump_table(&parse_program("+[>[-]<]")).unwrap();
+        assert_eq!(table, vec![0, 7, 0, 5, 0, 3, 0, 1]);
+        assert!(build_jump_table(&parse_program("")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn build_jump_table_unbalanced () {
+        assert_eq!(build_jump_table(&parse_program("+]")),
+                   Err(dtypes::BFError::UnmatchedBracket { bracket: ']', position: 1, source: None }));
+        assert_eq!(build_jump_table(&parse_program("[[]")),
+                   Err(dtypes::BFError::UnmatchedBracket { bracket: '[', position: 0, source: None }));
+    }
+
+    #[test]
+    fn build_jump_table_procedures () {
+        let pbrain = |prog| parse_dialect(prog, dtypes::Dialect::Pbrain);
+        assert_eq!(build_jump_table(&pbrain("+(-[]):")).unwrap(), vec![0, 5, 0, 4, 3, 1, 0]);
+        // brackets and parentheses can't cross
+        assert_eq!(build_jump_table(&pbrain("([)]")),
+                   Err(dtypes::BFError::UnmatchedBracket { bracket: ')', position: 2, source: None }));
+        assert_eq!(find_bracket_errors(&pbrain("([)]")), vec![
+            dtypes::BFError::UnmatchedBracket { bracket: '(', position: 0, source: None },
+            dtypes::BFError::UnmatchedBracket { bracket: ')', position: 2, source: None },
+        ]);
+        assert_eq!(build_jump_table(&pbrain("((")),
+                   Err(dtypes::BFError::UnmatchedBracket { bracket: '(', position: 1, source: None }));
+    }
+
+    #[test]
+    fn find_debug_marks_positions () {
+        assert_eq!(find_debug_marks("#+ comment +#[-]#"), vec![0, 2, 5]);
+        assert!(find_debug_marks("+[-]").is_empty());
+    }
+
+    #[test]
+    fn tokenize_hash_debug () {
+        // # is a comment unless asked for
+        assert_eq!(tokenize("+#-", dtypes::Dialect::Brainfuck).len(), 2);
+        let tokens = tokenize_with_debug("+#-", dtypes::Dialect::Brainfuck, true);
+        assert_eq!(tokens[1], Token { command: dtypes::Command::Debug, pos: dtypes::SourcePos { line: 1, column: 2 } });
+        let tokens = tokenize_with_debug("Ook. Ook. # Ook! Ook!", dtypes::Dialect::Ook, true);
+        assert_eq!(tokens.iter().map(|token| token.command).collect::<Vec<_>>(),
+                   vec![dtypes::Command::IncrementByte, dtypes::Command::Debug, dtypes::Command::DecrementByte]);
+    }
+
+    #[test]
+    fn split_program_input_at_bang () {
+        assert_eq!(split_program_input(",[.,]!hello\n"), (",[.,]", Some("hello\n")));
+        // only the first ! counts, and not inside a loop
+        assert_eq!(split_program_input("+[!-]>!a!b"), ("+[!-]>", Some("a!b")));
+        assert_eq!(split_program_input(",[.,]!"), (",[.,]", Some("")));
+        assert_eq!(split_program_input("+++."), ("+++.", None));
+    }
+
+    #[test]
+    fn command_positions_lines_and_columns () {
+        let positions = command_positions("+ a\n  [-]\n\u{e9}.");
+        let pos = |line, column| dtypes::SourcePos { line, column };
+        assert_eq!(positions, vec![pos(1, 1), pos(2, 3), pos(2, 4), pos(2, 5), pos(3, 2)]);
+        assert_eq!(positions.len(), parse_program("+ a\n  [-]\n\u{e9}.").len());
+    }
+
+    #[test]
+    fn source_snippet_caret () {
+        let prog = "+[\n  -]]\n";
+        let pos = command_positions(prog)[4];
+        assert_eq!(source_snippet(prog, pos), "2 |   -]]\n  |     ^");
+        assert_eq!(source_snippet("\t+", command_positions("\t+")[0]), "1 | \t+\n  | \t^");
+    }
+
+    #[test]
+    fn tokenize_dialects () {
+        let ook = "Ook. Ook. Ook! Ook! Ook! Ook?\nOok. Ook?   Ook? Ook.\nOok. Ook! Ook? Ook! Ook! Ook.";
+        assert_eq!(parse_dialect(ook, dtypes::Dialect::Ook), parse_program("+-[><,]."));
+        let tokens = tokenize(ook, dtypes::Dialect::Ook);
+        let pos = |line, column| dtypes::SourcePos { line, column };
+        assert_eq!(tokens[4], Token { command: dtypes::Command::MovePointerLeft, pos: pos(2, 13) });
+        assert_eq!(tokens[7].pos, pos(3, 21));
+        // the words of a command can be split over lines
+        assert_eq!(parse_dialect("Ook.\nOok.", dtypes::Dialect::Ook), parse_program("+"));
+        let blub = "Blub. Blub. Blub! Blub.";
+        assert_eq!(parse_dialect(blub, dtypes::Dialect::Blub), parse_program("+."));
+        // other dialects' spellings are comments
+        assert!(parse_dialect(blub, dtypes::Dialect::Ook).is_empty());
+        assert_eq!(parse_dialect("Ook? Ook! +", dtypes::Dialect::Brainfuck), parse_program("+"));
+        // extensions are comments unless asked for
+        assert_eq!(parse_program("+@$!}{~^&|"), parse_program("+"));
+        let ext = parse_dialect("+@ $!}{~^&| x", dtypes::Dialect::Extended);
+        assert_eq!(ext.len(), 10);
+        assert_eq!(ext.iter().map(|cmd| cmd.to_string()).collect::<String>(), "+@$!}{~^&|");
+    }
+
+    #[test]
+    fn comment_chars_dialects () {
+        assert_eq!(comment_chars("+ add one\n[-] clear", dtypes::Dialect::Brainfuck), 11);
+        assert_eq!(comment_chars("Ook. Ook? eh Ook.\nOok.", dtypes::Dialect::Ook), 2);
+        assert_eq!(comment_chars("+@", dtypes::Dialect::Brainfuck), 1);
+        assert_eq!(comment_chars("+@", dtypes::Dialect::Extended), 0);
+    }
+
+    #[test]
+    fn mapping_tokenizes () {
+        let mapping = Mapping::parse("+=up,-=down, >=right\n<=left\n\n.=say it,,=hear,[=do,]=done").unwrap();
+        let tokens = mapping.tokenize("up up do right down done\nsay  it");
+        assert_eq!(tokens.iter().map(|token| token.command.as_char()).collect::<String>(), "++[>-].");
+        assert_eq!(tokens[6].pos, dtypes::SourcePos { line: 2, column: 1 });
+        // longer tokens win over ones they start with, and brainfuck is a comment
+        let mapping = Mapping::parse("+=a,-=aa").unwrap();
+        assert_eq!(mapping.tokenize("aaa+").iter().map(|token| token.command.as_char()).collect::<String>(), "-+");
+        assert_eq!(Mapping::parse("+=x,+=y"), Err(String::from("'+' is mapped more than once")));
+        assert_eq!(Mapping::parse("+=x,-=x"), Err(String::from("'x' is mapped to more than one command")));
+        assert_eq!(Mapping::parse("@=x"), Err(String::from("'@' in mapping is not a brainfuck command")));
+        assert!(Mapping::parse("+x").is_err());
+        assert!(Mapping::parse("+=,-=y").is_err());
+        assert!(Mapping::parse(" \n").is_err());
+    }
+
+    #[test]
+    fn find_all_bracket_errors () {
+        assert_eq!(find_bracket_errors(&parse_program("][[]]][")), vec![
+            dtypes::BFError::UnmatchedBracket { bracket: ']', position: 0, source: None },
+            dtypes::BFError::UnmatchedBracket { bracket: ']', position: 5, source: None },
+            dtypes::BFError::UnmatchedBracket { bracket: '[', position: 6, source: None },
+        ]);
+        assert!(find_bracket_errors(&parse_program("+[>[-]<]")).is_empty());
+    }
 }
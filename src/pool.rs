@@ -0,0 +1,199 @@
+/*
+    Module for evaluating programs concurrently in a fixed set of worker
+    threads, e.g. behind a web playground, each program is run in a fresh
+    interpreter held to the pool's limits so nothing carries over between
+    jobs and no job can run forever
+
+        let limits = rust_bfi::Limits { max_steps: Option::Some(1_000_000), ..Default::default() };
+        let pool = rust_bfi::EvalPool::new(4, limits, || rust_bfi::BFInterpreter::new(30000));
+        let pending = pool.submit(",+.", Option::Some(b"a"));
+        // ...
+        let result = pending.recv()?;
+        assert_eq!(result.output, b"b");
+*/
+
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::{batch, dtypes, interpreter};
+
+
+// a program waiting for a worker
+struct Job {
+    prog: String,
+    // None to read any input embedded in the program (or none at all)
+    input: Option<Vec<u8>>,
+    reply: mpsc::Sender<EvalResult>,
+}
+
+
+// how a program went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalResult {
+    pub output: Vec<u8>,
+    // statistics about the run, the result holds any error loading the program
+    pub report: dtypes::RunReport,
+}
+
+
+// makes the interpreter for each job
+type Setup = dyn Fn() -> interpreter::BFInterpreter + Send + Sync;
+
+
+pub struct EvalPool {
+    // None once the pool is shutting down
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+
+// take jobs off the queue until the pool is dropped, a job that panics is
+// dropped without a result rather than taking the worker down with it
+fn work (queue: &Mutex<mpsc::Receiver<Job>>, setup: &Setup, limits: dtypes::Limits) {
+    loop {
+        // only hold the lock while waiting, not while running the job
+        let next = match queue.lock() {
+            Ok(queue) => queue.recv(),
+            Err(_) => return,
+        };
+        let Ok(job) = next else {
+            return;
+        };
+        // nothing from the job is used again after a panic, only its reply
+        // channel is dropped
+        let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut bfi = setup();
+            bfi.set_limits(limits);
+            batch::run_program(bfi, &job.prog, job.input.as_deref())
+        }));
+        if let Ok((output, report)) = ran {
+            // nobody waiting for the result is fine
+            let _ = job.reply.send(EvalResult { output, report });
+        }
+    }
+}
+
+
+impl EvalPool {
+    // start threads (at least one) workers, setup is called on a worker to make
+    // the interpreter for each job, whose limits are then replaced by the pool's
+    // and whose output sink and input are replaced by the job's
+    pub fn new<F: Fn() -> interpreter::BFInterpreter + Send + Sync + 'static> (threads: usize, limits: dtypes::Limits, setup: F) -> EvalPool {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        let setup: Arc<Setup> = Arc::new(setup);
+        let workers = (0..threads.max(1)).map(|_| {
+            let (queue, setup) = (Arc::clone(&queue), Arc::clone(&setup));
+            thread::spawn(move || work(&queue, &*setup, limits))
+        }).collect();
+        EvalPool { jobs: Option::Some(jobs), workers }
+    }
+
+    // number of worker threads
+    pub fn threads (&self) -> usize {
+        self.workers.len()
+    }
+
+    // queue a program to run with the given input (or any embedded in it if
+    // None), the result arrives on the returned channel once a worker has run
+    // it, the channel is closed without a result if running the program
+    // panicked (the worker carries on with the next job)
+    pub fn submit (&self, prog: &str, input: Option<&[u8]>) -> mpsc::Receiver<EvalResult> {
+        let (reply, result) = mpsc::channel();
+        let job = Job { prog: prog.to_string(), input: input.map(|i| i.to_vec()), reply };
+        if let Some(jobs) = &self.jobs {
+            // can only fail if every worker has died, which closes the result
+            // channel too
+            let _ = jobs.send(job);
+        }
+        result
+    }
+
+    // run a program and wait for how it went
+    pub fn eval (&self, prog: &str, input: Option<&[u8]>) -> Result<EvalResult, mpsc::RecvError> {
+        self.submit(prog, input).recv()
+    }
+}
+
+
+// finish the jobs already queued then stop the workers
+impl Drop for EvalPool {
+    fn drop (&mut self) {
+        self.jobs = Option::None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn pool () -> EvalPool {
+        let limits = dtypes::Limits { max_steps: Some(10000), ..Default::default() };
+        EvalPool::new(3, limits, || interpreter::BFInterpreter::new(8))
+    }
+
+    #[test]
+    fn pool_eval () {
+        let pool = pool();
+        assert_eq!(pool.threads(), 3);
+        let result = pool.eval("++++++++[>++++++++<-]>+.", None).unwrap();
+        assert_eq!(result.output, b"A");
+        assert_eq!(result.report.result, Ok(()));
+        assert_eq!(pool.eval(",+.,+.", Some(b"ab")).unwrap().output, b"bc");
+        assert_eq!(pool.eval(",.!z", None).unwrap().output, b"z");
+        let result = pool.eval("[", None).unwrap();
+        assert!(matches!(result.report.result, Err(dtypes::BFError::UnmatchedBracket { bracket: '[', .. })));
+        // the pool's limits apply however the interpreter was set up
+        let result = pool.eval("+[]", None).unwrap();
+        assert!(matches!(result.report.result, Err(dtypes::BFError::StepLimitExceeded { limit: 10000, .. })));
+        // nothing is left over from the previous job
+        assert_eq!(pool.eval(">+++.", None).unwrap().output, [3]);
+        assert_eq!(pool.eval(">+++.", None).unwrap().output, [3]);
+    }
+
+    #[test]
+    fn pool_concurrent_jobs () {
+        let pool = Arc::new(pool());
+        let pending: Vec<_> = (0..20_u8).map(|n| pool.submit(",+.", Some(&[n]))).collect();
+        // jobs can be submitted from other threads too
+        let other = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || pool.eval(",.", Some(b"x")).unwrap())
+        };
+        for (n, result) in (0..20_u8).zip(pending) {
+            assert_eq!(result.recv_timeout(Duration::from_secs(10)).unwrap().output, [n + 1]);
+        }
+        assert_eq!(other.join().unwrap().output, b"x");
+        // dropping the pool finishes jobs that are still queued
+        let pending = pool.submit(",.", Some(b"y"));
+        drop(Arc::into_inner(pool));
+        assert_eq!(pending.recv().unwrap().output, b"y");
+    }
+
+    #[test]
+    fn pool_survives_panics () {
+        // only the first interpreter made panics
+        let made = Arc::new(AtomicUsize::new(0));
+        let pool = {
+            let made = Arc::clone(&made);
+            EvalPool::new(1, dtypes::Limits::default(), move || {
+                assert!(made.fetch_add(1, Ordering::SeqCst) > 0, "setup failed");
+                interpreter::BFInterpreter::new(8)
+            })
+        };
+        assert!(pool.eval("+.", None).is_err());
+        // the only worker is still there to run the next job
+        let result = pool.submit("+.", None).recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(result.output, [1]);
+        assert_eq!(made.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,200 @@
+/*
+    Module with execution counters for finding a program's hotspots, and
+    optionally a timeline of every loop execution that can be written out as
+    Chrome trace event JSON for chrome://tracing or Perfetto
+*/
+
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::{dtypes, json, trace};
+
+
+// number of entries in each section of the hotspots report
+const REPORT_ROWS: usize = 10;
+
+
+// most loop executions kept for the timeline, later ones are left out
+const MAX_SPANS: usize = 1_000_000;
+
+
+// counters for one loop
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoopProfile {
+    // times the loop body was entered from its [
+    pub entries: u64,
+    // times the body ran to its ]
+    pub iterations: u64,
+    // wall clock time spent inside the loop (including nested loops)
+    pub time: Duration,
+}
+
+
+// one execution of a loop, from its [ going into the body to its ] falling through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopSpan {
+    // index of the loop's [
+    pub position: usize,
+    // when the loop started, since the profile did
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+
+// counters collected while a program runs with profiling on, everything is
+// keyed by source command index, with optimization on an instruction covering
+// a run of commands is counted against the first command in the run
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    // number of times the instruction at each command was executed
+    pub hits: BTreeMap<usize, u64>,
+    // keyed by the index of the loop's [
+    pub loops: BTreeMap<usize, LoopProfile>,
+    // loops currently being executed, innermost last
+    open: Vec<(usize, Instant)>,
+    // every loop execution in the order they finished, with when the profile
+    // started, if the timeline is kept
+    spans: Option<(Instant, Vec<LoopSpan>)>,
+}
+
+
+impl Profile {
+    pub fn new () -> Profile {
+        Profile::default()
+    }
+
+    // a profile that also keeps a timeline of every loop execution (up to a
+    // million of them)
+    pub fn with_spans () -> Profile {
+        Profile { spans: Option::Some((Instant::now(), Vec::new())), ..Profile::default() }
+    }
+
+    // a fresh profile keeping the same things as this one
+    pub fn restart (&self) -> Profile {
+        match self.spans {
+            Some(_) => Profile::with_spans(),
+            None => Profile::new(),
+        }
+    }
+
+    // the loop executions in the timeline, in the order they finished (empty if
+    // it isn't being kept)
+    pub fn spans (&self) -> &[LoopSpan] {
+        self.spans.as_ref().map_or(&[], |(_, spans)| spans)
+    }
+
+    // count an executed instruction
+    pub fn record (&mut self, event: &trace::TraceEvent) {
+        *self.hits.entry(event.position).or_insert(0) += 1;
+        match event.instr {
+            // a [ on a non-zero cell enters the loop body
+            dtypes::Instr::JumpIfZero(_) if event.before != 0 => {
+                self.loops.entry(event.position).or_default().entries += 1;
+                self.open.push((event.position, Instant::now()));
+            },
+            // a ] always finishes an iteration and leaves the loop on a zero cell
+            dtypes::Instr::JumpIfNonZero(_) => {
+                if let Some(&(start, _)) = self.open.last() {
+                    self.loops.entry(start).or_default().iterations += 1;
+                }
+                if event.before == 0 {
+                    self.close_loop();
+                }
+            },
+            _ => {},
+        };
+    }
+
+    // stop timing the innermost open loop
+    fn close_loop (&mut self) {
+        if let Some((start, entered)) = self.open.pop() {
+            let duration = entered.elapsed();
+            self.loops.entry(start).or_default().time += duration;
+            if let Some((started, spans)) = &mut self.spans {
+                if spans.len() < MAX_SPANS {
+                    spans.push(LoopSpan { position: start, start: entered.duration_since(*started), duration });
+                }
+            }
+        }
+    }
+
+    // stop timing any loops that were still running when the program stopped
+    pub fn finish (&mut self) {
+        while !self.open.is_empty() {
+            self.close_loop();
+        }
+    }
+
+    // the timeline as Chrome trace event JSON for the program at path, with each
+    // loop execution a span named after where its [ is in the source (sources
+    // are the line and column of each command, the command index is used for
+    // any without one)
+    pub fn chrome_trace (&self, path: &str, sources: &[dtypes::SourcePos]) -> String {
+        let mut out = String::from("{\"traceEvents\":[\n");
+        let _ = write!(out, "{{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{{\"name\":{}}}}}", json::string(path));
+        for span in self.spans() {
+            let name = match sources.get(span.position) {
+                Some(pos) => format!("[ at {}:{}", pos.line, pos.column),
+                None => format!("[ at command {}", span.position),
+            };
+            // times are in microseconds
+            let _ = write!(out, ",\n{{\"name\":{},\"cat\":\"loop\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":1,\"args\":{{\"command\":{}}}}}",
+                           json::string(&name), span.start.as_nanos() as f64 / 1000.0, span.duration.as_nanos() as f64 / 1000.0, span.position);
+        }
+        out.push_str("\n],\"displayTimeUnit\":\"ns\"}\n");
+        out
+    }
+}
+
+
+// hotspots report: the most executed commands and the loops that took the longest
+impl fmt::Display for Profile {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut hits: Vec<_> = self.hits.iter().collect();
+        hits.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(f, "hottest commands:")?;
+        writeln!(f, "{:>10} {:>14}", "command", "hits")?;
+        for (position, count) in hits.iter().take(REPORT_ROWS) {
+            writeln!(f, "{:>10} {:>14}", position, count)?;
+        }
+        let mut loops: Vec<_> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+        writeln!(f, "slowest loops:")?;
+        write!(f, "{:>10} {:>10} {:>14} {:>14}", "command", "entries", "iterations", "time")?;
+        for (position, stats) in loops.iter().take(REPORT_ROWS) {
+            write!(f, "\n{:>10} {:>10} {:>14} {:>14}",
+                   position, stats.entries, stats.iterations, format!("{:.3?}", stats.time))?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn profile_chrome_trace () {
+        let mut profile = Profile::with_spans();
+        if let Some((_, spans)) = &mut profile.spans {
+            spans.push(LoopSpan { position: 3, start: Duration::from_nanos(1500), duration: Duration::from_micros(2) });
+            spans.push(LoopSpan { position: 0, start: Duration::from_micros(1), duration: Duration::from_micros(10) });
+        }
+        let sources = [dtypes::SourcePos { line: 1, column: 1 }, dtypes::SourcePos { line: 2, column: 4 }];
+        assert_eq!(profile.chrome_trace("a \"b\".bf", &sources), concat!(
+            "{\"traceEvents\":[\n",
+            "{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"a \\\"b\\\".bf\"}},\n",
+            "{\"name\":\"[ at command 3\",\"cat\":\"loop\",\"ph\":\"X\",\"ts\":1.500,\"dur\":2.000,\"pid\":1,\"tid\":1,\"args\":{\"command\":3}},\n",
+            "{\"name\":\"[ at 1:1\",\"cat\":\"loop\",\"ph\":\"X\",\"ts\":1.000,\"dur\":10.000,\"pid\":1,\"tid\":1,\"args\":{\"command\":0}}\n",
+            "],\"displayTimeUnit\":\"ns\"}\n",
+        ));
+        // nothing is kept unless asked for
+        assert!(Profile::new().spans().is_empty());
+        assert!(Profile::new().restart().spans.is_none());
+        assert!(profile.restart().spans.is_some_and(|(_, spans)| spans.is_empty()));
+    }
+}
@@ -0,0 +1,514 @@
+/*
+    Module for recording a run so that it can be replayed exactly later, e.g.
+    to debug a session with input that can't be given again. A recording holds
+    the settings the program ran with, the input it read and the index of
+    every instruction it executed, kept small by only storing the steps where
+    it didn't carry straight on to the next instruction
+
+    Layout (n is an unsigned LEB128 varint, other integers little endian):
+        magic       b"BFREC"
+        version     u8
+        cell width  u8 (8, 16 or 32)
+        tape mode   u8 (0 strict, 1 wrapping, 2 growable, 3 bidirectional)
+        eof         u8 (0 no change, 1 zero, 2 minus one, 3 error)
+        dialect     u8 (0 brainfuck, 1 ook, 2 blub, 3 extended, 4 pbrain, 5 brainfork)
+        flags       u8 (1 optimize, 2 embedded input, 4 hash debug, 8 sparse)
+        mem size    n
+        program     u64 fingerprint of the loaded instructions
+        input       n count, then the bytes
+        steps       n instructions executed
+        jumps       n count, then for each the n steps since the last jump (or
+                    the start) and the n index of the instruction jumped to
+*/
+
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::{error, fmt};
+
+use crate::{dtypes, interpreter, snapshot, trace};
+
+
+const MAGIC: &[u8] = b"BFREC";
+const VERSION: u8 = 1;
+
+
+// problems reading a recording back in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    // the bytes ended part way through the recording
+    Truncated,
+    // not a recording at all
+    BadMagic,
+    UnsupportedVersion(u8),
+    // a field held a value that can't be right
+    Corrupt(&'static str),
+}
+
+
+impl fmt::Display for ReplayError {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Truncated => write!(f, "recording is truncated"),
+            ReplayError::BadMagic => write!(f, "not a recording"),
+            ReplayError::UnsupportedVersion(v) => write!(f, "unsupported recording version {}", v),
+            ReplayError::Corrupt(field) => write!(f, "recording has an invalid {}", field),
+        }
+    }
+}
+
+
+impl error::Error for ReplayError {}
+
+
+// how the interpreter was set up, everything that changes what a program does
+// apart from its input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub mem_size: usize,
+    pub tape_mode: dtypes::TapeMode,
+    // only the pages of memory in use were stored
+    pub sparse: bool,
+    pub cell_width: dtypes::CellWidth,
+    pub eof_behavior: dtypes::EofBehavior,
+    pub dialect: dtypes::Dialect,
+    pub optimize: bool,
+    pub embedded_input: bool,
+    pub hash_debug: bool,
+}
+
+
+impl Settings {
+    // set up an interpreter (made with the same memory size, tape and cell
+    // width) the same way, before loading the program
+    pub fn apply (&self, bfi: &mut interpreter::BFInterpreter) {
+        bfi.set_eof_behavior(self.eof_behavior);
+        bfi.set_optimize(self.optimize);
+        bfi.set_split_input(self.embedded_input);
+        bfi.set_dialect(self.dialect);
+        bfi.set_hash_debug(self.hash_debug);
+    }
+}
+
+
+// a recorded run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub settings: Settings,
+    // from snapshot::program_fingerprint()
+    pub program: u64,
+    // every byte the program read
+    pub input: Vec<u8>,
+    // number of instructions executed
+    pub steps: u64,
+    // (step, index) for each step that didn't execute the instruction after
+    // the one before (the first step follows instruction -1)
+    pub jumps: Vec<(u64, usize)>,
+}
+
+
+// the recorded instructions, jumps and all
+#[derive(Debug, Clone)]
+pub struct Indices<'a> {
+    recording: &'a Recording,
+    step: u64,
+    next: usize,
+    jump: usize,
+}
+
+
+impl Iterator for Indices<'_> {
+    type Item = usize;
+
+    fn next (&mut self) -> Option<usize> {
+        if self.step >= self.recording.steps {
+            return Option::None;
+        }
+        let mut index = self.next;
+        if let Some(&(step, target)) = self.recording.jumps.get(self.jump) {
+            if step == self.step {
+                index = target;
+                self.jump += 1;
+            }
+        }
+        self.step += 1;
+        self.next = index + 1;
+        Option::Some(index)
+    }
+}
+
+
+impl Recording {
+    // index of every instruction executed, in order
+    pub fn indices (&self) -> Indices<'_> {
+        Indices { recording: self, step: 0, next: 0, jump: 0 }
+    }
+
+    pub fn to_bytes (&self) -> Vec<u8> {
+        let s = &self.settings;
+        let mut bytes = Vec::with_capacity(32 + self.input.len() + self.jumps.len() * 4);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(match s.cell_width {
+            dtypes::CellWidth::U8 => 8,
+            dtypes::CellWidth::U16 => 16,
+            dtypes::CellWidth::U32 => 32,
+        });
+        bytes.push(match s.tape_mode {
+            dtypes::TapeMode::Strict => 0,
+            dtypes::TapeMode::Wrapping => 1,
+            dtypes::TapeMode::Growable => 2,
+            dtypes::TapeMode::Bidirectional => 3,
+        });
+        bytes.push(match s.eof_behavior {
+            dtypes::EofBehavior::NoChange => 0,
+            dtypes::EofBehavior::Zero => 1,
+            dtypes::EofBehavior::MinusOne => 2,
+            dtypes::EofBehavior::Error => 3,
+        });
+        bytes.push(match s.dialect {
+            dtypes::Dialect::Brainfuck => 0,
+            dtypes::Dialect::Ook => 1,
+            dtypes::Dialect::Blub => 2,
+            dtypes::Dialect::Extended => 3,
+            dtypes::Dialect::Pbrain => 4,
+            dtypes::Dialect::Brainfork => 5,
+        });
+        bytes.push(s.optimize as u8 | (s.embedded_input as u8) << 1 | (s.hash_debug as u8) << 2 | (s.sparse as u8) << 3);
+        write_varint(&mut bytes, s.mem_size as u64);
+        bytes.extend_from_slice(&self.program.to_le_bytes());
+        write_varint(&mut bytes, self.input.len() as u64);
+        bytes.extend_from_slice(&self.input);
+        write_varint(&mut bytes, self.steps);
+        write_varint(&mut bytes, self.jumps.len() as u64);
+        let mut last = 0;
+        for &(step, index) in &self.jumps {
+            write_varint(&mut bytes, step - last);
+            write_varint(&mut bytes, index as u64);
+            last = step;
+        }
+        bytes
+    }
+
+    pub fn from_bytes (bytes: &[u8]) -> Result<Recording, ReplayError> {
+        let mut r = Reader { bytes };
+        if r.take(MAGIC.len()).map_err(|_| ReplayError::BadMagic)? != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let cell_width = match r.u8()? {
+            8 => dtypes::CellWidth::U8,
+            16 => dtypes::CellWidth::U16,
+            32 => dtypes::CellWidth::U32,
+            _ => return Err(ReplayError::Corrupt("cell width")),
+        };
+        let tape_mode = match r.u8()? {
+            0 => dtypes::TapeMode::Strict,
+            1 => dtypes::TapeMode::Wrapping,
+            2 => dtypes::TapeMode::Growable,
+            3 => dtypes::TapeMode::Bidirectional,
+            _ => return Err(ReplayError::Corrupt("tape mode")),
+        };
+        let eof_behavior = match r.u8()? {
+            0 => dtypes::EofBehavior::NoChange,
+            1 => dtypes::EofBehavior::Zero,
+            2 => dtypes::EofBehavior::MinusOne,
+            3 => dtypes::EofBehavior::Error,
+            _ => return Err(ReplayError::Corrupt("eof behavior")),
+        };
+        let dialect = match r.u8()? {
+            0 => dtypes::Dialect::Brainfuck,
+            1 => dtypes::Dialect::Ook,
+            2 => dtypes::Dialect::Blub,
+            3 => dtypes::Dialect::Extended,
+            4 => dtypes::Dialect::Pbrain,
+            5 => dtypes::Dialect::Brainfork,
+            _ => return Err(ReplayError::Corrupt("dialect")),
+        };
+        let flags = r.u8()?;
+        if flags > 15 {
+            return Err(ReplayError::Corrupt("flags"));
+        }
+        let mem_size = r.usize("memory size")?;
+        if mem_size == 0 {
+            return Err(ReplayError::Corrupt("memory size"));
+        }
+        let settings = Settings {
+            mem_size,
+            tape_mode,
+            sparse: flags & 8 != 0,
+            cell_width,
+            eof_behavior,
+            dialect,
+            optimize: flags & 1 != 0,
+            embedded_input: flags & 2 != 0,
+            hash_debug: flags & 4 != 0,
+        };
+        let program = u64::from_le_bytes(r.take(8)?.try_into().map_err(|_| ReplayError::Truncated)?);
+        let n = r.usize("input length")?;
+        let input = r.take(n)?.to_vec();
+        let steps = r.varint()?;
+        // don't trust the count for allocations, the data has to be there
+        let mut jumps = Vec::new();
+        let mut step = 0_u64;
+        for i in 0..r.usize("jump count")? {
+            let gap = r.varint()?;
+            // jumps are in order, only the first can be at step 0
+            if (i > 0 && gap == 0) || step.checked_add(gap).is_none_or(|s| s >= steps) {
+                return Err(ReplayError::Corrupt("jump step"));
+            }
+            step += gap;
+            jumps.push((step, r.usize("jump index")?));
+        }
+        if !r.bytes.is_empty() {
+            return Err(ReplayError::Corrupt("length"));
+        }
+        Ok(Recording { settings, program, input, steps, jumps })
+    }
+}
+
+
+// append an unsigned LEB128 varint
+fn write_varint (bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+
+// reads fields off the front of a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+
+impl<'a> Reader<'a> {
+    fn take (&mut self, n: usize) -> Result<&'a [u8], ReplayError> {
+        if self.bytes.len() < n {
+            return Err(ReplayError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8 (&mut self) -> Result<u8, ReplayError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint (&mut self) -> Result<u64, ReplayError> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            n |= ((byte & 0x7f) as u64).checked_shl(shift).filter(|v| v >> shift == (byte & 0x7f) as u64)
+                .ok_or(ReplayError::Corrupt("varint"))?;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(ReplayError::Corrupt("varint"))
+    }
+
+    // a varint that has to fit in a usize
+    fn usize (&mut self, field: &'static str) -> Result<usize, ReplayError> {
+        usize::try_from(self.varint()?).map_err(|_| ReplayError::Corrupt(field))
+    }
+}
+
+
+// what the recorder has seen so far
+#[derive(Debug, Default)]
+struct Trail {
+    input: Vec<u8>,
+    steps: u64,
+    next: usize,
+    jumps: Vec<(u64, usize)>,
+}
+
+
+// tracer that records the input a program reads and the instructions it
+// executes, give a clone of it to BFInterpreter::set_tracer() before running
+// the program, then take the recording once it stops
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    trail: Rc<RefCell<Trail>>,
+}
+
+
+impl Recorder {
+    pub fn new () -> Recorder {
+        Recorder::default()
+    }
+
+    // the run so far, for a program with the given settings and loaded
+    // instructions
+    pub fn recording (&self, settings: Settings, ops: &[dtypes::Op]) -> Recording {
+        let trail = self.trail.borrow();
+        Recording {
+            settings,
+            program: snapshot::program_fingerprint(ops),
+            input: trail.input.clone(),
+            steps: trail.steps,
+            jumps: trail.jumps.clone(),
+        }
+    }
+}
+
+
+impl trace::Tracer for Recorder {
+    fn trace (&mut self, event: &trace::TraceEvent) {
+        let mut trail = self.trail.borrow_mut();
+        if event.index != trail.next {
+            let step = trail.steps;
+            trail.jumps.push((step, event.index));
+        }
+        trail.next = event.index + 1;
+        trail.steps += 1;
+        if let Some(byte) = event.input {
+            trail.input.push(byte);
+        }
+    }
+}
+
+
+// the first place a replay went differently from the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    // the loaded program isn't the one that was recorded
+    Program,
+    // the instruction executed at a step (None once the program had stopped,
+    // or had no more recorded steps)
+    Step { step: u64, expected: Option<usize>, actual: Option<usize> },
+}
+
+
+impl fmt::Display for Divergence {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let index = |i: &Option<usize>| i.map_or(String::from("nothing"), |i| format!("instruction {}", i));
+        match self {
+            Divergence::Program => write!(f, "the program is not the one that was recorded"),
+            Divergence::Step { step, expected, actual } => {
+                write!(f, "step {} executed {}, expected {}", step, index(actual), index(expected))
+            },
+        }
+    }
+}
+
+
+// check the loaded program is the one that was recorded
+pub fn check_program (bfi: &interpreter::BFInterpreter, recording: &Recording) -> Result<(), Divergence> {
+    match snapshot::program_fingerprint(bfi.instructions()) == recording.program {
+        true => Ok(()),
+        false => Err(Divergence::Program),
+    }
+}
+
+
+// run a loaded program one instruction at a time with the recorded input,
+// checking it executes exactly the recorded instructions, the interpreter is
+// left wherever it got to (with any error that stopped it)
+pub fn replay (bfi: &mut interpreter::BFInterpreter, recording: &Recording) -> Result<(), Divergence> {
+    check_program(bfi, recording)?;
+    bfi.set_input(&recording.input);
+    for (step, expected) in (0..).zip(recording.indices()) {
+        let actual = (!bfi.is_finished()).then(|| bfi.instruction_pointer());
+        if actual != Option::Some(expected) {
+            return Err(Divergence::Step { step, expected: Option::Some(expected), actual });
+        }
+        // errors are kept in the interpreter
+        let _ = bfi.step();
+    }
+    // a recording of a run cut short by the step limit or an interrupt ends
+    // with the program still going
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn settings () -> Settings {
+        Settings {
+            mem_size: 30000,
+            tape_mode: dtypes::TapeMode::Strict,
+            sparse: false,
+            cell_width: dtypes::CellWidth::U8,
+            eof_behavior: dtypes::EofBehavior::Zero,
+            dialect: dtypes::Dialect::Brainfuck,
+            optimize: true,
+            embedded_input: true,
+            hash_debug: false,
+        }
+    }
+
+    // record a program running with some input
+    fn record (prog: &str, input: &[u8]) -> (Recording, Vec<u8>) {
+        let mut bfi = interpreter::BFInterpreter::new(30000);
+        settings().apply(&mut bfi);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        let recorder = Recorder::new();
+        bfi.set_tracer(Some(Box::new(recorder.clone())));
+        bfi.load_program(prog).unwrap();
+        bfi.set_input(input);
+        bfi.run().unwrap();
+        (recorder.recording(settings(), bfi.instructions()), bfi.take_output())
+    }
+
+    #[test]
+    fn replay_recorded_run () {
+        let prog = ",[.,]++[>+<-]";
+        let (recording, output) = record(prog, b"abc");
+        assert_eq!(output, b"abc");
+        assert_eq!(recording.input, b"abc");
+        // `]` jumps back to the instruction after `[`, and `++[>+<-]` is
+        // optimized to an add and a move
+        assert_eq!(recording.indices().collect::<Vec<_>>(), [0, 1, 2, 3, 4, 2, 3, 4, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(recording.steps, 14);
+        assert_eq!(recording.jumps, [(5, 2), (8, 2)]);
+        // replaying gives the same output without any input of its own
+        let mut bfi = interpreter::BFInterpreter::new(30000);
+        settings().apply(&mut bfi);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.load_program(prog).unwrap();
+        assert_eq!(replay(&mut bfi, &recording), Ok(()));
+        assert!(bfi.is_finished());
+        assert_eq!(bfi.take_output(), b"abc");
+        // a different program or different steps are caught
+        bfi.load_program(",[.,]").unwrap();
+        assert_eq!(replay(&mut bfi, &recording), Err(Divergence::Program));
+        let mut changed = recording.clone();
+        changed.jumps[1].1 += 1;
+        bfi.load_program(prog).unwrap();
+        let step = changed.jumps[1].0;
+        assert!(matches!(replay(&mut bfi, &changed), Err(Divergence::Step { step: s, .. }) if s == step));
+    }
+
+    #[test]
+    fn recording_round_trip () {
+        let (recording, _) = record("+[,.]", b"hi\n");
+        let bytes = recording.to_bytes();
+        assert_eq!(Recording::from_bytes(&bytes), Ok(recording.clone()));
+        let recording = Recording {
+            settings: Settings { mem_size: 1 << 40, sparse: true, cell_width: dtypes::CellWidth::U16, dialect: dtypes::Dialect::Pbrain, hash_debug: true, ..settings() },
+            steps: 1 << 35,
+            jumps: vec![(0, 9), (300, 2), (1 << 34, 1 << 20)],
+            ..recording
+        };
+        assert_eq!(Recording::from_bytes(&recording.to_bytes()), Ok(recording.clone()));
+        assert_eq!(Recording::from_bytes(b"nope"), Err(ReplayError::BadMagic));
+        assert_eq!(Recording::from_bytes(&bytes[..bytes.len() - 1]), Err(ReplayError::Truncated));
+        let mut wrong = bytes.clone();
+        wrong[5] = 9;
+        assert_eq!(Recording::from_bytes(&wrong), Err(ReplayError::UnsupportedVersion(9)));
+        let mut wrong = bytes.clone();
+        wrong.push(0);
+        assert_eq!(Recording::from_bytes(&wrong), Err(ReplayError::Corrupt("length")));
+    }
+}
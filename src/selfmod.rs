@@ -0,0 +1,372 @@
+/*
+    Module for running self-modifying brainfuck, where the program lives on
+    the same tape as its data rather than being parsed into instructions
+    first: each byte of the source goes in a cell from cell 0 on, the data
+    pointer starts on the cell right after the program and the instruction
+    pointer executes whatever command is in the cell it is on, so a program
+    can rewrite commands it hasn't got to yet
+
+        <,x     reads a byte over the x and then executes it
+
+    cells holding anything other than a command (comments, or values past 255
+    with wider cells) do nothing, brackets are matched by scanning the tape
+    when a jump is taken rather than up front, and the program stops at the
+    first cell holding 0 it comes to (usually the first data cell not
+    written yet) or at the end of the tape
+
+        let tape = rust_bfi::tape::new_tape(rust_bfi::TapeMode::Strict, 30000);
+        let mut smbf = rust_bfi::selfmod::SelfModifying::new(prog, tape, rust_bfi::CellWidth::U8)?;
+        smbf.run()?;
+*/
+
+
+use std::fmt;
+
+use crate::{bfio, dtypes, tape};
+
+
+// a program running on the tape it is stored on
+pub struct SelfModifying {
+    tape: Box<dyn tape::Tape>,
+    cell_width: dtypes::CellWidth,
+    eof_behavior: dtypes::EofBehavior,
+    max_steps: Option<u64>,
+    io: Box<dyn bfio::BfIo>,
+    // indexes of cells on the tape, which move along if it grows to the left
+    instr_ptr: usize,
+    data_ptr: usize,
+    steps: u64,
+    error: Option<dtypes::BFError>,
+    // where each byte of the program came from, to locate errors with
+    sources: Vec<dtypes::SourcePos>,
+}
+
+
+// sizes rather than the whole tape
+impl fmt::Debug for SelfModifying {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelfModifying")
+            .field("memory_size", &self.tape.len())
+            .field("instr_ptr", &self.instr_ptr)
+            .field("data_ptr", &self.data_ptr)
+            .field("steps", &self.steps)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+
+impl SelfModifying {
+    // the program written to the start of a blank tape, which has to have room
+    // for it and the cell after it (or be able to grow), reading stdin and
+    // writing stdout
+    pub fn new (program: &str, mut tape: Box<dyn tape::Tape>, cell_width: dtypes::CellWidth) -> Result<SelfModifying, dtypes::BFError> {
+        let len = program.len();
+        let data_ptr = match tape.offset(0, len as isize) {
+            Some(ptr) if ptr == len => ptr,
+            _ => return Err(dtypes::BFError::MemoryLimitExceeded { position: 0, source: Option::None, limit: tape.len() }),
+        };
+        let mut sources = Vec::with_capacity(len);
+        let mut pos = dtypes::SourcePos { line: 1, column: 1 };
+        for c in program.chars() {
+            sources.extend((0..c.len_utf8()).map(|_| pos));
+            if c == '\n' {
+                pos = dtypes::SourcePos { line: pos.line + 1, column: 1 };
+            } else {
+                pos.column += 1;
+            }
+        }
+        for (i, &byte) in program.as_bytes().iter().enumerate() {
+            tape.set(i, byte as u32);
+        }
+        Ok(SelfModifying {
+            tape,
+            cell_width,
+            eof_behavior: dtypes::EofBehavior::NoChange,
+            max_steps: Option::None,
+            io: Box::new(bfio::StdIo),
+            instr_ptr: 0,
+            data_ptr,
+            steps: 0,
+            error: Option::None,
+            sources,
+        })
+    }
+
+    // where input comes from and output goes
+    pub fn set_io (&mut self, io: Box<dyn bfio::BfIo>) {
+        self.io = io;
+    }
+
+    // the io backend if it is a T, e.g. to get at the output of a bfio::BufferIo
+    pub fn io<T: bfio::BfIo> (&self) -> Option<&T> {
+        (self.io.as_ref() as &dyn std::any::Any).downcast_ref::<T>()
+    }
+
+    pub fn set_eof_behavior (&mut self, eof_behavior: dtypes::EofBehavior) {
+        self.eof_behavior = eof_behavior;
+    }
+
+    // stop the program with BFError::StepLimitExceeded once it has executed
+    // this many commands, None for no limit
+    pub fn set_max_steps (&mut self, max_steps: Option<u64>) {
+        self.max_steps = max_steps;
+    }
+
+    // true once the instruction pointer is on a cell holding 0 or off the end
+    // of the tape, or an error has stopped the program
+    pub fn is_finished (&self) -> bool {
+        self.error.is_some() || self.instr_ptr >= self.tape.len() || self.tape.get(self.instr_ptr) == 0
+    }
+
+    // index of the instruction pointer's cell counted from the start of the
+    // program (cells a tape grew by to the left aren't counted)
+    fn position (&self) -> usize {
+        self.instr_ptr.saturating_sub(self.tape.origin())
+    }
+
+    // execute the command in the cell at the instruction pointer, stepping a
+    // program that has finished does nothing (or returns the error that stopped it)
+    pub fn step (&mut self) -> Result<(), dtypes::BFError> {
+        if let Some(e) = &self.error {
+            return Err(e.clone());
+        }
+        if self.is_finished() {
+            return Ok(());
+        }
+        let result = match self.max_steps {
+            Some(limit) if self.steps >= limit => Err(dtypes::BFError::StepLimitExceeded { position: self.position(), source: Option::None, limit }),
+            _ => {
+                self.steps += 1;
+                self.execute()
+            },
+        };
+        match result {
+            Ok(()) => {
+                self.instr_ptr += 1;
+                Ok(())
+            },
+            Err(e) => {
+                let e = e.locate(&self.sources);
+                self.error = Option::Some(e.clone());
+                let _ = self.io.flush();
+                Err(e)
+            },
+        }
+    }
+
+    // run until the program finishes or hits an error
+    pub fn run (&mut self) -> Result<(), dtypes::BFError> {
+        while !self.is_finished() {
+            self.step()?;
+        }
+        self.io.flush().map_err(|e| dtypes::BFError::Io {
+            position: self.position(),
+            source: Option::None,
+            msg: format!("unable to write output: {}", e),
+        })?;
+        match &self.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    // the command at the instruction pointer, which is left on the last cell
+    // of a jump
+    fn execute (&mut self) -> Result<(), dtypes::BFError> {
+        let val = self.tape.get(self.data_ptr);
+        let max = self.cell_width.max_value();
+        match u8::try_from(self.tape.get(self.instr_ptr)).map(char::from) {
+            Ok('+') => self.tape.set(self.data_ptr, val.wrapping_add(1) & max),
+            Ok('-') => self.tape.set(self.data_ptr, val.wrapping_sub(1) & max),
+            Ok('>') => self.move_pointer(1)?,
+            Ok('<') => self.move_pointer(-1)?,
+            Ok('.') => self.io.write_byte(val as u8).map_err(|e| dtypes::BFError::Io {
+                position: self.position(),
+                source: Option::None,
+                msg: format!("unable to write output: {}", e),
+            })?,
+            Ok(',') => self.input_byte()?,
+            Ok('[') if val == 0 => self.instr_ptr = self.partner('[')?,
+            Ok(']') if val != 0 => self.instr_ptr = self.partner(']')?,
+            _ => {},
+        };
+        Ok(())
+    }
+
+    // move the data pointer, keeping the instruction pointer on the same cell
+    // if the tape grows to the left
+    fn move_pointer (&mut self, offset: isize) -> Result<(), dtypes::BFError> {
+        let origin = self.tape.origin();
+        match self.tape.offset(self.data_ptr, offset) {
+            Some(ptr) => {
+                self.data_ptr = ptr;
+                self.instr_ptr += self.tape.origin() - origin;
+                Ok(())
+            },
+            None if offset > 0 => Err(dtypes::BFError::PointerOverrun { position: self.position(), source: Option::None }),
+            None => Err(dtypes::BFError::PointerUnderrun { position: self.position(), source: Option::None }),
+        }
+    }
+
+    fn input_byte (&mut self) -> Result<(), dtypes::BFError> {
+        let io_error = |position, e: std::io::Error| dtypes::BFError::Io { position, source: Option::None, msg: format!("unable to read input: {}", e) };
+        self.io.flush().map_err(|e| io_error(self.position(), e))?;
+        match self.io.read_byte() {
+            Ok(Some(byte)) => self.tape.set(self.data_ptr, byte as u32),
+            Ok(None) => match self.eof_behavior {
+                dtypes::EofBehavior::NoChange => {},
+                dtypes::EofBehavior::Zero => self.tape.set(self.data_ptr, 0),
+                dtypes::EofBehavior::MinusOne => self.tape.set(self.data_ptr, self.cell_width.max_value()),
+                dtypes::EofBehavior::Error => return Err(dtypes::BFError::InputExhausted { position: self.position(), source: Option::None }),
+            },
+            Err(e) => return Err(io_error(self.position(), e)),
+        };
+        Ok(())
+    }
+
+    // index of the bracket matching the one at the instruction pointer, found by
+    // scanning the tape as it is now towards the right for a [ or the left for
+    // a ], stopping at a cell holding 0 or the end of the tape
+    fn partner (&self, bracket: char) -> Result<usize, dtypes::BFError> {
+        let (open, close) = match bracket {
+            '[' => ('[', ']'),
+            _ => (']', '['),
+        };
+        let mut depth = 0_usize;
+        let mut i = self.instr_ptr;
+        loop {
+            i = match bracket {
+                '[' => Option::Some(i + 1).filter(|&i| i < self.tape.len()),
+                _ => i.checked_sub(1),
+            }.ok_or(dtypes::BFError::UnmatchedBracket { bracket, position: self.position(), source: Option::None })?;
+            match u8::try_from(self.tape.get(i)).map(char::from) {
+                Ok('\0') => return Err(dtypes::BFError::UnmatchedBracket { bracket, position: self.position(), source: Option::None }),
+                Ok(c) if c == close && depth == 0 => return Ok(i),
+                Ok(c) if c == close => depth -= 1,
+                Ok(c) if c == open => depth += 1,
+                _ => {},
+            };
+        }
+    }
+
+    // index of the cell the instruction pointer is on
+    pub fn instruction_pointer (&self) -> usize {
+        self.instr_ptr
+    }
+
+    pub fn data_pointer (&self) -> usize {
+        self.data_ptr
+    }
+
+    // how many of the cells are left of where the program started (see
+    // tape::Tape::origin())
+    pub fn tape_origin (&self) -> usize {
+        self.tape.origin()
+    }
+
+    pub fn steps_executed (&self) -> u64 {
+        self.steps
+    }
+
+    pub fn error (&self) -> Option<&dtypes::BFError> {
+        self.error.as_ref()
+    }
+
+    pub fn memory_size (&self) -> usize {
+        self.tape.len()
+    }
+
+    // value of the memory cell at index, None if it is past the end of memory
+    pub fn cell (&self, index: usize) -> Option<u32> {
+        (index < self.tape.len()).then(|| self.tape.get(index))
+    }
+
+    // the program as it stands now, the cells from where it started up to the
+    // first one holding 0, with any that aren't bytes left out
+    pub fn code (&self) -> String {
+        let cells = (self.tape.origin()..self.tape.len()).map(|i| self.tape.get(i)).take_while(|&cell| cell != 0);
+        let bytes: Vec<u8> = cells.filter_map(|cell| u8::try_from(cell).ok()).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    // copy of the cells from the start of the tape up to the last one written
+    // or the data pointer, whichever is further along
+    pub fn dump_memory (&self) -> dtypes::MemoryDump {
+        let used = (0..self.tape.len()).rev().find(|&i| self.tape.get(i) != 0).map_or(0, |i| i + 1);
+        let end = used.max(self.data_ptr + 1).min(self.tape.len());
+        dtypes::MemoryDump {
+            start: 0,
+            cells: (0..end).map(|i| self.tape.get(i)).collect(),
+            data_ptr: self.data_ptr,
+            cell_width: self.cell_width,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // run a program on a small tape with the given input
+    fn run (prog: &str, input: &[u8]) -> (SelfModifying, Result<(), dtypes::BFError>) {
+        let mut smbf = SelfModifying::new(prog, tape::new_tape(dtypes::TapeMode::Strict, 100), dtypes::CellWidth::U8).unwrap();
+        smbf.set_io(Box::new(bfio::BufferIo::new(input)));
+        smbf.set_max_steps(Some(10_000));
+        let result = smbf.run();
+        (smbf, result)
+    }
+
+    fn output (smbf: &SelfModifying) -> &[u8] {
+        smbf.io::<bfio::BufferIo>().unwrap().output()
+    }
+
+    #[test]
+    fn selfmod_runs_plain_programs () {
+        let (smbf, result) = run("++++++++[>++++++++<-]>+.,.,.", b"hi");
+        assert_eq!(result, Ok(()));
+        assert_eq!(output(&smbf), b"Ahi");
+        // the data starts right after the program
+        assert_eq!(smbf.data_pointer(), 29);
+        assert_eq!((smbf.cell(28), smbf.cell(29)), (Some(0), Some(105)));
+        assert_eq!(smbf.code(), "++++++++[>++++++++<-]>+.,.,.");
+    }
+
+    #[test]
+    fn selfmod_rewrites_code () {
+        // a command read from input over the x
+        let (smbf, result) = run("<,x", b".");
+        assert_eq!((result, output(&smbf)), (Ok(()), &b"."[..]));
+        // the . is turned into a - before it is reached, which then turns itself into a ,
+        let (smbf, result) = run("<-.", b"");
+        assert_eq!((result, output(&smbf)), (Ok(()), &b""[..]));
+        assert_eq!(smbf.code(), "<-,");
+        assert_eq!(smbf.steps_executed(), 3);
+        // brackets are matched against the code as it is when the jump is taken
+        assert_eq!(run("<,>[x", b"]").1, Ok(()));
+        assert!(matches!(run("<,>[x", b"").1, Err(dtypes::BFError::UnmatchedBracket { bracket: '[', position: 3, .. })));
+    }
+
+    #[test]
+    fn selfmod_errors () {
+        assert!(matches!(run("[", b"").1, Err(dtypes::BFError::UnmatchedBracket { bracket: '[', position: 0, .. })));
+        let (mut smbf, result) = run("+\n]", b"");
+        assert_eq!(result, Err(dtypes::BFError::UnmatchedBracket { bracket: ']', position: 2, source: Some(dtypes::SourcePos { line: 2, column: 1 }) }));
+        assert_eq!(smbf.step(), result);
+        assert!(matches!(run("+[<]", b"").1, Err(dtypes::BFError::PointerUnderrun { position: 2, .. })));
+        assert!(matches!(run("+[]", b"").1, Err(dtypes::BFError::StepLimitExceeded { limit: 10_000, .. })));
+        // no room for the program on the tape
+        let tape = tape::new_tape(dtypes::TapeMode::Strict, 3);
+        assert!(matches!(SelfModifying::new("+++", tape, dtypes::CellWidth::U8), Err(dtypes::BFError::MemoryLimitExceeded { limit: 3, .. })));
+        // unless it can grow, to either side
+        let tape = tape::new_tape(dtypes::TapeMode::Bidirectional, 3);
+        let mut smbf = SelfModifying::new("+[<]", tape, dtypes::CellWidth::U8).unwrap();
+        smbf.set_io(Box::new(bfio::BufferIo::new(b"")));
+        assert_eq!(smbf.run(), Ok(()));
+        assert_eq!((smbf.tape_origin(), smbf.data_pointer()), (5, 4));
+        // the 1 left in the first data cell runs on as a comment
+        assert_eq!(smbf.code(), "+[<]\u{1}");
+    }
+}
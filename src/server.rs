@@ -0,0 +1,454 @@
+/*
+    Module for a small HTTP server that runs programs sent to it, enough to
+    put a Brainfuck playground online. Programs run on an EvalPool, held to
+    its limits. Endpoints:
+
+        POST /run   body {"program":"+[-]...","input":"..."} (input optional,
+                    without it any input embedded in the program is read),
+                    answered with the JSON document from json::run_document()
+        GET /       a page with a form for trying programs out
+
+    anything else gets an error status with {"error":"..."}
+*/
+
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{json, pool};
+
+
+// how long a client has to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+// longest request line or header accepted
+const MAX_LINE: usize = 8192;
+
+
+// most headers accepted, and most bytes in all of them together
+const MAX_HEADERS: usize = 100;
+const MAX_HEAD: usize = 16384;
+
+
+// connections answered at once for each of the pool's threads, the rest wait
+// to be accepted
+const CONNECTIONS_PER_THREAD: usize = 4;
+
+
+// the playground page
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Brainfuck playground</title>
+<style>
+body { font-family: sans-serif; max-width: 50em; margin: 2em auto; }
+textarea, pre { width: 100%; font-family: monospace; box-sizing: border-box; }
+pre { background: #eee; padding: 0.5em; min-height: 3em; white-space: pre-wrap; }
+</style>
+</head>
+<body>
+<h1>Brainfuck playground</h1>
+<p><textarea id="program" rows="12">++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.</textarea></p>
+<p><label>Input <textarea id="input" rows="2"></textarea></label></p>
+<p><button id="run">Run</button></p>
+<pre id="output"></pre>
+<pre id="stats"></pre>
+<script>
+document.getElementById("run").onclick = async () => {
+  const body = JSON.stringify({ program: document.getElementById("program").value, input: document.getElementById("input").value });
+  const doc = await (await fetch("/run", { method: "POST", headers: { "Content-Type": "application/json" }, body })).json();
+  document.getElementById("output").textContent = doc.output ? doc.output.text : "";
+  const error = doc.error ? (doc.error.message || doc.error) : "finished";
+  document.getElementById("stats").textContent = error + (doc.stats ? "\n" + JSON.stringify(doc.stats) : "");
+};
+</script>
+</body>
+</html>
+"#;
+
+
+// a request as read off the connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    // without any query string
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+
+// what to send back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+
+impl Response {
+    fn json (status: u16, body: String) -> Response {
+        Response { status, content_type: "application/json", body }
+    }
+
+    // an error status with {"error":msg}
+    fn error (status: u16, msg: &str) -> Response {
+        Response::json(status, format!("{{\"error\":{}}}", json::string(msg)))
+    }
+
+    // the response as sent over HTTP/1.1, the connection is closed after it
+    pub fn to_bytes (&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            411 => "Length Required",
+            413 => "Payload Too Large",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            _ => "",
+        };
+        // anyone's page may call the API
+        let head = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\n\
+                            Access-Control-Allow-Methods: GET, POST, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\n\
+                            Connection: close\r\n\r\n",
+                           self.status, reason, self.content_type, self.body.len());
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(self.body.as_bytes());
+        bytes
+    }
+}
+
+
+// one line of the request head, without the line ending
+fn read_line<R: BufRead> (reader: &mut R) -> Result<String, Response> {
+    let mut line = Vec::new();
+    let read = reader.by_ref().take(MAX_LINE as u64 + 1).read_until(b'\n', &mut line);
+    match read {
+        Ok(0) => return Err(Response::error(400, "incomplete request")),
+        Ok(_) if line.len() > MAX_LINE => return Err(Response::error(431, "request line or header too long")),
+        Ok(_) => {},
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+            return Err(Response::error(408, "timed out reading the request"));
+        },
+        Err(e) => return Err(Response::error(400, &e.to_string())),
+    };
+    let line = String::from_utf8(line).map_err(|_| Response::error(400, "request head is not UTF-8"))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+
+// read a request, with a body of at most max_body bytes, or the response to
+// send back if it can't be read
+pub fn read_request<R: BufRead> (reader: &mut R, max_body: usize) -> Result<Request, Response> {
+    let line = read_line(reader)?;
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(Response::error(400, "malformed request line"));
+    };
+    let path = target.split('?').next().unwrap_or(target).to_string();
+    let mut length: Option<usize> = Option::None;
+    let (mut headers, mut head) = (0, 0);
+    loop {
+        let header = read_line(reader)?;
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        head += header.len();
+        if headers > MAX_HEADERS {
+            return Err(Response::error(431, "too many headers"));
+        }
+        if head > MAX_HEAD {
+            return Err(Response::error(431, "headers too long"));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                let n = value.trim().parse::<usize>().map_err(|_| Response::error(400, "invalid Content-Length"))?;
+                length = Option::Some(n);
+            }
+            if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+                return Err(Response::error(411, "send the body with a Content-Length"));
+            }
+        }
+    }
+    let length = match (method, length) {
+        (_, Some(n)) if n > max_body => return Err(Response::error(413, &format!("request body is over {} bytes", max_body))),
+        (_, Some(n)) => n,
+        ("POST", None) => return Err(Response::error(411, "send the body with a Content-Length")),
+        (_, None) => 0,
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Response::error(408, "timed out reading the request"),
+        _ => Response::error(400, "request body is shorter than its Content-Length"),
+    })?;
+    Ok(Request { method: method.to_string(), path, body })
+}
+
+
+// run the program in a POST /run body
+fn run (pool: &pool::EvalPool, body: &[u8]) -> Response {
+    let doc = match std::str::from_utf8(body).map_err(|_| String::from("request body is not UTF-8")).and_then(json::parse) {
+        Ok(doc) => doc,
+        Err(e) => return Response::error(400, &e),
+    };
+    let Some(program) = doc.get("program").and_then(json::Value::as_str) else {
+        return Response::error(400, "the request needs a \"program\" string");
+    };
+    let input = match doc.get("input") {
+        None | Some(json::Value::Null) => Option::None,
+        Some(json::Value::String(input)) => Option::Some(input.as_bytes()),
+        Some(_) => return Response::error(400, "\"input\" must be a string"),
+    };
+    match pool.eval(program, input) {
+        Ok(result) => Response::json(200, json::run_document("program", &result.output, &result.report)),
+        Err(_) => Response::error(500, "the program could not be run"),
+    }
+}
+
+
+// answer a request
+pub fn handle (pool: &pool::EvalPool, request: &Request) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/run") => run(pool, &request.body),
+        ("GET", "/") => Response { status: 200, content_type: "text/html; charset=utf-8", body: String::from(PAGE) },
+        // preflight for calls from other origins
+        ("OPTIONS", "/run") => Response { status: 204, content_type: "text/plain", body: String::new() },
+        (_, "/run" | "/") => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "not found"),
+    }
+}
+
+
+// reads from and writes to a connection that time out once a deadline has
+// passed, however often the client sends or takes a byte
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+
+impl Read for Deadline<'_> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        self.stream.set_read_timeout(Option::Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+
+impl Write for Deadline<'_> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        self.stream.set_write_timeout(Option::Some(left))?;
+        self.stream.write(buf)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+// read a request off a connection, giving the client timeout to send all of it,
+// and answer it, giving the client as long again to take the response
+fn answer (stream: TcpStream, pool: &pool::EvalPool, max_body: usize, timeout: Duration) -> io::Result<()> {
+    let mut reader = BufReader::new(Deadline { stream: &stream, until: Instant::now() + timeout });
+    let response = match read_request(&mut reader, max_body) {
+        Ok(request) => handle(pool, &request),
+        Err(response) => response,
+    };
+    Deadline { stream: &stream, until: Instant::now() + timeout }.write_all(&response.to_bytes())
+}
+
+
+// answer connections forever on a fixed set of threads, a few for each of the
+// pool's, with programs run on the pool, connections that can't be accepted
+// are reported and skipped
+pub fn serve (listener: TcpListener, pool: Arc<pool::EvalPool>, max_body: usize) -> io::Result<()> {
+    let mut acceptors = Vec::new();
+    for _ in 0..pool.threads() * CONNECTIONS_PER_THREAD {
+        let (listener, pool) = (listener.try_clone()?, Arc::clone(&pool));
+        acceptors.push(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    // a client going away part way isn't the server's problem
+                    Ok(stream) => {
+                        let _ = answer(stream, &pool, max_body, REQUEST_TIMEOUT);
+                    },
+                    // e.g. running out of file descriptors, which takes a moment to clear up
+                    Err(e) => {
+                        eprintln!("error: unable to accept a connection: {}", e);
+                        thread::sleep(Duration::from_millis(100));
+                    },
+                };
+            }
+        }));
+    }
+    for acceptor in acceptors {
+        let _ = acceptor.join();
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{dtypes, interpreter};
+
+    fn pool () -> pool::EvalPool {
+        let limits = dtypes::Limits { max_steps: Some(1000), ..Default::default() };
+        pool::EvalPool::new(2, limits, || interpreter::BFInterpreter::new(100))
+    }
+
+    fn request (raw: &str) -> Result<Request, Response> {
+        read_request(&mut raw.as_bytes(), 64)
+    }
+
+    #[test]
+    fn server_read_request () {
+        assert_eq!(request("POST /run?x=1 HTTP/1.1\r\nHost: a\r\ncontent-length: 4\r\n\r\nbody"), Ok(Request {
+            method: String::from("POST"),
+            path: String::from("/run"),
+            body: b"body".to_vec(),
+        }));
+        assert_eq!(request("GET / HTTP/1.1\n\n").unwrap().body, b"");
+        assert_eq!(request("POST /run HTTP/1.1\r\n\r\n").unwrap_err().status, 411);
+        assert_eq!(request("POST /run HTTP/1.1\r\nContent-Length: 65\r\n\r\n").unwrap_err().status, 413);
+        assert_eq!(request("POST /run HTTP/1.1\r\nContent-Length: 9\r\n\r\nshort").unwrap_err().status, 400);
+        assert_eq!(request("nonsense\r\n\r\n").unwrap_err().status, 400);
+        assert_eq!(request("GET / HTTP/1.1\r\nHost: a").unwrap_err().status, 400);
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(request(&long).unwrap_err().status, 431);
+    }
+
+    #[test]
+    fn server_read_request_caps_headers () {
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(MAX_HEADERS));
+        assert!(request(&many).is_ok());
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(MAX_HEADERS + 1));
+        assert_eq!(request(&many).unwrap_err(), Response::error(431, "too many headers"));
+        // each under MAX_LINE, but too long together
+        let header = format!("X: {}\r\n", "a".repeat(MAX_LINE - 10));
+        let long = format!("GET / HTTP/1.1\r\n{}\r\n", header.repeat(MAX_HEAD / MAX_LINE + 1));
+        assert_eq!(request(&long).unwrap_err(), Response::error(431, "headers too long"));
+    }
+
+    #[test]
+    fn server_answer_deadline () {
+        // a client sending a byte at a time still has to finish in time
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let answering = thread::spawn(move || {
+            let start = Instant::now();
+            answer(stream, &pool(), 64, Duration::from_secs(1)).unwrap();
+            start.elapsed()
+        });
+        // it would be given another second after the last of these with only a
+        // timeout on each read
+        for byte in b"GET / HTTP/1.1\r\n" {
+            client.write_all(&[*byte]).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(answering.join().unwrap() < Duration::from_millis(1500));
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn server_answer_write_deadline () {
+        // a client that never reads its response doesn't hold the connection
+        // once the response is more than the socket will buffer
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let limits = dtypes::Limits { max_output_bytes: Some(1 << 21), ..Default::default() };
+        let pool = pool::EvalPool::new(1, limits, || interpreter::BFInterpreter::new(100));
+        let body = "{\"program\": \"+[.]\"}";
+        write!(client, "POST /run HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let error = answer(stream, &pool, 1024, Duration::from_secs(1)).unwrap_err();
+        assert!(matches!(error.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock), "{:?}", error);
+    }
+
+    #[test]
+    fn server_handle () {
+        let pool = pool();
+        let post = |body: &str| handle(&pool, &Request { method: String::from("POST"), path: String::from("/run"), body: body.as_bytes().to_vec() });
+        let response = post("{\"program\": \",+.\", \"input\": \"a\"}");
+        assert_eq!(response.status, 200);
+        assert!(response.body.starts_with("{\"program\":\"program\",\"exit_code\":0,\"output\":{\"text\":\"b\",\"base64\":\"Yg==\"},\"error\":null,"));
+        // errors, including the limits, are in the document
+        let response = post("{\"program\": \"+[]\"}");
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains("\"kind\":\"step_limit_exceeded\""));
+        assert!(post("{\"program\": \",.!x\", \"input\": null}").body.contains("\"text\":\"x\""));
+        assert_eq!(post("{\"input\": \"a\"}").body, "{\"error\":\"the request needs a \\\"program\\\" string\"}");
+        assert_eq!(post("{\"program\": \"+\", \"input\": 3}").status, 400);
+        assert_eq!(post("not json").status, 400);
+        let get = |method: &str, path: &str| handle(&pool, &Request { method: method.to_string(), path: path.to_string(), body: Vec::new() });
+        assert_eq!(get("GET", "/").content_type, "text/html; charset=utf-8");
+        assert_eq!(get("OPTIONS", "/run").status, 204);
+        assert_eq!(get("GET", "/run").status, 405);
+        assert_eq!(get("GET", "/other").status, 404);
+        let bytes = Response::error(404, "not found").to_bytes();
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: 21\r\n"));
+        assert!(text.ends_with("\r\n\r\n{\"error\":\"not found\"}"));
+    }
+
+    #[test]
+    fn server_serve () {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, Arc::new(pool()), 1024));
+        let body = "{\"program\": \"++++++++[>++++++++<-]>+.\"}";
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "POST /run HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\"output\":{\"text\":\"A\""));
+    }
+
+    #[test]
+    fn server_serve_bounded () {
+        // only so many connections are answered at once, the next waits for one of
+        // them to finish
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = Arc::new(pool());
+        let slots = pool.threads() * CONNECTIONS_PER_THREAD;
+        thread::spawn(move || serve(listener, pool, 1024));
+        let mut idle: Vec<TcpStream> = (0..slots).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        thread::sleep(Duration::from_millis(200));
+        let mut waiting = TcpStream::connect(addr).unwrap();
+        waiting.write_all(b"GET /other HTTP/1.1\r\n\r\n").unwrap();
+        waiting.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        assert!(waiting.read(&mut [0; 16]).is_err());
+        // hanging up frees a thread
+        idle.pop();
+        waiting.set_read_timeout(None).unwrap();
+        let mut response = String::new();
+        waiting.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
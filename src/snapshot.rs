@@ -0,0 +1,343 @@
+/*
+    Module with a binary format for saving interpreter state so that a
+    program can be suspended and carried on later
+
+    Layout (integers are little endian):
+        magic       b"BFSNAP"
+        version     u8
+        cell width  u8 (8, 16 or 32)
+        tape mode   u8 (0 strict, 1 wrapping, 2 growable, 3 bidirectional)
+        program     u64 fingerprint of the loaded instructions
+        data ptr    u64
+        instr ptr   u64
+        storage     u32 Extended Type I storage cell (from version 2, 0 before)
+        procedures  u64 count, then a u32 number and u64 start index for each
+                    pbrain procedure (from version 3, none before)
+        call stack  u64 count, then a u64 return index for each (from version 3)
+        origin      u64 index of the cell the program started on (from
+                    version 4, 0 before)
+        threads     u64 count, then a u64 instr ptr and u64 data ptr for each
+                    Brainfork thread waiting its turn (from version 5, none
+                    before)
+        cells       u64 count, then a u32 per cell
+        input       u8 (0 no pending input, 1 pending input), then a u64
+                    count and the bytes if there is any
+*/
+
+
+use std::collections::BTreeMap;
+use std::{error, fmt};
+
+use crate::dtypes;
+
+
+const MAGIC: &[u8] = b"BFSNAP";
+const VERSION: u8 = 5;
+
+
+// problems reading a snapshot back in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    // the bytes ended part way through the snapshot
+    Truncated,
+    // not a snapshot at all
+    BadMagic,
+    UnsupportedVersion(u8),
+    // a field held a value that can't be right
+    Corrupt(&'static str),
+    // taken with a different program loaded
+    ProgramMismatch,
+    // taken with a different cell width or tape mode
+    ConfigMismatch,
+}
+
+
+impl fmt::Display for SnapshotError {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Corrupt(field) => write!(f, "snapshot has an invalid {}", field),
+            SnapshotError::ProgramMismatch => write!(f, "snapshot was taken with a different program loaded"),
+            SnapshotError::ConfigMismatch => {
+                write!(f, "snapshot was taken with a different cell width or tape mode")
+            },
+        }
+    }
+}
+
+
+impl error::Error for SnapshotError {}
+
+
+// everything needed to carry on running a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub cell_width: dtypes::CellWidth,
+    pub tape_mode: dtypes::TapeMode,
+    // from program_fingerprint()
+    pub program: u64,
+    pub data_ptr: usize,
+    pub instr_ptr: usize,
+    pub storage: u32,
+    // pbrain procedures by number, with where their body starts
+    pub procedures: BTreeMap<u32, usize>,
+    // pbrain return addresses
+    pub call_stack: Vec<usize>,
+    // cells the tape grew to the left by
+    pub origin: usize,
+    // instruction and data pointers of the Brainfork threads waiting their
+    // turn, in the order they take it
+    pub threads: Vec<(usize, usize)>,
+    pub cells: Vec<u32>,
+    // input that had been supplied up front but not read yet (None if the
+    // program reads from a stream like stdin)
+    pub input: Option<Vec<u8>>,
+}
+
+
+// FNV-1a hash of the lowered instructions, so a snapshot is only restored
+// into the program it was taken from
+pub fn program_fingerprint (ops: &[dtypes::Op]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for op in ops {
+        for byte in format!("{}@{};", op.instr, op.position).bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+
+// reads fields off the front of a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+
+impl<'a> Reader<'a> {
+    fn take (&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < n {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8 (&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32 (&mut self) -> Result<u32, SnapshotError> {
+        let mut buf = [0_u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64 (&mut self) -> Result<u64, SnapshotError> {
+        let mut buf = [0_u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    // a u64 that has to fit in a usize
+    fn usize (&mut self, field: &'static str) -> Result<usize, SnapshotError> {
+        usize::try_from(self.u64()?).map_err(|_| SnapshotError::Corrupt(field))
+    }
+}
+
+
+impl Snapshot {
+    pub fn to_bytes (&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64 + self.cells.len() * 4);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(match self.cell_width {
+            dtypes::CellWidth::U8 => 8,
+            dtypes::CellWidth::U16 => 16,
+            dtypes::CellWidth::U32 => 32,
+        });
+        bytes.push(match self.tape_mode {
+            dtypes::TapeMode::Strict => 0,
+            dtypes::TapeMode::Wrapping => 1,
+            dtypes::TapeMode::Growable => 2,
+            dtypes::TapeMode::Bidirectional => 3,
+        });
+        bytes.extend_from_slice(&self.program.to_le_bytes());
+        bytes.extend_from_slice(&(self.data_ptr as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.instr_ptr as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.storage.to_le_bytes());
+        bytes.extend_from_slice(&(self.procedures.len() as u64).to_le_bytes());
+        for (id, start) in &self.procedures {
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.extend_from_slice(&(*start as u64).to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.call_stack.len() as u64).to_le_bytes());
+        for ret in &self.call_stack {
+            bytes.extend_from_slice(&(*ret as u64).to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.origin as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.threads.len() as u64).to_le_bytes());
+        for (instr_ptr, data_ptr) in &self.threads {
+            bytes.extend_from_slice(&(*instr_ptr as u64).to_le_bytes());
+            bytes.extend_from_slice(&(*data_ptr as u64).to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.cells.len() as u64).to_le_bytes());
+        for cell in &self.cells {
+            bytes.extend_from_slice(&cell.to_le_bytes());
+        }
+        match &self.input {
+            Some(input) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(input.len() as u64).to_le_bytes());
+                bytes.extend_from_slice(input);
+            },
+            None => bytes.push(0),
+        };
+        bytes
+    }
+
+    pub fn from_bytes (bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut r = Reader { bytes };
+        if r.take(MAGIC.len()).map_err(|_| SnapshotError::BadMagic)? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = r.u8()?;
+        // older versions are the same without the fields added since
+        if version == 0 || version > VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let cell_width = match r.u8()? {
+            8 => dtypes::CellWidth::U8,
+            16 => dtypes::CellWidth::U16,
+            32 => dtypes::CellWidth::U32,
+            _ => return Err(SnapshotError::Corrupt("cell width")),
+        };
+        let tape_mode = match r.u8()? {
+            0 => dtypes::TapeMode::Strict,
+            1 => dtypes::TapeMode::Wrapping,
+            2 => dtypes::TapeMode::Growable,
+            3 => dtypes::TapeMode::Bidirectional,
+            _ => return Err(SnapshotError::Corrupt("tape mode")),
+        };
+        let program = r.u64()?;
+        let data_ptr = r.usize("data pointer")?;
+        let instr_ptr = r.usize("instruction pointer")?;
+        let storage = if version < 2 { 0 } else { r.u32()? };
+        let mut procedures = BTreeMap::new();
+        let mut call_stack = Vec::new();
+        if version >= 3 {
+            // don't trust the counts for allocations, the data has to be there
+            for _ in 0..r.usize("procedure count")? {
+                let id = r.u32()?;
+                procedures.insert(id, r.usize("procedure start")?);
+            }
+            for _ in 0..r.usize("call stack depth")? {
+                call_stack.push(r.usize("return address")?);
+            }
+        }
+        let origin = if version < 4 { 0 } else { r.usize("origin")? };
+        let mut threads = Vec::new();
+        if version >= 5 {
+            for _ in 0..r.usize("thread count")? {
+                threads.push((r.usize("thread instruction pointer")?, r.usize("thread data pointer")?));
+            }
+        }
+        let n_cells = r.usize("cell count")?;
+        // don't trust the count for the allocation, the data has to be there
+        if n_cells == 0 || n_cells > r.bytes.len() / 4 {
+            return Err(if n_cells == 0 { SnapshotError::Corrupt("cell count") } else { SnapshotError::Truncated });
+        }
+        let cells = (0..n_cells).map(|_| r.u32()).collect::<Result<Vec<u32>, _>>()?;
+        if data_ptr >= cells.len() {
+            return Err(SnapshotError::Corrupt("data pointer"));
+        }
+        if origin >= cells.len() {
+            return Err(SnapshotError::Corrupt("origin"));
+        }
+        if threads.iter().any(|&(_, data_ptr)| data_ptr >= cells.len()) {
+            return Err(SnapshotError::Corrupt("thread data pointer"));
+        }
+        if storage > cell_width.max_value() || cells.iter().any(|&cell| cell > cell_width.max_value()) {
+            return Err(SnapshotError::Corrupt("cell value"));
+        }
+        let input = match r.u8()? {
+            0 => Option::None,
+            1 => {
+                let n = r.usize("input length")?;
+                Option::Some(r.take(n)?.to_vec())
+            },
+            _ => return Err(SnapshotError::Corrupt("input flag")),
+        };
+        if !r.bytes.is_empty() {
+            return Err(SnapshotError::Corrupt("length"));
+        }
+        Ok(Snapshot { cell_width, tape_mode, program, data_ptr, instr_ptr, storage, procedures, call_stack, origin, threads, cells, input })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn snapshot () -> Snapshot {
+        Snapshot {
+            cell_width: dtypes::CellWidth::U16,
+            tape_mode: dtypes::TapeMode::Growable,
+            program: 0xdeadbeef,
+            data_ptr: 2,
+            instr_ptr: 7,
+            storage: 9,
+            procedures: BTreeMap::from([(1, 4), (7, 12)]),
+            call_stack: vec![20, 3],
+            origin: 1,
+            threads: vec![(5, 0), (8, 2)],
+            cells: vec![1, 65535, 3],
+            input: Some(b"rest".to_vec()),
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip () {
+        let snap = snapshot();
+        assert_eq!(Snapshot::from_bytes(&snap.to_bytes()), Ok(snap.clone()));
+        let snap = Snapshot { input: None, ..snap };
+        assert_eq!(Snapshot::from_bytes(&snap.to_bytes()), Ok(snap.clone()));
+        // version 1 snapshots have no storage cell, procedures, origin or threads
+        let snap = Snapshot { procedures: BTreeMap::new(), call_stack: Vec::new(), origin: 0, threads: Vec::new(), ..snap };
+        let mut old = snap.to_bytes();
+        old[6] = 1;
+        old.drain(33..69);
+        assert_eq!(Snapshot::from_bytes(&old), Ok(Snapshot { storage: 0, ..snap }));
+    }
+
+    #[test]
+    fn snapshot_bad_bytes () {
+        let bytes = snapshot().to_bytes();
+        assert_eq!(Snapshot::from_bytes(b"nope"), Err(SnapshotError::BadMagic));
+        assert_eq!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]), Err(SnapshotError::Truncated));
+        let mut wrong = bytes.clone();
+        wrong[6] = 99;
+        assert_eq!(Snapshot::from_bytes(&wrong), Err(SnapshotError::UnsupportedVersion(99)));
+        let mut wrong = bytes.clone();
+        wrong[7] = 12;
+        assert_eq!(Snapshot::from_bytes(&wrong), Err(SnapshotError::Corrupt("cell width")));
+        let mut wrong = bytes.clone();
+        wrong.push(0);
+        assert_eq!(Snapshot::from_bytes(&wrong), Err(SnapshotError::Corrupt("length")));
+    }
+
+    #[test]
+    fn program_fingerprints_differ () {
+        let op = |instr| dtypes::Op { instr, position: 0 };
+        let a = program_fingerprint(&[op(dtypes::Instr::Add(1))]);
+        assert_eq!(a, program_fingerprint(&[op(dtypes::Instr::Add(1))]));
+        assert_ne!(a, program_fingerprint(&[op(dtypes::Instr::Add(2))]));
+        assert_ne!(a, program_fingerprint(&[]));
+    }
+}
@@ -0,0 +1,515 @@
+/*
+    Module with the memory tapes the interpreter runs programs on, each
+    backend decides what happens when the data pointer goes past either
+    end and how the cells are stored:
+
+        FixedTape           a fixed array, moving off either end is an error
+        GrowableTape        a vec that grows to the right
+        WrappingTape        a ring, moving off one end comes back on the other
+        BidirectionalTape   a vec that grows in both directions
+        SparseTape          pages of cells are only stored once they are
+                            written to, so huge tapes cost nothing until
+                            they are used
+*/
+
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::dtypes;
+
+
+// the memory cells, indexes are positions in the cells currently held, which
+// for a tape that grows to the left start some way left of where the program
+// started
+pub trait Tape: fmt::Debug {
+    // how moving off either end is handled
+    fn mode (&self) -> dtypes::TapeMode;
+
+    // number of cells, including any a sparse tape isn't storing
+    fn len (&self) -> usize;
+
+    fn is_empty (&self) -> bool {
+        self.len() == 0
+    }
+
+    // value of the cell at index, which has to be less than len()
+    fn get (&self, index: usize) -> u32;
+
+    fn set (&mut self, index: usize, val: u32);
+
+    // index of the cell n cells right of index, growing the tape if it can,
+    // None if the cell is off the end
+    fn move_right (&mut self, index: usize, n: usize) -> Option<usize>;
+
+    // index of the cell n cells left of index, growing the tape to the left
+    // moves every cell along (see origin()), None if the cell is off the end
+    fn move_left (&mut self, index: usize, n: usize) -> Option<usize>;
+
+    // how many of the cells are left of where the program started, i.e. the index
+    // of the cell that was index 0 before the tape grew to the left
+    fn origin (&self) -> usize {
+        0
+    }
+
+    // drop cells past len added by growing to the right
+    fn truncate (&mut self, _len: usize) {}
+
+    // the cells as a single slice, None for tapes that don't hold them that way
+    fn cells (&self) -> Option<&[u32]> {
+        Option::None
+    }
+
+    fn cells_mut (&mut self) -> Option<&mut [u32]> {
+        Option::None
+    }
+
+    // replace every cell, e.g. with the ones from a snapshot
+    fn load (&mut self, cells: Vec<u32>, origin: usize);
+
+    // index of the nearest cell holding 0 from index (including it) towards the
+    // right or left end, None if there isn't one before the end
+    fn find_zero (&self, index: usize, right: bool) -> Option<usize> {
+        match (self.cells(), right) {
+            (Some(cells), true) => cells[index..].iter().position(|&c| c == 0).map(|i| index + i),
+            (Some(cells), false) => cells[..=index].iter().rposition(|&c| c == 0),
+            (None, true) => (index..self.len()).find(|&i| self.get(i) == 0),
+            (None, false) => (0..=index).rev().find(|&i| self.get(i) == 0),
+        }
+    }
+
+    // index of the cell offset cells away from index, see move_right() and move_left()
+    fn offset (&mut self, index: usize, offset: isize) -> Option<usize> {
+        match offset < 0 {
+            true => self.move_left(index, offset.unsigned_abs()),
+            false => self.move_right(index, offset as usize),
+        }
+    }
+}
+
+
+// tape of zeroed cells using the usual backend for a mode
+pub fn new_tape (mode: dtypes::TapeMode, size: usize) -> Box<dyn Tape> {
+    match mode {
+        dtypes::TapeMode::Strict => Box::new(FixedTape::new(size)),
+        dtypes::TapeMode::Wrapping => Box::new(WrappingTape::new(size)),
+        dtypes::TapeMode::Growable => Box::new(GrowableTape::new(size)),
+        dtypes::TapeMode::Bidirectional => Box::new(BidirectionalTape::new(size)),
+    }
+}
+
+
+// the parts of Tape that are the same for every backend holding a vec of cells
+macro_rules! vec_cells {
+    () => {
+        fn len (&self) -> usize {
+            self.cells.len()
+        }
+
+        fn get (&self, index: usize) -> u32 {
+            self.cells[index]
+        }
+
+        fn set (&mut self, index: usize, val: u32) {
+            self.cells[index] = val;
+        }
+
+        fn cells (&self) -> Option<&[u32]> {
+            Option::Some(&self.cells)
+        }
+
+        fn cells_mut (&mut self) -> Option<&mut [u32]> {
+            Option::Some(&mut self.cells)
+        }
+    };
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedTape {
+    cells: Vec<u32>,
+}
+
+
+impl FixedTape {
+    pub fn new (size: usize) -> FixedTape {
+        FixedTape { cells: vec![0; size] }
+    }
+}
+
+
+impl Tape for FixedTape {
+    vec_cells!();
+
+    fn mode (&self) -> dtypes::TapeMode {
+        dtypes::TapeMode::Strict
+    }
+
+    fn move_right (&mut self, index: usize, n: usize) -> Option<usize> {
+        index.checked_add(n).filter(|&i| i < self.cells.len())
+    }
+
+    fn move_left (&mut self, index: usize, n: usize) -> Option<usize> {
+        index.checked_sub(n)
+    }
+
+    fn load (&mut self, cells: Vec<u32>, _origin: usize) {
+        self.cells = cells;
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrowableTape {
+    cells: Vec<u32>,
+}
+
+
+impl GrowableTape {
+    pub fn new (size: usize) -> GrowableTape {
+        GrowableTape { cells: vec![0; size] }
+    }
+}
+
+
+impl Tape for GrowableTape {
+    vec_cells!();
+
+    fn mode (&self) -> dtypes::TapeMode {
+        dtypes::TapeMode::Growable
+    }
+
+    fn move_right (&mut self, index: usize, n: usize) -> Option<usize> {
+        let target = index.checked_add(n)?;
+        if target >= self.cells.len() {
+            self.cells.resize(target + 1, 0);
+        }
+        Option::Some(target)
+    }
+
+    fn move_left (&mut self, index: usize, n: usize) -> Option<usize> {
+        index.checked_sub(n)
+    }
+
+    fn truncate (&mut self, len: usize) {
+        self.cells.truncate(len);
+    }
+
+    fn load (&mut self, cells: Vec<u32>, _origin: usize) {
+        self.cells = cells;
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrappingTape {
+    cells: Vec<u32>,
+}
+
+
+impl WrappingTape {
+    pub fn new (size: usize) -> WrappingTape {
+        WrappingTape { cells: vec![0; size] }
+    }
+}
+
+
+impl Tape for WrappingTape {
+    vec_cells!();
+
+    fn mode (&self) -> dtypes::TapeMode {
+        dtypes::TapeMode::Wrapping
+    }
+
+    fn move_right (&mut self, index: usize, n: usize) -> Option<usize> {
+        Option::Some((index + n % self.cells.len()) % self.cells.len())
+    }
+
+    fn move_left (&mut self, index: usize, n: usize) -> Option<usize> {
+        let len = self.cells.len();
+        Option::Some((index + len - n % len) % len)
+    }
+
+    fn load (&mut self, cells: Vec<u32>, _origin: usize) {
+        self.cells = cells;
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BidirectionalTape {
+    cells: Vec<u32>,
+    // number of cells added to the left of the original start
+    origin: usize,
+}
+
+
+impl BidirectionalTape {
+    pub fn new (size: usize) -> BidirectionalTape {
+        BidirectionalTape { cells: vec![0; size], origin: 0 }
+    }
+}
+
+
+impl Tape for BidirectionalTape {
+    vec_cells!();
+
+    fn mode (&self) -> dtypes::TapeMode {
+        dtypes::TapeMode::Bidirectional
+    }
+
+    fn move_right (&mut self, index: usize, n: usize) -> Option<usize> {
+        let target = index.checked_add(n)?;
+        if target >= self.cells.len() {
+            self.cells.resize(target + 1, 0);
+        }
+        Option::Some(target)
+    }
+
+    fn move_left (&mut self, index: usize, n: usize) -> Option<usize> {
+        if let Some(target) = index.checked_sub(n) {
+            return Option::Some(target);
+        }
+        // grow by at least as much again so that walking left isn't quadratic
+        let short = n - index;
+        let grow = short.max(self.cells.len()).max(1);
+        self.cells.splice(0..0, std::iter::repeat_n(0, grow));
+        self.origin += grow;
+        Option::Some(grow - short)
+    }
+
+    fn origin (&self) -> usize {
+        self.origin
+    }
+
+    fn truncate (&mut self, len: usize) {
+        self.cells.truncate(len);
+    }
+
+    fn load (&mut self, cells: Vec<u32>, origin: usize) {
+        self.cells = cells;
+        self.origin = origin;
+    }
+}
+
+
+// number of cells in each page of a SparseTape
+pub const PAGE_SIZE: usize = 4096;
+
+
+// a tape of any mode that only stores the pages of cells that have been written
+// to, keyed by their position relative to where the program started so that
+// growing to the left doesn't have to move anything
+#[derive(Clone, PartialEq, Eq)]
+pub struct SparseTape {
+    // page number (which may be negative) to its cells
+    pages: HashMap<isize, Box<[u32]>>,
+    mode: dtypes::TapeMode,
+    len: usize,
+    origin: usize,
+}
+
+
+// listing every page would be far too much, just say how many there are
+impl fmt::Debug for SparseTape {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SparseTape")
+            .field("pages", &self.pages.len())
+            .field("mode", &self.mode)
+            .field("len", &self.len)
+            .field("origin", &self.origin)
+            .finish()
+    }
+}
+
+
+impl SparseTape {
+    pub fn new (mode: dtypes::TapeMode, size: usize) -> SparseTape {
+        SparseTape { pages: HashMap::new(), mode, len: size, origin: 0 }
+    }
+
+    // number of pages allocated so far
+    pub fn pages (&self) -> usize {
+        self.pages.len()
+    }
+
+    // page number and position in the page of the cell at index
+    fn locate (&self, index: usize) -> (isize, usize) {
+        let position = index as isize - self.origin as isize;
+        (position.div_euclid(PAGE_SIZE as isize), position.rem_euclid(PAGE_SIZE as isize) as usize)
+    }
+}
+
+
+impl Tape for SparseTape {
+    fn mode (&self) -> dtypes::TapeMode {
+        self.mode
+    }
+
+    fn len (&self) -> usize {
+        self.len
+    }
+
+    fn get (&self, index: usize) -> u32 {
+        let (page, i) = self.locate(index);
+        self.pages.get(&page).map_or(0, |cells| cells[i])
+    }
+
+    fn set (&mut self, index: usize, val: u32) {
+        let (page, i) = self.locate(index);
+        // cells not in a page are already 0
+        if val == 0 && !self.pages.contains_key(&page) {
+            return;
+        }
+        self.pages.entry(page).or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice())[i] = val;
+    }
+
+    fn move_right (&mut self, index: usize, n: usize) -> Option<usize> {
+        let target = index.checked_add(n)?;
+        if target < self.len {
+            return Option::Some(target);
+        }
+        match self.mode {
+            dtypes::TapeMode::Wrapping => Option::Some((index + n % self.len) % self.len),
+            dtypes::TapeMode::Growable | dtypes::TapeMode::Bidirectional => {
+                self.len = target + 1;
+                Option::Some(target)
+            },
+            dtypes::TapeMode::Strict => Option::None,
+        }
+    }
+
+    fn move_left (&mut self, index: usize, n: usize) -> Option<usize> {
+        if let Some(target) = index.checked_sub(n) {
+            return Option::Some(target);
+        }
+        match self.mode {
+            dtypes::TapeMode::Wrapping => Option::Some((index + self.len - n % self.len) % self.len),
+            // growing is free, so only by as much as is needed
+            dtypes::TapeMode::Bidirectional => {
+                let short = n - index;
+                self.origin += short;
+                self.len += short;
+                Option::Some(0)
+            },
+            dtypes::TapeMode::Growable | dtypes::TapeMode::Strict => Option::None,
+        }
+    }
+
+    fn origin (&self) -> usize {
+        self.origin
+    }
+
+    fn truncate (&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        // drop whole pages past the end and clear the rest of the last one
+        let (last, i) = self.locate(len);
+        self.pages.retain(|&page, _| page < last || (page == last && i > 0));
+        if let Some(cells) = self.pages.get_mut(&last) {
+            cells[i..].fill(0);
+        }
+        self.len = len;
+    }
+
+    fn load (&mut self, cells: Vec<u32>, origin: usize) {
+        self.pages.clear();
+        self.len = cells.len();
+        self.origin = origin;
+        for (i, val) in cells.into_iter().enumerate() {
+            self.set(i, val);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn tape_offsets () {
+        let mut strict = FixedTape::new(4);
+        assert_eq!((strict.offset(1, 2), strict.offset(1, 3), strict.offset(1, -2)), (Some(3), None, None));
+        let mut wrapping = WrappingTape::new(4);
+        assert_eq!((wrapping.offset(1, 3), wrapping.offset(1, -2), wrapping.offset(1, -9)), (Some(0), Some(3), Some(0)));
+        let mut growable = GrowableTape::new(4);
+        assert_eq!((growable.offset(1, 5), growable.offset(1, -2)), (Some(6), None));
+        assert_eq!(growable.len(), 7);
+        assert_eq!(wrapping.len() + strict.len(), 8);
+    }
+
+    #[test]
+    fn tape_grows_left () {
+        let mut tape = BidirectionalTape::new(2);
+        tape.set(0, 7);
+        // the original cells move along by however much the tape grew
+        assert_eq!(tape.offset(0, -1), Some(1));
+        assert_eq!((tape.origin(), tape.cells()), (2, Some(&[0, 0, 7, 0][..])));
+        assert_eq!(tape.offset(0, -5), Some(0));
+        assert_eq!((tape.origin(), tape.len()), (7, 9));
+        assert_eq!(tape.get(7), 7);
+        assert_eq!(tape.offset(8, 2), Some(10));
+    }
+
+    #[test]
+    fn sparse_tape_matches_vec_tapes () {
+        // the same moves and writes give the same cells whichever way they're stored
+        let moves = [3, -5, 2, 9, -1, -20, 4];
+        for mode in [dtypes::TapeMode::Strict, dtypes::TapeMode::Wrapping, dtypes::TapeMode::Growable, dtypes::TapeMode::Bidirectional] {
+            let (mut dense, mut sparse) = (new_tape(mode, 6), SparseTape::new(mode, 6));
+            let (mut i, mut j) = (0, 0);
+            for (k, &offset) in moves.iter().enumerate() {
+                match (dense.offset(i, offset), sparse.offset(j, offset)) {
+                    (Some(a), Some(b)) => (i, j) = (a, b),
+                    (None, None) => {},
+                    other => panic!("{:?} moves differ: {:?}", mode, other),
+                };
+                dense.set(i, k as u32 + 1);
+                sparse.set(j, k as u32 + 1);
+            }
+            // a bidirectional vec grows further to the left, so compare positions
+            // relative to where the program started
+            let nonzero = |tape: &dyn Tape, at: usize| {
+                let origin = tape.origin() as isize;
+                let cells: Vec<(isize, u32)> = (0..tape.len()).filter(|&i| tape.get(i) != 0).map(|i| (i as isize - origin, tape.get(i))).collect();
+                (cells, at as isize - origin)
+            };
+            assert_eq!(nonzero(dense.as_ref(), i), nonzero(&sparse, j), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn tape_find_zero () {
+        let mut dense = GrowableTape::new(6);
+        let mut sparse = SparseTape::new(dtypes::TapeMode::Growable, 6);
+        for tape in [&mut dense as &mut dyn Tape, &mut sparse] {
+            for (i, val) in [0, 3, 4, 0, 5, 6].into_iter().enumerate() {
+                tape.set(i, val);
+            }
+            assert_eq!((tape.find_zero(1, true), tape.find_zero(3, true), tape.find_zero(4, true)), (Some(3), Some(3), None));
+            assert_eq!((tape.find_zero(2, false), tape.find_zero(5, false), tape.find_zero(0, false)), (Some(0), Some(3), Some(0)));
+        }
+    }
+
+    #[test]
+    fn sparse_tape_allocates_pages_as_needed () {
+        let mut tape = SparseTape::new(dtypes::TapeMode::Growable, 1_000_000_000);
+        let far = tape.offset(0, 999_999_999).unwrap();
+        tape.set(far, 5);
+        tape.set(3, 0);
+        assert_eq!((tape.get(far), tape.get(3), tape.pages()), (5, 0, 1));
+        tape.set(3, 1);
+        tape.set(PAGE_SIZE + 1, 2);
+        assert_eq!(tape.offset(far, 2), Some(1_000_000_001));
+        // cutting back down drops the pages past the end and clears the last one
+        tape.truncate(PAGE_SIZE + 1);
+        assert_eq!((tape.len(), tape.pages(), tape.get(3)), (PAGE_SIZE + 1, 2, 1));
+        tape.offset(PAGE_SIZE, 1);
+        assert_eq!(tape.get(PAGE_SIZE + 1), 0);
+        tape.load(vec![0, 4, 0], 1);
+        assert_eq!((tape.get(1), tape.pages(), tape.origin()), (4, 1, 1));
+    }
+}
@@ -0,0 +1,85 @@
+/*
+    Module for reading keystrokes from the terminal as soon as they are typed,
+    rather than a line at a time once enter is pressed, for interactive
+    programs like games. Keys aren't echoed either (the program prints what it
+    wants to), but Ctrl-C still interrupts the program and enter still reads
+    as a newline. The terminal is put back how it was when raw input is
+    dropped, when the program panics, or by calling restore() before exiting
+*/
+
+
+use std::io;
+use std::panic;
+use std::sync::Mutex;
+
+
+// the settings to put back, while raw input is on
+#[cfg(unix)]
+static SAVED: Mutex<Option<libc::termios>> = Mutex::new(Option::None);
+
+
+// raw input is on for as long as this is held
+pub struct RawInput {
+    _private: (),
+}
+
+
+impl RawInput {
+    // turn raw input on for stdin, which has to be a terminal
+    #[cfg(unix)]
+    pub fn enable () -> io::Result<RawInput> {
+        let fd = libc::STDIN_FILENO;
+        // SAFETY: isatty only looks at the file descriptor
+        if unsafe { libc::isatty(fd) } != 1 {
+            return Err(io::Error::other("stdin is not a terminal"));
+        }
+        // SAFETY: termios is plain data, which tcgetattr fills in
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        // hand over each byte as soon as there is one
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: raw is a valid termios from tcgetattr
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        *SAVED.lock().unwrap_or_else(|e| e.into_inner()) = Option::Some(saved);
+        // put the terminal back before the message is printed, in case the
+        // panic doesn't unwind
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore();
+            hook(info);
+        }));
+        Ok(RawInput { _private: () })
+    }
+
+    #[cfg(not(unix))]
+    pub fn enable () -> io::Result<RawInput> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "raw input is only supported on unix"))
+    }
+}
+
+
+// put the terminal back how it was before raw input was turned on, does
+// nothing if it isn't on
+pub fn restore () {
+    #[cfg(unix)]
+    if let Some(saved) = SAVED.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        // SAFETY: saved came from tcgetattr
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+        }
+    }
+}
+
+
+impl Drop for RawInput {
+    fn drop (&mut self) {
+        restore();
+    }
+}
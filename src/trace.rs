@@ -0,0 +1,106 @@
+/*
+    Module with hooks for logging every instruction the interpreter executes
+*/
+
+
+use std::io::Write;
+
+use crate::dtypes;
+
+
+// everything known about one executed instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    // number of instructions executed before this one
+    pub step: u64,
+    // index of the instruction
+    pub index: usize,
+    // index of the source command it came from
+    pub position: usize,
+    pub instr: dtypes::Instr,
+    // data pointer when the instruction started
+    pub data_ptr: usize,
+    // value of the cell at data_ptr before and after the instruction
+    pub before: u32,
+    pub after: u32,
+    // byte read by `,` (None for other instructions and at the end of input)
+    pub input: Option<u8>,
+}
+
+
+// receives an event after each instruction executes, set one with
+// BFInterpreter::set_tracer(), any FnMut(&TraceEvent) closure is a tracer
+pub trait Tracer {
+    fn trace (&mut self, event: &TraceEvent);
+
+    // called once the program has finished or stopped on an error
+    fn finish (&mut self) {}
+}
+
+
+impl<F: FnMut(&TraceEvent)> Tracer for F {
+    fn trace (&mut self, event: &TraceEvent) {
+        self(event)
+    }
+}
+
+
+// tracer that writes one line per event, e.g. to stderr or a file
+pub struct WriteTracer<W: Write> {
+    out: W,
+}
+
+
+impl<W: Write> WriteTracer<W> {
+    pub fn new (out: W) -> WriteTracer<W> {
+        WriteTracer { out }
+    }
+}
+
+
+impl<W: Write> Tracer for WriteTracer<W> {
+    // tracing is best effort, a failed write shouldn't stop the program
+    fn trace (&mut self, event: &TraceEvent) {
+        let _ = writeln!(self.out, "{}", format_event(event));
+    }
+
+    fn finish (&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+
+// one line description of an event: step, instruction index, source command,
+// instruction, data pointer then the cell value before and after
+pub fn format_event (event: &TraceEvent) -> String {
+    format!("{:>8} {:>6} {:>6}  {:<16} ptr {:<6} {} -> {}",
+            event.step, event.index, event.position, event.instr.to_string(),
+            event.data_ptr, event.before, event.after)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn format_trace_event () {
+        let event = TraceEvent {
+            step: 12,
+            index: 3,
+            position: 5,
+            instr: dtypes::Instr::Add(2),
+            data_ptr: 1,
+            before: 7,
+            after: 9,
+            input: None,
+        };
+        assert_eq!(format_event(&event), "      12      3      5  +2               ptr 1      7 -> 9");
+        let mut out = Vec::new();
+        let mut tracer = WriteTracer::new(&mut out);
+        tracer.trace(&event);
+        tracer.finish();
+        assert_eq!(out, format!("{}\n", format_event(&event)).into_bytes());
+    }
+}
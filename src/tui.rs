@@ -0,0 +1,290 @@
+/*
+    Module with a full screen terminal view of a program as it runs, with the
+    source, output and tape, only built with the tui feature
+*/
+
+
+use std::io;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::Frame;
+
+use rust_bfi::{dtypes, interpreter};
+
+
+// how long to wait for a key between frames while the program is running
+const FRAME_TIME: Duration = Duration::from_millis(50);
+
+
+const HELP: &str = " space step  r run/pause  +/- speed  up/down/pgup/pgdn scroll tape  f follow  q quit";
+
+
+pub struct Tui<'a> {
+    // program source with the line and column of each command in it
+    source: &'a str,
+    positions: &'a [dtypes::SourcePos],
+    running: bool,
+    // instructions executed per frame while running
+    speed: u64,
+    // first row of the tape shown, None to keep the data pointer in view
+    tape_row: Option<usize>,
+    // first row and number of rows of the tape shown the last time it was drawn
+    tape_top: usize,
+    tape_height: usize,
+    result: Option<Result<(), dtypes::BFError>>,
+}
+
+
+impl<'a> Tui<'a> {
+    pub fn new (source: &'a str, positions: &'a [dtypes::SourcePos]) -> Tui<'a> {
+        Tui {
+            source,
+            positions,
+            running: false,
+            speed: 1,
+            tape_row: Option::None,
+            tape_top: 0,
+            tape_height: 0,
+            result: Option::None,
+        }
+    }
+
+    // step once, unless the program has already stopped
+    fn step (&mut self, bfi: &mut interpreter::BFInterpreter) {
+        if self.result.is_some() {
+            return;
+        }
+        match bfi.step() {
+            Err(e) => self.result = Option::Some(Err(e)),
+            Ok(()) if bfi.is_finished() => self.result = Option::Some(Ok(())),
+            Ok(()) => {},
+        };
+        if self.result.is_some() {
+            self.running = false;
+        }
+    }
+
+    // act on a key press, returns false to quit
+    fn handle_key (&mut self, key: KeyCode, bfi: &mut interpreter::BFInterpreter) -> bool {
+        let scroll = |tui: &mut Tui, rows: isize| {
+            tui.tape_row = Option::Some(tui.tape_row.unwrap_or(tui.tape_top).saturating_add_signed(rows));
+        };
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char(' ') | KeyCode::Char('s') => {
+                self.running = false;
+                self.step(bfi);
+            },
+            KeyCode::Char('r') | KeyCode::Enter => self.running = !self.running && self.result.is_none(),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.speed = (self.speed * 10).min(1_000_000),
+            KeyCode::Char('-') => self.speed = (self.speed / 10).max(1),
+            KeyCode::Up => scroll(self, -1),
+            KeyCode::Down => scroll(self, 1),
+            KeyCode::PageUp => scroll(self, -(self.tape_height as isize)),
+            KeyCode::PageDown => scroll(self, self.tape_height as isize),
+            KeyCode::Char('f') => self.tape_row = Option::None,
+            _ => {},
+        };
+        true
+    }
+
+    // cells in each row of the tape and the width of each one for an area
+    fn tape_layout (area: Rect, bfi: &interpreter::BFInterpreter) -> (usize, usize) {
+        let widest = bfi.memory().iter().max().copied().unwrap_or(0);
+        let cell_width = widest.to_string().len().max(3);
+        // room for the index of the first cell in the row
+        let room = (area.width as usize).saturating_sub(2 + 8);
+        ((room / (cell_width + 1)).max(1), cell_width)
+    }
+
+    // the program with the command about to run highlighted
+    fn draw_source (&self, frame: &mut Frame, area: Rect, bfi: &interpreter::BFInterpreter) {
+        let current = bfi.instructions().get(bfi.instruction_pointer()).and_then(|op| self.positions.get(op.position));
+        let lines: Vec<Line> = self.source.lines().enumerate().map(|(i, line)| {
+            match current {
+                Some(pos) if pos.line == i + 1 => {
+                    let before: String = line.chars().take(pos.column - 1).collect();
+                    let at: String = line.chars().skip(pos.column - 1).take(1).collect();
+                    let after: String = line.chars().skip(pos.column).collect();
+                    Line::from(vec![Span::raw(before), Span::styled(at, Style::new().reversed()), Span::raw(after)])
+                },
+                _ => Line::raw(line),
+            }
+        }).collect();
+        // keep the current command in view
+        let (rows, cols) = (area.height.saturating_sub(2) as usize, area.width.saturating_sub(2) as usize);
+        let (top, left) = match current {
+            Some(pos) => ((pos.line - 1).saturating_sub(rows / 2), pos.column.saturating_sub(cols)),
+            None => (0, 0),
+        };
+        let source = Paragraph::new(Text::from(lines))
+            .scroll((top as u16, left as u16))
+            .block(Block::bordered().title(" source "));
+        frame.render_widget(source, area);
+    }
+
+    // the end of the program's output
+    fn draw_output (&self, frame: &mut Frame, area: Rect, bfi: &interpreter::BFInterpreter) {
+        let (rows, cols) = (area.height.saturating_sub(2) as usize, area.width.saturating_sub(2).max(1) as usize);
+        // wrap long lines and show the end of the output
+        let text = String::from_utf8_lossy(bfi.output());
+        let mut lines: Vec<String> = Vec::new();
+        for line in text.split('\n') {
+            let chars: Vec<char> = line.chars().filter(|c| !c.is_control()).collect();
+            if chars.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend(chars.chunks(cols).map(|chunk| chunk.iter().collect::<String>()));
+        }
+        let shown: Vec<Line> = lines[lines.len().saturating_sub(rows)..].iter().map(|l| Line::raw(l.clone())).collect();
+        frame.render_widget(Paragraph::new(Text::from(shown)).block(Block::bordered().title(" output ")), area);
+    }
+
+    // rows of cells with the one under the data pointer highlighted
+    fn draw_tape (&mut self, frame: &mut Frame, area: Rect, bfi: &interpreter::BFInterpreter) {
+        let (columns, cell_width) = Tui::tape_layout(area, bfi);
+        let (size, ptr) = (bfi.memory_size(), bfi.data_pointer());
+        let rows = size.div_ceil(columns);
+        self.tape_height = area.height.saturating_sub(2) as usize;
+        let top = match self.tape_row {
+            Some(row) => row.min(rows.saturating_sub(1)),
+            None => (ptr / columns).saturating_sub(self.tape_height / 2),
+        };
+        self.tape_top = top;
+        let lines: Vec<Line> = (top..rows.min(top + self.tape_height)).map(|row| {
+            let start = row * columns;
+            let mut spans = vec![Span::raw(format!("{:>7} ", start))];
+            for (i, val) in (start..size.min(start + columns)).filter_map(|i| Option::Some((i, bfi.cell(i)?))) {
+                let cell = format!("{:>width$}", val, width = cell_width);
+                spans.push(Span::raw(" "));
+                spans.push(if i == ptr { Span::styled(cell, Style::new().reversed()) } else { Span::raw(cell) });
+            }
+            Line::from(spans)
+        }).collect();
+        frame.render_widget(Paragraph::new(Text::from(lines)).block(Block::bordered().title(" tape ")), area);
+    }
+
+    // where the program is up to and the keys
+    fn draw_status (&self, frame: &mut Frame, area: Rect, bfi: &interpreter::BFInterpreter) {
+        let state = match &self.result {
+            Some(Ok(())) => String::from("finished"),
+            Some(Err(e)) => format!("error: {}", e),
+            None if self.running => format!("running, {} per frame", self.speed),
+            None => format!("paused, {} per frame", self.speed),
+        };
+        let status = format!(" step {}  command {}  data pointer {}  {}",
+                             bfi.steps_executed(), bfi.instruction_pointer(), bfi.data_pointer(), state);
+        let text = Text::from(vec![Line::raw(status).bold(), Line::raw(HELP)]);
+        frame.render_widget(Paragraph::new(text), area);
+    }
+
+    // source and output side by side above the tape, with the status at the bottom
+    pub fn draw (&mut self, frame: &mut Frame, bfi: &interpreter::BFInterpreter) {
+        let [top, tape, status] = Layout::vertical([Constraint::Percentage(50), Constraint::Fill(1), Constraint::Length(2)])
+            .areas(frame.area());
+        let [source, output] = Layout::horizontal([Constraint::Percentage(60), Constraint::Fill(1)]).areas(top);
+        self.draw_source(frame, source, bfi);
+        self.draw_output(frame, output, bfi);
+        self.draw_tape(frame, tape, bfi);
+        self.draw_status(frame, status, bfi);
+    }
+}
+
+
+// watch a loaded program step in a full screen view until it is quit, returns
+// the result of the program (if it finished)
+pub fn run (bfi: &mut interpreter::BFInterpreter, source: &str, positions: &[dtypes::SourcePos])
+        -> io::Result<Option<Result<(), dtypes::BFError>>> {
+    let mut tui = Tui::new(source, positions);
+    let mut terminal = ratatui::init();
+    let result = (|| loop {
+        terminal.draw(|frame| tui.draw(frame, bfi))?;
+        let timeout = if tui.running { FRAME_TIME } else { Duration::from_secs(1) };
+        if event::poll(timeout)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !tui.handle_key(key.code, bfi) {
+                    return Ok(());
+                }
+            }
+        } else if tui.running {
+            for _ in 0..tui.speed {
+                tui.step(bfi);
+                if !tui.running {
+                    break;
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+    result.map(|()| tui.result)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use rust_bfi::parsing;
+
+    // draw on a fake terminal and return what ended up on screen, a line per row
+    fn render (tui: &mut Tui, bfi: &interpreter::BFInterpreter, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| tui.draw(frame, bfi)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height).map(|y| (0..width).map(|x| buffer[(x, y)].symbol()).collect::<String>()).collect()
+    }
+
+    #[test]
+    fn tui_shows_state () {
+        let source = "++++++++[>++++++++<-]>+.\n>+++";
+        let positions: Vec<dtypes::SourcePos> = parsing::tokenize(source, dtypes::Dialect::Brainfuck).iter().map(|t| t.pos).collect();
+        let mut bfi = interpreter::BFInterpreter::new(40);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.set_optimize(false);
+        bfi.load_program(source).unwrap();
+        let mut tui = Tui::new(source, &positions);
+        for _ in 0..10 {
+            tui.step(&mut bfi);
+        }
+        let screen = render(&mut tui, &bfi, 60, 20);
+        assert!(screen[0].starts_with("┌ source "));
+        assert!(screen[1].contains("│++++++++[>++++++++<-]>+."));
+        assert!(screen.iter().any(|row| row.contains("│      0    8   0   0")), "{:#?}", screen);
+        assert!(screen[18].starts_with(" step 10  command 10  data pointer 1  paused, 1 per frame"));
+        // run to the end, the output shows up and the status says so
+        tui.handle_key(KeyCode::Char('+'), &mut bfi);
+        tui.running = true;
+        while tui.running {
+            tui.step(&mut bfi);
+        }
+        let screen = render(&mut tui, &bfi, 60, 20);
+        assert!(screen[1].ends_with("││A                     │"), "{:#?}", screen);
+        assert!(screen[18].contains("finished"));
+        assert!(!tui.handle_key(KeyCode::Char('q'), &mut bfi));
+    }
+
+    #[test]
+    fn tui_scrolls_tape () {
+        let positions = Vec::new();
+        let mut bfi = interpreter::BFInterpreter::new(1000);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.load_program("").unwrap();
+        let mut tui = Tui::new("", &positions);
+        render(&mut tui, &bfi, 60, 20);
+        tui.handle_key(KeyCode::PageDown, &mut bfi);
+        tui.handle_key(KeyCode::Down, &mut bfi);
+        let screen = render(&mut tui, &bfi, 60, 20);
+        // 12 cells per row, 6 rows shown, scrolled down 7 rows
+        assert!(screen[11].starts_with("│     84 "), "{:#?}", screen);
+        tui.handle_key(KeyCode::Char('f'), &mut bfi);
+        let screen = render(&mut tui, &bfi, 60, 20);
+        assert!(screen[11].starts_with("│      0 "));
+    }
+}
@@ -0,0 +1,93 @@
+/*
+    Module with entry points for programs from anywhere, e.g. a fuzzer or a
+    web form, that take arbitrary bytes rather than text and that nothing
+    given to them can make panic, overflow the stack or run forever
+
+        let limits = rust_bfi::Limits { max_steps: Option::Some(100_000), ..Default::default() };
+        let (output, report) = rust_bfi::run_untrusted_with_limits(b",+.", b"a", limits);
+        assert_eq!(output, b"b");
+*/
+
+
+use crate::{batch, dtypes, interpreter, optimizer, parsing};
+
+
+// number of memory cells programs are run with, per the classic spec
+const MEM_SIZE: usize = 30000;
+
+
+// step limit for programs run without one, so they always stop
+pub const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+
+// the commands of a brainfuck program given as raw bytes, anything that isn't
+// UTF-8 is a comment like any other character that isn't a command, fails if
+// its brackets don't match
+pub fn parse_untrusted (source: &[u8]) -> Result<Vec<dtypes::Command>, dtypes::BFError> {
+    let cmds = parsing::parse_program(&String::from_utf8_lossy(source));
+    optimizer::lower(&cmds)?;
+    Ok(cmds)
+}
+
+
+// run a brainfuck program given as raw bytes in a fresh interpreter (30000
+// cells on a strict tape) with the given input, held to limits, along with a
+// step limit of DEFAULT_MAX_STEPS if they don't have one, gives the output
+// and how it went, the report's result holds any error loading the program
+pub fn run_untrusted_with_limits (source: &[u8], input: &[u8], limits: dtypes::Limits) -> (Vec<u8>, dtypes::RunReport) {
+    let mut bfi = interpreter::BFInterpreter::new(MEM_SIZE);
+    bfi.set_limits(dtypes::Limits { max_steps: limits.max_steps.or(Option::Some(DEFAULT_MAX_STEPS)), ..limits });
+    // the input is all given separately
+    bfi.set_split_input(false);
+    batch::run_program(bfi, &String::from_utf8_lossy(source), Option::Some(input))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // bytes from a xorshift generator, mostly commands
+    fn random_bytes (seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            match state % 4 {
+                0 => (state >> 8) as u8,
+                _ => b"+-<>[].,"[(state >> 8) as usize % 8],
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn untrusted_parse () {
+        assert_eq!(parse_untrusted(b"+\xff\xfe-"), Ok(vec![dtypes::Command::IncrementByte, dtypes::Command::DecrementByte]));
+        assert!(matches!(parse_untrusted(b"[\x80"), Err(dtypes::BFError::UnmatchedBracket { bracket: '[', .. })));
+        let deep = [vec![b'['; 100_000], vec![b']'; 100_000]].concat();
+        assert_eq!(parse_untrusted(&deep).map(|cmds| cmds.len()), Ok(200_000));
+    }
+
+    #[test]
+    fn untrusted_run () {
+        let limits = dtypes::Limits { max_steps: Some(10_000), ..Default::default() };
+        assert_eq!(run_untrusted_with_limits(b",+.\xff!", b"a", limits).0, b"b");
+        let (_, report) = run_untrusted_with_limits(b"+[]", b"", dtypes::Limits::default());
+        assert!(matches!(report.result, Err(dtypes::BFError::StepLimitExceeded { limit: DEFAULT_MAX_STEPS, .. })));
+        // deeply nested and exponentially hard to follow programs still load
+        for prog in [[vec![b'['; 100_000], vec![b']'; 100_000]].concat(), [b"+".to_vec(), b"[>".repeat(60), b"]".repeat(60)].concat()] {
+            let (_, report) = run_untrusted_with_limits(&prog, b"", limits);
+            assert!(!matches!(report.result, Err(dtypes::BFError::UnmatchedBracket { .. })));
+        }
+        // and random bytes never panic
+        for seed in 0..300 {
+            let prog = random_bytes(seed, 200);
+            let (_, report) = run_untrusted_with_limits(&prog, &random_bytes(!seed, 20), limits);
+            if parse_untrusted(&prog).is_err() {
+                assert!(matches!(report.result, Err(dtypes::BFError::UnmatchedBracket { .. })), "{:?}", prog);
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+/*
+    Module with javascript bindings for running programs in the browser,
+    only built with the wasm feature
+
+        const bfi = new BFInterpreterWasm(30000);
+        bfi.setOutputCallback((byte) => console.log(String.fromCharCode(byte)));
+        bfi.load("++++++++[>++++++++<-]>+.");
+        bfi.run();
+*/
+
+
+use std::io::{self, Read, Write};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{dtypes, interpreter};
+
+
+// reads input one byte at a time from a javascript function, which returns
+// the next byte or anything that isn't a byte (e.g. -1 or null) at the end of
+// the input
+struct CallbackReader {
+    callback: js_sys::Function,
+}
+
+
+impl Read for CallbackReader {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let value = self.callback.call0(&JsValue::NULL)
+            .map_err(|e| io::Error::other(format!("input callback failed: {:?}", e)))?;
+        match value.as_f64() {
+            Some(byte) if (0.0..256.0).contains(&byte) => {
+                buf[0] = byte as u8;
+                Ok(1)
+            },
+            _ => Ok(0),
+        }
+    }
+}
+
+
+// passes each byte of output to a javascript function as it is produced
+struct CallbackWriter {
+    callback: js_sys::Function,
+}
+
+
+impl Write for CallbackWriter {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.callback.call1(&JsValue::NULL, &JsValue::from(byte))
+                .map_err(|e| io::Error::other(format!("output callback failed: {:?}", e)))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+// an interpreter with a strict tape of 8 bit cells that collects its output (until
+// an output callback is set) and has no input (until some is given)
+#[wasm_bindgen]
+pub struct BFInterpreterWasm {
+    bfi: interpreter::BFInterpreter,
+}
+
+
+#[wasm_bindgen]
+impl BFInterpreterWasm {
+    #[wasm_bindgen(constructor)]
+    pub fn new (mem_size: usize) -> BFInterpreterWasm {
+        let mut bfi = interpreter::BFInterpreter::new(mem_size);
+        bfi.set_output_sink(interpreter::OutputSink::Buffer);
+        bfi.set_input(&[]);
+        BFInterpreterWasm { bfi }
+    }
+
+    // load a program, ready to run from the start
+    pub fn load (&mut self, prog: &str) -> Result<(), JsError> {
+        self.bfi.load_program(prog).map_err(to_js_error)
+    }
+
+    // execute a single instruction, returns true once the program has finished
+    pub fn step (&mut self) -> Result<bool, JsError> {
+        self.bfi.step().map_err(to_js_error)?;
+        Ok(self.bfi.is_finished())
+    }
+
+    // run the program until it finishes, or until it needs more input
+    pub fn run (&mut self) -> Result<(), JsError> {
+        self.bfi.run().map_err(to_js_error)
+    }
+
+    // stop the program with an error once it has executed this many instructions
+    // (undefined for no limit)
+    #[wasm_bindgen(js_name = setMaxSteps)]
+    pub fn set_max_steps (&mut self, max_steps: Option<u32>) {
+        self.bfi.set_max_steps(max_steps.map(u64::from));
+    }
+
+    // have the program read these bytes as its input
+    #[wasm_bindgen(js_name = setInput)]
+    pub fn set_input (&mut self, input: &[u8]) {
+        self.bfi.set_input(input);
+    }
+
+    // give the program more input, the first time replacing any set before, from
+    // then on running out of it suspends the program until more is given
+    #[wasm_bindgen(js_name = provideInput)]
+    pub fn provide_input (&mut self, input: &[u8]) {
+        self.bfi.provide_input(input);
+    }
+
+    // no more input is coming after what has been provided
+    #[wasm_bindgen(js_name = endInput)]
+    pub fn end_input (&mut self) {
+        self.bfi.end_input();
+    }
+
+    // true while the program is waiting for more input to be provided
+    #[wasm_bindgen(js_name = needsInput)]
+    pub fn needs_input (&self) -> bool {
+        self.bfi.needs_input()
+    }
+
+    // have the program read its input by calling callback() for each byte
+    #[wasm_bindgen(js_name = setInputCallback)]
+    pub fn set_input_callback (&mut self, callback: js_sys::Function) {
+        self.bfi.set_input_source(Box::new(CallbackReader { callback }));
+    }
+
+    // pass each byte the program outputs to callback(byte) rather than collecting them
+    #[wasm_bindgen(js_name = setOutputCallback)]
+    pub fn set_output_callback (&mut self, callback: js_sys::Function) {
+        self.bfi.set_output_sink(interpreter::OutputSink::Writer(Box::new(CallbackWriter { callback })));
+    }
+
+    // a copy of every memory cell
+    pub fn memory (&self) -> Vec<u32> {
+        self.bfi.memory().to_vec()
+    }
+
+    // a copy of the output collected so far (empty with an output callback)
+    pub fn output (&self) -> Vec<u8> {
+        self.bfi.output().to_vec()
+    }
+
+    #[wasm_bindgen(js_name = dataPointer)]
+    pub fn data_pointer (&self) -> usize {
+        self.bfi.data_pointer()
+    }
+
+    #[wasm_bindgen(js_name = isFinished)]
+    pub fn is_finished (&self) -> bool {
+        self.bfi.is_finished()
+    }
+}
+
+
+// a BFError as a javascript Error with the same message
+fn to_js_error (e: dtypes::BFError) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // only what runs without a javascript host can be tested natively
+    #[test]
+    fn wasm_interpreter_runs () {
+        let mut bfi = BFInterpreterWasm::new(8);
+        bfi.load("+++[>++<-],>.").unwrap();
+        assert!(!bfi.step().unwrap());
+        bfi.run().unwrap();
+        assert!(bfi.is_finished());
+        assert_eq!(bfi.output(), [6]);
+        assert_eq!(bfi.memory(), [0, 6, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bfi.data_pointer(), 1);
+        // input can be given a bit at a time
+        bfi.load(",.,.").unwrap();
+        bfi.provide_input(b"a");
+        bfi.run().unwrap();
+        assert!(bfi.needs_input() && !bfi.is_finished());
+        bfi.provide_input(b"b");
+        bfi.run().unwrap();
+        assert_eq!(bfi.output(), [6, b'a', b'b']);
+        assert!(bfi.is_finished());
+    }
+}
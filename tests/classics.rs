@@ -0,0 +1,47 @@
+/*
+    Corpus of well known programs (cat, rot13, the squares, bubble and
+    insertion sorts, and a test for obscure interpreter problems) kept as
+    golden files in tests/classics, each run on every tape mode, cell width
+    and engine (each program in a thread of its own, as the unoptimized runs
+    of the longer ones take a while)
+*/
+
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use rust_bfi::{bench, crossval, golden, BFInterpreter, CellWidth, TapeMode};
+
+
+#[test]
+fn classic_corpus () {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("classics");
+    let tests = golden::find_tests(&dir).unwrap();
+    assert!(tests.len() >= 6);
+    thread::scope(|scope| for test in &tests {
+        scope.spawn(move || run_everywhere(test));
+    });
+}
+
+
+// run a program with every configuration and check its output
+fn run_everywhere (test: &golden::GoldenTest) {
+    let source = fs::read_to_string(&test.program).unwrap();
+    let input = test.input.as_ref().map(|path| fs::read(path).unwrap()).unwrap_or_default();
+    let expected = fs::read(test.expected.as_ref().unwrap()).unwrap();
+    for tape_mode in [TapeMode::Strict, TapeMode::Wrapping, TapeMode::Growable, TapeMode::Bidirectional] {
+        for cell_width in [CellWidth::U8, CellWidth::U16, CellWidth::U32] {
+            for engine in bench::engines() {
+                let mut bfi = BFInterpreter::with_config(1000, tape_mode, cell_width);
+                bfi.set_optimize(engine.optimize);
+                bfi.set_engine(engine.engine);
+                bfi.set_max_steps(Some(10_000_000));
+                let outcome = crossval::run_engine(engine.name, &mut bfi, &source, Some(&input));
+                let config = format!("{} on a {:?} tape of {:?} cells {}", test.name, tape_mode, cell_width, engine.name);
+                assert_eq!(outcome.result, Ok(()), "{}", config);
+                assert_eq!(outcome.output, expected, "{}", config);
+            }
+        }
+    }
+}
@@ -0,0 +1,5 @@
+cat: copy input to output until the end of it
+(clearing each byte before the next read works whether the end of input
+leaves the cell alone or sets it to zero)
+
+,[.[-],]
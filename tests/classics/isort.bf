@@ -0,0 +1,8 @@
+insertion sort the bytes of its input
+by Daniel B Cristofani
+
+>>+>,[
+    <[
+        [>>+<<-]>[<<+<[->>+[<]]>>>[>]<<-]<<<
+    ]>>[<<+>>-]<[>+<-]>[>>]<,
+]<<<[<+<]>[>.>]
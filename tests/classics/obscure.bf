@@ -0,0 +1,8 @@
+tests for obscure problems: an empty loop to skip at the start and
+stray characters among the commands (with a tilde in place of the
+original bang which starts embedded input here)
+prints H and a newline
+by Daniel B Cristofani
+
+[]++++++++++[>>+>+>++++++[<<+<+++>>>-]<<<<-]
+"A*$";?@~[#>>+<<]>[>>]<<<<[>++<[-]]>.>.
@@ -0,0 +1,35 @@
+/*
+    Runs of the command line tool itself, for what only shows up in how it
+    exits
+*/
+
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+
+// run the tool on a program written to a file of its own
+fn run (name: &str, program: &str, args: &[&str]) -> Output {
+    let path: PathBuf = env::temp_dir().join(format!("rust_bfi_cli_{}_{}.bf", name, std::process::id()));
+    fs::write(&path, program).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rust_bfi")).args(args).arg(&path).output().unwrap();
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+
+#[test]
+fn cli_exit_cell () {
+    let output = run("exit_cell", "+++>++<", &["--exit-cell", "1"]);
+    assert_eq!(output.status.code(), Some(2));
+    // cells a bidirectional tape never grew to are 0, however far away
+    let output = run("exit_cell_far", "<+++>", &["--tape", "bidirectional", "--exit-cell", &usize::MAX.to_string()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stderr.is_empty());
+    // there's no such cell on a fixed size tape
+    let output = run("exit_cell_past", "+++", &["--mem-size", "30000", "--exit-cell", "99999"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: --exit-cell 99999 is past the end of the 30000 cell tape\n"));
+}
@@ -0,0 +1,4 @@
+copy a cell through a temporary one and print both
+>++++++++[<++++++++>-]<+
+[>+>+<<-]>>[<<+>>-]
+<<.>.
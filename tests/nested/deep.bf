@@ -0,0 +1,2 @@
+loops nested three deep multiply 2 * 2 * 2 then print it as a digit
+++[>++[>++[>+<-]<-]<-]>>>++++++++++++++++++++++++++++++++++++++++++++++++.
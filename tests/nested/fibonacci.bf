@@ -0,0 +1,8 @@
+the first ten fibonacci numbers as rows of asterisks
+cells: count a b tmp asterisk newline
+++++++++++>+>+>>>++++++[<+++++++>-]++++++++++<<<<<
+[
+    >[->>+<<]>>[-<<+>>>.<]>>.    print a stars keeping a
+    <<<<[->>+<<]>[-<+>>+<]>[-<+>] a b = b a plus b
+    <<<-
+]
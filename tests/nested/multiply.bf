@@ -0,0 +1,2 @@
+2 * 3 * 7 = 42 which is an asterisk
+++[>+++[>+++++++<-]<-]>>.
@@ -0,0 +1,4 @@
+a loop entered on zero skips its inner loops too
+[[-]+++[>+<-]>.]
++++[>[-]<-]
+++++++++[>++++++++<-]>+.
@@ -0,0 +1,32 @@
+/*
+    Regression corpus of programs with nested loops (multiplication, copying
+    through a temporary cell, fibonacci, loops skipped on zero), kept as
+    golden files in tests/nested and run on every engine
+*/
+
+
+use std::fs;
+use std::path::Path;
+
+use rust_bfi::{bench, golden};
+
+
+#[test]
+fn nested_loop_corpus () {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("nested");
+    let tests = golden::find_tests(&dir).unwrap();
+    assert!(tests.len() >= 5);
+    for test in &tests {
+        let expected = fs::read(test.expected.as_ref().unwrap()).unwrap();
+        let program = bench::Program {
+            name: test.name.clone(),
+            source: fs::read_to_string(&test.program).unwrap(),
+            input: test.input.as_ref().map(|path| fs::read(path).unwrap()).unwrap_or_default(),
+        };
+        for engine in bench::engines() {
+            let timing = bench::time(&program, &engine, 1, Some(1_000_000));
+            assert_eq!(timing.report.result, Ok(()), "{} on {}", test.name, engine.name);
+            assert_eq!(timing.output, expected, "{} on {}", test.name, engine.name);
+        }
+    }
+}